@@ -18,6 +18,9 @@ echo "Running end to end tests"
 find ./resources/test_input -name '*.csv' | while read input_file;
 do
   echo "Testing $input_file"
-  cargo run -- $input_file > /tmp/test_output.csv 2> /tmp/cargo_error_output || (echo "${RED}Program failed on input${NC}" && cat /tmp/cargo_error_output);
+  config_file="./resources/test_config/$(basename $input_file .csv).toml"
+  config_args=()
+  [[ -f $config_file ]] && config_args=(--config $config_file)
+  cargo run -- $config_args $input_file > /tmp/test_output.csv 2> /tmp/cargo_error_output || (echo "${RED}Program failed on input${NC}" && cat /tmp/cargo_error_output);
   diff_csvs "./resources/test_output/$(basename $input_file)" /tmp/test_output.csv > /tmp/diff_output && (echo "${GREEN}Test successful${NC}") || (echo "${RED}Test failed${NC}" && cat /tmp/diff_output);
 done
\ No newline at end of file
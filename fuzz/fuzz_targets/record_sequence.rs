@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use transactor::fuzz::FuzzRecord;
+
+fuzz_target!(|records: Vec<FuzzRecord>| {
+    transactor::fuzz::fuzz_records(&records);
+});
@@ -0,0 +1,273 @@
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::bank::{Account, Bank, ClientId, Outcome, TransactionId};
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::{Command, InputRecord};
+use crate::sla::SlaBreach;
+
+mod history;
+mod ledger;
+mod merkle;
+
+pub use history::History;
+pub use ledger::{verify_ledger, LedgerEntry, LedgerReader, LedgerWriter};
+pub use merkle::{prove_transaction, InclusionProof, MerkleTree, ProofStep, Side};
+
+/// One line of the audit log.
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    line: u64,
+    #[serde(rename = "type")]
+    kind: Option<&'static str>,
+    client: Option<u16>,
+    tx: Option<u32>,
+    amount: Option<Decimal>,
+    currency: Option<String>,
+    timestamp: Option<u64>,
+    batch: Option<&'a str>,
+    /// The reason code given on a freeze, dispute or chargeback row.
+    reason_code: Option<String>,
+    /// When the row was processed, in seconds since the unix epoch.
+    processed_at: u64,
+    outcome: &'static str,
+    reason: Option<String>,
+    error: Option<String>,
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+}
+
+/// Writes a json line for every input row recording what was done with it and the resulting
+/// balances of the account, so the processing of a file can be reconstructed and replayed.
+pub struct AuditLog<W: io::Write> {
+    writer: W,
+}
+
+impl<W: io::Write> AuditLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Record the outcome of applying a command to the bank. The outcome is `applied` or `ignored`
+    /// with the reason, or `rejected` with the error if the bank refused the command.
+    pub fn record(
+        &mut self,
+        record: &InputRecord,
+        result: &Result<Outcome, TransactorError>,
+        bank: &Bank,
+    ) -> Result<(), TransactorError> {
+        let (amount, currency) = transaction_amount(record);
+        let (outcome, reason, error) = match result {
+            Ok(Outcome::Applied) => ("applied", None, None),
+            Ok(Outcome::Ignored(reason)) => ("ignored", Some(reason.to_string()), None),
+            Err(e) => ("rejected", None, Some(e.to_string())),
+        };
+        let account = bank.get_account(record.command.client());
+        let total = account.map(Account::total).transpose()?;
+        self.write(&AuditEntry {
+            line: record.position.line,
+            kind: Some(record.command.name()),
+            client: Some(record.command.client().0),
+            tx: record
+                .command
+                .transaction_id()
+                .map(|transaction| transaction.0),
+            amount,
+            currency,
+            timestamp: record.timestamp.map(|timestamp| timestamp.0),
+            batch: record.batch.as_ref().map(|batch| batch.as_str()),
+            reason_code: record.command.reason().map(|reason| reason.to_string()),
+            processed_at: processed_at(),
+            outcome,
+            reason,
+            error,
+            available: account.map(Account::available),
+            held: account.map(Account::held),
+            total,
+            locked: account.map(Account::is_locked),
+        })
+    }
+
+    /// Record an input row that could not be read, the error carries its position.
+    pub fn record_unreadable(&mut self, error: &TransactorError) -> Result<(), TransactorError> {
+        let position = match error {
+            Record { position, .. } => Some(*position),
+            _ => None,
+        };
+        self.write(&AuditEntry {
+            line: position.map_or(0, |position| position.line),
+            kind: None,
+            client: position
+                .and_then(|position| position.client)
+                .map(|client| client.0),
+            tx: position
+                .and_then(|position| position.transaction)
+                .map(|transaction| transaction.0),
+            amount: None,
+            currency: None,
+            timestamp: None,
+            batch: None,
+            reason_code: None,
+            processed_at: processed_at(),
+            outcome: "rejected",
+            reason: None,
+            error: Some(error.to_string()),
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        })
+    }
+
+    /// Record a dispute escalated for going over its SLA, noticed on reading the row at `line`.
+    pub fn record_escalation(
+        &mut self,
+        breach: &SlaBreach,
+        line: u64,
+    ) -> Result<(), TransactorError> {
+        self.write(&AuditEntry {
+            line,
+            kind: Some("dispute"),
+            client: Some(breach.client.0),
+            tx: Some(breach.transaction.0),
+            amount: None,
+            currency: None,
+            timestamp: Some(breach.breached_at.0),
+            batch: None,
+            reason_code: None,
+            processed_at: processed_at(),
+            outcome: "escalated",
+            reason: Some("sla-breached".to_string()),
+            error: None,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        })
+    }
+
+    /// Record a row that broke a rule which only flags it. The row itself is recorded separately.
+    pub fn record_flag(&mut self, record: &InputRecord, rule: &str) -> Result<(), TransactorError> {
+        self.write(&AuditEntry {
+            line: record.position.line,
+            kind: Some(record.command.name()),
+            client: Some(record.command.client().0),
+            tx: record
+                .command
+                .transaction_id()
+                .map(|transaction| transaction.0),
+            amount: None,
+            currency: None,
+            timestamp: record.timestamp.map(|timestamp| timestamp.0),
+            batch: record.batch.as_ref().map(|batch| batch.as_str()),
+            reason_code: None,
+            processed_at: processed_at(),
+            outcome: "flagged",
+            reason: Some(rule.to_string()),
+            error: None,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        })
+    }
+
+    /// Record an authorization voided for going unsettled too long, noticed on reading the row at
+    /// `line`.
+    pub fn record_expiry(
+        &mut self,
+        client: ClientId,
+        transaction: TransactionId,
+        line: u64,
+    ) -> Result<(), TransactorError> {
+        self.write(&AuditEntry {
+            line,
+            kind: Some("authorize"),
+            client: Some(client.0),
+            tx: Some(transaction.0),
+            amount: None,
+            currency: None,
+            timestamp: None,
+            batch: None,
+            reason_code: None,
+            processed_at: processed_at(),
+            outcome: "expired",
+            reason: None,
+            error: None,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), TransactorError> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn write(&mut self, entry: &AuditEntry) -> Result<(), TransactorError> {
+        serde_json::to_writer(&mut self.writer, entry).map_err(io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// The amount and currency of a row that carries a transaction.
+pub(crate) fn transaction_amount(record: &InputRecord) -> (Option<Decimal>, Option<String>) {
+    match record.command {
+        Command::Deposit { transaction, .. }
+        | Command::Withdrawal { transaction, .. }
+        | Command::Authorize { transaction, .. }
+        | Command::Hold { transaction, .. }
+        | Command::Move { transaction, .. } => (
+            Some(transaction.amount().abs()),
+            transaction.currency().map(|currency| currency.to_string()),
+        ),
+        _ => (None, None),
+    }
+}
+
+fn processed_at() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn every_row_is_logged_with_its_outcome() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,5\ndispute,1,9,\n";
+        let mut bank = Bank::new();
+        let mut output = Vec::new();
+        let mut audit = AuditLog::new(&mut output);
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let result = record.command.apply(&mut bank);
+            audit.record(&record, &result, &bank)?;
+        }
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["outcome"], "applied");
+        assert_eq!(lines[0]["available"], "1.5");
+        assert_eq!(lines[1]["outcome"], "ignored");
+        assert_eq!(lines[1]["reason"], "insufficient-funds");
+        assert_eq!(lines[1]["amount"], "5");
+        assert_eq!(lines[2]["reason"], "unknown-transaction");
+        assert_eq!(lines[2]["line"], 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use crate::bank::Outcome;
+use crate::error::TransactorError;
+use crate::input::InputRecord;
+use crate::report::{ReportValue, ReportWriter};
+
+use super::transaction_amount;
+
+/// The columns of the history, one row for every input row given to the bank.
+const COLUMNS: [&str; 9] = [
+    "line",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "timestamp",
+    "batch",
+    "outcome",
+];
+
+/// The full history of a run as a table in a report format, so it can be loaded for analysis,
+/// such as into a Parquet file too long to keep as csv. Each row is written as it is applied
+/// with whether it was applied, ignored or rejected.
+pub struct History<'a> {
+    report: Box<dyn ReportWriter + 'a>,
+}
+
+impl<'a> History<'a> {
+    pub fn new(mut report: Box<dyn ReportWriter + 'a>) -> Result<Self, TransactorError> {
+        report.begin(&COLUMNS)?;
+        Ok(Self { report })
+    }
+
+    pub fn record(
+        &mut self,
+        record: &InputRecord,
+        result: &Result<Outcome, TransactorError>,
+    ) -> Result<(), TransactorError> {
+        let (amount, currency) = transaction_amount(record);
+        let command = &record.command;
+        let outcome = match result {
+            Ok(Outcome::Applied) => "applied",
+            Ok(Outcome::Ignored(_)) => "ignored",
+            Err(_) => "rejected",
+        };
+        self.report.row(&[
+            ReportValue::Integer(record.position.line),
+            ReportValue::Text(command.name().to_string()),
+            ReportValue::Integer(command.client().0.into()),
+            command
+                .transaction_id()
+                .map_or(ReportValue::Empty, |transaction| {
+                    ReportValue::Integer(transaction.0.into())
+                }),
+            amount.map_or(ReportValue::Empty, |amount| {
+                ReportValue::Number(amount.normalize())
+            }),
+            currency.map_or(ReportValue::Empty, ReportValue::Text),
+            record.timestamp.map_or(ReportValue::Empty, |timestamp| {
+                ReportValue::Integer(timestamp.0)
+            }),
+            record.batch.as_ref().map_or(ReportValue::Empty, |batch| {
+                ReportValue::Text(batch.as_str().to_string())
+            }),
+            ReportValue::Text(outcome.to_string()),
+        ])
+    }
+
+    pub fn finish(mut self) -> Result<(), TransactorError> {
+        self.report.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::Bank;
+    use crate::input::{InputReader, InputSchema};
+    use crate::report::CsvReportWriter;
+
+    #[test]
+    fn history_has_a_row_for_every_row_given_to_the_bank() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount,timestamp,batch\n\
+            deposit,1,1,2.50,100,day-1\n\
+            withdrawal,1,2,5,101,\n\
+            resolve,1,1,,102,\n";
+        let mut bank = Bank::new();
+        let mut output = Vec::new();
+        let mut history = History::new(Box::new(CsvReportWriter::new(&mut output)))?;
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let result = record.command.apply(&mut bank);
+            history.record(&record, &result)?;
+        }
+        history.finish()?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "line,type,client,tx,amount,currency,timestamp,batch,outcome\n\
+             2,deposit,1,1,2.5,,100,day-1,applied\n\
+             3,withdrawal,1,2,5,,101,,ignored\n\
+             4,resolve,1,1,,,102,,ignored\n"
+        );
+        Ok(())
+    }
+}
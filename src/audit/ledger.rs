@@ -0,0 +1,243 @@
+use std::io::{self, BufRead};
+
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::transaction_amount;
+use crate::bank::Outcome;
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::InputRecord;
+
+/// The hash the first entry of a ledger chains from.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One operation applied to the bank, chained to the entry before it by that entry's hash.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// The position of the entry in the ledger, from 1.
+    pub seq: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub client: u16,
+    /// The sub-account of the operation, absent for the main account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub tx: Option<u32>,
+    pub amount: Option<Decimal>,
+    pub currency: Option<String>,
+    pub timestamp: Option<u64>,
+    /// The hash of the entry before, or all zeroes for the first.
+    pub prev: String,
+    /// The sha256 of every other field of the entry, in hex.
+    pub hash: String,
+}
+
+/// The fields of an entry that are hashed, in the order they are hashed in.
+#[derive(Serialize)]
+struct HashedFields<'a> {
+    seq: u64,
+    kind: &'a str,
+    client: u16,
+    account: Option<&'a str>,
+    tx: Option<u32>,
+    amount: Option<Decimal>,
+    currency: Option<&'a str>,
+    timestamp: Option<u64>,
+    prev: &'a str,
+}
+
+impl LedgerEntry {
+    /// The hash the entry should have, which differs from `hash` if the entry was altered.
+    pub fn digest(&self) -> Result<String, TransactorError> {
+        let fields = HashedFields {
+            seq: self.seq,
+            kind: &self.kind,
+            client: self.client,
+            account: self.account.as_deref(),
+            tx: self.tx,
+            amount: self.amount,
+            currency: self.currency.as_deref(),
+            timestamp: self.timestamp,
+            prev: &self.prev,
+        };
+        let mut hasher = Sha256::new();
+        serde_json::to_writer(&mut hasher, &fields).map_err(io::Error::from)?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+/// Appends an entry for every operation applied to the bank, each holding the hash of the one
+/// before, so any entry altered, removed or reordered afterwards breaks the chain from there on.
+/// `verify_ledger` checks the chain.
+pub struct LedgerWriter<W: io::Write> {
+    writer: W,
+    /// The last entry written, None until the first entry of a new ledger.
+    head: Option<LedgerEntry>,
+}
+
+impl<W: io::Write> LedgerWriter<W> {
+    /// Start a new ledger.
+    pub fn new(writer: W) -> Self {
+        Self { writer, head: None }
+    }
+
+    /// Carry on the chain of a ledger whose last entry is `head`, such as a ledger being appended
+    /// to by a later run.
+    pub fn continuing(writer: W, head: Option<LedgerEntry>) -> Self {
+        Self { writer, head }
+    }
+
+    /// Add an entry for the record if it was applied, rows ignored or refused change nothing so
+    /// are left out.
+    pub fn record(
+        &mut self,
+        record: &InputRecord,
+        result: &Result<Outcome, TransactorError>,
+    ) -> Result<(), TransactorError> {
+        if !matches!(result, Ok(Outcome::Applied)) {
+            return Ok(());
+        }
+        let (amount, currency) = transaction_amount(record);
+        let mut entry = LedgerEntry {
+            seq: self.head.as_ref().map_or(1, |head| head.seq + 1),
+            kind: record.command.name().to_string(),
+            client: record.command.client().0,
+            account: Some(record.account.to_string()).filter(|_| !record.account.is_main()),
+            tx: record
+                .command
+                .transaction_id()
+                .map(|transaction| transaction.0),
+            amount: amount.map(|amount| amount.normalize()),
+            currency,
+            timestamp: record.timestamp.map(|timestamp| timestamp.0),
+            prev: self
+                .head
+                .as_ref()
+                .map_or(GENESIS.to_string(), |head| head.hash.clone()),
+            hash: String::new(),
+        };
+        entry.hash = entry.digest()?;
+        serde_json::to_writer(&mut self.writer, &entry).map_err(io::Error::from)?;
+        self.writer.write_all(b"\n")?;
+        self.head = Some(entry);
+        Ok(())
+    }
+
+    pub fn head(&self) -> Option<&LedgerEntry> {
+        self.head.as_ref()
+    }
+
+    pub fn flush(&mut self) -> Result<(), TransactorError> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the entries of a ledger in order, checking each holds its own hash and the hash of the
+/// entry before, numbered one after it. Fails at the first entry that does not.
+pub struct LedgerReader<R: BufRead> {
+    lines: io::Lines<R>,
+    /// The number of lines read.
+    line: usize,
+    /// The seq and hash of the last entry read.
+    head: Option<(u64, String)>,
+}
+
+impl<R: BufRead> LedgerReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+            head: None,
+        }
+    }
+
+    fn check(&mut self, line: String) -> Result<LedgerEntry, TransactorError> {
+        let number = self.line;
+        let broken = |reason: &str| InvalidLedger(format!("line {}: {}", number, reason));
+        let entry: LedgerEntry = serde_json::from_str(&line).map_err(|e| broken(&e.to_string()))?;
+        let (seq, prev) = match &self.head {
+            Some((seq, hash)) => (seq + 1, hash.as_str()),
+            None => (1, GENESIS),
+        };
+        if entry.seq != seq {
+            return Err(broken(&format!(
+                "expected entry {} but found {}",
+                seq, entry.seq
+            )));
+        }
+        if entry.prev != prev {
+            return Err(broken("the entry does not follow the one before"));
+        }
+        if entry.hash != entry.digest()? {
+            return Err(broken("the entry does not match its hash"));
+        }
+        self.head = Some((entry.seq, entry.hash.clone()));
+        Ok(entry)
+    }
+}
+
+impl<R: BufRead> Iterator for LedgerReader<R> {
+    type Item = Result<LedgerEntry, TransactorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        self.line += 1;
+        Some(
+            line.map_err(TransactorError::from)
+                .and_then(|line| self.check(line)),
+        )
+    }
+}
+
+/// Check the chain of a whole ledger as `LedgerReader` does, returning the last entry.
+pub fn verify_ledger<R: BufRead>(reader: R) -> Result<Option<LedgerEntry>, TransactorError> {
+    let mut head = None;
+    for entry in LedgerReader::new(reader) {
+        head = Some(entry?);
+    }
+    Ok(head)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::Bank;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn ledger_chains_applied_rows_and_detects_tampering() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1.50\n\
+            withdrawal,1,2,5\n\
+            deposit,2,3,2\n\
+            dispute,1,1,\n";
+        let mut bank = Bank::new();
+        let mut ledger = LedgerWriter::new(Vec::new());
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let result = record.command.apply(&mut bank);
+            ledger.record(&record, &result)?;
+        }
+        let head = ledger.head().cloned();
+        let written = String::from_utf8(ledger.into_inner()).unwrap();
+        assert_eq!(written.lines().count(), 3);
+        assert_eq!(verify_ledger(written.as_bytes())?, head);
+        assert_eq!(head.map(|head| head.seq), Some(3));
+
+        let altered = written.replacen("\"1.5\"", "\"15\"", 1);
+        assert!(matches!(
+            verify_ledger(altered.as_bytes()),
+            Err(InvalidLedger(_))
+        ));
+        let reordered: Vec<&str> = written.lines().rev().collect();
+        assert!(verify_ledger(reordered.join("\n").as_bytes()).is_err());
+        let truncated: Vec<&str> = written.lines().skip(1).collect();
+        assert!(verify_ledger(truncated.join("\n").as_bytes()).is_err());
+        Ok(())
+    }
+}
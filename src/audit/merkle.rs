@@ -0,0 +1,227 @@
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{LedgerEntry, LedgerReader};
+use crate::bank::TransactionId;
+use crate::error::{TransactorError, TransactorError::*};
+
+type Hash = [u8; 32];
+
+/// A Merkle tree over the entries of a ledger, in ledger order. Leaves and inner nodes are
+/// hashed with different prefixes so neither can pass for the other, and a node without a
+/// sibling is carried up a level as it is.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MerkleTree {
+    /// Every level of the tree, the leaves first and the root last.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// The tree over entries with the given hashes.
+    pub fn new(entry_hashes: &[String]) -> Result<Self, TransactorError> {
+        let leaves = entry_hashes
+            .iter()
+            .map(|hash| decode(hash).map(|hash| leaf(&hash)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks are of one or two nodes"),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Ok(Self { levels })
+    }
+
+    /// The tree over every entry of a ledger, checking its chain as it is read.
+    pub fn from_ledger<R: BufRead>(reader: R) -> Result<Self, TransactorError> {
+        let hashes = LedgerReader::new(reader)
+            .map(|entry| entry.map(|entry| entry.hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(&hashes)
+    }
+
+    /// The root hash in hex, None for an empty ledger.
+    pub fn root(&self) -> Option<String> {
+        self.levels.last()?.first().map(hex::encode)
+    }
+
+    /// The hashes leading from the leaf at `index` to the root, None if there is no such leaf.
+    pub fn path(&self, index: usize) -> Option<Vec<ProofStep>> {
+        self.levels.first()?.get(index)?;
+        let mut path = Vec::new();
+        let mut index = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = match index % 2 {
+                0 => level.get(index + 1).map(|hash| (Side::Right, hash)),
+                _ => level.get(index - 1).map(|hash| (Side::Left, hash)),
+            };
+            if let Some((side, hash)) = sibling {
+                path.push(ProofStep {
+                    side,
+                    hash: hex::encode(hash),
+                });
+            }
+            index /= 2;
+        }
+        Some(path)
+    }
+}
+
+/// Which side of the node being proven a sibling hash goes on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// One step from a node towards the root: the hash of its sibling.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub side: Side,
+    pub hash: String,
+}
+
+/// That a ledger entry is in the ledger with a given Merkle root, checked without the ledger by
+/// hashing the entry up the path to the root.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// The root of the ledger the proof was made from.
+    pub root: String,
+    pub entry: LedgerEntry,
+    pub path: Vec<ProofStep>,
+}
+
+impl InclusionProof {
+    /// Check the entry holds its own hash and that its path leads to `root`, which should be the
+    /// root published for the ledger rather than the one the proof carries.
+    pub fn verify(&self, root: &str) -> Result<(), TransactorError> {
+        if self.entry.hash != self.entry.digest()? {
+            return Err(InvalidProof(
+                "the entry does not match its hash".to_string(),
+            ));
+        }
+        let mut hash = leaf(&decode(&self.entry.hash)?);
+        for step in &self.path {
+            let sibling = decode(&step.hash)?;
+            hash = match step.side {
+                Side::Left => node(&sibling, &hash),
+                Side::Right => node(&hash, &sibling),
+            };
+        }
+        match hex::encode(hash) == root.to_ascii_lowercase() {
+            true => Ok(()),
+            false => Err(InvalidProof(format!(
+                "the path of entry {} leads to {} rather than the root {}",
+                self.entry.seq,
+                hex::encode(hash),
+                root
+            ))),
+        }
+    }
+}
+
+/// A proof that the entry first naming the transaction, the deposit or withdrawal that made it,
+/// is in the ledger. None if no entry names it.
+pub fn prove_transaction<R: BufRead>(
+    reader: R,
+    transaction_id: TransactionId,
+) -> Result<Option<InclusionProof>, TransactorError> {
+    let mut hashes = Vec::new();
+    let mut proven = None;
+    for entry in LedgerReader::new(reader) {
+        let entry = entry?;
+        hashes.push(entry.hash.clone());
+        if proven.is_none() && entry.tx == Some(transaction_id.0) {
+            proven = Some((hashes.len() - 1, entry));
+        }
+    }
+    let (index, entry) = match proven {
+        Some(proven) => proven,
+        None => return Ok(None),
+    };
+    let tree = MerkleTree::new(&hashes)?;
+    Ok(Some(InclusionProof {
+        root: tree.root().unwrap_or_default(),
+        entry,
+        path: tree.path(index).unwrap_or_default(),
+    }))
+}
+
+fn decode(hash: &str) -> Result<Hash, TransactorError> {
+    let mut decoded = [0; 32];
+    hex::decode_to_slice(hash, &mut decoded)
+        .map_err(|_| InvalidProof(format!("{} is not a sha256 hash", hash)))?;
+    Ok(decoded)
+}
+
+fn leaf(entry_hash: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([0])
+        .chain_update(entry_hash)
+        .finalize()
+        .into()
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([1])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::LedgerWriter;
+    use crate::bank::Bank;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn every_entry_is_proven_against_the_root() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1\n\
+            deposit,2,2,2\n\
+            deposit,3,3,3\n\
+            dispute,1,1,\n\
+            deposit,1,4,4\n";
+        let mut bank = Bank::new();
+        let mut ledger = LedgerWriter::new(Vec::new());
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let result = record.command.apply(&mut bank);
+            ledger.record(&record, &result)?;
+        }
+        let written = ledger.into_inner();
+        let root = MerkleTree::from_ledger(&written[..])?.root().unwrap();
+
+        for tx in 1..=4 {
+            let proof = prove_transaction(&written[..], TransactionId(tx))?.unwrap();
+            assert_eq!(proof.entry.tx, Some(tx));
+            assert_eq!(proof.root, root);
+            proof.verify(&root)?;
+        }
+        let proof = prove_transaction(&written[..], TransactionId(1))?.unwrap();
+        assert_eq!(proof.entry.kind, "deposit");
+        assert!(prove_transaction(&written[..], TransactionId(9))?.is_none());
+
+        let mut forged = proof.clone();
+        forged.entry.amount = Some(100.into());
+        assert!(matches!(forged.verify(&root), Err(InvalidProof(_))));
+        let mut moved = proof.clone();
+        moved.path[0].side = Side::Left;
+        assert!(moved.verify(&root).is_err());
+        assert!(proof.verify(&proof.path[0].hash).is_err());
+        Ok(())
+    }
+}
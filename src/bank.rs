@@ -1,7 +1,34 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::currency::{Currency, PrecisionTable};
 use crate::error::{TransactorError, TransactorError::*};
+use crate::metrics::{Metrics, Operation};
 use rust_decimal::prelude::*;
+use tracing::{debug, instrument};
+
+mod clients;
+mod concurrent;
+mod events;
+mod fees;
+mod invariants;
+mod snapshot;
+mod spill;
+mod sub_accounts;
+
+pub use clients::{ClientDirectory, ClientField, ClientMetadata, RiskTier};
+pub use concurrent::ConcurrentBank;
+pub use events::{BankEvent, EventReceiver, NextEvent};
+pub use fees::{Fee, FeeEntry, FeeRule, FeeSchedule};
+pub use snapshot::InputCheckpoint;
+pub use sub_accounts::AccountName;
+
+use spill::SpillStore;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct TransactionId(pub u32);
@@ -9,10 +36,111 @@ pub struct TransactionId(pub u32);
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct ClientId(pub u16);
 
+/// A caller supplied correlation id for a batch of operations, for example one per input file.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct BatchId(Arc<str>);
+
+impl BatchId {
+    pub fn new(id: &str) -> Self {
+        Self(Arc::from(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A short code given with a freeze, dispute or chargeback saying why, such as `fraud`, up to 16
+/// ascii letters, digits, `-` or `_`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ReasonCode {
+    code: [u8; ReasonCode::MAX_LEN],
+    len: u8,
+}
+
+impl ReasonCode {
+    pub const MAX_LEN: usize = 16;
+
+    pub fn as_str(&self) -> &str {
+        // Only ascii is ever stored
+        std::str::from_utf8(&self.code[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ReasonCode {
+    type Err = TransactorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = !s.is_empty()
+            && s.len() <= Self::MAX_LEN
+            && s.bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+        if !valid {
+            return Err(InvalidReasonCode(s.to_string()));
+        }
+        let mut code = [0; Self::MAX_LEN];
+        code[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Self {
+            code,
+            len: s.len() as u8,
+        })
+    }
+}
+
+/// When an operation happened upstream, in seconds since the unix epoch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// The utc year, month and day of the timestamp and the seconds into that day.
+    pub fn civil(self) -> (i64, i64, i64, u64) {
+        let days = (self.0 / 86_400) as i64;
+        // Days to a civil date, from Howard Hinnant's date algorithms
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        (year, month, day, self.0 % 86_400)
+    }
+
+    /// The timestamp of a utc year, month and day and the seconds into that day, the inverse of
+    /// `civil`. None for dates before the epoch.
+    pub fn from_civil(year: i64, month: i64, day: i64, seconds: u64) -> Option<Self> {
+        let year = year - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month_index = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        match era * 146_097 + day_of_era - 719_468 {
+            days if days < 0 => None,
+            days => Some(Timestamp(days as u64 * 86_400 + seconds)),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct Transaction {
     transaction_id: TransactionId,
     amount: Decimal,
+    currency: Option<Currency>,
+    timestamp: Option<Timestamp>,
 }
 
 impl Transaction {
@@ -20,586 +148,4975 @@ impl Transaction {
         Self {
             transaction_id,
             amount,
+            currency: None,
+            timestamp: None,
         }
     }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        self.transaction_id
+    }
+
+    /// The signed amount, withdrawals are negative.
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
 }
 
-pub struct Account {
+/// Where a recorded transaction is in the dispute lifecycle.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TransactionState {
+    Posted,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    /// Charged back and then represented by the merchant, so the funds were credited back.
+    Represented,
+}
+
+impl fmt::Display for TransactionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionState::Posted => "posted",
+            TransactionState::Disputed => "disputed",
+            TransactionState::Resolved => "resolved",
+            TransactionState::ChargedBack => "chargedback",
+            TransactionState::Represented => "represented",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for TransactionState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "posted" => Ok(TransactionState::Posted),
+            "disputed" => Ok(TransactionState::Disputed),
+            "resolved" => Ok(TransactionState::Resolved),
+            "chargedback" => Ok(TransactionState::ChargedBack),
+            "represented" => Ok(TransactionState::Represented),
+            _ => Err(format!(
+                "Unknown transaction state {}, expected one of: posted, disputed, resolved, \
+                 chargedback, represented",
+                s
+            )),
+        }
+    }
+}
+
+impl TransactionState {
+    /// Transactions may be disputed when first posted and again after a dispute is resolved, but
+    /// never once charged back, even if the chargeback was represented.
+    fn is_disputable(self) -> bool {
+        matches!(self, TransactionState::Posted | TransactionState::Resolved)
+    }
+}
+
+/// Whether a transaction paid funds into or out of an account.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            _ => Err(format!(
+                "Unknown transaction type {}, expected one of: deposit, withdrawal",
+                s
+            )),
+        }
+    }
+}
+
+/// Criteria for `Bank::find_transactions`, a transaction matches if it satisfies every criterion
+/// that is set.
+#[derive(Clone, Debug, Default)]
+pub struct TransactionFilter {
+    pub client: Option<ClientId>,
+    /// Inclusive lower bound on the unsigned amount of the transaction.
+    pub min_amount: Option<Decimal>,
+    /// Inclusive upper bound on the unsigned amount of the transaction.
+    pub max_amount: Option<Decimal>,
+    pub kind: Option<TransactionKind>,
+    pub state: Option<TransactionState>,
+    /// Only transactions timestamped at or after this time, transactions without one never match.
+    pub from: Option<Timestamp>,
+    /// Only transactions timestamped before this time, transactions without one never match.
+    pub to: Option<Timestamp>,
+}
+
+impl TransactionFilter {
+    fn matches(&self, client_id: ClientId, recorded: &RecordedTransaction) -> bool {
+        let amount = recorded.transaction.amount;
+        let kind = if amount < Decimal::zero() {
+            TransactionKind::Withdrawal
+        } else {
+            TransactionKind::Deposit
+        };
+        self.client.is_none_or(|client| client == client_id)
+            && self.min_amount.is_none_or(|min| amount.abs() >= min)
+            && self.max_amount.is_none_or(|max| amount.abs() <= max)
+            && self.kind.is_none_or(|expected| expected == kind)
+            && self.state.is_none_or(|state| state == recorded.state)
+            && self.within_period(recorded.transaction.timestamp)
+    }
+
+    fn within_period(&self, timestamp: Option<Timestamp>) -> bool {
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        timestamp.is_some_and(|timestamp| {
+            self.from.is_none_or(|from| timestamp >= from)
+                && self.to.is_none_or(|to| timestamp < to)
+        })
+    }
+}
+
+/// A transaction found by `Bank::find_transactions`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TransactionMatch {
+    pub client_id: ClientId,
+    pub transaction: Transaction,
+    pub state: TransactionState,
+}
+
+/// The balances of an account at one moment, which stays as it was however the account changes
+/// afterwards.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AccountView {
     pub client_id: ClientId,
     pub available: Decimal,
     pub held: Decimal,
+    /// None if the available and held funds overflow when added.
+    pub total: Option<Decimal>,
     pub locked: bool,
-    transaction_history: HashMap<TransactionId, Transaction>,
-    disputed_transactions: HashSet<TransactionId>,
+    pub status: AccountStatus,
+    pub currency: Option<Currency>,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+struct RecordedTransaction {
+    transaction: Transaction,
+    state: TransactionState,
+    /// Why the transaction was last disputed, or charged back if a reason was given then.
+    reason: Option<ReasonCode>,
+}
+
+/// A step in the dispute lifecycle of a transaction, see `Account::dispute_history`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DisputeEntry {
+    /// The state the step moved the transaction to.
+    pub state: TransactionState,
+    /// The reason given with a dispute or chargeback.
+    pub reason: Option<ReasonCode>,
+    /// The batch the step was made in, if one was set.
+    pub batch: Option<BatchId>,
+}
+
+/// Where an account is in its lifecycle, which decides the operations it accepts, see
+/// `StatusRules`. Every status but active is locked.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Frozen by a chargeback or a freeze.
+    Frozen,
+    /// Closed by the customer, for good.
+    Closed,
+    /// Locked for going over a risk limit.
+    UnderReview,
+}
+
+impl AccountStatus {
+    pub const ALL: [AccountStatus; 4] = [
+        AccountStatus::Active,
+        AccountStatus::Frozen,
+        AccountStatus::Closed,
+        AccountStatus::UnderReview,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Closed => "closed",
+            AccountStatus::UnderReview => "under-review",
+        }
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AccountStatus::ALL
+            .iter()
+            .copied()
+            .find(|status| status.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown account status {}, expected one of: active, frozen, closed, \
+                     under-review",
+                    s
+                )
+            })
+    }
+}
+
+/// A clients account. The balances are set when the account is made and after that only
+/// changed by the operations of the bank, so they cannot be left disagreeing with each other.
+#[derive(Clone)]
+pub struct Account {
+    pub client_id: ClientId,
+    name: AccountName,
+    available: Decimal,
+    held: Decimal,
+    status: AccountStatus,
+    currency: Option<Currency>,
+    transaction_history: HashMap<TransactionId, RecordedTransaction>,
+    transaction_order: Vec<TransactionId>,
+    /// Every dispute, resolve, chargeback and representment of each transaction, oldest first.
+    /// Kept in memory when the transaction is spilled, as only disputed transactions have one.
+    dispute_history: HashMap<TransactionId, Vec<DisputeEntry>>,
+    version: u64,
+    last_batch: Option<BatchId>,
+    /// Frozen by a freeze rather than a chargeback, with the reason if one was given.
+    frozen: Option<Option<ReasonCode>>,
+    /// Funds held for authorizations not yet captured, voided or expired. The amounts are positive.
+    authorizations: HashMap<TransactionId, Transaction>,
+    /// Funds held administratively, such as for a regulatory hold, by the id of the hold. Kept
+    /// apart from disputes so neither releases the funds of the other. The amounts are positive.
+    holds: HashMap<TransactionId, Decimal>,
+    /// Chargebacks applied to the account this period, see `Bank::close_period`, for the risk
+    /// limits. Representments do not take them back.
+    chargebacks: u32,
+    /// Chargebacks not since represented, which keep the account locked.
+    standing_chargebacks: u32,
+    /// Transactions currently in dispute, for the risk limits.
+    open_disputes: u32,
+    /// What chargebacks of funds already withdrawn left the account owing, still to be recovered.
+    recovery: Decimal,
+    /// What has been recovered of such debts so far.
+    recovered: Decimal,
+    /// Fees charged on the transactions of the account, oldest first.
+    fees: Vec<FeeEntry>,
 }
 
 impl Account {
     pub fn new(client_id: ClientId) -> Self {
         Self {
             client_id,
+            name: AccountName::MAIN,
             available: Decimal::zero(),
             held: Decimal::zero(),
-            locked: false,
+            status: AccountStatus::Active,
+            currency: None,
             transaction_history: HashMap::new(),
-            disputed_transactions: HashSet::new(),
+            transaction_order: Vec::new(),
+            dispute_history: HashMap::new(),
+            version: 0,
+            last_batch: None,
+            frozen: None,
+            authorizations: HashMap::new(),
+            holds: HashMap::new(),
+            chargebacks: 0,
+            standing_chargebacks: 0,
+            open_disputes: 0,
+            recovery: Decimal::zero(),
+            recovered: Decimal::zero(),
+            fees: Vec::new(),
         }
     }
-}
 
-pub struct Bank {
-    client_accounts: HashMap<ClientId, Account>,
-}
+    /// An account starting with the given balances rather than from nothing, for seeding accounts
+    /// carried over from elsewhere, frozen if `locked`. Fails if the total of the balances
+    /// overflows.
+    pub fn with_balances(
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+    ) -> Result<Self, TransactorError> {
+        available.checked_add(held).ok_or(Overflow)?;
+        let status = match locked {
+            true => AccountStatus::Frozen,
+            false => AccountStatus::Active,
+        };
+        Ok(Self {
+            available,
+            held,
+            status,
+            ..Self::new(client_id)
+        })
+    }
 
-impl Bank {
-    pub fn new() -> Self {
-        Self {
-            client_accounts: HashMap::new(),
-        }
+    /// The funds available for withdrawal.
+    pub fn available(&self) -> Decimal {
+        self.available
     }
 
-    pub fn get_accounts(&self) -> impl Iterator<Item = &Account> {
-        self.client_accounts.values()
+    /// The funds held by disputes, authorizations and administrative holds.
+    pub fn held(&self) -> Decimal {
+        self.held
     }
 
-    /// Perform a transaction on a clients account.
-    /// Error can occur if any of:
-    /// * the transaction causes an overflow
-    /// * the transaction has already been recorded as occurring
-    /// If the transaction is a withdrawal and would leave the account in negative balance the transaction will not occur and will not be recorded.
-    /// If the account is locked, no action will be taken and the transaction will not be recorded.
-    pub fn transact(
-        &mut self,
-        client_id: ClientId,
-        transaction: Transaction,
-    ) -> Result<(), TransactorError> {
-        let account = self.account(client_id);
+    /// The available and held funds together, which fails if they overflow.
+    pub fn total(&self) -> Result<Decimal, TransactorError> {
+        self.available.checked_add(self.held).ok_or(Overflow)
+    }
 
-        if account.locked {
-            return Ok(());
-        }
+    /// Whether the account is locked, that is in any status but active.
+    pub fn is_locked(&self) -> bool {
+        self.status != AccountStatus::Active
+    }
 
-        if account
-            .transaction_history
-            .contains_key(&transaction.transaction_id)
-        {
-            return Err(TransactionIdReuse);
-        }
+    /// Whether the account has been closed, see `Bank::close_account`.
+    pub fn is_closed(&self) -> bool {
+        self.status == AccountStatus::Closed
+    }
 
-        let new_balance = account
-            .available
-            .checked_add(transaction.amount)
-            .ok_or_else(|| Overflow)?;
-        // We only allow the transaction to occur if it is depositing or it leaves the account in
-        // the positive
-        let zero = Decimal::zero();
-        if transaction.amount > zero || new_balance >= zero {
-            account.available = new_balance;
-            account
-                .transaction_history
-                .insert(transaction.transaction_id, transaction);
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// What chargebacks of funds already withdrawn left the account owing, still to be recovered.
+    pub fn recovery(&self) -> Decimal {
+        self.recovery
+    }
+
+    /// What has been recovered of the debts chargebacks left so far.
+    pub fn recovered(&self) -> Decimal {
+        self.recovered
+    }
+
+    /// The fees charged on transactions of the account, oldest first.
+    pub fn fees(&self) -> &[FeeEntry] {
+        &self.fees
+    }
+
+    /// The fees charged on the account added up, which fails if they overflow.
+    pub fn total_fees(&self) -> Result<Decimal, TransactorError> {
+        self.fees.iter().try_fold(Decimal::zero(), |total, entry| {
+            total.checked_add(entry.amount).ok_or(Overflow)
+        })
+    }
+
+    /// Record a fee already taken from the available funds in the fee ledger.
+    fn record_fee(&mut self, transaction: TransactionId, operation: Operation, amount: Decimal) {
+        if !amount.is_zero() {
+            self.fees.push(FeeEntry {
+                transaction,
+                operation,
+                amount,
+            });
         }
+    }
+
+    /// Record what a chargeback of `amount` left the account owing: the debt grows by the amount
+    /// but never past how far the available funds are below zero.
+    fn owe(&mut self, amount: Decimal) -> Result<(), TransactorError> {
+        let shortfall = (-self.available).max(Decimal::zero());
+        let owed = self.recovery.checked_add(amount).ok_or(Overflow)?;
+        self.recovery = owed.min(shortfall).max(self.recovery);
         Ok(())
     }
 
-    /// Handle a dispute on a transaction.
-    /// If the transaction does not exist this will be ignored.
-    /// If the transaction has already been disputed this will be ignored.
-    /// This can fail if moving the disputed funds causes an overflow
-    pub fn dispute_transaction(
-        &mut self,
-        client_id: ClientId,
-        dispute: TransactionId,
-    ) -> Result<(), TransactorError> {
-        let account = self.account(client_id);
-        // Only handle disputes that have not been handled and only if the transaction has been enacted.
-        if account.disputed_transactions.contains(&dispute)
-            || !account.transaction_history.contains_key(&dispute)
-        {
-            return Ok(());
+    /// Pay the debt down by up to `amount`, returning how much was paid.
+    fn recover(&mut self, amount: Decimal) -> Result<Decimal, TransactorError> {
+        let paid = amount.min(self.recovery).max(Decimal::zero());
+        self.recovery -= paid;
+        self.recovered = self.recovered.checked_add(paid).ok_or(Overflow)?;
+        Ok(paid)
+    }
+
+    /// Why an operation the status of the account does not accept was refused.
+    fn lock_reason(&self) -> IgnoreReason {
+        match self.status {
+            AccountStatus::Active => IgnoreReason::StatusRule,
+            AccountStatus::Closed => IgnoreReason::AccountClosed,
+            AccountStatus::Frozen | AccountStatus::UnderReview => IgnoreReason::AccountLocked,
         }
-        let transaction_amount = account.transaction_history[&dispute].amount;
-        // no matter if this is a withdrawal or a deposit we need to
-        // withhold the absolute value of the funds
-        let disputed_amount = transaction_amount.abs();
-        Bank::move_funds_from_available_to_held(account, disputed_amount)?;
-        account.disputed_transactions.insert(dispute);
-        Ok(())
     }
 
-    /// Resolve a previously disputed transaction
-    /// If the transaction does not exist, or this transaction was never
-    /// previously disputed this will be ignored.
-    /// This can fail if moving the disputed funds causes an overflow
-    pub fn resolve_disputed_transaction(
-        &mut self,
-        client_id: ClientId,
-        disputed_transaction: TransactionId,
-    ) -> Result<(), TransactorError> {
-        let account = self.account(client_id);
-        // Only handle disputes that have been made already and only if the transaction has been enacted.
-        if !account
-            .disputed_transactions
-            .contains(&disputed_transaction)
-            || !account
-                .transaction_history
-                .contains_key(&disputed_transaction)
-        {
-            return Ok(());
+    /// Freeze an active account at the request of the input, returning whether it was active.
+    fn freeze(&mut self, reason: Option<ReasonCode>) -> bool {
+        if self.status != AccountStatus::Active {
+            return false;
         }
-        let transaction_amount = account.transaction_history[&disputed_transaction].amount;
-        // no matter if this is a withdrawal or a deposit we need to
-        // move the funds from held into available
-        let disputed_amount = -transaction_amount.abs();
-        Bank::move_funds_from_available_to_held(account, disputed_amount)?;
-        account.disputed_transactions.remove(&disputed_transaction);
-        Ok(())
+        self.status = AccountStatus::Frozen;
+        self.frozen = Some(reason);
+        true
     }
 
-    /// Chargeback a disputed transaction
-    /// If the transaction does not exist, or this transaction was never
-    /// previously disputed this will be ignored.
-    /// This can fail if removing the funds causes overflow.
-    pub fn chargeback(
-        &mut self,
-        client_id: ClientId,
-        disputed_transaction: TransactionId,
-    ) -> Result<(), TransactorError> {
-        let account = self.account(client_id);
-        // Only handle disputes that have been made already and only if the transaction has been enacted.
-        if !account
-            .disputed_transactions
-            .contains(&disputed_transaction)
-            || !account
-                .transaction_history
-                .contains_key(&disputed_transaction)
-        {
-            return Ok(());
+    /// Lift a freeze, returning whether the account was frozen by one.
+    fn unfreeze(&mut self) -> bool {
+        if self.frozen.take().is_none() {
+            return false;
         }
-        let transaction_amount = account.transaction_history[&disputed_transaction].amount;
-        let disputed_amount = transaction_amount.abs();
-        account.held = account
-            .held
-            .checked_sub(disputed_amount)
-            .ok_or_else(|| Overflow)?;
-        account.locked = true;
-        account.disputed_transactions.remove(&disputed_transaction);
-        Ok(())
+        self.status = AccountStatus::Active;
+        true
     }
 
-    fn move_funds_from_available_to_held(
-        account: &mut Account,
-        amount: Decimal,
-    ) -> Result<(), TransactorError> {
-        let new_available = account.available.checked_sub(amount);
-        let new_held = account.held.checked_add(amount);
-        return if let (Some(available), Some(held)) = (new_available, new_held) {
-            account.available = available;
-            account.held = held;
-            Ok(())
-        } else {
-            return Err(Overflow);
-        };
+    /// Freeze the account for a chargeback, which outlasts any freeze so only an unlock lifts it.
+    /// Returns whether the account was active.
+    fn freeze_for_chargeback(&mut self) -> bool {
+        self.lock(AccountStatus::Frozen)
     }
 
-    fn account(&mut self, client_id: ClientId) -> &mut Account {
-        self.client_accounts
-            .entry(client_id)
-            .or_insert(Account::new(client_id))
+    /// Put the account under review for going over a risk limit, returning whether it was active.
+    fn put_under_review(&mut self) -> bool {
+        self.lock(AccountStatus::UnderReview)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Lock the account in `status` unless it is closed, returning whether it was active.
+    fn lock(&mut self, status: AccountStatus) -> bool {
+        let was_active = self.status == AccountStatus::Active;
+        if self.status != AccountStatus::Closed {
+            self.status = status;
+            self.frozen = None;
+        }
+        was_active
+    }
 
-    #[test]
-    fn do_not_withdraw_into_negative_amount_and_transaction_is_not_recorded(
-    ) -> Result<(), TransactorError> {
-        let client = ClientId(1);
-        let mut bank = Bank::new();
-        bank.transact(
-            client,
-            Transaction::new(TransactionId(1), Decimal::new(-10, 1)),
-        )?;
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert!(bank.account(client).transaction_history.is_empty());
-        Ok(())
+    /// Make a frozen or under review account active again, returning whether it was. Closed
+    /// accounts stay closed.
+    fn reactivate(&mut self) -> bool {
+        if !matches!(
+            self.status,
+            AccountStatus::Frozen | AccountStatus::UnderReview
+        ) {
+            return false;
+        }
+        self.status = AccountStatus::Active;
+        self.frozen = None;
+        true
     }
 
-    #[test]
-    fn transaction_for_positive_adds_to_total_and_is_recorded() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
-        let client = ClientId(1);
-        let tx = TransactionId(2);
-        let transaction = Transaction::new(tx, Decimal::new(10, 1));
-        bank.transact(client, transaction.clone())?;
-        assert_eq!(bank.account(client).available, Decimal::new(10, 1));
-        assert_eq!(
-            *bank.account(client).transaction_history.get(&tx).unwrap(),
-            transaction
-        );
-        Ok(())
+    /// Close the account for good, returning whether it was active.
+    fn close(&mut self) -> bool {
+        let was_active = self.status == AccountStatus::Active;
+        self.status = AccountStatus::Closed;
+        self.frozen = None;
+        was_active
     }
 
-    #[test]
-    fn transaction_for_negative_lowers_balance_and_is_recorded() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
-        let client = ClientId(1);
-        let transaction_id1 = TransactionId(1);
-        let transaction_id2 = TransactionId(2);
-        let transaction1 = Transaction::new(transaction_id1, Decimal::new(1, 0));
-        let transaction2 = Transaction::new(transaction_id2, Decimal::new(-1, 1));
+    /// A copy of the balances of the account as they are now.
+    pub fn view(&self) -> AccountView {
+        AccountView {
+            client_id: self.client_id,
+            available: self.available,
+            held: self.held,
+            total: self.total().ok(),
+            locked: self.is_locked(),
+            status: self.status,
+            currency: self.currency,
+        }
+    }
 
-        bank.transact(client, transaction1.clone())?;
-        bank.transact(client, transaction2.clone())?;
+    /// The number of mutations applied to this account so far.
+    /// Callers doing read-modify-write orchestration can record this and later
+    /// use `Bank::check_version` to detect that the account changed in between.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
 
-        assert_eq!(bank.account(client).available, Decimal::new(9, 1));
-        assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id1)
-                .unwrap(),
+    /// The batch that made the most recent change to this account, if one was set.
+    pub fn last_batch(&self) -> Option<&BatchId> {
+        self.last_batch.as_ref()
+    }
+
+    /// Whether the account was locked by a freeze, which an unfreeze can undo.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// The reason given for freezing the account, if it is frozen and one was given.
+    pub fn freeze_reason(&self) -> Option<ReasonCode> {
+        self.frozen.flatten()
+    }
+
+    /// The number of chargebacks applied to the account this period, represented or not.
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+
+    /// The number of chargebacks not since represented.
+    pub fn standing_chargebacks(&self) -> u32 {
+        self.standing_chargebacks
+    }
+
+    /// The number of transactions currently in dispute.
+    pub fn open_disputes(&self) -> u32 {
+        self.open_disputes
+    }
+
+    /// A pending authorization, with the positive amount held for it.
+    pub fn authorization(&self, transaction_id: TransactionId) -> Option<Transaction> {
+        self.authorizations.get(&transaction_id).copied()
+    }
+
+    /// The amount of an administrative hold not yet released.
+    pub fn hold(&self, transaction_id: TransactionId) -> Option<Decimal> {
+        self.holds.get(&transaction_id).copied()
+    }
+
+    /// The funds held by administrative holds, apart from those held by disputes and
+    /// authorizations. Fails if they overflow when added.
+    pub fn held_administratively(&self) -> Result<Decimal, TransactorError> {
+        self.holds
+            .values()
+            .try_fold(Decimal::zero(), |total, amount| total.checked_add(*amount))
+            .ok_or(Overflow)
+    }
+
+    /// Every transaction id used by the account, including spilled transactions, pending
+    /// authorizations and administrative holds.
+    fn transaction_ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.transaction_order
+            .iter()
+            .chain(self.authorizations.keys())
+            .chain(self.holds.keys())
+            .copied()
+    }
+
+    /// Remove every transaction not currently in dispute, see `Bank::close_period`, returning
+    /// them in application order.
+    fn close_period(&mut self) -> Vec<TransactionMatch> {
+        let client_id = self.client_id;
+        let history = &mut self.transaction_history;
+        let mut archived = Vec::new();
+        self.transaction_order
+            .retain(|transaction_id| match history.get(transaction_id) {
+                Some(recorded) if recorded.state != TransactionState::Disputed => {
+                    archived.push(TransactionMatch {
+                        client_id,
+                        transaction: recorded.transaction,
+                        state: recorded.state,
+                    });
+                    history.remove(transaction_id);
+                    false
+                }
+                _ => true,
+            });
+        for found in &archived {
+            self.dispute_history
+                .remove(&found.transaction.transaction_id);
+        }
+        self.fees.clear();
+        self.chargebacks = 0;
+        let open_disputes = history
+            .values()
+            .filter(|recorded| recorded.state == TransactionState::Disputed)
+            .count();
+        self.open_disputes = u32::try_from(open_disputes).unwrap_or(u32::MAX);
+        archived
+    }
+
+    /// Which of the client's accounts this is, `main` unless it is a named sub-account.
+    pub fn name(&self) -> AccountName {
+        self.name
+    }
+
+    /// The currency of the first transaction on this account that had one. All later transactions
+    /// with a currency must be in the same one.
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// Every transaction recorded against this account, in the order they were applied, with
+    /// their current state.
+    /// If the bank spills history to disk only the transactions currently held in memory are listed.
+    pub fn transactions(&self) -> impl Iterator<Item = (Transaction, TransactionState)> + '_ {
+        self.transaction_order
+            .iter()
+            .filter_map(move |transaction_id| {
+                self.transaction_history
+                    .get(transaction_id)
+                    .map(|recorded| (recorded.transaction, recorded.state))
+            })
+    }
+
+    /// A page of `transactions`, skipping the first `offset` and listing at most `limit`, for
+    /// serving long histories a page at a time.
+    pub fn transactions_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> impl Iterator<Item = (Transaction, TransactionState)> + '_ {
+        self.transactions().skip(offset).take(limit)
+    }
+
+    /// The number of transactions `transactions` lists.
+    pub fn transaction_count(&self) -> usize {
+        self.transaction_history.len()
+    }
+
+    /// Why a transaction was last disputed, or charged back if a reason was given then. None if
+    /// no reason was given or the transaction is not in memory.
+    pub fn dispute_reason(&self, transaction_id: TransactionId) -> Option<ReasonCode> {
+        self.transaction_history
+            .get(&transaction_id)
+            .and_then(|recorded| recorded.reason)
+    }
+
+    /// Every step of the dispute lifecycle of a transaction, oldest first, empty if it was never
+    /// disputed.
+    pub fn dispute_history(&self, transaction_id: TransactionId) -> &[DisputeEntry] {
+        self.dispute_history
+            .get(&transaction_id)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The number of times a transaction has been disputed.
+    fn dispute_cycles(&self, transaction_id: TransactionId) -> usize {
+        self.dispute_history(transaction_id)
+            .iter()
+            .filter(|entry| entry.state == TransactionState::Disputed)
+            .count()
+    }
+
+    /// The state of a transaction recorded against this account, or None if it was never recorded.
+    pub fn transaction_state(&self, transaction_id: TransactionId) -> Option<TransactionState> {
+        self.transaction_history
+            .get(&transaction_id)
+            .map(|recorded| recorded.state)
+    }
+
+    /// The amount of a recorded transaction if it is currently in the given state.
+    fn amount_if_in_state(
+        &self,
+        transaction_id: TransactionId,
+        state: TransactionState,
+    ) -> Option<Decimal> {
+        self.transaction_history
+            .get(&transaction_id)
+            .filter(|recorded| recorded.state == state)
+            .map(|recorded| recorded.transaction.amount)
+    }
+
+    /// Why a resolve or chargeback of a transaction that is not currently disputed is ignored.
+    fn ignore_undisputed(&self, transaction_id: TransactionId) -> Outcome {
+        if self.transaction_history.contains_key(&transaction_id) {
+            Outcome::Ignored(IgnoreReason::NotDisputed)
+        } else {
+            Outcome::Ignored(IgnoreReason::UnknownTransaction)
+        }
+    }
+
+    fn set_dispute_reason(&mut self, transaction_id: TransactionId, reason: Option<ReasonCode>) {
+        if let Some(recorded) = self.transaction_history.get_mut(&transaction_id) {
+            recorded.reason = reason;
+        }
+    }
+
+    /// Move a transaction to the next state of its dispute lifecycle, adding the step to its
+    /// dispute history.
+    fn advance_dispute(
+        &mut self,
+        transaction_id: TransactionId,
+        state: TransactionState,
+        reason: Option<ReasonCode>,
+        batch: Option<BatchId>,
+    ) {
+        if let Some(recorded) = self.transaction_history.get_mut(&transaction_id) {
+            recorded.state = state;
+        }
+        self.dispute_history
+            .entry(transaction_id)
+            .or_default()
+            .push(DisputeEntry {
+                state,
+                reason,
+                batch,
+            });
+    }
+
+    fn record_change(&mut self, batch: Option<BatchId>) {
+        self.version += 1;
+        self.last_batch = batch;
+    }
+}
+
+/// What a bank operation did.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The operation changed the account.
+    Applied,
+    /// The operation was valid input but had no effect.
+    Ignored(IgnoreReason),
+}
+
+/// What an operation did along with the version of the account after it, for callers that read an
+/// account and then change it to tell whether anything else changed it in between, see
+/// `Bank::check_version`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Versioned {
+    pub outcome: Outcome,
+    /// The version of the account the operation ran against, 0 if it was never opened.
+    pub version: u64,
+}
+
+/// Why an operation was ignored.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IgnoreReason {
+    /// Transactions on locked accounts are ignored.
+    AccountLocked,
+    /// Transactions on closed accounts are ignored, as is closing them again.
+    AccountClosed,
+    /// The account was never opened.
+    UnknownAccount,
+    /// Only accounts without held funds can be closed.
+    FundsHeld,
+    /// A withdrawal would have taken the available funds below zero.
+    InsufficientFunds,
+    /// The referenced transaction was never recorded against the account.
+    UnknownTransaction,
+    /// The transaction is already disputed or has been charged back.
+    NotDisputable,
+    /// The dispute policy does not allow this kind of transaction to be disputed.
+    DisputePolicy,
+    /// The transaction has already been disputed as many times as are allowed.
+    DisputeLimit,
+    /// Only disputed transactions can be resolved or charged back.
+    NotDisputed,
+    /// Only locked accounts can be unlocked.
+    NotLocked,
+    /// Only accounts locked by a freeze can be unfrozen.
+    NotFrozen,
+    /// Only pending authorizations can be captured or voided.
+    NotAuthorized,
+    /// Only administrative holds not yet released can be released.
+    NotHeld,
+    /// Only charged back transactions can be represented.
+    NotChargedBack,
+    /// The status rules refuse the operation on active accounts, see `StatusRules`.
+    StatusRule,
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IgnoreReason::AccountLocked => "account-locked",
+            IgnoreReason::AccountClosed => "account-closed",
+            IgnoreReason::UnknownAccount => "unknown-account",
+            IgnoreReason::FundsHeld => "funds-held",
+            IgnoreReason::InsufficientFunds => "insufficient-funds",
+            IgnoreReason::UnknownTransaction => "unknown-transaction",
+            IgnoreReason::NotDisputable => "not-disputable",
+            IgnoreReason::DisputePolicy => "dispute-policy",
+            IgnoreReason::DisputeLimit => "dispute-limit",
+            IgnoreReason::NotDisputed => "not-disputed",
+            IgnoreReason::NotLocked => "not-locked",
+            IgnoreReason::NotFrozen => "not-frozen",
+            IgnoreReason::NotAuthorized => "not-authorized",
+            IgnoreReason::NotHeld => "not-held",
+            IgnoreReason::NotChargedBack => "not-charged-back",
+            IgnoreReason::StatusRule => "status-rule",
+        })
+    }
+}
+
+/// Which transactions a client is allowed to dispute.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DisputePolicy {
+    /// Any transaction may be disputed, withdrawals hold the absolute value of the funds.
+    #[default]
+    All,
+    /// Only deposits may be disputed, disputes on withdrawals are ignored.
+    DepositsOnly,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(DisputePolicy::All),
+            "deposits-only" => Ok(DisputePolicy::DepositsOnly),
+            _ => Err(format!(
+                "Unknown dispute policy {}, expected one of: all, deposits-only",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether representing a chargeback unlocks the account the chargeback locked.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RepresentmentPolicy {
+    /// The account is unlocked once none of its chargebacks stand, unless it is over a risk limit.
+    #[default]
+    Unlock,
+    /// The account stays locked until it is unlocked.
+    KeepLocked,
+}
+
+impl FromStr for RepresentmentPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unlock" => Ok(RepresentmentPolicy::Unlock),
+            "keep-locked" => Ok(RepresentmentPolicy::KeepLocked),
+            _ => Err(format!(
+                "Unknown representment policy {}, expected one of: unlock, keep-locked",
+                s
+            )),
+        }
+    }
+}
+
+/// Which transactions locked accounts refuse, on top of the status rules.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum LockedAccountPolicy {
+    /// Locked accounts take no deposits, withdrawals, authorizations or captures.
+    #[default]
+    BlockAll,
+    /// Frozen and under review accounts still take deposits, so a balance a chargeback took
+    /// negative can be paid back. Closed accounts take none.
+    BlockWithdrawalsOnly,
+}
+
+impl LockedAccountPolicy {
+    fn accepts(&self, status: AccountStatus, operation: Operation) -> bool {
+        *self == LockedAccountPolicy::BlockWithdrawalsOnly
+            && operation == Operation::Deposit
+            && matches!(status, AccountStatus::Frozen | AccountStatus::UnderReview)
+    }
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block-all" => Ok(LockedAccountPolicy::BlockAll),
+            "block-withdrawals-only" => Ok(LockedAccountPolicy::BlockWithdrawalsOnly),
+            _ => Err(format!(
+                "Unknown locked account policy {}, expected one of: block-all, \
+                 block-withdrawals-only",
+                s
+            )),
+        }
+    }
+}
+
+/// How the debts chargebacks of funds already withdrawn leave are recovered.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RecoveryPolicy {
+    /// Deposits pay the debt down before anything else.
+    #[default]
+    DepositsFirst,
+    /// The debt is only tracked, it is paid down by representments alone.
+    TrackOnly,
+}
+
+impl FromStr for RecoveryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposits-first" => Ok(RecoveryPolicy::DepositsFirst),
+            "track-only" => Ok(RecoveryPolicy::TrackOnly),
+            _ => Err(format!(
+                "Unknown recovery policy {}, expected one of: deposits-first, track-only",
+                s
+            )),
+        }
+    }
+}
+
+/// A debt left by chargebacks of funds already withdrawn, see `Bank::outstanding_recoveries`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Recovery {
+    pub client_id: ClientId,
+    pub outstanding: Decimal,
+    pub recovered: Decimal,
+    pub currency: Option<Currency>,
+}
+
+/// What the bank does with deposits and withdrawals it refuses, because the account is locked or a
+/// withdrawal exceeds the available funds.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RefusalPolicy {
+    /// Refused transactions are reported as an ignored outcome.
+    #[default]
+    Ignore,
+    /// Refused transactions fail with `InsufficientFunds` or `AccountLocked`.
+    Error,
+}
+
+impl FromStr for RefusalPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(RefusalPolicy::Ignore),
+            "error" => Ok(RefusalPolicy::Error),
+            _ => Err(format!(
+                "Unknown refusal policy {}, expected one of: ignore, error",
+                s
+            )),
+        }
+    }
+}
+
+impl RefusalPolicy {
+    /// The result of refusing an operation, ignored with the reason or failed with the error.
+    fn refuse(
+        self,
+        reason: IgnoreReason,
+        error: TransactorError,
+    ) -> Result<Outcome, TransactorError> {
+        match self {
+            RefusalPolicy::Ignore => Ok(Outcome::Ignored(reason)),
+            RefusalPolicy::Error => Err(error),
+        }
+    }
+}
+
+/// Which operations on transactions accounts accept in each status. By default active accounts
+/// accept every operation and locked accounts refuse deposits, withdrawals, authorizations and
+/// captures, still handling disputes, their outcomes and voids. Freezes, unfreezes, unlocks and
+/// closing follow the transitions of the statuses whatever the rules say.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatusRules {
+    accepted: HashMap<AccountStatus, Vec<Operation>>,
+}
+
+/// The operations status rules can accept or refuse.
+const RULED_OPERATIONS: [Operation; 9] = [
+    Operation::Deposit,
+    Operation::Withdrawal,
+    Operation::Authorize,
+    Operation::Capture,
+    Operation::Dispute,
+    Operation::Resolve,
+    Operation::Chargeback,
+    Operation::Represent,
+    Operation::Void,
+];
+
+impl Default for StatusRules {
+    fn default() -> Self {
+        let locked = RULED_OPERATIONS[4..].to_vec();
+        Self {
+            accepted: AccountStatus::ALL
+                .iter()
+                .map(|status| match status {
+                    AccountStatus::Active => (*status, RULED_OPERATIONS.to_vec()),
+                    _ => (*status, locked.clone()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl StatusRules {
+    /// Accept only the given operations on accounts in `status`.
+    pub fn with_rule(mut self, rule: StatusRule) -> Self {
+        self.accepted.insert(rule.status, rule.accepted);
+        self
+    }
+
+    pub fn accepts(&self, status: AccountStatus, operation: Operation) -> bool {
+        self.accepted
+            .get(&status)
+            .is_some_and(|accepted| accepted.contains(&operation))
+    }
+}
+
+/// The operations accounts in one status accept, such as `frozen=dispute,resolve` for frozen
+/// accounts only handling disputes and resolves. Nothing after the `=` accepts no operation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatusRule {
+    pub status: AccountStatus,
+    pub accepted: Vec<Operation>,
+}
+
+impl FromStr for StatusRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (status, operations) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Status rule {} is not status=operation,...", s))?;
+        let accepted = operations
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                RULED_OPERATIONS
+                    .iter()
+                    .copied()
+                    .find(|operation| operation.to_string() == name)
+                    .ok_or_else(|| {
+                        format!(
+                            "Unknown operation {} in status rule, expected one of: deposit, \
+                             withdrawal, authorize, capture, dispute, resolve, chargeback, \
+                             represent, void",
+                            name
+                        )
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            status: status.trim().parse()?,
+            accepted,
+        })
+    }
+}
+
+/// How far below zero withdrawals and authorizations may take the available funds of an account.
+/// Every account gets the default limit, zero unless set, unless it has a limit of its own.
+#[derive(Clone, Debug, Default)]
+pub struct OverdraftPolicy {
+    default_limit: Decimal,
+    limits: HashMap<ClientId, Decimal>,
+}
+
+impl OverdraftPolicy {
+    pub fn new(default_limit: Decimal) -> Self {
+        Self {
+            default_limit,
+            limits: HashMap::new(),
+        }
+    }
+
+    /// Give one account its own limit in place of the default.
+    pub fn with_limit(mut self, client_id: ClientId, limit: Decimal) -> Self {
+        self.limits.insert(client_id, limit);
+        self
+    }
+
+    /// The most the available funds of the account may go below zero.
+    pub fn limit(&self, client_id: ClientId) -> Decimal {
+        self.limits
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default_limit)
+    }
+}
+
+/// Thresholds of suspicious activity past which an account is put under review, none are set by
+/// default.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RiskLimits {
+    /// The most chargebacks an account may have had this period, represented or not.
+    pub max_chargebacks: Option<u32>,
+    /// The most transactions an account may have in dispute at once.
+    pub max_open_disputes: Option<u32>,
+}
+
+/// Which of the `RiskLimits` an account went over.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RiskLimit {
+    Chargebacks,
+    OpenDisputes,
+}
+
+impl fmt::Display for RiskLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RiskLimit::Chargebacks => "max-chargebacks",
+            RiskLimit::OpenDisputes => "max-open-disputes",
+        })
+    }
+}
+
+/// Callbacks invoked by the bank after each change it makes, so embedding code can react to engine
+/// events without wrapping every call. Every callback defaults to doing nothing.
+/// The account passed is the state after the change.
+pub trait BankObserver: Send {
+    /// The changes that follow belong to another batch, see `Bank::set_batch`.
+    fn batch_changed(&mut self, _batch: Option<&BatchId>) {}
+
+    /// A deposit or withdrawal was recorded against the account.
+    fn transaction_applied(&mut self, _account: &Account, _transaction: &Transaction) {}
+
+    /// A withdrawal was not recorded because the account has insufficient available funds.
+    fn withdrawal_rejected(&mut self, _account: &Account, _transaction: &Transaction) {}
+
+    fn transaction_disputed(&mut self, _account: &Account, _transaction_id: TransactionId) {}
+
+    fn dispute_resolved(&mut self, _account: &Account, _transaction_id: TransactionId) {}
+
+    fn chargeback_applied(&mut self, _account: &Account, _transaction_id: TransactionId) {}
+
+    /// A charged back transaction was represented and its funds credited back.
+    fn chargeback_represented(&mut self, _account: &Account, _transaction_id: TransactionId) {}
+
+    /// The account was locked, by a chargeback or a freeze.
+    fn account_locked(&mut self, _account: &Account) {}
+
+    fn account_unlocked(&mut self, _account: &Account) {}
+
+    /// The account was closed and takes no more transactions.
+    fn account_closed(&mut self, _account: &Account) {}
+
+    /// The account went over one of the risk limits and has been locked.
+    fn risk_limit_exceeded(&mut self, _account: &Account, _limit: RiskLimit) {}
+}
+
+/// Transaction history evicted to disk once more than `max_in_memory` transactions are recorded.
+struct Spill {
+    store: SpillStore,
+    max_in_memory: usize,
+    /// The transactions currently held in memory, oldest first.
+    in_memory: VecDeque<(ClientId, TransactionId)>,
+}
+
+/// The clients that have recorded a transaction id. Ids are only unique within an account unless
+/// global ids are required, though almost every id has the one owner.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Owners {
+    One(ClientId),
+    Many(Vec<ClientId>),
+}
+
+impl Owners {
+    /// Index the client as an owner of the transaction id.
+    fn add(
+        index: &mut HashMap<TransactionId, Owners>,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+    ) {
+        let owners = match index.entry(transaction_id) {
+            Entry::Vacant(entry) => {
+                entry.insert(Owners::One(client_id));
+                return;
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        };
+        match owners {
+            Owners::One(owner) if *owner != client_id => {
+                *owners = Owners::Many(vec![*owner, client_id]);
+            }
+            Owners::Many(clients) if !clients.contains(&client_id) => clients.push(client_id),
+            _ => {}
+        }
+    }
+
+    fn clients(&self) -> &[ClientId] {
+        match self {
+            Owners::One(client_id) => std::slice::from_ref(client_id),
+            Owners::Many(clients) => clients,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Bank {
+    client_accounts: HashMap<ClientId, Account>,
+    /// The named sub-accounts of each client, see `Bank::in_account`.
+    sub_accounts: HashMap<ClientId, BTreeMap<AccountName, Account>>,
+    dispute_policy: DisputePolicy,
+    refusal_policy: RefusalPolicy,
+    representment_policy: RepresentmentPolicy,
+    locked_account_policy: LockedAccountPolicy,
+    recovery_policy: RecoveryPolicy,
+    fee_schedule: FeeSchedule,
+    /// The decimal places amounts the bank works out, such as fees, are rounded to.
+    precision: PrecisionTable,
+    overdraft_policy: OverdraftPolicy,
+    risk_limits: RiskLimits,
+    status_rules: StatusRules,
+    /// The largest amount of a single deposit, withdrawal or authorization, any if None.
+    max_amount: Option<Decimal>,
+    /// How many times one transaction may be disputed, any number if None.
+    max_dispute_cycles: Option<u32>,
+    batch: Option<BatchId>,
+    /// The time the state of the bank is as of, up to which standing orders have been applied.
+    as_of: Option<Timestamp>,
+    /// The next occurrence of each standing order to apply, by the key of the order, see
+    /// `apply_standing_order`.
+    standing_orders: BTreeMap<String, u64>,
+    spill: Option<Spill>,
+    /// Every transaction that has ever been disputed, so searches by dispute state need not scan
+    /// the full history.
+    dispute_index: HashSet<(ClientId, TransactionId)>,
+    /// The client owning each recorded transaction, only maintained when enabled because it costs
+    /// memory for every transaction.
+    transaction_index: Option<HashMap<TransactionId, Owners>>,
+    global_transaction_ids: bool,
+    /// Pending authorizations with a timestamp by when they were made, so expiry need not scan
+    /// every account.
+    authorization_times: BTreeSet<(Timestamp, u16, u32)>,
+    observers: Vec<Box<dyn BankObserver>>,
+    metrics: Option<Metrics>,
+    /// Metadata of clients for reports, see `Bank::with_client_directory`.
+    clients: ClientDirectory,
+}
+
+impl Bank {
+    pub fn new() -> Self {
+        Self {
+            client_accounts: HashMap::new(),
+            sub_accounts: HashMap::new(),
+            dispute_policy: DisputePolicy::default(),
+            refusal_policy: RefusalPolicy::default(),
+            representment_policy: RepresentmentPolicy::default(),
+            locked_account_policy: LockedAccountPolicy::default(),
+            recovery_policy: RecoveryPolicy::default(),
+            fee_schedule: FeeSchedule::default(),
+            precision: PrecisionTable::default(),
+            overdraft_policy: OverdraftPolicy::default(),
+            risk_limits: RiskLimits::default(),
+            status_rules: StatusRules::default(),
+            max_amount: None,
+            max_dispute_cycles: None,
+            batch: None,
+            as_of: None,
+            standing_orders: BTreeMap::new(),
+            spill: None,
+            dispute_index: HashSet::new(),
+            transaction_index: None,
+            global_transaction_ids: false,
+            authorization_times: BTreeSet::new(),
+            observers: Vec::new(),
+            metrics: None,
+            clients: ClientDirectory::new(),
+        }
+    }
+
+    /// Count every operation by outcome and time how long each takes, see `Bank::metrics`.
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics.get_or_insert_with(Metrics::new);
+        self
+    }
+
+    /// Notify an observer of every change the bank makes, observers are called in the order they
+    /// were added.
+    pub fn with_observer<O: BankObserver + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Receive every change the bank makes from now on as a `BankEvent`, buffering up to
+    /// `capacity` events for the receiver. Any number of receivers can subscribe.
+    pub fn subscribe(&mut self, capacity: usize) -> EventReceiver {
+        let (publisher, receiver) = events::EventPublisher::channel(capacity, self.batch.clone());
+        self.observers.push(Box::new(publisher));
+        receiver
+    }
+
+    /// Require transaction ids to be unique across all clients rather than just within each account.
+    /// This enables the transaction index.
+    pub fn with_global_transaction_ids(mut self) -> Self {
+        self.global_transaction_ids = true;
+        self.with_transaction_index()
+    }
+
+    /// Maintain a global index from transaction id to the owning clients so `find_owner` does not
+    /// need to search every account.
+    pub fn with_transaction_index(mut self) -> Self {
+        self.transaction_index.get_or_insert_with(HashMap::new);
+        self
+    }
+
+    /// Bound the memory used by transaction history: once more than `max_in_memory` transactions
+    /// are recorded the oldest are moved to a file in `dir` and read back when they are disputed.
+    /// This fails if the spill file cannot be created.
+    pub fn with_spill(mut self, dir: &Path, max_in_memory: usize) -> Result<Self, TransactorError> {
+        self.spill = Some(Spill {
+            store: SpillStore::create(dir)?,
+            max_in_memory: max_in_memory.max(1),
+            in_memory: VecDeque::new(),
+        });
+        Ok(self)
+    }
+
+    /// Fail reads and writes of spilled history at random, this has no effect unless spilling was
+    /// already enabled with `with_spill`.
+    #[cfg(feature = "failure-injection")]
+    pub fn with_storage_faults(mut self, faults: crate::fault::FaultInjector) -> Self {
+        if let Some(spill) = &mut self.spill {
+            spill.store.faults = Some(faults);
+        }
+        self
+    }
+
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    /// Allow withdrawals and authorizations to take accounts overdrawn, down to their limit.
+    pub fn with_overdraft_policy(mut self, overdraft_policy: OverdraftPolicy) -> Self {
+        self.overdraft_policy = overdraft_policy;
+        self
+    }
+
+    /// Accept operations on accounts by their status as the rules say, see `StatusRules`.
+    pub fn with_status_rules(mut self, status_rules: StatusRules) -> Self {
+        self.status_rules = status_rules;
+        self
+    }
+
+    /// Fail on deposits, withdrawals and authorizations of more than `limit`, so a mistyped
+    /// amount is refused rather than applied.
+    pub fn with_max_amount(mut self, limit: Decimal) -> Self {
+        self.max_amount = Some(limit);
+        self
+    }
+
+    /// Ignore disputes of a transaction that has already been disputed `limit` times, however
+    /// those disputes ended.
+    pub fn with_max_dispute_cycles(mut self, limit: u32) -> Self {
+        self.max_dispute_cycles = Some(limit);
+        self
+    }
+
+    /// Lock accounts once they go over any of the limits. Observers are told which limit was
+    /// exceeded, each time an account goes over it.
+    pub fn with_risk_limits(mut self, risk_limits: RiskLimits) -> Self {
+        self.risk_limits = risk_limits;
+        self
+    }
+
+    /// Whether deposits and withdrawals refused for insufficient funds or a locked account are
+    /// ignored, the default, or fail so embedding code can tell them apart from success.
+    pub fn with_refusal_policy(mut self, refusal_policy: RefusalPolicy) -> Self {
+        self.refusal_policy = refusal_policy;
+        self
+    }
+
+    /// Whether representing a chargeback unlocks the account, by default it does.
+    pub fn with_representment_policy(mut self, representment_policy: RepresentmentPolicy) -> Self {
+        self.representment_policy = representment_policy;
+        self
+    }
+
+    /// Whether locked accounts still take deposits, by default they take none.
+    pub fn with_locked_account_policy(
+        mut self,
+        locked_account_policy: LockedAccountPolicy,
+    ) -> Self {
+        self.locked_account_policy = locked_account_policy;
+        self
+    }
+
+    /// Charge fees on withdrawals and chargebacks, none are charged by default.
+    pub fn with_fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    /// Round the amounts the bank works out, such as percentage fees, to the decimal places of
+    /// their currency with its rounding, by default to four decimal places with bankers rounding.
+    pub fn with_precision(mut self, precision: PrecisionTable) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Whether deposits pay down the debts chargebacks leave, by default they do.
+    pub fn with_recovery_policy(mut self, recovery_policy: RecoveryPolicy) -> Self {
+        self.recovery_policy = recovery_policy;
+        self
+    }
+
+    /// Set the batch that subsequent operations belong to. Every account changed while the batch is
+    /// set records it, so a change can be traced back to the batch that caused it.
+    pub fn set_batch(&mut self, batch: Option<BatchId>) {
+        if batch != self.batch {
+            for observer in &mut self.observers {
+                observer.batch_changed(batch.as_ref());
+            }
+        }
+        self.batch = batch;
+    }
+
+    /// The metrics of the operations handled so far, if enabled with `with_metrics`, with the
+    /// accounts locked counted as they are now however they came to be locked.
+    pub fn metrics(&self) -> Option<Metrics> {
+        let mut metrics = self.metrics.clone()?;
+        let locked = self
+            .get_accounts()
+            .filter(|account| account.is_locked())
+            .count();
+        metrics.set_locked_accounts(u64::try_from(locked).unwrap_or(u64::MAX));
+        Some(metrics)
+    }
+
+    /// The time the state of the bank is as of, None unless standing orders have been applied.
+    pub fn as_of(&self) -> Option<Timestamp> {
+        self.as_of
+    }
+
+    /// Record that the state of the bank is as of `as_of`, saved with the state.
+    pub fn set_as_of(&mut self, as_of: Timestamp) {
+        self.as_of = Some(as_of);
+    }
+
+    pub fn get_accounts(&self) -> impl Iterator<Item = &Account> {
+        self.client_accounts.values()
+    }
+
+    /// A clients account, to read its balances and history, None if the client has never been
+    /// seen.
+    pub fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+        self.client_accounts.get(&client_id)
+    }
+
+    /// The debts chargebacks of funds already withdrawn left and are still owed, in client order.
+    pub fn outstanding_recoveries(&self) -> Vec<Recovery> {
+        let mut recoveries: Vec<Recovery> = self
+            .client_accounts
+            .values()
+            .filter(|account| account.recovery > Decimal::zero())
+            .map(|account| Recovery {
+                client_id: account.client_id,
+                outstanding: account.recovery,
+                recovered: account.recovered,
+                currency: account.currency,
+            })
+            .collect();
+        recoveries.sort_by_key(|recovery| recovery.client_id.0);
+        recoveries
+    }
+
+    /// The current version of a clients account, or None if the account has never been seen.
+    pub fn account_version(&self, client_id: ClientId) -> Option<u64> {
+        self.client_accounts
+            .get(&client_id)
+            .map(|account| account.version)
+    }
+
+    /// The state of a transaction on a clients account, or None if it was never recorded.
+    pub fn transaction_state(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Option<TransactionState> {
+        self.recorded_transaction(client_id, transaction_id)
+            .map(|recorded| recorded.state)
+    }
+
+    /// A recorded transaction, None if the client never made it.
+    pub fn transaction(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Option<Transaction> {
+        self.recorded_transaction(client_id, transaction_id)
+            .map(|recorded| recorded.transaction)
+    }
+
+    /// The client whose account a transaction was recorded against, the lowest numbered if
+    /// several clients have used its id, or None if it was never recorded.
+    pub fn find_owner(&self, transaction_id: TransactionId) -> Option<ClientId> {
+        self.find_owners(transaction_id).into_iter().next()
+    }
+
+    /// Every client with a transaction of this id recorded, in client order. Unless ids must be
+    /// globally unique several clients can each use the same id. Without the transaction index
+    /// this searches every account.
+    pub fn find_owners(&self, transaction_id: TransactionId) -> Vec<ClientId> {
+        let mut owners: Vec<ClientId> = match &self.transaction_index {
+            Some(index) => index
+                .get(&transaction_id)
+                .map_or_else(Vec::new, |owners| owners.clients().to_vec()),
+            None => self
+                .client_accounts
+                .keys()
+                .copied()
+                .filter(|client_id| {
+                    self.recorded_transaction(*client_id, transaction_id)
+                        .is_some()
+                })
+                .collect(),
+        };
+        owners.sort_by_key(|client_id| client_id.0);
+        owners
+    }
+
+    /// A recorded transaction, whether it is held in memory or has been spilled to disk.
+    fn recorded_transaction(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Option<RecordedTransaction> {
+        self.read_recorded(client_id, transaction_id).ok().flatten()
+    }
+
+    /// A recorded transaction, failing if it was spilled to disk and cannot be read back.
+    fn read_recorded(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Option<RecordedTransaction>, TransactorError> {
+        let account = match self.client_accounts.get(&client_id) {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+        match (
+            account.transaction_history.get(&transaction_id),
+            &self.spill,
+        ) {
+            (Some(recorded), _) => Ok(Some(*recorded)),
+            (None, Some(spill)) => Ok(spill.store.read(client_id, transaction_id)?),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Find every retained transaction matching the filter, ordered by client and then transaction id.
+    /// Searches on one client only look at that clients history and searches for transactions
+    /// that have been disputed only look at the disputed transactions, anything else scans all
+    /// history, including any spilled to disk.
+    /// This fails if spilled history cannot be read back.
+    pub fn find_transactions(
+        &self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionMatch>, TransactorError> {
+        let spilled = self
+            .spill
+            .iter()
+            .flat_map(|spill| spill.store.spilled())
+            .filter(|(client_id, _)| filter.client.is_none_or(|client| client == *client_id));
+        let candidates: Vec<(ClientId, TransactionId)> = match (filter.client, filter.state) {
+            (_, Some(state)) if state != TransactionState::Posted => self
+                .dispute_index
+                .iter()
+                .filter(|(client_id, _)| filter.client.is_none_or(|client| client == *client_id))
+                .copied()
+                .collect(),
+            (Some(client_id), _) => self
+                .client_accounts
+                .get(&client_id)
+                .map(|account| {
+                    account
+                        .transaction_history
+                        .keys()
+                        .map(|transaction_id| (client_id, *transaction_id))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+                .into_iter()
+                .chain(spilled)
+                .collect(),
+            (None, _) => self
+                .client_accounts
+                .values()
+                .flat_map(|account| {
+                    account
+                        .transaction_history
+                        .keys()
+                        .map(move |transaction_id| (account.client_id, *transaction_id))
+                })
+                .chain(spilled)
+                .collect(),
+        };
+        let mut matches = Vec::new();
+        for (client_id, transaction_id) in candidates {
+            let recorded = match self.read_recorded(client_id, transaction_id)? {
+                Some(recorded) => recorded,
+                None => continue,
+            };
+            if filter.matches(client_id, &recorded) {
+                matches.push(TransactionMatch {
+                    client_id,
+                    transaction: recorded.transaction,
+                    state: recorded.state,
+                });
+            }
+        }
+        matches.sort_by_key(|found| (found.client_id.0, found.transaction.transaction_id.0));
+        Ok(matches)
+    }
+
+    /// Check that a clients account is still at the version the caller last observed.
+    /// An account that has never been seen is treated as being at version 0.
+    /// This fails with `VersionConflict` if the account has been mutated since.
+    pub fn check_version(&self, client_id: ClientId, expected: u64) -> Result<(), TransactorError> {
+        let actual = self.account_version(client_id).unwrap_or(0);
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(VersionConflict { expected, actual })
+        }
+    }
+
+    /// Open an account with balances carried over from elsewhere, such as the account report of a
+    /// previous run, so only the transactions since then need to be applied. The balances have no
+    /// transactions behind them so funds held here can never be released by a resolve or
+    /// chargeback.
+    /// This fails if the client already has an account.
+    pub fn open_account(
+        &mut self,
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactorError> {
+        if self.client_accounts.contains_key(&client_id) {
+            return Err(AccountExists { client: client_id });
+        }
+        let mut account = Account::with_balances(client_id, available, held, locked)?;
+        account.currency = currency;
+        self.client_accounts.insert(client_id, account);
+        Ok(())
+    }
+
+    /// Perform a transaction on a clients account.
+    /// Error can occur if any of:
+    /// * the transaction causes an overflow
+    /// * the transaction has already been recorded as occurring, on any account if transaction ids
+    ///   are global
+    /// * the transaction is in a different currency to the account
+    ///
+    /// If the transaction is a withdrawal and would take the account below its overdraft limit, zero
+    /// by default, the transaction will not occur and will not be recorded.
+    /// If the account is locked, no action will be taken and the transaction will not be recorded.
+    /// Both of these are ignored unless the refusal policy makes them errors.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = transaction.transaction_id.0))]
+    pub fn transact(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        let operation = if transaction.amount < Decimal::zero() {
+            Operation::Withdrawal
+        } else {
+            Operation::Deposit
+        };
+        self.measured(operation, |bank| {
+            bank.apply_transaction(client_id, transaction, true)
+        })
+    }
+
+    /// Apply an occurrence of a standing order, a deposit or a withdrawal for a negative amount,
+    /// unless it was already applied. The occurrences of an order must be applied in order.
+    ///
+    /// Occurrences are checked like any deposit or withdrawal, but are kept in a ledger of each
+    /// order's occurrences rather than recorded as transactions, so they use up no transaction id
+    /// and an input transaction can never be taken for one. The id of the transaction only
+    /// identifies the occurrence to observers. Returns None for an occurrence an earlier call
+    /// already applied or ignored.
+    pub fn apply_standing_order(
+        &mut self,
+        order: &str,
+        occurrence: u64,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Option<Outcome>, TransactorError> {
+        if self
+            .standing_orders
+            .get(order)
+            .is_some_and(|next| occurrence < *next)
+        {
+            return Ok(None);
+        }
+        let operation = if transaction.amount < Decimal::zero() {
+            Operation::Withdrawal
+        } else {
+            Operation::Deposit
+        };
+        let outcome = self.measured(operation, |bank| {
+            bank.apply_transaction(client_id, transaction, false)
+        })?;
+        self.standing_orders
+            .insert(order.to_string(), occurrence + 1);
+        Ok(Some(outcome))
+    }
+
+    /// Check and post a deposit or withdrawal, recording it in the history of the account unless
+    /// it is an occurrence of a standing order.
+    fn apply_transaction(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+        record: bool,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_amount(client_id, &transaction)?;
+        let used = record && self.is_used(client_id, transaction.transaction_id)?;
+        let refusal_policy = self.refusal_policy;
+        let floor = -self.overdraft_policy.limit(client_id);
+        let operation = match transaction.amount < Decimal::zero() {
+            true => Operation::Withdrawal,
+            false => Operation::Deposit,
+        };
+        let fee = match operation {
+            Operation::Withdrawal => {
+                let currency = transaction
+                    .currency
+                    .or_else(|| self.client_accounts.get(&client_id)?.currency);
+                self.precision
+                    .precision(currency)
+                    .round(self.fee_schedule.fee_on(operation, transaction.amount)?)
+            }
+            _ => Decimal::zero(),
+        };
+        let refused = self.refused(client_id, operation);
+        let account = self.account_mut(client_id);
+
+        if let Some(reason) = refused {
+            return refusal_policy.refuse(
+                reason,
+                AccountLocked {
+                    client: client_id,
+                    tx: transaction.transaction_id,
+                },
+            );
+        }
+
+        if used {
+            return Err(TransactionIdReuse);
+        }
+
+        if let (Some(expected), Some(found)) = (account.currency, transaction.currency) {
+            if expected != found {
+                return Err(CurrencyMismatch { expected, found });
+            }
+        }
+
+        // A fee is taken together with the withdrawal it is charged on, or not at all
+        let new_balance = account
+            .available
+            .checked_add(transaction.amount)
+            .and_then(|balance| balance.checked_sub(fee))
+            .ok_or(Overflow)?;
+        // We only allow the transaction to occur if it is depositing or it leaves the account
+        // within its overdraft limit
+        if transaction.amount > Decimal::zero() || new_balance >= floor {
+            match record {
+                true => self.post_transaction(client_id, transaction, operation, fee),
+                false => self.post_unrecorded(client_id, transaction, operation, fee),
+            }
+        } else {
+            let available = account.available;
+            self.notify(client_id, |observer, account| {
+                observer.withdrawal_rejected(account, &transaction)
+            });
+            refusal_policy.refuse(
+                IgnoreReason::InsufficientFunds,
+                InsufficientFunds {
+                    client: client_id,
+                    tx: transaction.transaction_id,
+                    requested: transaction.amount.abs().checked_add(fee).ok_or(Overflow)?,
+                    available,
+                },
+            )
+        }
+    }
+
+    /// Record a deposit or withdrawal that has been checked against the account, taking its fee
+    /// with it.
+    fn post_transaction(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+        operation: Operation,
+        fee: Decimal,
+    ) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        self.credit(client_id, &transaction, operation, fee)?;
+        let account = self.account_mut(client_id);
+        account.transaction_history.insert(
+            transaction.transaction_id,
+            RecordedTransaction {
+                transaction,
+                state: TransactionState::Posted,
+                reason: None,
+            },
+        );
+        account.transaction_order.push(transaction.transaction_id);
+        if account.currency.is_none() {
+            account.currency = transaction.currency;
+        }
+        account.record_change(batch);
+        if let Some(index) = &mut self.transaction_index {
+            Owners::add(index, transaction.transaction_id, client_id);
+        }
+        self.notify(client_id, |observer, account| {
+            observer.transaction_applied(account, &transaction)
+        });
+        self.track_in_memory(client_id, transaction.transaction_id)?;
+        Ok(Outcome::Applied)
+    }
+
+    /// Post a deposit or withdrawal that has been checked against the account without recording
+    /// it in the history, for occurrences of standing orders.
+    fn post_unrecorded(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+        operation: Operation,
+        fee: Decimal,
+    ) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        self.credit(client_id, &transaction, operation, fee)?;
+        let account = self.account_mut(client_id);
+        if account.currency.is_none() {
+            account.currency = transaction.currency;
+        }
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            observer.transaction_applied(account, &transaction)
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Add the amount of a deposit or withdrawal to the available funds, less its fee.
+    fn credit(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+        operation: Operation,
+        fee: Decimal,
+    ) -> Result<(), TransactorError> {
+        let recovers = self.recovery_policy == RecoveryPolicy::DepositsFirst;
+        let account = self.account_mut(client_id);
+        account.available = account
+            .available
+            .checked_add(transaction.amount)
+            .and_then(|balance| balance.checked_sub(fee))
+            .ok_or(Overflow)?;
+        account.record_fee(transaction.transaction_id, operation, fee);
+        if recovers && transaction.amount > Decimal::zero() {
+            account.recover(transaction.amount)?;
+        }
+        Ok(())
+    }
+
+    /// Record a deposit or withdrawal made before the period being processed, so later disputes
+    /// of it find it, without changing any balance. Returns false without recording it if the
+    /// transaction id is already recorded, as it is when starting from a snapshot with it.
+    pub fn record_history(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<bool, TransactorError> {
+        if self.is_used(client_id, transaction.transaction_id)? {
+            return Ok(false);
+        }
+        let account = self.account_mut(client_id);
+        account.transaction_history.insert(
+            transaction.transaction_id,
+            RecordedTransaction {
+                transaction,
+                state: TransactionState::Posted,
+                reason: None,
+            },
+        );
+        account.transaction_order.push(transaction.transaction_id);
+        if account.currency.is_none() {
+            account.currency = transaction.currency;
+        }
+        if let Some(index) = &mut self.transaction_index {
+            Owners::add(index, transaction.transaction_id, client_id);
+        }
+        self.track_in_memory(client_id, transaction.transaction_id)?;
+        Ok(true)
+    }
+
+    /// Fail if the transaction is larger than the largest amount allowed.
+    fn check_amount(
+        &self,
+        client_id: ClientId,
+        transaction: &Transaction,
+    ) -> Result<(), TransactorError> {
+        match self.max_amount {
+            Some(limit) if transaction.amount.abs() > limit => Err(AmountTooLarge {
+                client: client_id,
+                tx: transaction.transaction_id,
+                amount: transaction.amount.abs(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Handle a dispute on a transaction.
+    /// If the transaction does not exist this will be ignored.
+    /// If the transaction index is enabled and the transaction belongs to another client this fails
+    /// with `ForeignTransaction`, otherwise such disputes are ignored like unknown transactions.
+    /// If the transaction is currently disputed or has been charged back this will be ignored.
+    /// If the transaction is a withdrawal and the bank only allows deposits to be disputed this will be ignored.
+    /// This can fail if moving the disputed funds causes an overflow
+    pub fn dispute_transaction(
+        &mut self,
+        client_id: ClientId,
+        dispute: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.dispute_with_reason(client_id, dispute, None)
+    }
+
+    /// Handle a dispute as `dispute_transaction` does, recording the reason given for it with the
+    /// transaction, see `Account::dispute_reason`.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = dispute.0))]
+    pub fn dispute_with_reason(
+        &mut self,
+        client_id: ClientId,
+        dispute: TransactionId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Dispute, |bank| {
+            bank.apply_dispute(client_id, dispute, reason)
+        })
+    }
+
+    fn apply_dispute(
+        &mut self,
+        client_id: ClientId,
+        dispute: TransactionId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_owner(client_id, dispute)?;
+        self.recall_spilled(client_id, dispute)?;
+        if let Some(reason) = self.refused(client_id, Operation::Dispute) {
+            return Ok(Outcome::Ignored(reason));
+        }
+        let dispute_policy = self.dispute_policy;
+        let max_cycles = self.max_dispute_cycles;
+        let batch = self.batch.clone();
+        let account = self.account_mut(client_id);
+        // Only handle disputes on transactions that have been enacted and are not already in dispute.
+        let transaction_amount = match account.transaction_history.get(&dispute) {
+            Some(recorded) if recorded.state.is_disputable() => recorded.transaction.amount,
+            Some(_) => return Ok(Outcome::Ignored(IgnoreReason::NotDisputable)),
+            None => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+        };
+        if dispute_policy == DisputePolicy::DepositsOnly && transaction_amount < Decimal::zero() {
+            return Ok(Outcome::Ignored(IgnoreReason::DisputePolicy));
+        }
+        if max_cycles.is_some_and(|max| account.dispute_cycles(dispute) >= max as usize) {
+            return Ok(Outcome::Ignored(IgnoreReason::DisputeLimit));
+        }
+        // no matter if this is a withdrawal or a deposit we need to
+        // withhold the absolute value of the funds
+        let disputed_amount = transaction_amount.abs();
+        Bank::move_funds_from_available_to_held(account, disputed_amount)?;
+        account.advance_dispute(dispute, TransactionState::Disputed, reason, batch.clone());
+        account.set_dispute_reason(dispute, reason);
+        account.open_disputes += 1;
+        account.record_change(batch);
+        self.dispute_index.insert((client_id, dispute));
+        self.notify(client_id, |observer, account| {
+            observer.transaction_disputed(account, dispute)
+        });
+        self.check_risk_limits(client_id);
+        Ok(Outcome::Applied)
+    }
+
+    /// Resolve a previously disputed transaction
+    /// If the transaction does not exist, or this transaction was never
+    /// previously disputed this will be ignored.
+    /// With the transaction index enabled this fails if the transaction belongs to another client.
+    /// This can fail if moving the disputed funds causes an overflow
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = disputed_transaction.0))]
+    pub fn resolve_disputed_transaction(
+        &mut self,
+        client_id: ClientId,
+        disputed_transaction: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Resolve, |bank| {
+            bank.apply_resolve(client_id, disputed_transaction)
+        })
+    }
+
+    fn apply_resolve(
+        &mut self,
+        client_id: ClientId,
+        disputed_transaction: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_owner(client_id, disputed_transaction)?;
+        self.recall_spilled(client_id, disputed_transaction)?;
+        if let Some(reason) = self.refused(client_id, Operation::Resolve) {
+            return Ok(Outcome::Ignored(reason));
+        }
+        let batch = self.batch.clone();
+        let account = self.account_mut(client_id);
+        // Only handle disputes that have been made already and only if the transaction has been enacted.
+        let transaction_amount =
+            match account.amount_if_in_state(disputed_transaction, TransactionState::Disputed) {
+                Some(amount) => amount,
+                None => return Ok(account.ignore_undisputed(disputed_transaction)),
+            };
+        // no matter if this is a withdrawal or a deposit we need to
+        // move the funds from held into available
+        let disputed_amount = -transaction_amount.abs();
+        Bank::move_funds_from_available_to_held(account, disputed_amount)?;
+        account.advance_dispute(
+            disputed_transaction,
+            TransactionState::Resolved,
+            None,
+            batch.clone(),
+        );
+        account.open_disputes = account.open_disputes.saturating_sub(1);
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            observer.dispute_resolved(account, disputed_transaction)
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Chargeback a disputed transaction
+    /// If the transaction does not exist, or this transaction was never
+    /// previously disputed this will be ignored.
+    /// With the transaction index enabled this fails if the transaction belongs to another client.
+    /// This can fail if removing the funds causes overflow.
+    pub fn chargeback(
+        &mut self,
+        client_id: ClientId,
+        disputed_transaction: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.chargeback_with_reason(client_id, disputed_transaction, None)
+    }
+
+    /// Chargeback as `chargeback` does, replacing the reason recorded with the dispute if one is
+    /// given.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = disputed_transaction.0))]
+    pub fn chargeback_with_reason(
+        &mut self,
+        client_id: ClientId,
+        disputed_transaction: TransactionId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Chargeback, |bank| {
+            bank.apply_chargeback(client_id, disputed_transaction, reason)
+        })
+    }
+
+    fn apply_chargeback(
+        &mut self,
+        client_id: ClientId,
+        disputed_transaction: TransactionId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_owner(client_id, disputed_transaction)?;
+        self.recall_spilled(client_id, disputed_transaction)?;
+        if let Some(reason) = self.refused(client_id, Operation::Chargeback) {
+            return Ok(Outcome::Ignored(reason));
+        }
+        let batch = self.batch.clone();
+        let account = self
+            .client_accounts
+            .entry(client_id)
+            .or_insert_with(|| Account::new(client_id));
+        // Only handle disputes that have been made already and only if the transaction has been enacted.
+        let transaction_amount =
+            match account.amount_if_in_state(disputed_transaction, TransactionState::Disputed) {
+                Some(amount) => amount,
+                None => return Ok(account.ignore_undisputed(disputed_transaction)),
+            };
+        let disputed_amount = transaction_amount.abs();
+        let fee = self.precision.precision(account.currency).round(
+            self.fee_schedule
+                .fee_on(Operation::Chargeback, disputed_amount)?,
+        );
+        let held = account.held.checked_sub(disputed_amount).ok_or(Overflow)?;
+        let available = account.available.checked_sub(fee).ok_or(Overflow)?;
+        account.owe(disputed_amount)?;
+        account.held = held;
+        account.available = available;
+        account.record_fee(disputed_transaction, Operation::Chargeback, fee);
+        let newly_locked = account.freeze_for_chargeback();
+        account.advance_dispute(
+            disputed_transaction,
+            TransactionState::ChargedBack,
+            reason,
+            batch.clone(),
+        );
+        if reason.is_some() {
+            account.set_dispute_reason(disputed_transaction, reason);
+        }
+        account.open_disputes = account.open_disputes.saturating_sub(1);
+        account.chargebacks = account.chargebacks.saturating_add(1);
+        account.standing_chargebacks = account.standing_chargebacks.saturating_add(1);
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            observer.chargeback_applied(account, disputed_transaction);
+            if newly_locked {
+                observer.account_locked(account);
+            }
+        });
+        self.check_risk_limits(client_id);
+        Ok(Outcome::Applied)
+    }
+
+    /// Represent a charged back transaction, as a merchant does to contest the chargeback. The
+    /// charged back funds are credited back to the available funds and, unless the representment
+    /// policy keeps it locked, the account is unlocked once none of its chargebacks stand and it
+    /// is within its risk limits. The chargeback still counts towards the limits.
+    /// If the transaction does not exist or was not charged back this will be ignored.
+    /// With the transaction index enabled this fails if the transaction belongs to another client.
+    /// This can fail if crediting the funds causes overflow.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = charged_back.0))]
+    pub fn represent(
+        &mut self,
+        client_id: ClientId,
+        charged_back: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Represent, |bank| {
+            bank.apply_represent(client_id, charged_back)
+        })
+    }
+
+    fn apply_represent(
+        &mut self,
+        client_id: ClientId,
+        charged_back: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_owner(client_id, charged_back)?;
+        self.recall_spilled(client_id, charged_back)?;
+        if let Some(reason) = self.refused(client_id, Operation::Represent) {
+            return Ok(Outcome::Ignored(reason));
+        }
+        let batch = self.batch.clone();
+        let unlocks = self.representment_policy == RepresentmentPolicy::Unlock;
+        let limits = self.risk_limits;
+        let account = self.account_mut(client_id);
+        let transaction_amount =
+            match account.amount_if_in_state(charged_back, TransactionState::ChargedBack) {
+                Some(amount) => amount,
+                None if account.transaction_history.contains_key(&charged_back) => {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotChargedBack))
+                }
+                None => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+            };
+        account.available = account
+            .available
+            .checked_add(transaction_amount.abs())
+            .ok_or(Overflow)?;
+        account.recover(transaction_amount.abs())?;
+        account.advance_dispute(
+            charged_back,
+            TransactionState::Represented,
+            None,
+            batch.clone(),
+        );
+        account.standing_chargebacks = account.standing_chargebacks.saturating_sub(1);
+        let over_limit = limits
+            .max_open_disputes
+            .is_some_and(|max| account.open_disputes > max)
+            || limits
+                .max_chargebacks
+                .is_some_and(|max| account.chargebacks > max);
+        let newly_unlocked = unlocks
+            && account.frozen.is_none()
+            && account.standing_chargebacks == 0
+            && !over_limit
+            && account.reactivate();
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            observer.chargeback_represented(account, charged_back);
+            if newly_unlocked {
+                observer.account_unlocked(account);
+            }
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Every account of every client, main and sub-accounts alike, in no particular order.
+    fn every_account(&self) -> impl Iterator<Item = &Account> {
+        self.client_accounts.values().chain(
+            self.sub_accounts
+                .values()
+                .flat_map(|accounts| accounts.values()),
+        )
+    }
+
+    /// Take over the accounts of another bank, such as one that processed another shard of the
+    /// clients, with their full history and sub-accounts. Nothing is merged if both banks have an
+    /// account for the same client or a transaction id of the other bank is already used here.
+    pub fn merge(&mut self, mut other: Bank) -> Result<(), TransactorError> {
+        if self.spill.is_some() && !other.sub_accounts.is_empty() {
+            return Err(InvalidConfig(
+                "sub-accounts cannot be used while spilling transaction history to disk"
+                    .to_string(),
+            ));
+        }
+        let used: HashSet<TransactionId> = self
+            .every_account()
+            .flat_map(Account::transaction_ids)
+            .collect();
+        for account in other.every_account() {
+            if self.client_accounts.contains_key(&account.client_id)
+                || self.sub_accounts.contains_key(&account.client_id)
+            {
+                return Err(AccountExists {
+                    client: account.client_id,
+                });
+            }
+            if let Some(transaction_id) = account.transaction_ids().find(|id| used.contains(id)) {
+                return Err(DuplicateTransaction {
+                    client: account.client_id,
+                    tx: transaction_id,
+                });
+            }
+        }
+        let mut accounts: Vec<Account> = other
+            .client_accounts
+            .drain()
+            .map(|(_, account)| account)
+            .collect();
+        accounts.sort_by_key(|account| account.client_id.0);
+        for mut account in accounts {
+            if let Some(spill) = &other.spill {
+                for transaction_id in &account.transaction_order {
+                    if account.transaction_history.contains_key(transaction_id) {
+                        continue;
+                    }
+                    if let Some(recorded) = spill.store.read(account.client_id, *transaction_id)? {
+                        account
+                            .transaction_history
+                            .insert(*transaction_id, recorded);
+                    }
+                }
+            }
+            self.adopt_account(account)?;
+        }
+        let mut sub_accounts: Vec<(ClientId, BTreeMap<AccountName, Account>)> =
+            other.sub_accounts.drain().collect();
+        sub_accounts.sort_by_key(|(client_id, _)| client_id.0);
+        for (client_id, accounts) in sub_accounts {
+            for (name, account) in accounts {
+                self.in_account(client_id, name, |bank| bank.adopt_account(account))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an account with its history all in memory, indexing it as if its transactions had
+    /// been applied here.
+    fn adopt_account(&mut self, account: Account) -> Result<(), TransactorError> {
+        let client_id = account.client_id;
+        let recorded: Vec<(TransactionId, TransactionState)> = account
+            .transaction_order
+            .iter()
+            .filter_map(|transaction_id| {
+                let state = account.transaction_history.get(transaction_id)?.state;
+                Some((*transaction_id, state))
+            })
+            .collect();
+        let authorizations: Vec<Transaction> = account.authorizations.values().copied().collect();
+        let holds: Vec<TransactionId> = account.holds.keys().copied().collect();
+        self.client_accounts.insert(client_id, account);
+        if let Some(index) = &mut self.transaction_index {
+            for hold in holds {
+                Owners::add(index, hold, client_id);
+            }
+        }
+        for authorized in authorizations {
+            if let Some(index) = &mut self.transaction_index {
+                Owners::add(index, authorized.transaction_id, client_id);
+            }
+            self.track_authorization_time(client_id, &authorized, true);
+        }
+        for (transaction_id, state) in recorded {
+            if state != TransactionState::Posted {
+                self.dispute_index.insert((client_id, transaction_id));
+            }
+            if let Some(index) = &mut self.transaction_index {
+                Owners::add(index, transaction_id, client_id);
+            }
+            self.track_in_memory(client_id, transaction_id)?;
+        }
+        Ok(())
+    }
+
+    /// Close the current period: every transaction not currently in dispute is removed from the
+    /// accounts and returned, ordered by client and then application order, so the caller can
+    /// archive it. Balances and lock status carry forward into the next period, as do disputed
+    /// transactions so their disputes can still be resolved or charged back. Later disputes on
+    /// archived transactions are ignored as the transaction is no longer known. The fees and
+    /// chargebacks of the period are counted afresh in the next, and its open disputes are those
+    /// carried forward. Sub-accounts are closed alike, each client's after its main account in
+    /// order of name.
+    /// When history is spilled to disk only transactions held in memory are archived.
+    pub fn close_period(&mut self) -> Vec<TransactionMatch> {
+        let mut client_ids: Vec<ClientId> = self
+            .client_accounts
+            .keys()
+            .chain(self.sub_accounts.keys())
+            .copied()
+            .collect();
+        client_ids.sort_by_key(|client_id| client_id.0);
+        client_ids.dedup();
+        let mut archived = Vec::new();
+        for client_id in client_ids {
+            let main = self.client_accounts.get_mut(&client_id);
+            let sub_accounts = self.sub_accounts.get_mut(&client_id);
+            let accounts = main.into_iter().chain(
+                sub_accounts
+                    .into_iter()
+                    .flat_map(|accounts| accounts.values_mut()),
+            );
+            for account in accounts {
+                archived.extend(account.close_period());
+            }
+        }
+        // Ids are unique across a client's accounts, so the index needs no account name
+        for found in &archived {
+            self.dispute_index
+                .remove(&(found.client_id, found.transaction.transaction_id));
+        }
+        if let Some(spill) = &mut self.spill {
+            let client_accounts = &self.client_accounts;
+            spill.in_memory.retain(|(client_id, transaction_id)| {
+                client_accounts
+                    .get(client_id)
+                    .is_some_and(|account| account.transaction_history.contains_key(transaction_id))
+            });
+        }
+        archived
+    }
+
+    /// Reinstate a locked account so that it accepts transactions again.
+    /// If the account does not exist, is not locked or has been closed this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0))]
+    pub fn unlock(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Unlock, |bank| bank.apply_unlock(client_id))
+    }
+
+    fn apply_unlock(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        let account = match self.client_accounts.get_mut(&client_id) {
+            Some(account) => account,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotLocked)),
+        };
+        if account.is_closed() {
+            return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+        }
+        if !account.reactivate() {
+            return Ok(Outcome::Ignored(IgnoreReason::NotLocked));
+        }
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            observer.account_unlocked(account)
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Close an account at the request of the customer. A closed account is locked for good: it
+    /// takes no more deposits, withdrawals or authorizations and cannot be unlocked or unfrozen,
+    /// though disputes of its transactions are still handled.
+    /// If the account does not exist, is already closed or holds funds this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0))]
+    pub fn close_account(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Close, |bank| bank.apply_close(client_id))
+    }
+
+    fn apply_close(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        let account = match self.client_accounts.get_mut(&client_id) {
+            Some(account) => account,
+            None => return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount)),
+        };
+        if account.is_closed() {
+            return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+        }
+        if !account.held.is_zero() {
+            return Ok(Outcome::Ignored(IgnoreReason::FundsHeld));
+        }
+        let newly_locked = account.close();
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            if newly_locked {
+                observer.account_locked(account);
+            }
+            observer.account_closed(account);
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Lock an account at the request of the input, such as an upstream risk system, rather than
+    /// as a result of a chargeback. The account is opened if it does not exist yet.
+    /// If the account is already locked this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0))]
+    pub fn freeze(
+        &mut self,
+        client_id: ClientId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Freeze, |bank| {
+            bank.apply_freeze(client_id, reason)
+        })
+    }
+
+    fn apply_freeze(
+        &mut self,
+        client_id: ClientId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        let account = self.account_mut(client_id);
+        if !account.freeze(reason) {
+            return Ok(Outcome::Ignored(account.lock_reason()));
+        }
+        account.record_change(batch);
+        self.notify(client_id, |observer, account| {
+            observer.account_locked(account)
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Undo a freeze. Accounts locked by a chargeback stay locked, only an unlock from the
+    /// administrative operations reinstates them.
+    /// If the account is not frozen this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0))]
+    pub fn unfreeze(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Unfreeze, |bank| bank.apply_unfreeze(client_id))
+    }
+
+    fn apply_unfreeze(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        match self.client_accounts.get_mut(&client_id) {
+            Some(account) if account.frozen.is_some() => {
+                account.unfreeze();
+                account.record_change(batch);
+            }
+            _ => return Ok(Outcome::Ignored(IgnoreReason::NotFrozen)),
+        }
+        self.notify(client_id, |observer, account| {
+            observer.account_unlocked(account)
+        });
+        Ok(Outcome::Applied)
+    }
+
+    /// Hold funds for a card style authorization without posting a transaction. The funds move
+    /// from available to held until the authorization is captured, voided or expires.
+    /// Authorizations on locked accounts or beyond the available funds and overdraft are refused
+    /// like withdrawals, and the transaction id must not have been used before.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = transaction.transaction_id.0))]
+    pub fn authorize(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Authorize, |bank| {
+            bank.apply_authorize(client_id, transaction)
+        })
+    }
+
+    fn apply_authorize(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_amount(client_id, &transaction)?;
+        let batch = self.batch.clone();
+        let used = self.is_used(client_id, transaction.transaction_id)?;
+        let refusal_policy = self.refusal_policy;
+        let floor = -self.overdraft_policy.limit(client_id);
+        let refused = self.refused(client_id, Operation::Authorize);
+        let account = self.account_mut(client_id);
+        if let Some(reason) = refused {
+            return refusal_policy.refuse(
+                reason,
+                AccountLocked {
+                    client: client_id,
+                    tx: transaction.transaction_id,
+                },
+            );
+        }
+        if used {
+            return Err(TransactionIdReuse);
+        }
+        if let (Some(expected), Some(found)) = (account.currency, transaction.currency) {
+            if expected != found {
+                return Err(CurrencyMismatch { expected, found });
+            }
+        }
+        let remaining = account
+            .available
+            .checked_sub(transaction.amount)
+            .ok_or(Overflow)?;
+        if remaining < floor {
+            return refusal_policy.refuse(
+                IgnoreReason::InsufficientFunds,
+                InsufficientFunds {
+                    client: client_id,
+                    tx: transaction.transaction_id,
+                    requested: transaction.amount,
+                    available: account.available,
+                },
+            );
+        }
+        Bank::move_funds_from_available_to_held(account, transaction.amount)?;
+        account
+            .authorizations
+            .insert(transaction.transaction_id, transaction);
+        if account.currency.is_none() {
+            account.currency = transaction.currency;
+        }
+        account.record_change(batch);
+        if let Some(index) = &mut self.transaction_index {
+            Owners::add(index, transaction.transaction_id, client_id);
+        }
+        self.track_authorization_time(client_id, &transaction, true);
+        Ok(Outcome::Applied)
+    }
+
+    /// Complete an authorization, posting the held funds as a withdrawal under the same
+    /// transaction id. Any withdrawal fee is taken from the available funds with it, and the
+    /// capture is refused as a withdrawal is if the fee would take them past the overdraft limit.
+    /// Captures on locked accounts are refused and the funds stay held.
+    /// If there is no such pending authorization this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = transaction_id.0))]
+    pub fn capture(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Capture, |bank| {
+            bank.apply_capture(client_id, transaction_id)
+        })
+    }
+
+    fn apply_capture(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        let refusal_policy = self.refusal_policy;
+        let refused = self.refused(client_id, Operation::Capture);
+        let authorized = match self
+            .client_accounts
+            .get(&client_id)
+            .and_then(|account| account.authorizations.get(&transaction_id))
+        {
+            Some(authorized) => *authorized,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized)),
+        };
+        if let Some(reason) = refused {
+            return refusal_policy.refuse(
+                reason,
+                AccountLocked {
+                    client: client_id,
+                    tx: transaction_id,
+                },
+            );
+        }
+        let withdrawal = Transaction {
+            amount: -authorized.amount,
+            ..authorized
+        };
+        let currency = authorized
+            .currency
+            .or_else(|| self.client_accounts.get(&client_id)?.currency);
+        let fee = self.precision.precision(currency).round(
+            self.fee_schedule
+                .fee_on(Operation::Withdrawal, withdrawal.amount)?,
+        );
+        let floor = -self.overdraft_policy.limit(client_id);
+        let account = self.account_mut(client_id);
+        // The fee is taken together with the capture it is charged on, or not at all
+        let available = account.available.checked_sub(fee).ok_or(Overflow)?;
+        let held = account
+            .held
+            .checked_sub(authorized.amount)
+            .ok_or(Overflow)?;
+        if !fee.is_zero() && available < floor {
+            let available = account.available;
+            self.notify(client_id, |observer, account| {
+                observer.withdrawal_rejected(account, &withdrawal)
+            });
+            return refusal_policy.refuse(
+                IgnoreReason::InsufficientFunds,
+                InsufficientFunds {
+                    client: client_id,
+                    tx: transaction_id,
+                    requested: fee,
+                    available,
+                },
+            );
+        }
+        account.held = held;
+        account.available = available;
+        account.record_fee(transaction_id, Operation::Withdrawal, fee);
+        account.authorizations.remove(&transaction_id);
+        account.transaction_history.insert(
+            transaction_id,
+            RecordedTransaction {
+                transaction: withdrawal,
+                state: TransactionState::Posted,
+                reason: None,
+            },
+        );
+        account.transaction_order.push(transaction_id);
+        account.record_change(batch);
+        self.track_authorization_time(client_id, &authorized, false);
+        self.notify(client_id, |observer, account| {
+            observer.transaction_applied(account, &withdrawal)
+        });
+        self.track_in_memory(client_id, transaction_id)?;
+        Ok(Outcome::Applied)
+    }
+
+    /// Cancel an authorization, releasing the held funds back to available. This is allowed on
+    /// locked accounts. If there is no such pending authorization this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = transaction_id.0))]
+    pub fn void(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Void, |bank| {
+            bank.apply_void(client_id, transaction_id)
+        })
+    }
+
+    fn apply_void(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        let batch = self.batch.clone();
+        let refused = self.refused(client_id, Operation::Void);
+        if let Some(reason) = refused {
+            return Ok(Outcome::Ignored(reason));
+        }
+        let account = match self.client_accounts.get_mut(&client_id) {
+            Some(account) => account,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized)),
+        };
+        let authorized = match account.authorizations.remove(&transaction_id) {
+            Some(authorized) => authorized,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized)),
+        };
+        Bank::move_funds_from_available_to_held(account, -authorized.amount)?;
+        account.record_change(batch);
+        self.track_authorization_time(client_id, &authorized, false);
+        Ok(Outcome::Applied)
+    }
+
+    /// Hold funds of the account administratively, such as for a regulatory hold, moving the
+    /// positive amount of the transaction from available to held until it is released. Holds are
+    /// not tied to a transaction in dispute so resolves and chargebacks never release them, and
+    /// they are placed and released whatever the status of the account. A hold of more than the
+    /// available funds is ignored, and the transaction id must not have been used before.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = transaction.transaction_id.0))]
+    pub fn hold(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Hold, |bank| {
+            bank.apply_hold(client_id, transaction)
+        })
+    }
+
+    fn apply_hold(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        self.check_amount(client_id, &transaction)?;
+        let batch = self.batch.clone();
+        if self.is_used(client_id, transaction.transaction_id)? {
+            return Err(TransactionIdReuse);
+        }
+        let refusal_policy = self.refusal_policy;
+        let account = self.account_mut(client_id);
+        if let (Some(expected), Some(found)) = (account.currency, transaction.currency) {
+            if expected != found {
+                return Err(CurrencyMismatch { expected, found });
+            }
+        }
+        if account.available < transaction.amount {
+            return refusal_policy.refuse(
+                IgnoreReason::InsufficientFunds,
+                InsufficientFunds {
+                    client: client_id,
+                    tx: transaction.transaction_id,
+                    requested: transaction.amount,
+                    available: account.available,
+                },
+            );
+        }
+        Bank::move_funds_from_available_to_held(account, transaction.amount)?;
+        account
+            .holds
+            .insert(transaction.transaction_id, transaction.amount);
+        account.record_change(batch);
+        if let Some(index) = &mut self.transaction_index {
+            Owners::add(index, transaction.transaction_id, client_id);
+        }
+        Ok(Outcome::Applied)
+    }
+
+    /// Release an administrative hold, moving its funds back from held to available. If there is
+    /// no such hold this will be ignored.
+    #[instrument(level = "debug", skip_all, fields(client = client_id.0, tx = transaction_id.0))]
+    pub fn release(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.measured(Operation::Release, |bank| {
+            let batch = bank.batch.clone();
+            let account = match bank.client_accounts.get_mut(&client_id) {
+                Some(account) => account,
+                None => return Ok(Outcome::Ignored(IgnoreReason::NotHeld)),
+            };
+            let amount = match account.holds.get(&transaction_id) {
+                Some(amount) => *amount,
+                None => return Ok(Outcome::Ignored(IgnoreReason::NotHeld)),
+            };
+            Bank::move_funds_from_available_to_held(account, -amount)?;
+            account.holds.remove(&transaction_id);
+            account.record_change(batch);
+            Ok(Outcome::Applied)
+        })
+    }
+
+    fn track_authorization_time(
+        &mut self,
+        client_id: ClientId,
+        authorized: &Transaction,
+        pending: bool,
+    ) {
+        if let Some(timestamp) = authorized.timestamp {
+            let key = (timestamp, client_id.0, authorized.transaction_id.0);
+            if pending {
+                self.authorization_times.insert(key);
+            } else {
+                self.authorization_times.remove(&key);
+            }
+        }
+    }
+
+    /// Void every authorization made before the cutoff, returning those voided ordered by client
+    /// and transaction id. Authorizations without a timestamp never expire, and those the status
+    /// rules of their account refuse to void stay pending.
+    pub fn expire_authorizations(
+        &mut self,
+        cutoff: Timestamp,
+    ) -> Result<Vec<(ClientId, TransactionId)>, TransactorError> {
+        let mut voided = Vec::new();
+        for (client_id, transaction_id) in self.expiring_authorizations(cutoff) {
+            if self.apply_void(client_id, transaction_id)? == Outcome::Applied {
+                voided.push((client_id, transaction_id));
+            }
+        }
+        Ok(voided)
+    }
+
+    /// The authorizations old enough for `expire_authorizations` to void with the cutoff, without
+    /// voiding them.
+    pub fn expiring_authorizations(&self, cutoff: Timestamp) -> Vec<(ClientId, TransactionId)> {
+        let mut expiring: Vec<(ClientId, TransactionId)> = self
+            .authorization_times
+            .range(..(cutoff, 0, 0))
+            .map(|&(_, client, transaction)| (ClientId(client), TransactionId(transaction)))
+            .collect();
+        expiring.sort_by_key(|(client_id, transaction_id)| (client_id.0, transaction_id.0));
+        expiring
+    }
+
+    /// Run an operation on a clients account, recording it in the metrics if they are enabled and
+    /// logging its outcome at debug level.
+    fn measured(
+        &mut self,
+        operation: Operation,
+        apply: impl FnOnce(&mut Self) -> Result<Outcome, TransactorError>,
+    ) -> Result<Outcome, TransactorError> {
+        let result = if self.metrics.is_none() {
+            apply(self)
+        } else {
+            let start = Instant::now();
+            let result = apply(self);
+            let elapsed = start.elapsed();
+            if let Some(metrics) = &mut self.metrics {
+                metrics.record(operation, &result, elapsed);
+            }
+            result
+        };
+        match &result {
+            Ok(Outcome::Applied) => debug!("applied"),
+            Ok(Outcome::Ignored(reason)) => debug!(%reason, "ignored"),
+            Err(e) => debug!(error = %e, "rejected"),
+        }
+        result
+    }
+
+    /// Call every observer with the current state of a clients account.
+    fn notify(&mut self, client_id: ClientId, event: impl Fn(&mut dyn BankObserver, &Account)) {
+        if let Some(account) = self.client_accounts.get(&client_id) {
+            for observer in &mut self.observers {
+                event(observer.as_mut(), account);
+            }
+        }
+    }
+
+    /// Put the account under review if it has just gone over a risk limit. Like a chargeback this
+    /// outlasts any freeze, only an unlock lifts it.
+    fn check_risk_limits(&mut self, client_id: ClientId) {
+        let limits = self.risk_limits;
+        let account = match self.client_accounts.get_mut(&client_id) {
+            Some(account) => account,
+            None => return,
+        };
+        // Counts only move by one so an account has just gone over when it is one past the limit
+        let exceeded = if limits
+            .max_chargebacks
+            .is_some_and(|max| account.chargebacks == max.saturating_add(1))
+        {
+            RiskLimit::Chargebacks
+        } else if limits
+            .max_open_disputes
+            .is_some_and(|max| account.open_disputes == max.saturating_add(1))
+        {
+            RiskLimit::OpenDisputes
+        } else {
+            return;
+        };
+        let newly_locked = account.put_under_review();
+        self.notify(client_id, |observer, account| {
+            observer.risk_limit_exceeded(account, exceeded);
+            if newly_locked {
+                observer.account_locked(account);
+            }
+        });
+    }
+
+    /// Using the transaction index, if enabled, check that a transaction referenced by a client is not
+    /// owned by a different client. It is only foreign if the client has no transaction of that id
+    /// of its own, as other clients may have used the same id.
+    fn check_owner(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), TransactorError> {
+        if self.transaction_index.is_none()
+            || self
+                .recorded_transaction(client_id, transaction_id)
+                .is_some()
+        {
+            return Ok(());
+        }
+        match self
+            .transaction_index
+            .as_ref()
+            .and_then(|index| index.get(&transaction_id))
+        {
+            Some(owners) if !owners.clients().contains(&client_id) => Err(ForeignTransaction {
+                client: client_id,
+                owner: owners.clients()[0],
+                transaction: transaction_id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a transaction id has already been used by the client, or by any client when ids
+    /// must be globally unique. Authorizations use up their transaction id too.
+    fn is_used(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<bool, TransactorError> {
+        let spilled = match &self.spill {
+            Some(spill) => spill.store.read(client_id, transaction_id)?.is_some(),
+            None => false,
+        };
+        let globally_reused = self.global_transaction_ids
+            && self
+                .transaction_index
+                .as_ref()
+                .is_some_and(|index| index.contains_key(&transaction_id));
+        // Ids are unique across all of a client's accounts, whichever one is in use
+        let recorded = self
+            .client_accounts
+            .get(&client_id)
+            .into_iter()
+            .chain(
+                self.sub_accounts
+                    .get(&client_id)
+                    .into_iter()
+                    .flat_map(|accounts| accounts.values()),
+            )
+            .any(|account| {
+                account.transaction_history.contains_key(&transaction_id)
+                    || account.authorizations.contains_key(&transaction_id)
+                    || account.holds.contains_key(&transaction_id)
+            });
+        Ok(spilled || globally_reused || recorded)
+    }
+
+    /// Note that a transaction is held in memory, spilling the oldest transactions to disk if that
+    /// takes the bank over its limit.
+    fn track_in_memory(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), TransactorError> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+        spill.in_memory.push_back((client_id, transaction_id));
+        while spill.in_memory.len() > spill.max_in_memory {
+            if let Some((client_id, transaction_id)) = spill.in_memory.pop_front() {
+                let recorded = self
+                    .client_accounts
+                    .get_mut(&client_id)
+                    .and_then(|account| account.transaction_history.remove(&transaction_id));
+                if let Some(recorded) = recorded {
+                    spill.store.write(client_id, &recorded)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring a spilled transaction back into memory so it can be disputed, resolved or charged back.
+    fn recall_spilled(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), TransactorError> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+        let account = match self.client_accounts.get_mut(&client_id) {
+            Some(account) if !account.transaction_history.contains_key(&transaction_id) => account,
+            _ => return Ok(()),
+        };
+        if let Some(recorded) = spill.store.read(client_id, transaction_id)? {
+            spill.store.remove(client_id, transaction_id)?;
+            account.transaction_history.insert(transaction_id, recorded);
+            self.track_in_memory(client_id, transaction_id)?;
+        }
+        Ok(())
+    }
+
+    fn move_funds_from_available_to_held(
+        account: &mut Account,
+        amount: Decimal,
+    ) -> Result<(), TransactorError> {
+        let new_available = account.available.checked_sub(amount);
+        let new_held = account.held.checked_add(amount);
+        if let (Some(available), Some(held)) = (new_available, new_held) {
+            account.available = available;
+            account.held = held;
+            Ok(())
+        } else {
+            Err(Overflow)
+        }
+    }
+
+    /// Why the status of the clients account refuses the operation, None if it is accepted.
+    /// Accounts not yet opened are active.
+    fn refused(&self, client_id: ClientId, operation: Operation) -> Option<IgnoreReason> {
+        let account = self.client_accounts.get(&client_id);
+        let status = account.map_or(AccountStatus::Active, Account::status);
+        let accepted = self.status_rules.accepts(status, operation)
+            || self.locked_account_policy.accepts(status, operation);
+        match accepted {
+            true => None,
+            false => Some(account.map_or(IgnoreReason::StatusRule, Account::lock_reason)),
+        }
+    }
+
+    fn account_mut(&mut self, client_id: ClientId) -> &mut Account {
+        self.client_accounts
+            .entry(client_id)
+            .or_insert_with(|| Account::new(client_id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn do_not_withdraw_into_negative_amount_and_transaction_is_not_recorded(
+    ) -> Result<(), TransactorError> {
+        let client = ClientId(1);
+        let mut bank = Bank::new();
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(-10, 1)),
+        )?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert!(bank.account_mut(client).transaction_history.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_for_positive_adds_to_total_and_is_recorded() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let tx = TransactionId(2);
+        let transaction = Transaction::new(tx, Decimal::new(10, 1));
+        bank.transact(client, transaction)?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(10, 1));
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&tx)
+                .unwrap()
+                .transaction,
+            transaction
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_for_negative_lowers_balance_and_is_recorded() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let transaction_id1 = TransactionId(1);
+        let transaction_id2 = TransactionId(2);
+        let transaction1 = Transaction::new(transaction_id1, Decimal::new(1, 0));
+        let transaction2 = Transaction::new(transaction_id2, Decimal::new(-1, 1));
+
+        bank.transact(client, transaction1)?;
+        bank.transact(client, transaction2)?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(9, 1));
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id1)
+                .unwrap()
+                .transaction,
             transaction1
         );
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id2)
-                .unwrap(),
-            transaction2
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id2)
+                .unwrap()
+                .transaction,
+            transaction2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn account_deposit_with_huge_numbers_fails_and_is_not_recorded() -> Result<(), TransactorError>
+    {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let max_decimal = Decimal::max_value();
+        let transaction_id1 = TransactionId(1);
+        let transaction_id2 = TransactionId(2);
+        bank.transact(client, Transaction::new(transaction_id1, max_decimal))?;
+        assert!(bank
+            .transact(client, Transaction::new(transaction_id2, max_decimal))
+            .is_err());
+        assert_eq!(bank.account_mut(client).transaction_history.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_to_locked_account_is_ignored_and_is_not_recorded() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let transaction_id = TransactionId(1);
+        bank.open_account(client, Decimal::zero(), Decimal::zero(), true, None)?;
+        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 1)))?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert!(bank.account_mut(client).transaction_history.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_transaction_ignored_if_transaction_does_not_exist() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.dispute_transaction(client, TransactionId(1))?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client).transaction_state(TransactionId(1)),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_positive_transaction_moves_funds_into_held() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let disputed_amount = Decimal::new(1, 1);
+        let transaction_id = TransactionId(1);
+        let transaction = Transaction::new(transaction_id, disputed_amount);
+
+        bank.transact(client, transaction)?;
+        bank.dispute_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), disputed_amount);
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            transaction
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Disputed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_negative_transaction_moves_funds_into_held() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let disputed_amount = Decimal::new(1, 1);
+        bank.open_account(client, disputed_amount, Decimal::zero(), false, None)?;
+        let transaction_id = TransactionId(1);
+        let transaction = Transaction::new(transaction_id, -disputed_amount);
+
+        bank.transact(client, transaction)?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        bank.dispute_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), -disputed_amount);
+        assert_eq!(bank.account_mut(client).held(), disputed_amount);
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            transaction
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Disputed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_transaction_fails_if_causes_overflow_in_held_and_dispute_is_not_recorded(
+    ) -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let max_value = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let transaction = Transaction::new(transaction_id, max_value);
+        bank.open_account(client, Decimal::zero(), max_value, false, None)?;
+
+        bank.transact(client, transaction)?;
+
+        assert!(bank.dispute_transaction(client, transaction_id).is_err());
+        assert_eq!(bank.account_mut(client).available(), max_value);
+        assert_eq!(bank.account_mut(client).held(), max_value);
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            transaction
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Posted)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_transaction_fails_if_causes_overflow_in_available_and_dispute_is_not_recorded(
+    ) -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_overdraft_policy(OverdraftPolicy::new(Decimal::one()));
+        let client = ClientId(1);
+        let max_value = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let huge_deposit = Transaction::new(transaction_id, max_value);
+
+        bank.transact(client, huge_deposit)?;
+        // Once the deposit is spent and the account overdrawn, taking it from available overflows
+        bank.transact(client, Transaction::new(TransactionId(2), -max_value))?;
+        bank.transact(client, Transaction::new(TransactionId(3), -Decimal::one()))?;
+        assert!(matches!(
+            bank.dispute_transaction(client, transaction_id),
+            Err(Overflow)
+        ));
+
+        assert_eq!(bank.account_mut(client).available(), -Decimal::one());
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            huge_deposit
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Posted)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dispute_fails_if_causes_overflow_in_available_and_dispute_is_not_resolved(
+    ) -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let max_value = Decimal::max_value();
+        let transaction_id1 = TransactionId(1);
+        let huge_deposit = Transaction::new(transaction_id1, max_value);
+        let transaction_id2 = TransactionId(2);
+        let huge_deposit2 = Transaction::new(transaction_id2, max_value);
+
+        bank.transact(client, huge_deposit)?;
+        bank.dispute_transaction(client, transaction_id1)?;
+        bank.transact(client, huge_deposit2)?;
+
+        assert!(bank
+            .resolve_disputed_transaction(client, transaction_id1)
+            .is_err());
+        assert_eq!(bank.account_mut(client).available(), max_value);
+        assert_eq!(bank.account_mut(client).held(), max_value);
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id1)
+                .unwrap()
+                .transaction,
+            huge_deposit
+        );
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id2)
+                .unwrap()
+                .transaction,
+            huge_deposit2
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id1),
+            Some(TransactionState::Disputed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dispute_ignores_if_transaction_does_not_exist() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.resolve_disputed_transaction(client, TransactionId(1))?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client).transaction_state(TransactionId(1)),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dispute_ignores_if_transaction_is_not_disputed() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let amount = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let deposit = Transaction::new(transaction_id, amount);
+
+        bank.transact(client, deposit)?;
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), amount);
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Posted)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dispute_correctly_resolves_disputed_withdrawal() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let amount = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let withdrawal = Transaction::new(transaction_id, -amount);
+
+        bank.open_account(client, amount, Decimal::zero(), false, None)?;
+        bank.transact(client, withdrawal)?;
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            withdrawal
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Resolved)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dispute_correctly_resolves_disputed_transaction() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let amount = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let deposit = Transaction::new(transaction_id, amount);
+
+        bank.transact(client, deposit)?;
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), amount);
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            deposit
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Resolved)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_correctly_pulls_back_disputed_transaction() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let amount = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let deposit = Transaction::new(transaction_id, amount);
+
+        bank.transact(client, deposit)?;
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.chargeback(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            deposit
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::ChargedBack)
+        );
+        assert!(bank.account_mut(client).is_locked());
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_correctly_ignored_if_transaction_not_disputed() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let amount = Decimal::max_value();
+        let transaction_id = TransactionId(1);
+        let deposit = Transaction::new(transaction_id, amount);
+
+        bank.transact(client, deposit)?;
+        bank.chargeback(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), amount);
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client)
+                .transaction_history
+                .get(&transaction_id)
+                .unwrap()
+                .transaction,
+            deposit
+        );
+        assert_eq!(
+            bank.account_mut(client).transaction_state(transaction_id),
+            Some(TransactionState::Posted)
+        );
+        assert!(!bank.account_mut(client).is_locked());
+        Ok(())
+    }
+
+    #[test]
+    fn account_version_increments_on_each_mutation() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let transaction_id = TransactionId(1);
+        assert_eq!(bank.account_version(client), None);
+
+        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 0)))?;
+        assert_eq!(bank.account_version(client), Some(1));
+        bank.dispute_transaction(client, transaction_id)?;
+        assert_eq!(bank.account_version(client), Some(2));
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+        assert_eq!(bank.account_version(client), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn account_version_unchanged_by_ignored_operations() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(-1, 0)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(2))?;
+        bank.chargeback(client, TransactionId(1))?;
+        assert_eq!(bank.account_version(client), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn check_version_fails_after_concurrent_change() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.check_version(client, 0)?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+        )?;
+        assert!(matches!(
+            bank.check_version(client, 0),
+            Err(VersionConflict {
+                expected: 0,
+                actual: 1
+            })
+        ));
+        bank.check_version(client, 1)
+    }
+
+    #[test]
+    fn dispute_on_withdrawal_ignored_when_only_deposits_disputable() -> Result<(), TransactorError>
+    {
+        let mut bank = Bank::new().with_dispute_policy(DisputePolicy::DepositsOnly);
+        let client = ClientId(1);
+        let deposit_id = TransactionId(1);
+        let withdrawal_id = TransactionId(2);
+        bank.transact(client, Transaction::new(deposit_id, Decimal::new(2, 0)))?;
+        bank.transact(client, Transaction::new(withdrawal_id, Decimal::new(-1, 0)))?;
+
+        bank.dispute_transaction(client, withdrawal_id)?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(1, 0));
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client).transaction_state(withdrawal_id),
+            Some(TransactionState::Posted)
+        );
+
+        bank.dispute_transaction(client, deposit_id)?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(-1, 0));
+        assert_eq!(bank.account_mut(client).held(), Decimal::new(2, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn account_records_batch_of_latest_change() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let transaction_id = TransactionId(1);
+        let first_batch = BatchId::new("first");
+        let second_batch = BatchId::new("second");
+
+        bank.set_batch(Some(first_batch.clone()));
+        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 0)))?;
+        assert_eq!(bank.account_mut(client).last_batch(), Some(&first_batch));
+
+        bank.set_batch(Some(second_batch.clone()));
+        bank.chargeback(client, transaction_id)?;
+        assert_eq!(bank.account_mut(client).last_batch(), Some(&first_batch));
+        bank.dispute_transaction(client, transaction_id)?;
+        assert_eq!(bank.account_mut(client).last_batch(), Some(&second_batch));
+        Ok(())
+    }
+
+    #[test]
+    fn charged_back_transaction_cannot_be_disputed_again() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let transaction_id = TransactionId(1);
+        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 0)))?;
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.chargeback(client, transaction_id)?;
+        bank.dispute_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.transaction_state(client, transaction_id),
+            Some(TransactionState::ChargedBack)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolved_transaction_can_be_disputed_again() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let amount = Decimal::new(1, 0);
+        let transaction_id = TransactionId(1);
+        bank.transact(client, Transaction::new(transaction_id, amount))?;
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+        bank.dispute_transaction(client, transaction_id)?;
+
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(bank.account_mut(client).held(), amount);
+        assert_eq!(
+            bank.transaction_state(client, transaction_id),
+            Some(TransactionState::Disputed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_cycles_are_kept_in_the_history_up_to_the_limit() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_max_dispute_cycles(2);
+        let client = ClientId(1);
+        let transaction_id = TransactionId(1);
+        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 0)))?;
+        bank.dispute_with_reason(client, transaction_id, Some("fraud".parse()?))?;
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+        bank.set_batch(Some(BatchId::new("day-2")));
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.resolve_disputed_transaction(client, transaction_id)?;
+        assert_eq!(
+            bank.dispute_transaction(client, transaction_id)?,
+            Outcome::Ignored(IgnoreReason::DisputeLimit)
+        );
+
+        let history = bank.account_mut(client).dispute_history(transaction_id);
+        let states: Vec<TransactionState> = history.iter().map(|entry| entry.state).collect();
+        assert_eq!(
+            states,
+            vec![
+                TransactionState::Disputed,
+                TransactionState::Resolved,
+                TransactionState::Disputed,
+                TransactionState::Resolved
+            ]
+        );
+        assert_eq!(history[0].reason, Some("fraud".parse()?));
+        assert_eq!(history[0].batch, None);
+        assert_eq!(history[3].batch, Some(BatchId::new("day-2")));
+        assert!(bank
+            .account_mut(client)
+            .dispute_history(TransactionId(2))
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn unlock_reinstates_charged_back_account() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let transaction_id = TransactionId(1);
+        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 0)))?;
+        bank.dispute_transaction(client, transaction_id)?;
+        bank.chargeback(client, transaction_id)?;
+        assert!(bank.account_mut(client).is_locked());
+
+        bank.unlock(client)?;
+        assert!(!bank.account_mut(client).is_locked());
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(1, 0)),
+        )?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(1, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn representment_credits_back_and_unlocks_once_no_chargeback_stands(
+    ) -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        for id in 1..=2 {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(id), Decimal::new(2, 0)),
+            )?;
+            bank.dispute_transaction(client, TransactionId(id))?;
+        }
+        bank.chargeback(client, TransactionId(1))?;
+        bank.chargeback(client, TransactionId(2))?;
+        assert_eq!(
+            bank.represent(client, TransactionId(3))?,
+            Outcome::Ignored(IgnoreReason::UnknownTransaction)
+        );
+        assert_eq!(bank.represent(client, TransactionId(1))?, Outcome::Applied);
+        assert_eq!(
+            bank.represent(client, TransactionId(1))?,
+            Outcome::Ignored(IgnoreReason::NotChargedBack)
+        );
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(2, 0));
+        assert!(bank.account_mut(client).is_locked());
+
+        bank.represent(client, TransactionId(2))?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(4, 0));
+        // Both still count towards the risk limits
+        assert_eq!(bank.account_mut(client).standing_chargebacks(), 0);
+        assert_eq!(bank.account_mut(client).chargebacks(), 2);
+        assert!(!bank.account_mut(client).is_locked());
+        assert_eq!(
+            bank.transaction_state(client, TransactionId(2)),
+            Some(TransactionState::Represented)
+        );
+        assert_eq!(
+            bank.dispute_transaction(client, TransactionId(2))?,
+            Outcome::Ignored(IgnoreReason::NotDisputable)
+        );
+        bank.check_funds_conserved()?;
+
+        let mut keeps_lock = Bank::new().with_representment_policy(RepresentmentPolicy::KeepLocked);
+        keeps_lock.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(2, 0)),
+        )?;
+        keeps_lock.dispute_transaction(client, TransactionId(1))?;
+        keeps_lock.chargeback(client, TransactionId(1))?;
+        keeps_lock.represent(client, TransactionId(1))?;
+        assert_eq!(
+            keeps_lock.account_mut(client).available(),
+            Decimal::new(2, 0)
+        );
+        assert!(keeps_lock.account_mut(client).is_locked());
+
+        // A represented chargeback still counts towards the limit
+        let mut limited = Bank::new().with_risk_limits(RiskLimits {
+            max_chargebacks: Some(1),
+            max_open_disputes: None,
+        });
+        for id in 1..=2 {
+            limited.transact(
+                client,
+                Transaction::new(TransactionId(id), Decimal::new(2, 0)),
+            )?;
+            limited.dispute_transaction(client, TransactionId(id))?;
+            limited.chargeback(client, TransactionId(id))?;
+            limited.represent(client, TransactionId(id))?;
+        }
+        assert_eq!(
+            limited.account_mut(client).status(),
+            AccountStatus::UnderReview
         );
         Ok(())
     }
 
     #[test]
-    fn account_deposit_with_huge_numbers_fails_and_is_not_recorded() -> Result<(), TransactorError>
+    fn closed_accounts_take_no_more_transactions() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        assert_eq!(
+            bank.close_account(client)?,
+            Outcome::Ignored(IgnoreReason::UnknownAccount)
+        );
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(3, 0)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(1))?;
+        assert_eq!(
+            bank.close_account(client)?,
+            Outcome::Ignored(IgnoreReason::FundsHeld)
+        );
+        bank.resolve_disputed_transaction(client, TransactionId(1))?;
+        assert_eq!(bank.account_mut(client).status(), AccountStatus::Active);
+
+        assert_eq!(bank.close_account(client)?, Outcome::Applied);
+        assert_eq!(bank.account_mut(client).status(), AccountStatus::Closed);
+        assert!(bank.account_mut(client).is_locked());
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(2), Decimal::new(1, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::AccountClosed)
+        );
+        assert_eq!(
+            bank.unlock(client)?,
+            Outcome::Ignored(IgnoreReason::AccountClosed)
+        );
+        assert_eq!(
+            bank.close_account(client)?,
+            Outcome::Ignored(IgnoreReason::AccountClosed)
+        );
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(3, 0));
+
+        bank.freeze(ClientId(2), None)?;
+        assert_eq!(
+            bank.account_mut(ClientId(2)).status(),
+            AccountStatus::Frozen
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn each_status_accepts_the_operations_its_rule_lists() -> Result<(), TransactorError> {
+        let rule: StatusRule = "frozen=deposit, dispute".parse().map_err(InvalidConfig)?;
+        assert_eq!(rule.accepted, vec![Operation::Deposit, Operation::Dispute]);
+        let mut bank = Bank::new()
+            .with_status_rules(StatusRules::default().with_rule(rule))
+            .with_risk_limits(RiskLimits {
+                max_chargebacks: Some(0),
+                max_open_disputes: None,
+            });
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        bank.freeze(client, None)?;
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(2), Decimal::new(2, 0))
+            )?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(3), Decimal::new(-1, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+        assert_eq!(
+            bank.dispute_transaction(client, TransactionId(1))?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            bank.resolve_disputed_transaction(client, TransactionId(1))?,
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+
+        // Going over a risk limit puts the account under review, which keeps the default rules
+        let reviewed = ClientId(2);
+        bank.transact(
+            reviewed,
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        bank.dispute_transaction(reviewed, TransactionId(1))?;
+        bank.chargeback(reviewed, TransactionId(1))?;
+        assert_eq!(
+            bank.account_mut(reviewed).status(),
+            AccountStatus::UnderReview
+        );
+        assert_eq!(
+            bank.unfreeze(reviewed)?,
+            Outcome::Ignored(IgnoreReason::NotFrozen)
+        );
+        assert_eq!(bank.unlock(reviewed)?, Outcome::Applied);
+        assert_eq!(bank.account_mut(reviewed).status(), AccountStatus::Active);
+
+        assert!("closed=".parse::<StatusRule>().is_ok());
+        assert!("frozen=refund"
+            .parse::<StatusRule>()
+            .is_err_and(|e| e.starts_with("Unknown operation refund")));
+        assert!("asleep=deposit"
+            .parse::<StatusRule>()
+            .is_err_and(|e| e.starts_with("Unknown account status asleep")));
+        Ok(())
+    }
+
+    #[test]
+    fn locked_accounts_can_take_deposits_to_recover_a_negative_balance(
+    ) -> Result<(), TransactorError> {
+        let mut bank =
+            Bank::new().with_locked_account_policy(LockedAccountPolicy::BlockWithdrawalsOnly);
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(-5, 0)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.chargeback(client, TransactionId(1))?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(-5, 0));
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(3), Decimal::new(5, 0))
+            )?,
+            Outcome::Applied
+        );
+        assert_eq!(bank.account_mut(client).available(), Decimal::zero());
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(4), Decimal::new(-1, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+        assert!(bank.account_mut(client).is_locked());
+
+        let closed = ClientId(2);
+        bank.transact(
+            closed,
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+        )?;
+        bank.close_account(closed)?;
+        assert_eq!(
+            bank.transact(
+                closed,
+                Transaction::new(TransactionId(2), Decimal::new(1, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::AccountClosed)
+        );
+        assert!("block-deposits".parse::<LockedAccountPolicy>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn debts_left_by_chargebacks_are_recovered_from_deposits() -> Result<(), TransactorError> {
+        let charged_back = |recovery_policy: RecoveryPolicy| -> Result<Bank, TransactorError> {
+            let mut bank = Bank::new()
+                .with_locked_account_policy(LockedAccountPolicy::BlockWithdrawalsOnly)
+                .with_recovery_policy(recovery_policy);
+            let client = ClientId(1);
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+            )?;
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(2), Decimal::new(-4, 0)),
+            )?;
+            bank.dispute_transaction(client, TransactionId(1))?;
+            bank.chargeback(client, TransactionId(1))?;
+            assert_eq!(bank.account_mut(client).recovery(), Decimal::new(4, 0));
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(3), Decimal::new(3, 0)),
+            )?;
+            Ok(bank)
+        };
+        let client = ClientId(1);
+        let mut bank = charged_back(RecoveryPolicy::DepositsFirst)?;
+        assert_eq!(
+            bank.outstanding_recoveries(),
+            vec![Recovery {
+                client_id: client,
+                outstanding: Decimal::new(1, 0),
+                recovered: Decimal::new(3, 0),
+                currency: None,
+            }]
+        );
+        bank.represent(client, TransactionId(1))?;
+        assert_eq!(bank.account_mut(client).recovered(), Decimal::new(4, 0));
+        assert!(bank.outstanding_recoveries().is_empty());
+
+        let mut tracked = charged_back(RecoveryPolicy::TrackOnly)?;
+        assert_eq!(tracked.account_mut(client).recovery(), Decimal::new(4, 0));
+        assert_eq!(tracked.account_mut(client).recovered(), Decimal::zero());
+        Ok(())
+    }
+
+    #[test]
+    fn unlock_of_unlocked_account_is_ignored() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.unlock(client)?;
+        assert!(!bank.account_mut(client).is_locked());
+        assert_eq!(bank.account_mut(client).version(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn transactions_are_listed_in_application_order() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let deposit = Transaction::new(TransactionId(3), Decimal::new(2, 0));
+        let withdrawal = Transaction::new(TransactionId(1), Decimal::new(-1, 0));
+        let rejected_withdrawal = Transaction::new(TransactionId(2), Decimal::new(-5, 0));
+        bank.transact(client, deposit)?;
+        bank.transact(client, withdrawal)?;
+        bank.transact(client, rejected_withdrawal)?;
+        bank.dispute_transaction(client, TransactionId(3))?;
+
+        let account = bank.get_account(client).expect("client 1 has transacted");
+        let transactions: Vec<_> = account.transactions().collect();
+        assert_eq!(
+            transactions,
+            vec![
+                (deposit, TransactionState::Disputed),
+                (withdrawal, TransactionState::Posted)
+            ]
+        );
+        assert_eq!(account.transaction_count(), 2);
+        let page: Vec<_> = account.transactions_page(1, 5).collect();
+        assert_eq!(page, vec![(withdrawal, TransactionState::Posted)]);
+        assert_eq!(account.transactions_page(2, 5).count(), 0);
+        assert!(bank.get_account(ClientId(2)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_in_different_currency_to_account_fails() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        let usd: Currency = "USD".parse().unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)).with_currency(usd),
+        )?;
+        assert_eq!(bank.account_mut(client).currency(), Some(usd));
+
+        assert!(matches!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(2), Decimal::new(1, 0)).with_currency(eur)
+            ),
+            Err(CurrencyMismatch { .. })
+        ));
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(3), Decimal::new(1, 0)),
+        )?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(2, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn spilled_transactions_can_still_be_disputed_and_are_not_reused() -> Result<(), TransactorError>
+    {
+        let dir = std::env::temp_dir().join("transactor-spill-test");
+        let mut bank = Bank::new().with_spill(&dir, 1)?;
+        let client = ClientId(1);
+        let amount = Decimal::new(15, 1);
+        let spilled_id = TransactionId(1);
+        bank.transact(client, Transaction::new(spilled_id, amount))?;
+        bank.transact(client, Transaction::new(TransactionId(2), amount))?;
+        assert!(!bank
+            .account_mut(client)
+            .transaction_history
+            .contains_key(&spilled_id));
+        assert_eq!(
+            bank.transaction_state(client, spilled_id),
+            Some(TransactionState::Posted)
+        );
+
+        assert!(matches!(
+            bank.transact(client, Transaction::new(spilled_id, amount)),
+            Err(TransactionIdReuse)
+        ));
+        bank.dispute_transaction(client, spilled_id)?;
+        assert_eq!(bank.account_mut(client).held(), amount);
+        assert_eq!(
+            bank.transaction_state(client, spilled_id),
+            Some(TransactionState::Disputed)
+        );
+        bank.resolve_disputed_transaction(client, spilled_id)?;
+        assert_eq!(bank.account_mut(client).held(), Decimal::zero());
+        assert_eq!(
+            bank.account_mut(client).available(),
+            amount * Decimal::new(2, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn spilled_transactions_of_clients_sharing_an_id_are_kept_apart() -> Result<(), TransactorError>
     {
+        let dir = std::env::temp_dir().join("transactor-spill-shared-id-test");
+        let mut bank = Bank::new().with_spill(&dir, 1)?;
+        let shared = TransactionId(5);
+        bank.transact(ClientId(1), Transaction::new(shared, Decimal::new(10, 0)))?;
+        bank.transact(ClientId(2), Transaction::new(shared, Decimal::new(20, 0)))?;
+        for id in 6..=7 {
+            bank.transact(
+                ClientId(3),
+                Transaction::new(TransactionId(id), Decimal::new(1, 0)),
+            )?;
+        }
+        bank.dispute_transaction(ClientId(1), shared)?;
+        bank.dispute_transaction(ClientId(2), shared)?;
+        for (client, amount) in [(1, 10), (2, 20)] {
+            let account = bank.get_account(ClientId(client)).unwrap();
+            assert_eq!(account.held(), Decimal::new(amount, 0));
+            assert_eq!(account.available(), Decimal::zero());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_keep_their_reason_through_a_spill() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
         let client = ClientId(1);
-        let max_decimal = Decimal::max_value();
-        let transaction_id1 = TransactionId(1);
-        let transaction_id2 = TransactionId(2);
-        bank.transact(client, Transaction::new(transaction_id1, max_decimal))?;
-        assert!(bank
-            .transact(client, Transaction::new(transaction_id2, max_decimal))
-            .is_err());
-        assert_eq!(bank.account(client).transaction_history.len(), 1);
+        for id in 1..=2 {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(id), Decimal::new(5, 0)),
+            )?;
+        }
+        let fraud: ReasonCode = "fraud".parse()?;
+        let not_received: ReasonCode = "not-received".parse()?;
+        bank.dispute_with_reason(client, TransactionId(1), Some(fraud))?;
+        bank.dispute_with_reason(client, TransactionId(2), Some(fraud))?;
+        bank.chargeback_with_reason(client, TransactionId(2), Some(not_received))?;
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(account.dispute_reason(TransactionId(1)), Some(fraud));
+        assert_eq!(account.dispute_reason(TransactionId(2)), Some(not_received));
+
+        let mut store =
+            spill::SpillStore::create(&std::env::temp_dir().join("transactor-reason-test"))?;
+        let recorded = account.transaction_history[&TransactionId(2)];
+        store.write(client, &recorded)?;
+        assert_eq!(store.read(client, TransactionId(2))?, Some(recorded));
+        Ok(())
+    }
+
+    #[test]
+    fn find_transactions_filters_on_every_criterion() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let first = ClientId(1);
+        let second = ClientId(2);
+        let small_deposit =
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)).with_timestamp(Timestamp(100));
+        let large_deposit =
+            Transaction::new(TransactionId(2), Decimal::new(10, 0)).with_timestamp(Timestamp(200));
+        let withdrawal =
+            Transaction::new(TransactionId(3), Decimal::new(-5, 0)).with_timestamp(Timestamp(300));
+        let other_client = Transaction::new(TransactionId(4), Decimal::new(10, 0));
+        bank.transact(first, small_deposit)?;
+        bank.transact(first, large_deposit)?;
+        bank.transact(first, withdrawal)?;
+        bank.transact(second, other_client)?;
+        bank.dispute_transaction(first, TransactionId(2))?;
+
+        let found = |filter: TransactionFilter| -> Vec<u32> {
+            bank.find_transactions(&filter)
+                .expect("history is held in memory")
+                .iter()
+                .map(|found| found.transaction.transaction_id().0)
+                .collect()
+        };
+        assert_eq!(found(TransactionFilter::default()), vec![1, 2, 3, 4]);
+        assert_eq!(
+            found(TransactionFilter {
+                client: Some(first),
+                min_amount: Some(Decimal::new(5, 0)),
+                ..Default::default()
+            }),
+            vec![2, 3]
+        );
+        assert_eq!(
+            found(TransactionFilter {
+                kind: Some(TransactionKind::Withdrawal),
+                ..Default::default()
+            }),
+            vec![3]
+        );
+        assert_eq!(
+            found(TransactionFilter {
+                max_amount: Some(Decimal::new(5, 0)),
+                state: Some(TransactionState::Posted),
+                ..Default::default()
+            }),
+            vec![1, 3]
+        );
+        assert_eq!(
+            found(TransactionFilter {
+                state: Some(TransactionState::Disputed),
+                ..Default::default()
+            }),
+            vec![2]
+        );
+        // The period includes its start but not its end, and never transactions without a time
+        assert_eq!(
+            found(TransactionFilter {
+                from: Some(Timestamp(200)),
+                to: Some(Timestamp(300)),
+                ..Default::default()
+            }),
+            vec![2]
+        );
+        assert_eq!(
+            found(TransactionFilter {
+                from: Some(Timestamp(150)),
+                ..Default::default()
+            }),
+            vec![2, 3]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn searches_find_transactions_spilled_to_disk() -> Result<(), TransactorError> {
+        let dir = std::env::temp_dir().join("transactor-spill-search-test");
+        let mut bank = Bank::new().with_spill(&dir, 1)?;
+        for id in 1..=3 {
+            bank.transact(
+                ClientId(1),
+                Transaction::new(TransactionId(id), Decimal::new(i64::from(id), 0))
+                    .with_timestamp(Timestamp(u64::from(id) * 100)),
+            )?;
+        }
+        bank.transact(
+            ClientId(2),
+            Transaction::new(TransactionId(1), Decimal::new(4, 0)),
+        )?;
+        let found = |filter: TransactionFilter| -> Result<Vec<(u16, u32)>, TransactorError> {
+            Ok(bank
+                .find_transactions(&filter)?
+                .iter()
+                .map(|found| (found.client_id.0, found.transaction.transaction_id().0))
+                .collect())
+        };
+        assert_eq!(
+            found(TransactionFilter::default())?,
+            vec![(1, 1), (1, 2), (1, 3), (2, 1)]
+        );
+        assert_eq!(
+            found(TransactionFilter {
+                client: Some(ClientId(1)),
+                state: Some(TransactionState::Posted),
+                to: Some(Timestamp(300)),
+                ..Default::default()
+            })?,
+            vec![(1, 1), (1, 2)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_owner_with_and_without_index() -> Result<(), TransactorError> {
+        for mut bank in [Bank::new(), Bank::new().with_transaction_index()] {
+            bank.transact(
+                ClientId(1),
+                Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+            )?;
+            bank.transact(
+                ClientId(2),
+                Transaction::new(TransactionId(2), Decimal::new(1, 0)),
+            )?;
+            bank.transact(
+                ClientId(2),
+                Transaction::new(TransactionId(3), Decimal::new(-5, 0)),
+            )?;
+            assert_eq!(bank.find_owner(TransactionId(1)), Some(ClientId(1)));
+            assert_eq!(bank.find_owner(TransactionId(2)), Some(ClientId(2)));
+            assert_eq!(bank.find_owner(TransactionId(3)), None);
+
+            // Both clients own an id they have each used
+            bank.transact(
+                ClientId(2),
+                Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+            )?;
+            assert_eq!(
+                bank.find_owners(TransactionId(1)),
+                vec![ClientId(1), ClientId(2)]
+            );
+            assert_eq!(bank.find_owner(TransactionId(1)), Some(ClientId(1)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_of_other_clients_transaction_fails_with_index() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_transaction_index();
+        let owner = ClientId(1);
+        let other = ClientId(2);
+        let transaction_id = TransactionId(1);
+        bank.transact(owner, Transaction::new(transaction_id, Decimal::new(1, 0)))?;
+
+        bank.dispute_transaction(other, TransactionId(2))?;
+        assert!(matches!(
+            bank.dispute_transaction(other, transaction_id),
+            Err(ForeignTransaction { .. })
+        ));
+        assert!(matches!(
+            bank.chargeback(other, transaction_id),
+            Err(ForeignTransaction { .. })
+        ));
+        assert_eq!(
+            bank.transaction_state(owner, transaction_id),
+            Some(TransactionState::Posted)
+        );
         Ok(())
     }
 
     #[test]
-    fn deposit_to_locked_account_is_ignored_and_is_not_recorded() -> Result<(), TransactorError> {
+    fn disputes_of_an_id_other_clients_also_used_are_not_foreign() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_transaction_index();
+        let shared = TransactionId(5);
+        bank.transact(ClientId(1), Transaction::new(shared, Decimal::new(10, 0)))?;
+        bank.transact(ClientId(2), Transaction::new(shared, Decimal::new(20, 0)))?;
+
+        bank.dispute_transaction(ClientId(1), shared)?;
+        let account = bank.get_account(ClientId(1)).unwrap();
+        assert_eq!(account.available(), Decimal::zero());
+        assert_eq!(account.held(), Decimal::new(10, 0));
+        assert_eq!(
+            bank.transaction_state(ClientId(2), shared),
+            Some(TransactionState::Posted)
+        );
+        assert!(matches!(
+            bank.dispute_transaction(ClientId(3), shared),
+            Err(ForeignTransaction {
+                owner: ClientId(1),
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_id_reuse_across_clients_fails_only_with_global_ids(
+    ) -> Result<(), TransactorError> {
+        let transaction = Transaction::new(TransactionId(1), Decimal::new(1, 0));
+        let mut bank = Bank::new();
+        bank.transact(ClientId(1), transaction)?;
+        bank.transact(ClientId(2), transaction)?;
+
+        let mut bank = Bank::new().with_global_transaction_ids();
+        bank.transact(ClientId(1), transaction)?;
+        assert!(matches!(
+            bank.transact(ClientId(2), transaction),
+            Err(TransactionIdReuse)
+        ));
+        assert!(bank.account_mut(ClientId(2)).transaction_history.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn close_period_archives_history_but_keeps_balances_and_open_disputes(
+    ) -> Result<(), TransactorError> {
+        let fee: FeeRule = "withdrawal=1".parse().map_err(InvalidConfig)?;
+        let mut bank = Bank::new().with_fee_schedule(FeeSchedule::default().with_rule(fee));
+        let client = ClientId(1);
+        let deposit = Transaction::new(TransactionId(1), Decimal::new(5, 0));
+        let disputed = Transaction::new(TransactionId(2), Decimal::new(2, 0));
+        bank.transact(client, deposit)?;
+        bank.transact(client, disputed)?;
+        bank.dispute_transaction(client, TransactionId(2))?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(3), Decimal::new(1, 0)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(4), Decimal::new(-1, 0)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(3))?;
+        bank.chargeback(client, TransactionId(3))?;
+        let account = bank.account_mut(client);
+        assert_eq!(account.total_fees()?, Decimal::new(1, 0));
+        assert_eq!((account.chargebacks(), account.open_disputes()), (1, 1));
+
+        let archived = bank.close_period();
+        assert_eq!(archived.len(), 3);
+        assert_eq!(
+            archived[0],
+            TransactionMatch {
+                client_id: client,
+                transaction: deposit,
+                state: TransactionState::Posted
+            }
+        );
+        let account = bank.account_mut(client);
+        assert_eq!(account.available(), Decimal::new(3, 0));
+        assert_eq!(account.held(), Decimal::new(2, 0));
+        assert_eq!(account.total_fees()?, Decimal::zero());
+        assert_eq!((account.chargebacks(), account.open_disputes()), (0, 1));
+        assert_eq!(bank.transaction_state(client, TransactionId(1)), None);
+
+        bank.resolve_disputed_transaction(client, TransactionId(2))?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(5, 0));
+        assert_eq!(bank.account_mut(client).open_disputes(), 0);
+        assert_eq!(bank.close_period().len(), 1);
+        assert_eq!(bank.account_mut(client).transactions().count(), 0);
+        Ok(())
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl BankObserver for RecordingObserver {
+        fn transaction_applied(&mut self, account: &Account, transaction: &Transaction) {
+            self.record(format!(
+                "applied {} {}",
+                account.client_id.0, transaction.transaction_id.0
+            ));
+        }
+
+        fn withdrawal_rejected(&mut self, account: &Account, transaction: &Transaction) {
+            self.record(format!(
+                "rejected {} {}",
+                account.client_id.0, transaction.transaction_id.0
+            ));
+        }
+
+        fn chargeback_applied(&mut self, account: &Account, transaction_id: TransactionId) {
+            self.record(format!(
+                "chargeback {} {}",
+                account.client_id.0, transaction_id.0
+            ));
+        }
+
+        fn account_locked(&mut self, account: &Account) {
+            self.record(format!("locked {}", account.client_id.0));
+        }
+    }
+
+    impl RecordingObserver {
+        fn record(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn opened_accounts_start_from_their_balances() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
         let client = ClientId(1);
-        let transaction_id = TransactionId(1);
-        bank.account(client).locked = true;
-        bank.transact(client, Transaction::new(transaction_id, Decimal::new(1, 1)))?;
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert!(bank.account(client).transaction_history.is_empty());
+        bank.open_account(client, Decimal::new(10, 0), Decimal::new(2, 0), false, None)?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(-4, 0)),
+        )?;
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(account.available(), Decimal::new(6, 0));
+        assert_eq!(account.held(), Decimal::new(2, 0));
+        assert_eq!(account.view().total, Some(Decimal::new(8, 0)));
+        assert!(matches!(
+            Account::with_balances(
+                ClientId(3),
+                Decimal::max_value(),
+                Decimal::max_value(),
+                false
+            ),
+            Err(Overflow)
+        ));
+
+        bank.open_account(ClientId(2), Decimal::zero(), Decimal::zero(), true, None)?;
+        assert_eq!(
+            bank.transact(
+                ClientId(2),
+                Transaction::new(TransactionId(2), Decimal::new(1, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+        assert!(matches!(
+            bank.open_account(client, Decimal::zero(), Decimal::zero(), false, None),
+            Err(AccountExists {
+                client: ClientId(1)
+            })
+        ));
         Ok(())
     }
 
     #[test]
-    fn dispute_transaction_ignored_if_transaction_does_not_exist() -> Result<(), TransactorError> {
+    fn saved_state_restores_balances_and_history() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
         let client = ClientId(1);
+        bank.set_batch(Some(BatchId::new("day-1")));
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(-2, 0)),
+        )?;
         bank.dispute_transaction(client, TransactionId(1))?;
+        bank.freeze(ClientId(2), Some("aml".parse()?))?;
+        let mut saved = Vec::new();
+        bank.save_state(&mut saved)?;
+
+        let mut restored = Bank::new().with_transaction_index();
+        assert_eq!(restored.restore(&saved[..])?, None);
+        let account = restored.get_account(client).unwrap();
+        assert_eq!(account.held(), Decimal::new(5, 0));
+        assert_eq!(account.available(), Decimal::new(-2, 0));
+        assert_eq!(account.version(), 3);
+        assert_eq!(account.last_batch(), Some(&BatchId::new("day-1")));
+        assert_eq!(
+            account.dispute_history(TransactionId(1)),
+            bank.get_account(client)
+                .unwrap()
+                .dispute_history(TransactionId(1))
+        );
+        assert!(restored.get_account(ClientId(2)).unwrap().is_frozen());
+        assert_eq!(restored.find_owner(TransactionId(2)), Some(client));
+        assert_eq!(
+            restored.resolve_disputed_transaction(client, TransactionId(1))?,
+            Outcome::Applied
+        );
+        assert!(matches!(
+            restored.restore(&saved[..]),
+            Err(AccountExists { .. })
+        ));
 
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert_eq!(bank.account(client).held, Decimal::zero());
-        assert!(bank.account(client).disputed_transactions.is_empty());
+        let checkpoint = InputCheckpoint {
+            input: "day-1.csv".to_string(),
+            line: 4,
+            offset: 52,
+        };
+        let mut saved = Vec::new();
+        bank.save_checkpoint(&mut saved, &checkpoint)?;
+        assert_eq!(Bank::new().restore(&saved[..])?, Some(checkpoint));
         Ok(())
     }
 
     #[test]
-    fn dispute_positive_transaction_moves_funds_into_held() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
-        let client = ClientId(1);
-        let disputed_amount = Decimal::new(1, 1);
-        let transaction_id = TransactionId(1);
-        let transaction = Transaction::new(transaction_id, disputed_amount);
+    fn state_hash_ignores_scale_batches_and_order_of_clients() -> Result<(), TransactorError> {
+        let mut first = Bank::new();
+        first.set_batch(Some(BatchId::new("a.csv")));
+        first.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(50, 1)),
+        )?;
+        first.transact(
+            ClientId(2),
+            Transaction::new(TransactionId(2), Decimal::new(1, 0)),
+        )?;
+        let mut second = Bank::new();
+        second.set_batch(Some(BatchId::new("b.csv")));
+        second.transact(
+            ClientId(2),
+            Transaction::new(TransactionId(2), Decimal::new(100, 2)),
+        )?;
+        second.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        assert_eq!(first.state_hash()?, second.state_hash()?);
+        assert_eq!(first.state_hash()?.len(), 64);
 
-        bank.transact(client, transaction.clone())?;
-        bank.dispute_transaction(client, transaction_id)?;
+        second.freeze(ClientId(2), None)?;
+        assert_ne!(first.state_hash()?, second.state_hash()?);
+        Ok(())
+    }
 
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert_eq!(bank.account(client).held, disputed_amount);
+    #[test]
+    fn merging_takes_over_accounts_unless_they_conflict() -> Result<(), TransactorError> {
+        let mut first = Bank::new().with_transaction_index();
+        first.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        let mut second = Bank::new();
+        second.transact(
+            ClientId(2),
+            Transaction::new(TransactionId(2), Decimal::new(3, 0)),
+        )?;
+        second.dispute_transaction(ClientId(2), TransactionId(2))?;
+        first.merge(second)?;
+        assert_eq!(first.find_owner(TransactionId(2)), Some(ClientId(2)));
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            transaction
+            first.resolve_disputed_transaction(ClientId(2), TransactionId(2))?,
+            Outcome::Applied
         );
-        assert!(bank
-            .account(client)
-            .disputed_transactions
-            .contains(&transaction_id));
+
+        let mut same_client = Bank::new();
+        same_client.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(9), Decimal::new(1, 0)),
+        )?;
+        assert!(matches!(
+            first.merge(same_client),
+            Err(AccountExists { .. })
+        ));
+        let mut same_transaction = Bank::new();
+        same_transaction.transact(
+            ClientId(3),
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+        )?;
+        assert!(matches!(
+            first.merge(same_transaction),
+            Err(DuplicateTransaction { .. })
+        ));
+        assert!(first.get_account(ClientId(3)).is_none());
         Ok(())
     }
 
     #[test]
-    fn dispute_negative_transaction_moves_funds_into_held() -> Result<(), TransactorError> {
+    fn authorizations_hold_funds_until_captured_voided_or_expired() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
         let client = ClientId(1);
-        let disputed_amount = Decimal::new(1, 1);
-        bank.account(client).available = disputed_amount;
-        let transaction_id = TransactionId(1);
-        let transaction = Transaction::new(transaction_id, -disputed_amount);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 0)),
+        )?;
+        let authorize = |id, amount, at| {
+            Transaction::new(TransactionId(id), Decimal::new(amount, 0))
+                .with_timestamp(Timestamp(at))
+        };
+        assert_eq!(
+            bank.authorize(client, authorize(2, 4, 100))?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            bank.authorize(client, authorize(3, 3, 200))?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            bank.authorize(client, authorize(4, 3, 300))?,
+            Outcome::Applied
+        );
+        assert_eq!(
+            bank.authorize(client, authorize(5, 1, 300))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert!(matches!(
+            bank.authorize(client, authorize(2, 1, 300)),
+            Err(TransactionIdReuse)
+        ));
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (Decimal::zero(), Decimal::new(10, 0))
+        );
 
-        bank.transact(client, transaction.clone())?;
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        bank.dispute_transaction(client, transaction_id)?;
+        assert_eq!(bank.capture(client, TransactionId(2))?, Outcome::Applied);
+        assert_eq!(bank.void(client, TransactionId(3))?, Outcome::Applied);
+        assert_eq!(
+            bank.capture(client, TransactionId(3))?,
+            Outcome::Ignored(IgnoreReason::NotAuthorized)
+        );
+        assert_eq!(
+            bank.expire_authorizations(Timestamp(300))?,
+            Vec::<(ClientId, TransactionId)>::new()
+        );
+        assert_eq!(
+            bank.expire_authorizations(Timestamp(301))?,
+            vec![(client, TransactionId(4))]
+        );
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (Decimal::new(6, 0), Decimal::zero())
+        );
+        assert_eq!(
+            bank.transaction(client, TransactionId(2))
+                .map(|captured| captured.amount()),
+            Some(Decimal::new(-4, 0))
+        );
 
-        assert_eq!(bank.account(client).available, -disputed_amount);
-        assert_eq!(bank.account(client).held, disputed_amount);
+        // Authorizations the status rules refuse to void are not reported as expired
+        let rule: StatusRule = "frozen=".parse().map_err(InvalidConfig)?;
+        let mut bank = Bank::new().with_status_rules(StatusRules::default().with_rule(rule));
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 0)),
+        )?;
+        bank.authorize(client, authorize(2, 4, 100))?;
+        bank.freeze(client, None)?;
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            transaction
+            bank.expire_authorizations(Timestamp(101))?,
+            Vec::<(ClientId, TransactionId)>::new()
+        );
+        bank.unfreeze(client)?;
+        assert_eq!(
+            bank.expire_authorizations(Timestamp(101))?,
+            vec![(client, TransactionId(2))]
         );
-        assert!(bank
-            .account(client)
-            .disputed_transactions
-            .contains(&transaction_id));
         Ok(())
     }
 
     #[test]
-    fn dispute_transaction_fails_if_causes_overflow_in_held_and_dispute_is_not_recorded(
-    ) -> Result<(), TransactorError> {
+    fn administrative_holds_are_only_released_by_a_release() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
         let client = ClientId(1);
-        let max_value = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let transaction = Transaction::new(transaction_id, max_value);
-        bank.account(client).held = max_value;
-
-        bank.transact(client, transaction.clone())?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 0)),
+        )?;
+        let hold = |id, amount| Transaction::new(TransactionId(id), Decimal::new(amount, 0));
+        assert_eq!(bank.hold(client, hold(2, 6))?, Outcome::Applied);
+        assert_eq!(
+            bank.hold(client, hold(3, 5))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.resolve_disputed_transaction(client, TransactionId(1))?;
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (Decimal::new(4, 0), Decimal::new(6, 0))
+        );
+        assert_eq!(account.held_administratively()?, Decimal::new(6, 0));
 
-        assert!(bank.dispute_transaction(client, transaction_id).is_err());
-        assert_eq!(bank.account(client).available, max_value);
-        assert_eq!(bank.account(client).held, max_value);
+        // Frozen accounts still have holds placed and released
+        bank.freeze(client, None)?;
+        assert_eq!(bank.release(client, TransactionId(2))?, Outcome::Applied);
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            transaction
+            bank.release(client, TransactionId(2))?,
+            Outcome::Ignored(IgnoreReason::NotHeld)
+        );
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (Decimal::new(10, 0), Decimal::zero())
         );
-        assert!(bank.account(client).disputed_transactions.is_empty());
+        bank.check_invariants()?;
         Ok(())
     }
 
     #[test]
-    fn dispute_transaction_fails_if_causes_overflow_in_available_and_dispute_is_not_recorded(
-    ) -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
+    fn recorded_history_is_disputable_without_changing_balances() -> Result<(), TransactorError> {
         let client = ClientId(1);
-        let max_value = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let huge_deposit = Transaction::new(transaction_id, max_value);
-
-        bank.transact(client, huge_deposit.clone())?;
-        bank.account(client).available = -max_value;
-        assert!(bank.dispute_transaction(client, transaction_id).is_err());
+        let mut bank = Bank::new();
+        let earlier = Transaction::new(TransactionId(1), Decimal::new(10, 0));
+        assert!(bank.record_history(client, earlier)?);
+        assert!(!bank.record_history(client, earlier)?);
+        assert_eq!(bank.get_account(client).unwrap().total()?, Decimal::zero());
 
-        assert_eq!(bank.account(client).available, -max_value);
-        assert_eq!(bank.account(client).held, Decimal::zero());
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            huge_deposit
+            bank.dispute_transaction(client, TransactionId(1))?,
+            Outcome::Applied
+        );
+        let account = bank.get_account(client).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (Decimal::new(-10, 0), Decimal::new(10, 0))
         );
-        assert!(bank.account(client).disputed_transactions.is_empty());
+        assert!(matches!(
+            bank.transact(client, earlier),
+            Err(TransactionIdReuse)
+        ));
         Ok(())
     }
 
     #[test]
-    fn resolve_dispute_fails_if_causes_overflow_in_available_and_dispute_is_not_resolved(
-    ) -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
-        let client = ClientId(1);
-        let max_value = Decimal::max_value();
-        let transaction_id1 = TransactionId(1);
-        let huge_deposit = Transaction::new(transaction_id1, max_value);
-        let transaction_id2 = TransactionId(2);
-        let huge_deposit2 = Transaction::new(transaction_id2, max_value);
-
-        bank.transact(client, huge_deposit.clone())?;
-        bank.dispute_transaction(client, transaction_id1)?;
-        bank.transact(client, huge_deposit2.clone())?;
-
-        assert!(bank
-            .resolve_disputed_transaction(client, transaction_id1)
-            .is_err());
-        assert_eq!(bank.account(client).available, max_value);
-        assert_eq!(bank.account(client).held, max_value);
+    fn withdrawals_may_go_down_to_the_overdraft_limit() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_overdraft_policy(
+            OverdraftPolicy::new(Decimal::new(5, 0)).with_limit(ClientId(2), Decimal::zero()),
+        );
+        let withdraw =
+            |id, amount: i64| Transaction::new(TransactionId(id), Decimal::new(-amount, 0));
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id1)
-                .unwrap(),
-            huge_deposit
+            bank.transact(ClientId(1), withdraw(1, 5))?,
+            Outcome::Applied
         );
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id2)
-                .unwrap(),
-            huge_deposit2
+            bank.transact(ClientId(1), withdraw(2, 1))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            bank.get_account(ClientId(1)).unwrap().available(),
+            Decimal::new(-5, 0)
+        );
+        assert_eq!(
+            bank.transact(ClientId(2), withdraw(3, 1))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
         );
-        assert!(bank
-            .account(client)
-            .disputed_transactions
-            .contains(&transaction_id1));
         Ok(())
     }
 
     #[test]
-    fn resolve_dispute_ignores_if_transaction_does_not_exist() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
+    fn transactions_over_the_max_amount_fail() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_max_amount(Decimal::new(1000, 0));
         let client = ClientId(1);
-        bank.resolve_disputed_transaction(client, TransactionId(1))?;
-
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert_eq!(bank.account(client).held, Decimal::zero());
-        assert!(bank.account(client).disputed_transactions.is_empty());
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(1000, 0)),
+        )?;
+        let fat_finger = Transaction::new(TransactionId(2), Decimal::new(1_000_000_000_000, 0));
+        assert!(matches!(
+            bank.transact(client, fat_finger),
+            Err(AmountTooLarge { amount, limit, .. })
+                if amount == Decimal::new(1_000_000_000_000, 0) && limit == Decimal::new(1000, 0)
+        ));
+        let withdrawal = Transaction::new(TransactionId(3), Decimal::new(-1001, 0));
+        assert!(matches!(
+            bank.transact(client, withdrawal),
+            Err(AmountTooLarge { .. })
+        ));
+        let authorization = Transaction::new(TransactionId(4), Decimal::new(1001, 0));
+        assert!(matches!(
+            bank.authorize(ClientId(2), authorization),
+            Err(AmountTooLarge { .. })
+        ));
+        assert_eq!(
+            bank.get_account(client).unwrap().available(),
+            Decimal::new(1000, 0)
+        );
+        assert!(bank.get_account(ClientId(2)).is_none());
         Ok(())
     }
 
     #[test]
-    fn resolve_dispute_ignores_if_transaction_is_not_disputed() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
+    fn accounts_over_a_risk_limit_are_locked() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_risk_limits(RiskLimits {
+            max_chargebacks: None,
+            max_open_disputes: Some(1),
+        });
+        let events = bank.subscribe(16);
         let client = ClientId(1);
-        let amount = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let deposit = Transaction::new(transaction_id, amount);
-
-        bank.transact(client, deposit.clone())?;
-        bank.resolve_disputed_transaction(client, transaction_id)?;
-
-        assert_eq!(bank.account(client).available, amount);
-        assert_eq!(bank.account(client).held, Decimal::zero());
-        assert!(bank.account(client).disputed_transactions.is_empty());
+        for id in 1..=3 {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(id), Decimal::new(1, 0)),
+            )?;
+        }
+        bank.dispute_transaction(client, TransactionId(1))?;
+        assert!(!bank.get_account(client).unwrap().is_locked());
+        bank.dispute_transaction(client, TransactionId(2))?;
+        let account = bank.get_account(client).unwrap();
+        assert!(account.is_locked());
+        assert_eq!(account.open_disputes(), 2);
+        let exceeded: Vec<BankEvent> = std::iter::from_fn(|| events.try_recv())
+            .filter(|event| matches!(event, BankEvent::RiskLimitExceeded { .. }))
+            .collect();
+        assert_eq!(
+            exceeded,
+            vec![BankEvent::RiskLimitExceeded {
+                client,
+                batch: None,
+                limit: RiskLimit::OpenDisputes
+            }]
+        );
+        // Resolving does not lift the lock
+        bank.resolve_disputed_transaction(client, TransactionId(2))?;
+        assert!(bank.get_account(client).unwrap().is_locked());
+        assert_eq!(bank.get_account(client).unwrap().open_disputes(), 1);
         Ok(())
     }
 
     #[test]
-    fn resolve_dispute_correctly_resolves_disputed_withdrawal() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
+    fn refusals_can_be_errors() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_refusal_policy(RefusalPolicy::Error);
         let client = ClientId(1);
-        let amount = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let withdrawal = Transaction::new(transaction_id, -amount);
-
-        bank.account(client).available = amount;
-        bank.transact(client, withdrawal.clone())?;
-        bank.dispute_transaction(client, transaction_id.clone())?;
-        bank.resolve_disputed_transaction(client, transaction_id)?;
-
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert_eq!(bank.account(client).held, Decimal::zero());
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(3, 0)),
+        )?;
+        assert!(matches!(
+            bank.transact(client, Transaction::new(TransactionId(2), Decimal::new(-5, 0))),
+            Err(InsufficientFunds { tx: TransactionId(2), requested, available, .. })
+                if requested == Decimal::new(5, 0) && available == Decimal::new(3, 0)
+        ));
+        bank.freeze(client, None)?;
+        assert!(matches!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(3), Decimal::new(1, 0))
+            ),
+            Err(AccountLocked {
+                client: ClientId(1),
+                tx: TransactionId(3)
+            })
+        ));
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            withdrawal
+            bank.get_account(client).unwrap().available(),
+            Decimal::new(3, 0)
         );
-        assert!(bank.account(client).disputed_transactions.is_empty());
         Ok(())
     }
 
     #[test]
-    fn resolve_dispute_correctly_resolves_disputed_transaction() -> Result<(), TransactorError> {
+    fn frozen_accounts_are_locked_until_unfrozen() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
         let client = ClientId(1);
-        let amount = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let deposit = Transaction::new(transaction_id, amount);
-
-        bank.transact(client, deposit.clone())?;
-        bank.dispute_transaction(client, transaction_id.clone())?;
-        bank.resolve_disputed_transaction(client, transaction_id)?;
+        let reason: ReasonCode = "kyc-review".parse()?;
+        assert_eq!(bank.freeze(client, Some(reason))?, Outcome::Applied);
+        assert_eq!(
+            bank.get_account(client).unwrap().freeze_reason(),
+            Some(reason)
+        );
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(1), Decimal::new(1, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+        assert_eq!(bank.unfreeze(client)?, Outcome::Applied);
+        assert_eq!(
+            bank.unfreeze(client)?,
+            Outcome::Ignored(IgnoreReason::NotFrozen)
+        );
+        assert!(!bank.get_account(client).unwrap().is_locked());
 
-        assert_eq!(bank.account(client).available, amount);
-        assert_eq!(bank.account(client).held, Decimal::zero());
+        // Unfreezing does not lift the lock from a chargeback
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(1, 0)),
+        )?;
+        bank.freeze(client, None)?;
+        bank.dispute_transaction(client, TransactionId(2))?;
+        bank.chargeback(client, TransactionId(2))?;
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            deposit
+            bank.unfreeze(client)?,
+            Outcome::Ignored(IgnoreReason::NotFrozen)
         );
-        assert!(bank.account(client).disputed_transactions.is_empty());
+        assert!(bank.get_account(client).unwrap().is_locked());
+        assert!("not a code".parse::<ReasonCode>().is_err());
         Ok(())
     }
 
     #[test]
-    fn chargeback_correctly_pulls_back_disputed_transaction() -> Result<(), TransactorError> {
+    fn subscribers_receive_events_and_count_lag() -> Result<(), TransactorError> {
         let mut bank = Bank::new();
+        let behind = bank.subscribe(1);
+        let events = bank.subscribe(10);
         let client = ClientId(1);
-        let amount = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let deposit = Transaction::new(transaction_id, amount);
+        let (first, second) = (BatchId::new("day-1"), BatchId::new("day-2"));
+        bank.set_batch(Some(first.clone()));
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+        )?;
+        // A subscriber joining part way through a batch sees the rest of it as of that batch
+        let late = bank.subscribe(10);
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.set_batch(Some(second.clone()));
+        bank.chargeback(client, TransactionId(1))?;
+        bank.set_batch(None);
+        bank.unlock(client)?;
+        drop(bank);
 
-        bank.transact(client, deposit.clone())?;
-        bank.dispute_transaction(client, transaction_id.clone())?;
-        bank.chargeback(client, transaction_id)?;
+        assert_eq!(behind.lagged(), 4);
+        assert_eq!(
+            behind.recv(),
+            Some(BankEvent::AccountUnlocked {
+                client,
+                batch: None
+            })
+        );
+        assert_eq!(events.lagged(), 0);
+        let received: Vec<_> = events.collect();
+        assert_eq!(
+            received,
+            vec![
+                BankEvent::TransactionApplied {
+                    client,
+                    batch: Some(first.clone()),
+                    transaction: Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+                },
+                BankEvent::TransactionDisputed {
+                    client,
+                    batch: Some(first.clone()),
+                    transaction: TransactionId(1),
+                },
+                BankEvent::ChargebackApplied {
+                    client,
+                    batch: Some(second.clone()),
+                    transaction: TransactionId(1),
+                },
+                BankEvent::AccountLocked {
+                    client,
+                    batch: Some(second.clone()),
+                },
+                BankEvent::AccountUnlocked {
+                    client,
+                    batch: None
+                },
+            ]
+        );
+        assert_eq!(
+            late.recv(),
+            Some(BankEvent::TransactionDisputed {
+                client,
+                batch: Some(first),
+                transaction: TransactionId(1),
+            })
+        );
+        Ok(())
+    }
 
-        assert_eq!(bank.account(client).available, Decimal::zero());
-        assert_eq!(bank.account(client).held, Decimal::zero());
+    #[test]
+    fn observers_are_notified_of_engine_events() -> Result<(), TransactorError> {
+        let observer = RecordingObserver::default();
+        let mut bank = Bank::new().with_observer(observer.clone());
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 1)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(-20, 1)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.chargeback(client, TransactionId(1))?;
         assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            deposit
+            *observer.0.lock().unwrap(),
+            vec!["applied 1 1", "rejected 1 2", "chargeback 1 1", "locked 1"]
         );
-        assert!(bank.account(client).disputed_transactions.is_empty());
-        assert!(bank.account(client).locked);
         Ok(())
     }
 
     #[test]
-    fn chargeback_correctly_ignored_if_transaction_not_disputed() -> Result<(), TransactorError> {
-        let mut bank = Bank::new();
+    fn metrics_count_operations_by_outcome_and_locked_accounts() -> Result<(), TransactorError> {
+        let mut bank = Bank::new().with_metrics();
         let client = ClientId(1);
-        let amount = Decimal::max_value();
-        let transaction_id = TransactionId(1);
-        let deposit = Transaction::new(transaction_id, amount);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 1)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(-20, 1)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.chargeback(client, TransactionId(1))?;
+        let metrics = bank.metrics().unwrap();
+        assert_eq!(metrics.count(Operation::Deposit, "applied"), 1);
+        assert_eq!(metrics.count(Operation::Withdrawal, "ignored"), 1);
+        assert_eq!(metrics.count(Operation::Chargeback, "applied"), 1);
+        assert_eq!(metrics.locked_accounts(), 1);
+        assert!(metrics
+            .render_prometheus()
+            .contains("transactor_operations_total{type=\"deposit\",outcome=\"applied\"} 1"));
 
-        bank.transact(client, deposit.clone())?;
-        bank.chargeback(client, transaction_id)?;
+        bank.unlock(client)?;
+        assert_eq!(bank.metrics().unwrap().locked_accounts(), 0);
 
-        assert_eq!(bank.account(client).available, amount);
-        assert_eq!(bank.account(client).held, Decimal::zero());
-        assert_eq!(
-            *bank
-                .account(client)
-                .transaction_history
-                .get(&transaction_id)
-                .unwrap(),
-            deposit
-        );
-        assert!(bank.account(client).disputed_transactions.is_empty());
-        assert!(!bank.account(client).locked);
+        // Locked however they came to be
+        bank.open_account(ClientId(2), Decimal::zero(), Decimal::zero(), true, None)?;
+        assert_eq!(bank.metrics().unwrap().locked_accounts(), 1);
         Ok(())
     }
 }
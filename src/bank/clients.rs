@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::Deserialize;
+
+use super::{Bank, ClientId};
+use crate::error::{RecordPosition, TransactorError, TransactorError::*};
+
+/// What is known about a client outside of their accounts, carried through to reports.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientMetadata {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub risk_tier: Option<RiskTier>,
+}
+
+impl ClientMetadata {
+    pub fn field(&self, field: ClientField) -> Option<&str> {
+        match field {
+            ClientField::Name => self.name.as_deref(),
+            ClientField::Email => self.email.as_deref(),
+            ClientField::RiskTier => self.risk_tier.map(RiskTier::name),
+        }
+    }
+}
+
+/// How far a client is trusted, deciding the limits of `RuleEngine` tiers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum RiskTier {
+    Basic,
+    Verified,
+    Premium,
+}
+
+impl RiskTier {
+    pub fn name(self) -> &'static str {
+        match self {
+            RiskTier::Basic => "basic",
+            RiskTier::Verified => "verified",
+            RiskTier::Premium => "premium",
+        }
+    }
+}
+
+impl fmt::Display for RiskTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for RiskTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "basic" => Ok(RiskTier::Basic),
+            "verified" => Ok(RiskTier::Verified),
+            "premium" => Ok(RiskTier::Premium),
+            _ => Err(format!(
+                "Unknown risk tier {}, expected one of: basic, verified, premium",
+                s
+            )),
+        }
+    }
+}
+
+/// A piece of client metadata that can be shown on reports and statements.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClientField {
+    Name,
+    Email,
+    RiskTier,
+}
+
+impl ClientField {
+    const ALL: [ClientField; 3] = [ClientField::Name, ClientField::Email, ClientField::RiskTier];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClientField::Name => "name",
+            ClientField::Email => "email",
+            ClientField::RiskTier => "risk_tier",
+        }
+    }
+
+    /// How the field is titled on a rendered statement.
+    pub fn label(self) -> &'static str {
+        match self {
+            ClientField::Name => "Name",
+            ClientField::Email => "Email",
+            ClientField::RiskTier => "Risk tier",
+        }
+    }
+}
+
+impl fmt::Display for ClientField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ClientField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ClientField::ALL
+            .iter()
+            .copied()
+            .find(|field| field.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = ClientField::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "Unknown client field {}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The metadata of every client it lists, keyed by client id.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientDirectory {
+    clients: HashMap<ClientId, ClientMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientRecord {
+    client: u16,
+    name: Option<String>,
+    email: Option<String>,
+    risk_tier: Option<String>,
+}
+
+impl ClientDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_client(mut self, client_id: ClientId, metadata: ClientMetadata) -> Self {
+        self.clients.insert(client_id, metadata);
+        self
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Read csv of client,name,email,risk_tier, where every column but the client may be left out
+    /// or empty. Each client may only be listed once.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, TransactorError> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut row = StringRecord::new();
+        let mut directory = Self::new();
+        while reader.read_record(&mut row)? {
+            let mut position = RecordPosition::new(row.position().map_or(0, |p| p.line()));
+            let record: ClientRecord = row
+                .deserialize(Some(&headers))
+                .map_err(|e| TransactorError::from(e).at(position))?;
+            let client_id = ClientId(record.client);
+            position.client = Some(client_id);
+            if directory.clients.contains_key(&client_id) {
+                return Err(InvalidConfig(format!(
+                    "client {} is listed more than once",
+                    record.client
+                ))
+                .at(position));
+            }
+            let risk_tier = record
+                .risk_tier
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(|e| InvalidConfig(e).at(position))?;
+            let metadata = ClientMetadata {
+                name: record.name,
+                email: record.email,
+                risk_tier,
+            };
+            directory = directory.with_client(client_id, metadata);
+        }
+        Ok(directory)
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<&ClientMetadata> {
+        self.clients.get(&client_id)
+    }
+
+    pub fn risk_tier(&self, client_id: ClientId) -> Option<RiskTier> {
+        self.get(client_id)?.risk_tier
+    }
+
+    /// The field of a client, None if the client is not listed or the field was left empty.
+    pub fn field(&self, client_id: ClientId, field: ClientField) -> Option<&str> {
+        self.get(client_id)?.field(field)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+impl Bank {
+    /// Keep the metadata of clients for reports and the tiers of `RuleEngine`. It has no effect on
+    /// how the bank applies operations.
+    pub fn with_client_directory(mut self, clients: ClientDirectory) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    pub fn clients(&self) -> &ClientDirectory {
+        &self.clients
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clients_are_read_with_optional_fields() -> Result<(), TransactorError> {
+        let input = "client,name,email\n\
+            1, Ada Lovelace ,ada@example.com\n\
+            2,,grace@example.com\n";
+        let directory = ClientDirectory::from_reader(input.as_bytes())?;
+        assert_eq!(directory.len(), 2);
+        assert_eq!(
+            directory.field(ClientId(1), ClientField::Name),
+            Some("Ada Lovelace")
+        );
+        assert_eq!(directory.field(ClientId(2), ClientField::Name), None);
+        assert_eq!(directory.field(ClientId(2), ClientField::RiskTier), None);
+        assert!(directory.get(ClientId(3)).is_none());
+
+        let repeated = "client,risk_tier\n1,basic\n1,premium\n";
+        assert!(matches!(
+            ClientDirectory::from_reader(repeated.as_bytes()),
+            Err(Record { .. })
+        ));
+        let unknown = "client,risk_tier\n1,gold\n";
+        assert!(ClientDirectory::from_reader(unknown.as_bytes()).is_err());
+        assert!("phone".parse::<ClientField>().is_err());
+        Ok(())
+    }
+}
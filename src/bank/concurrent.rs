@@ -0,0 +1,584 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use dashmap::DashMap;
+use rust_decimal::prelude::*;
+
+use super::{
+    Account, AccountView, Bank, ClientId, DisputePolicy, FeeSchedule, LockedAccountPolicy, Outcome,
+    OverdraftPolicy, ReasonCode, RecoveryPolicy, RefusalPolicy, RepresentmentPolicy, RiskLimits,
+    StatusRules, Timestamp, Transaction, TransactionId, TransactionState, Versioned,
+};
+use crate::currency::{Currency, PrecisionTable};
+use crate::error::TransactorError;
+use crate::input::Command;
+
+/// A bank that can be shared between threads, for pipelines and servers applying transactions
+/// from several threads at once. Each client has a lock of its own, so operations on different
+/// clients run in parallel and only operations on the same client wait for each other.
+///
+/// Operations behave as they do on `Bank` with the same policies, since each client is kept in a
+/// `Bank` of its own. Transaction ids are unique within each account; observers, metrics, batches
+/// and spilling are only available on `Bank`, which `into_bank` turns this into once done.
+#[derive(Default)]
+pub struct ConcurrentBank {
+    clients: DashMap<ClientId, Arc<Mutex<Bank>>>,
+    dispute_policy: DisputePolicy,
+    refusal_policy: RefusalPolicy,
+    representment_policy: RepresentmentPolicy,
+    locked_account_policy: LockedAccountPolicy,
+    recovery_policy: RecoveryPolicy,
+    fee_schedule: FeeSchedule,
+    precision: PrecisionTable,
+    overdraft_policy: OverdraftPolicy,
+    risk_limits: RiskLimits,
+    status_rules: StatusRules,
+    max_amount: Option<Decimal>,
+    max_dispute_cycles: Option<u32>,
+}
+
+impl ConcurrentBank {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    pub fn with_refusal_policy(mut self, refusal_policy: RefusalPolicy) -> Self {
+        self.refusal_policy = refusal_policy;
+        self
+    }
+
+    pub fn with_representment_policy(mut self, representment_policy: RepresentmentPolicy) -> Self {
+        self.representment_policy = representment_policy;
+        self
+    }
+
+    pub fn with_locked_account_policy(
+        mut self,
+        locked_account_policy: LockedAccountPolicy,
+    ) -> Self {
+        self.locked_account_policy = locked_account_policy;
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    pub fn with_precision(mut self, precision: PrecisionTable) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn with_recovery_policy(mut self, recovery_policy: RecoveryPolicy) -> Self {
+        self.recovery_policy = recovery_policy;
+        self
+    }
+
+    pub fn with_overdraft_policy(mut self, overdraft_policy: OverdraftPolicy) -> Self {
+        self.overdraft_policy = overdraft_policy;
+        self
+    }
+
+    pub fn with_risk_limits(mut self, risk_limits: RiskLimits) -> Self {
+        self.risk_limits = risk_limits;
+        self
+    }
+
+    pub fn with_status_rules(mut self, status_rules: StatusRules) -> Self {
+        self.status_rules = status_rules;
+        self
+    }
+
+    pub fn with_max_amount(mut self, limit: Decimal) -> Self {
+        self.max_amount = Some(limit);
+        self
+    }
+
+    pub fn with_max_dispute_cycles(mut self, limit: u32) -> Self {
+        self.max_dispute_cycles = Some(limit);
+        self
+    }
+
+    /// A copy of a clients account as it is now.
+    pub fn get_account(&self, client_id: ClientId) -> Option<Account> {
+        self.read(client_id, |bank| bank.get_account(client_id).cloned())
+    }
+
+    /// The balances of a clients account as they are now, without copying its history.
+    pub fn account_view(&self, client_id: ClientId) -> Option<AccountView> {
+        self.read(client_id, |bank| {
+            bank.get_account(client_id).map(Account::view)
+        })
+    }
+
+    /// A copy of every account, ordered by client.
+    pub fn get_accounts(&self) -> Vec<Account> {
+        let mut client_ids: Vec<ClientId> = self.clients.iter().map(|entry| *entry.key()).collect();
+        client_ids.sort_by_key(|client_id| client_id.0);
+        client_ids
+            .into_iter()
+            .filter_map(|client_id| self.get_account(client_id))
+            .collect()
+    }
+
+    /// A page of a clients transactions in the order they were applied, see
+    /// `Account::transactions_page`. None if the client has never been seen.
+    pub fn transactions_page(
+        &self,
+        client_id: ClientId,
+        offset: usize,
+        limit: usize,
+    ) -> Option<Vec<(Transaction, TransactionState)>> {
+        self.read(client_id, |bank| {
+            bank.get_account(client_id)
+                .map(|account| account.transactions_page(offset, limit).collect())
+        })
+    }
+
+    pub fn account_version(&self, client_id: ClientId) -> Option<u64> {
+        self.read(client_id, |bank| bank.account_version(client_id))
+    }
+
+    pub fn transaction_state(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Option<TransactionState> {
+        self.read(client_id, |bank| {
+            bank.transaction_state(client_id, transaction_id)
+        })
+    }
+
+    /// See `Bank::open_account`.
+    pub fn open_account(
+        &self,
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactorError> {
+        self.apply(client_id, |bank| {
+            bank.open_account(client_id, available, held, locked, currency)
+        })
+    }
+
+    /// See `Bank::transact`.
+    pub fn transact(
+        &self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.transact(client_id, transaction))
+    }
+
+    /// See `Bank::dispute_transaction`.
+    pub fn dispute_transaction(
+        &self,
+        client_id: ClientId,
+        dispute: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| {
+            bank.dispute_transaction(client_id, dispute)
+        })
+    }
+
+    /// See `Bank::resolve_disputed_transaction`.
+    pub fn resolve_disputed_transaction(
+        &self,
+        client_id: ClientId,
+        dispute: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| {
+            bank.resolve_disputed_transaction(client_id, dispute)
+        })
+    }
+
+    /// See `Bank::chargeback`.
+    pub fn chargeback(
+        &self,
+        client_id: ClientId,
+        dispute: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.chargeback(client_id, dispute))
+    }
+
+    /// See `Bank::represent`.
+    pub fn represent(
+        &self,
+        client_id: ClientId,
+        charged_back: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.represent(client_id, charged_back))
+    }
+
+    /// See `Bank::authorize`.
+    pub fn authorize(
+        &self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.authorize(client_id, transaction))
+    }
+
+    /// See `Bank::capture`.
+    pub fn capture(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.capture(client_id, transaction_id))
+    }
+
+    /// See `Bank::void`.
+    pub fn void(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.void(client_id, transaction_id))
+    }
+
+    /// See `Bank::expire_authorizations`.
+    pub fn expire_authorizations(
+        &self,
+        cutoff: Timestamp,
+    ) -> Result<Vec<(ClientId, TransactionId)>, TransactorError> {
+        let clients: Vec<ClientId> = self.clients.iter().map(|entry| *entry.key()).collect();
+        let mut expired = Vec::new();
+        for client_id in clients {
+            expired.extend(self.apply(client_id, |bank| bank.expire_authorizations(cutoff))?);
+        }
+        expired.sort_by_key(|(client_id, transaction_id)| (client_id.0, transaction_id.0));
+        Ok(expired)
+    }
+
+    /// See `Bank::freeze`.
+    pub fn freeze(
+        &self,
+        client_id: ClientId,
+        reason: Option<ReasonCode>,
+    ) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.freeze(client_id, reason))
+    }
+
+    /// See `Bank::unfreeze`.
+    pub fn unfreeze(&self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.unfreeze(client_id))
+    }
+
+    /// See `Bank::close_account`.
+    pub fn close_account(&self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.close_account(client_id))
+    }
+
+    /// See `Bank::unlock`.
+    pub fn unlock(&self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        self.apply(client_id, |bank| bank.unlock(client_id))
+    }
+
+    /// Apply a parsed input record, as the command line does with a `Bank`.
+    pub fn enact(&self, command: Command) -> Result<Outcome, TransactorError> {
+        self.apply(command.client(), |bank| command.apply(bank))
+    }
+
+    /// Apply a parsed input record if the account is still at the `expected` version, or whatever
+    /// version it is at if None, returning the version it is at afterwards. The check and the
+    /// change are made under the same lock, so a caller that read the account at `expected` knows
+    /// nothing else changed it in between. Fails with `VersionConflict` if something did.
+    pub fn enact_versioned(
+        &self,
+        command: Command,
+        expected: Option<u64>,
+    ) -> Result<Versioned, TransactorError> {
+        let client_id = command.client();
+        self.apply(client_id, |bank| {
+            if let Some(expected) = expected {
+                bank.check_version(client_id, expected)?;
+            }
+            command.apply_versioned(bank)
+        })
+    }
+
+    /// A `Bank` holding every account, with the same policies.
+    pub fn into_bank(self) -> Result<Bank, TransactorError> {
+        let mut bank = Bank::new()
+            .with_dispute_policy(self.dispute_policy)
+            .with_refusal_policy(self.refusal_policy)
+            .with_representment_policy(self.representment_policy)
+            .with_locked_account_policy(self.locked_account_policy)
+            .with_recovery_policy(self.recovery_policy)
+            .with_fee_schedule(self.fee_schedule)
+            .with_precision(self.precision)
+            .with_overdraft_policy(self.overdraft_policy)
+            .with_risk_limits(self.risk_limits)
+            .with_status_rules(self.status_rules);
+        bank.max_amount = self.max_amount;
+        bank.max_dispute_cycles = self.max_dispute_cycles;
+        let mut clients: Vec<(ClientId, Arc<Mutex<Bank>>)> = self.clients.into_iter().collect();
+        clients.sort_by_key(|(client_id, _)| client_id.0);
+        for (_, client) in clients {
+            let mut client = lock(&client);
+            for (_, account) in client.client_accounts.drain() {
+                bank.adopt_account(account)?;
+            }
+            for (client_id, accounts) in client.sub_accounts.drain() {
+                for (name, account) in accounts {
+                    bank.in_account(client_id, name, |bank| bank.adopt_account(account))?;
+                }
+            }
+        }
+        Ok(bank)
+    }
+
+    /// Read the bank of a client, None if the client has never been seen.
+    fn read<T>(&self, client_id: ClientId, read: impl FnOnce(&Bank) -> Option<T>) -> Option<T> {
+        // The map entry is released before locking so a slow operation on one client never
+        // holds up finding others
+        let client = self.clients.get(&client_id)?.value().clone();
+        let bank = lock(&client);
+        read(&bank)
+    }
+
+    /// Change the bank of a client, made if the client has never been seen.
+    fn apply<T>(&self, client_id: ClientId, apply: impl FnOnce(&mut Bank) -> T) -> T {
+        let client = match self.clients.get(&client_id) {
+            Some(client) => client.value().clone(),
+            // The map is only locked for writing the first time a client is seen
+            None => self
+                .clients
+                .entry(client_id)
+                .or_insert_with(|| Arc::new(Mutex::new(self.client_bank())))
+                .value()
+                .clone(),
+        };
+        let mut bank = lock(&client);
+        apply(&mut bank)
+    }
+
+    fn client_bank(&self) -> Bank {
+        let mut bank = Bank::new()
+            .with_dispute_policy(self.dispute_policy)
+            .with_refusal_policy(self.refusal_policy)
+            .with_representment_policy(self.representment_policy)
+            .with_locked_account_policy(self.locked_account_policy)
+            .with_recovery_policy(self.recovery_policy)
+            .with_fee_schedule(self.fee_schedule.clone())
+            .with_precision(self.precision.clone())
+            .with_overdraft_policy(self.overdraft_policy.clone())
+            .with_risk_limits(self.risk_limits)
+            .with_status_rules(self.status_rules.clone());
+        bank.max_amount = self.max_amount;
+        bank.max_dispute_cycles = self.max_dispute_cycles;
+        bank
+    }
+}
+
+fn lock(client: &Mutex<Bank>) -> MutexGuard<'_, Bank> {
+    // A panic part way through an operation has already been reported by its own thread, other
+    // threads carry on with the account as it was left
+    client
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn clients_are_served_from_many_threads() -> Result<(), TransactorError> {
+        let bank = ConcurrentBank::new().with_dispute_policy(DisputePolicy::DepositsOnly);
+        thread::scope(|scope| {
+            let threads: Vec<_> = (1..=4u16)
+                .map(|client| {
+                    let bank = &bank;
+                    scope.spawn(move || -> Result<(), TransactorError> {
+                        for tx in 1..=100u32 {
+                            bank.transact(
+                                ClientId(client),
+                                Transaction::new(TransactionId(tx), Decimal::new(1, 0)),
+                            )?;
+                        }
+                        bank.dispute_transaction(ClientId(client), TransactionId(1))?;
+                        Ok(())
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .try_for_each(|thread| thread.join().expect("thread panicked"))
+        })?;
+        let account = bank
+            .get_account(ClientId(3))
+            .expect("client 3 has deposited");
+        assert_eq!(account.available(), Decimal::new(99, 0));
+        assert_eq!(account.held(), Decimal::new(1, 0));
+        let view = bank
+            .account_view(ClientId(3))
+            .expect("client 3 has deposited");
+        assert_eq!(view.total, Some(Decimal::new(100, 0)));
+        let page = bank
+            .transactions_page(ClientId(3), 10, 2)
+            .expect("client 3 has deposited");
+        assert_eq!(
+            page.iter()
+                .map(|(transaction, _)| transaction.transaction_id())
+                .collect::<Vec<_>>(),
+            vec![TransactionId(11), TransactionId(12)]
+        );
+        assert!(matches!(
+            bank.transact(
+                ClientId(3),
+                Transaction::new(TransactionId(1), Decimal::new(1, 0))
+            ),
+            Err(TransactorError::TransactionIdReuse)
+        ));
+
+        let bank = bank.into_bank()?;
+        assert_eq!(bank.get_accounts().count(), 4);
+        assert_eq!(
+            bank.transaction_state(ClientId(4), TransactionId(1)),
+            Some(TransactionState::Disputed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn commands_return_the_version_they_leave_the_account_at() -> Result<(), TransactorError> {
+        let bank = ConcurrentBank::new();
+        let client = ClientId(1);
+        let deposit = |tx| Command::Deposit {
+            client,
+            transaction: Transaction::new(TransactionId(tx), Decimal::new(1, 0)),
+        };
+        let applied = bank.enact_versioned(deposit(1), None)?;
+        assert_eq!(applied.outcome, Outcome::Applied);
+        assert_eq!(applied.version, 1);
+        assert_eq!(bank.enact_versioned(deposit(2), Some(1))?.version, 2);
+
+        // A caller that read the account before the last deposit is refused
+        assert!(matches!(
+            bank.enact_versioned(deposit(3), Some(1)),
+            Err(TransactorError::VersionConflict {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        let ignored = bank.enact_versioned(
+            Command::Withdrawal {
+                client,
+                transaction: Transaction::new(TransactionId(4), Decimal::new(-5, 0)),
+            },
+            Some(2),
+        )?;
+        assert!(matches!(ignored.outcome, Outcome::Ignored(_)));
+        assert_eq!(ignored.version, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn policies_apply_as_they_do_on_a_bank() -> Result<(), TransactorError> {
+        let fees = FeeSchedule::default()
+            .with_rule(
+                "withdrawal=0.5+10%"
+                    .parse()
+                    .map_err(TransactorError::InvalidConfig)?,
+            )
+            .with_rule(
+                "chargeback=2"
+                    .parse()
+                    .map_err(TransactorError::InvalidConfig)?,
+            );
+        let overdraft = OverdraftPolicy::new(Decimal::new(5, 0));
+        let risk_limits = RiskLimits {
+            max_chargebacks: None,
+            max_open_disputes: Some(1),
+        };
+        let mut bank = Bank::new()
+            .with_dispute_policy(DisputePolicy::DepositsOnly)
+            .with_refusal_policy(RefusalPolicy::Error)
+            .with_locked_account_policy(LockedAccountPolicy::BlockWithdrawalsOnly)
+            .with_fee_schedule(fees.clone())
+            .with_overdraft_policy(overdraft.clone())
+            .with_risk_limits(risk_limits);
+        bank.max_amount = Some(Decimal::new(1000, 0));
+        let concurrent = ConcurrentBank::new()
+            .with_dispute_policy(DisputePolicy::DepositsOnly)
+            .with_refusal_policy(RefusalPolicy::Error)
+            .with_locked_account_policy(LockedAccountPolicy::BlockWithdrawalsOnly)
+            .with_fee_schedule(fees)
+            .with_overdraft_policy(overdraft)
+            .with_risk_limits(risk_limits)
+            .with_max_amount(Decimal::new(1000, 0));
+
+        let transaction = |tx, amount| Transaction::new(TransactionId(tx), Decimal::new(amount, 0));
+        let deposit = |client, tx, amount| Command::Deposit {
+            client: ClientId(client),
+            transaction: transaction(tx, amount),
+        };
+        let withdrawal = |client, tx, amount: i64| Command::Withdrawal {
+            client: ClientId(client),
+            transaction: transaction(tx, -amount),
+        };
+        let dispute = |client, tx| Command::Dispute {
+            client: ClientId(client),
+            transaction: TransactionId(tx),
+            reason: None,
+        };
+        let commands = [
+            deposit(1, 1, 10),
+            // A fee of 0.8, an overdraft past the limit refused as an error and one within it
+            withdrawal(1, 2, 3),
+            withdrawal(1, 3, 20),
+            withdrawal(1, 4, 8),
+            dispute(1, 1),
+            Command::Chargeback {
+                client: ClientId(1),
+                transaction: TransactionId(1),
+                reason: None,
+            },
+            // The locked account takes deposits but not withdrawals
+            deposit(1, 5, 4),
+            withdrawal(1, 6, 1),
+            deposit(2, 7, 2000),
+            deposit(2, 8, 5),
+            withdrawal(2, 9, 1),
+            // Withdrawals cannot be disputed, and a second open dispute is over the limit
+            dispute(2, 9),
+            deposit(2, 10, 5),
+            dispute(2, 8),
+            dispute(2, 10),
+        ];
+        for command in commands {
+            let expected = format!("{:?}", command.apply(&mut bank));
+            assert_eq!(
+                format!("{:?}", concurrent.enact(command)),
+                expected,
+                "{:?}",
+                command
+            );
+        }
+        assert!(bank.get_account(ClientId(1)).unwrap().is_locked());
+        assert!(bank.get_account(ClientId(2)).unwrap().is_locked());
+        for client in [ClientId(1), ClientId(2)] {
+            let expected = bank.get_account(client).unwrap();
+            let account = concurrent.get_account(client).unwrap();
+            assert_eq!(account.available(), expected.available());
+            assert_eq!(account.held(), expected.held());
+            assert_eq!(account.status(), expected.status());
+            assert_eq!(account.fees(), expected.fees());
+        }
+        assert_eq!(
+            bank.get_account(ClientId(1)).unwrap().available(),
+            Decimal::new(-111, 1)
+        );
+        Ok(())
+    }
+}
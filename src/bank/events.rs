@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use super::{Account, BankObserver, BatchId, ClientId, RiskLimit, Transaction, TransactionId};
+
+/// A change made by the bank, as delivered to subscribers. Mirrors the `BankObserver` callbacks
+/// without the account, which subscribers can look up if they need it. Each carries the batch the
+/// bank was in when it made the change, see `Bank::set_batch`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BankEvent {
+    TransactionApplied {
+        client: ClientId,
+        batch: Option<BatchId>,
+        transaction: Transaction,
+    },
+    WithdrawalRejected {
+        client: ClientId,
+        batch: Option<BatchId>,
+        transaction: Transaction,
+    },
+    TransactionDisputed {
+        client: ClientId,
+        batch: Option<BatchId>,
+        transaction: TransactionId,
+    },
+    DisputeResolved {
+        client: ClientId,
+        batch: Option<BatchId>,
+        transaction: TransactionId,
+    },
+    ChargebackApplied {
+        client: ClientId,
+        batch: Option<BatchId>,
+        transaction: TransactionId,
+    },
+    ChargebackRepresented {
+        client: ClientId,
+        batch: Option<BatchId>,
+        transaction: TransactionId,
+    },
+    AccountLocked {
+        client: ClientId,
+        batch: Option<BatchId>,
+    },
+    AccountUnlocked {
+        client: ClientId,
+        batch: Option<BatchId>,
+    },
+    AccountClosed {
+        client: ClientId,
+        batch: Option<BatchId>,
+    },
+    RiskLimitExceeded {
+        client: ClientId,
+        batch: Option<BatchId>,
+        limit: RiskLimit,
+    },
+}
+
+struct Queue {
+    events: VecDeque<BankEvent>,
+    capacity: usize,
+    /// Events dropped because the subscriber fell behind.
+    lagged: u64,
+    /// The bank has been dropped, no more events will arrive.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        // A panic while holding the lock cannot leave the queue inconsistent
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The receiving end of `Bank::subscribe`. Each subscriber has its own bounded buffer, when it is
+/// full the oldest event is dropped and counted as lag rather than holding up the bank.
+///
+/// Events can be waited for on a thread with `recv` or from async code with `next_event`.
+pub struct EventReceiver {
+    shared: Arc<Shared>,
+}
+
+impl EventReceiver {
+    /// Wait for the next event, None once the bank has been dropped and every event taken.
+    pub fn recv(&self) -> Option<BankEvent> {
+        let mut queue = self.shared.lock();
+        loop {
+            if let Some(event) = queue.events.pop_front() {
+                return Some(event);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self
+                .shared
+                .available
+                .wait(queue)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// The next event if one is waiting.
+    pub fn try_recv(&self) -> Option<BankEvent> {
+        self.shared.lock().events.pop_front()
+    }
+
+    /// A future resolving to the next event, or None once the bank has been dropped and every
+    /// event taken.
+    pub fn next_event(&self) -> NextEvent<'_> {
+        NextEvent { receiver: self }
+    }
+
+    /// The number of events dropped so far because this subscriber fell behind.
+    pub fn lagged(&self) -> u64 {
+        self.shared.lock().lagged
+    }
+
+    /// The number of events waiting to be taken.
+    pub fn len(&self) -> usize {
+        self.shared.lock().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Iterator for EventReceiver {
+    type Item = BankEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+/// The future returned by `EventReceiver::next_event`.
+pub struct NextEvent<'a> {
+    receiver: &'a EventReceiver,
+}
+
+impl Future for NextEvent<'_> {
+    type Output = Option<BankEvent>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut queue = self.receiver.shared.lock();
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The observer the bank holds for each subscriber, publishing into its buffer.
+pub(super) struct EventPublisher {
+    shared: Arc<Shared>,
+    /// The batch of the changes being published.
+    batch: Option<BatchId>,
+}
+
+impl EventPublisher {
+    pub(super) fn channel(capacity: usize, batch: Option<BatchId>) -> (Self, EventReceiver) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                capacity: capacity.max(1),
+                lagged: 0,
+                closed: false,
+                waker: None,
+            }),
+            available: Condvar::new(),
+        });
+        (
+            Self {
+                shared: shared.clone(),
+                batch,
+            },
+            EventReceiver { shared },
+        )
+    }
+
+    fn publish(&mut self, event: BankEvent) {
+        // Nobody is listening once the receiver is dropped
+        if Arc::strong_count(&self.shared) == 1 {
+            return;
+        }
+        let mut queue = self.shared.lock();
+        if queue.events.len() == queue.capacity {
+            queue.events.pop_front();
+            queue.lagged += 1;
+        }
+        queue.events.push_back(event);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        self.shared.available.notify_one();
+    }
+}
+
+impl Drop for EventPublisher {
+    fn drop(&mut self) {
+        let mut queue = self.shared.lock();
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        self.shared.available.notify_all();
+    }
+}
+
+impl BankObserver for EventPublisher {
+    fn batch_changed(&mut self, batch: Option<&BatchId>) {
+        self.batch = batch.cloned();
+    }
+
+    fn transaction_applied(&mut self, account: &Account, transaction: &Transaction) {
+        self.publish(BankEvent::TransactionApplied {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            transaction: *transaction,
+        });
+    }
+
+    fn withdrawal_rejected(&mut self, account: &Account, transaction: &Transaction) {
+        self.publish(BankEvent::WithdrawalRejected {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            transaction: *transaction,
+        });
+    }
+
+    fn transaction_disputed(&mut self, account: &Account, transaction_id: TransactionId) {
+        self.publish(BankEvent::TransactionDisputed {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            transaction: transaction_id,
+        });
+    }
+
+    fn dispute_resolved(&mut self, account: &Account, transaction_id: TransactionId) {
+        self.publish(BankEvent::DisputeResolved {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            transaction: transaction_id,
+        });
+    }
+
+    fn chargeback_applied(&mut self, account: &Account, transaction_id: TransactionId) {
+        self.publish(BankEvent::ChargebackApplied {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            transaction: transaction_id,
+        });
+    }
+
+    fn chargeback_represented(&mut self, account: &Account, transaction_id: TransactionId) {
+        self.publish(BankEvent::ChargebackRepresented {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            transaction: transaction_id,
+        });
+    }
+
+    fn account_locked(&mut self, account: &Account) {
+        self.publish(BankEvent::AccountLocked {
+            client: account.client_id,
+            batch: self.batch.clone(),
+        });
+    }
+
+    fn account_unlocked(&mut self, account: &Account) {
+        self.publish(BankEvent::AccountUnlocked {
+            client: account.client_id,
+            batch: self.batch.clone(),
+        });
+    }
+
+    fn account_closed(&mut self, account: &Account) {
+        self.publish(BankEvent::AccountClosed {
+            client: account.client_id,
+            batch: self.batch.clone(),
+        });
+    }
+
+    fn risk_limit_exceeded(&mut self, account: &Account, limit: RiskLimit) {
+        self.publish(BankEvent::RiskLimitExceeded {
+            client: account.client_id,
+            batch: self.batch.clone(),
+            limit,
+        });
+    }
+}
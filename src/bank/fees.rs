@@ -0,0 +1,277 @@
+use std::str::FromStr;
+
+use rust_decimal::prelude::*;
+
+use super::TransactionId;
+use crate::error::{TransactorError, TransactorError::*};
+use crate::metrics::Operation;
+
+/// The operations a fee can be charged on.
+const FEE_OPERATIONS: [Operation; 2] = [Operation::Withdrawal, Operation::Chargeback];
+
+/// A fee of a flat amount plus a percentage of the amount of the transaction, written as `0.25`,
+/// `1.5%` or `0.25+1.5%`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Fee {
+    pub flat: Decimal,
+    pub percent: Decimal,
+}
+
+impl Fee {
+    /// The fee on a transaction of `amount`, which fails if it overflows.
+    pub fn on(&self, amount: Decimal) -> Result<Decimal, TransactorError> {
+        amount
+            .abs()
+            .checked_mul(self.percent)
+            .and_then(|fee| fee.checked_div(Decimal::new(100, 0)))
+            .and_then(|fee| fee.checked_add(self.flat))
+            .ok_or(Overflow)
+    }
+}
+
+impl FromStr for Fee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid fee {}, expected a flat amount, a percentage such as 1.5% or both as \
+                 0.25+1.5%",
+                s
+            )
+        };
+        let mut fee = Fee::default();
+        for part in s.split('+').map(str::trim) {
+            let (amount, percent) = match part.strip_suffix('%') {
+                Some(amount) => (amount, true),
+                None => (part, false),
+            };
+            let amount: Decimal = amount.trim().parse().map_err(|_| invalid())?;
+            if amount < Decimal::zero() {
+                return Err(invalid());
+            }
+            match percent {
+                true => fee.percent = amount,
+                false => fee.flat = amount,
+            }
+        }
+        Ok(fee)
+    }
+}
+
+/// The fee charged on one operation, such as `withdrawal=0.25+1%`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FeeRule {
+    pub operation: Operation,
+    pub fee: Fee,
+}
+
+impl FromStr for FeeRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (operation, fee) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Fee rule {} is not operation=fee", s))?;
+        let operation = FEE_OPERATIONS
+            .iter()
+            .copied()
+            .find(|known| known.to_string() == operation.trim())
+            .ok_or_else(|| {
+                format!(
+                    "Unknown operation {} in fee rule, expected one of: withdrawal, chargeback",
+                    operation
+                )
+            })?;
+        Ok(Self {
+            operation,
+            fee: fee.parse()?,
+        })
+    }
+}
+
+/// The fees charged on each operation, none by default. A fee is taken from the available funds
+/// together with the transaction it is charged on, and kept in the fee ledger of the account.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FeeSchedule {
+    rules: Vec<FeeRule>,
+}
+
+impl FeeSchedule {
+    /// Charge the fee of the rule on its operation, in place of any fee already set for it.
+    pub fn with_rule(mut self, rule: FeeRule) -> Self {
+        self.rules.retain(|set| set.operation != rule.operation);
+        self.rules.push(rule);
+        self
+    }
+
+    /// The fee on an operation on a transaction of `amount`, zero without a fee for it.
+    pub(super) fn fee_on(
+        &self,
+        operation: Operation,
+        amount: Decimal,
+    ) -> Result<Decimal, TransactorError> {
+        match self.rules.iter().find(|rule| rule.operation == operation) {
+            Some(rule) => rule.fee.on(amount),
+            None => Ok(Decimal::zero()),
+        }
+    }
+}
+
+/// A fee charged on a transaction, kept in the fee ledger of the account apart from its
+/// transaction history.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FeeEntry {
+    /// The transaction the fee was charged on.
+    pub transaction: TransactionId,
+    pub operation: Operation,
+    pub amount: Decimal,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, IgnoreReason, Outcome, Transaction};
+    use crate::currency::{Currency, PrecisionTable};
+
+    #[test]
+    fn fees_are_taken_with_the_transaction_they_are_charged_on() -> Result<(), TransactorError> {
+        let withdrawal: FeeRule = "withdrawal=0.5+10%".parse().map_err(InvalidConfig)?;
+        assert_eq!(
+            withdrawal.fee,
+            Fee {
+                flat: Decimal::new(5, 1),
+                percent: Decimal::new(10, 0),
+            }
+        );
+        let schedule = FeeSchedule::default()
+            .with_rule(withdrawal)
+            .with_rule("chargeback=2".parse().map_err(InvalidConfig)?);
+        let mut bank = Bank::new().with_fee_schedule(schedule);
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 0)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(-5, 0)),
+        )?;
+        assert_eq!(bank.account_mut(client).available(), Decimal::new(4, 0));
+
+        // Neither the withdrawal nor its fee is taken when together they are more than available
+        assert_eq!(
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(3), Decimal::new(-4, 0))
+            )?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.chargeback(client, TransactionId(1))?;
+        let account = bank.account_mut(client);
+        assert_eq!(account.available(), Decimal::new(-8, 0));
+        assert_eq!(
+            account.fees(),
+            [
+                FeeEntry {
+                    transaction: TransactionId(2),
+                    operation: Operation::Withdrawal,
+                    amount: Decimal::new(1, 0),
+                },
+                FeeEntry {
+                    transaction: TransactionId(1),
+                    operation: Operation::Chargeback,
+                    amount: Decimal::new(2, 0),
+                },
+            ]
+        );
+        assert_eq!(account.total_fees()?, Decimal::new(3, 0));
+        bank.check_funds_conserved()?;
+
+        assert!("deposit=1".parse::<FeeRule>().is_err());
+        assert!("withdrawal=-1".parse::<FeeRule>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fees_are_rounded_to_the_decimal_places_of_their_currency() -> Result<(), TransactorError> {
+        let jpy: Currency = "JPY".parse().map_err(InvalidConfig)?;
+        let bhd: Currency = "BHD".parse().map_err(InvalidConfig)?;
+        let precision = PrecisionTable::from_reader(
+            "currency,decimal_places,rounding\nJPY,0,half-up\nBHD,3,\n".as_bytes(),
+        )?;
+        let schedule = FeeSchedule::default()
+            .with_rule("withdrawal=2.5%".parse().map_err(InvalidConfig)?)
+            .with_rule("chargeback=1%".parse().map_err(InvalidConfig)?);
+        let mut bank = Bank::new()
+            .with_fee_schedule(schedule)
+            .with_precision(precision);
+        let (yen, dinar) = (ClientId(1), ClientId(2));
+        let in_currency = |tx, amount, currency| {
+            Transaction::new(TransactionId(tx), amount).with_currency(currency)
+        };
+        bank.transact(yen, in_currency(1, Decimal::new(1000, 0), jpy))?;
+        // 2.5% of 100 yen is 2.5, rounded half up to whole yen
+        bank.transact(yen, in_currency(2, Decimal::new(-100, 0), jpy))?;
+        bank.transact(dinar, in_currency(1, Decimal::new(1235, 3), bhd))?;
+        // 2.5% of 0.5 dinar is 0.0125, rounded to even fils
+        bank.transact(dinar, in_currency(2, Decimal::new(-5, 1), bhd))?;
+        // 1% of 1.235 dinar is 0.01235, a fee without a currency of its own takes the account's
+        bank.dispute_transaction(dinar, TransactionId(1))?;
+        bank.chargeback(dinar, TransactionId(1))?;
+
+        let fees = |client| -> Vec<Decimal> {
+            bank.get_account(client)
+                .map(|account| account.fees().iter().map(|fee| fee.amount).collect())
+                .unwrap_or_default()
+        };
+        assert_eq!(fees(yen), vec![Decimal::new(3, 0)]);
+        assert_eq!(fees(dinar), vec![Decimal::new(12, 3), Decimal::new(12, 3)]);
+        assert_eq!(
+            bank.get_account(yen).map(|account| account.available()),
+            Some(Decimal::new(897, 0))
+        );
+        bank.check_funds_conserved()?;
+        Ok(())
+    }
+
+    #[test]
+    fn captures_are_charged_the_withdrawal_fee() -> Result<(), TransactorError> {
+        let schedule =
+            FeeSchedule::default().with_rule("withdrawal=1+10%".parse().map_err(InvalidConfig)?);
+        let mut bank = Bank::new().with_fee_schedule(schedule);
+        let client = ClientId(1);
+        let transaction = |id, amount| Transaction::new(TransactionId(id), Decimal::new(amount, 0));
+        bank.transact(client, transaction(1, 10))?;
+        bank.authorize(client, transaction(2, 5))?;
+        bank.authorize(client, transaction(3, 3))?;
+        assert_eq!(bank.capture(client, TransactionId(2))?, Outcome::Applied);
+        let account = bank.account_mut(client);
+        // The fee of 1.5 comes out of the available funds as the held 5 are posted
+        assert_eq!(account.available(), Decimal::new(5, 1));
+        assert_eq!(account.held(), Decimal::new(3, 0));
+        assert_eq!(
+            account.fees(),
+            [FeeEntry {
+                transaction: TransactionId(2),
+                operation: Operation::Withdrawal,
+                amount: Decimal::new(15, 1),
+            }]
+        );
+
+        // Without the funds for its fee of 1.3 the capture is refused and the funds stay held
+        assert_eq!(
+            bank.capture(client, TransactionId(3))?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        let account = bank.account_mut(client);
+        assert_eq!(account.available(), Decimal::new(5, 1));
+        assert_eq!(
+            account.authorization(TransactionId(3)),
+            Some(transaction(3, 3))
+        );
+        bank.check_funds_conserved()?;
+        Ok(())
+    }
+}
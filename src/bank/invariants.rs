@@ -0,0 +1,233 @@
+use std::fmt::Write;
+
+use rust_decimal::prelude::*;
+
+use super::{Account, Bank, ClientId, TransactionId, TransactionState};
+use crate::error::{TransactorError, TransactorError::*};
+
+impl Bank {
+    /// Check that every account is consistent, in client order, see `check_account_invariants`.
+    pub fn check_invariants(&self) -> Result<(), TransactorError> {
+        let mut client_ids: Vec<ClientId> = self.client_accounts.keys().copied().collect();
+        client_ids.sort_by_key(|client_id| client_id.0);
+        for client_id in client_ids {
+            self.check_account_invariants(client_id)?;
+        }
+        Ok(())
+    }
+
+    /// Check that a clients account is consistent, for catching bugs in new policies as soon as
+    /// they leave an account in a state no sequence of operations should reach:
+    /// * the total of the available and held funds does not overflow
+    /// * held funds are never negative and cover every open dispute, authorization and
+    ///   administrative hold
+    /// * every transaction that has been disputed has a history entry, and the count of open
+    ///   disputes matches the history
+    ///
+    /// This reads the whole history of the account, so it is slow on long histories.
+    pub fn check_account_invariants(&self, client_id: ClientId) -> Result<(), TransactorError> {
+        let account = match self.client_accounts.get(&client_id) {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+        let broken = |invariant: String| InvariantViolation {
+            client: client_id,
+            invariant,
+            dump: dump(account),
+        };
+        account.total().map_err(|_| {
+            broken(format!(
+                "available {} and held {} overflow when added",
+                account.available, account.held
+            ))
+        })?;
+        if account.held < Decimal::zero() {
+            return Err(broken(format!("held {} is negative", account.held)));
+        }
+        // In memory disputes must all be indexed, and indexed disputes must all be recorded
+        for transaction_id in account.transaction_history.keys() {
+            let disputed =
+                account.transaction_state(*transaction_id) != Some(TransactionState::Posted);
+            if disputed && !self.dispute_index.contains(&(client_id, *transaction_id)) {
+                return Err(broken(format!(
+                    "disputed transaction {} is missing from the dispute index",
+                    transaction_id.0
+                )));
+            }
+        }
+        let mut open_disputes = 0;
+        let mut holds = Decimal::zero();
+        // The index covers all of the client's accounts, whose other accounts are checked apart
+        let elsewhere = |transaction_id: &TransactionId| {
+            self.sub_accounts.get(&client_id).is_some_and(|accounts| {
+                accounts
+                    .values()
+                    .any(|other| other.transaction_history.contains_key(transaction_id))
+            })
+        };
+        for (_, transaction_id) in self
+            .dispute_index
+            .iter()
+            .filter(|(indexed, transaction_id)| *indexed == client_id && !elsewhere(transaction_id))
+        {
+            let recorded = self
+                .recorded_transaction(client_id, *transaction_id)
+                .ok_or_else(|| {
+                    broken(format!(
+                        "disputed transaction {} has no history entry",
+                        transaction_id.0
+                    ))
+                })?;
+            if recorded.state == TransactionState::Disputed {
+                open_disputes += 1;
+                holds = holds
+                    .checked_add(recorded.transaction.amount.abs())
+                    .ok_or_else(|| broken("open disputes overflow when added".to_string()))?;
+            }
+        }
+        if open_disputes != account.open_disputes {
+            return Err(broken(format!(
+                "{} transactions are disputed but the account counts {} open disputes",
+                open_disputes, account.open_disputes
+            )));
+        }
+        for authorized in account.authorizations.values() {
+            holds = holds
+                .checked_add(authorized.amount)
+                .ok_or_else(|| broken("authorizations overflow when added".to_string()))?;
+        }
+        holds = account
+            .held_administratively()
+            .ok()
+            .and_then(|held| holds.checked_add(held))
+            .ok_or_else(|| broken("administrative holds overflow when added".to_string()))?;
+        if holds > account.held {
+            return Err(broken(format!(
+                "held {} does not cover the {} held for open disputes, authorizations and holds",
+                account.held, holds
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that no account has made or lost money, in client order: the total of each account
+    /// must be what its history adds up to. Deposits and withdrawals count by their amount,
+    /// charged back transactions are taken back on top, fees are taken off and authorizations
+    /// only move funds.
+    ///
+    /// This only holds for banks that started empty and keep every transaction in memory, as
+    /// opening balances and spilled history are not counted, nor is history recorded with
+    /// `Bank::record_history`, so it is not part of `check_invariants`.
+    pub fn check_funds_conserved(&self) -> Result<(), TransactorError> {
+        let mut client_ids: Vec<ClientId> = self.client_accounts.keys().copied().collect();
+        client_ids.sort_by_key(|client_id| client_id.0);
+        for client_id in client_ids {
+            let account = &self.client_accounts[&client_id];
+            let broken = |invariant: String| InvariantViolation {
+                client: client_id,
+                invariant,
+                dump: dump(account),
+            };
+            let mut expected = Decimal::zero();
+            for (transaction, state) in account.transactions() {
+                let taken_back = match state {
+                    TransactionState::ChargedBack => transaction.amount.abs(),
+                    _ => Decimal::zero(),
+                };
+                // Taken back separately, as a withdrawal and its chargeback can overflow together
+                expected = expected
+                    .checked_add(transaction.amount)
+                    .and_then(|expected| expected.checked_sub(taken_back))
+                    .ok_or_else(|| broken("history overflows when added".to_string()))?;
+            }
+            expected = expected
+                .checked_sub(account.total_fees()?)
+                .ok_or_else(|| broken("fees overflow when taken off".to_string()))?;
+            let total = account.total()?;
+            if total != expected {
+                return Err(broken(format!(
+                    "total {} is not the {} the history adds up to",
+                    total, expected
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of an account for diagnosing a broken invariant, leaving out undisputed history.
+fn dump(account: &Account) -> String {
+    let mut dump = format!(
+        "available {}, held {}, {}, frozen {}, open disputes {}, chargebacks {}, standing {}, version {}",
+        account.available,
+        account.held,
+        account.status,
+        account.is_frozen(),
+        account.open_disputes,
+        account.chargebacks,
+        account.standing_chargebacks,
+        account.version
+    );
+    for (transaction, state) in account.transactions() {
+        if state != TransactionState::Posted {
+            // Writing to a string cannot fail
+            let _ = write!(
+                dump,
+                "\n  transaction {} of {} is {}",
+                transaction.transaction_id.0, transaction.amount, state
+            );
+        }
+    }
+    let mut authorizations: Vec<_> = account.authorizations.values().collect();
+    authorizations.sort_by_key(|authorized| authorized.transaction_id.0);
+    for authorized in authorizations {
+        let _ = write!(
+            dump,
+            "\n  authorization {} holds {}",
+            authorized.transaction_id.0, authorized.amount
+        );
+    }
+    dump
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Transaction, TransactionId};
+
+    #[test]
+    fn broken_accounts_are_caught_with_their_state() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(3), Decimal::new(3, 0)),
+        )?;
+        bank.dispute_transaction(client, TransactionId(1))?;
+        bank.authorize(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(1, 0)),
+        )?;
+        bank.check_invariants()?;
+
+        bank.account_mut(client).held = Decimal::new(5, 0);
+        let error = bank.check_invariants().unwrap_err().to_string();
+        assert!(error.starts_with(
+            "Invariant broken for client 1: held 5 does not cover the 6 held for open disputes"
+        ));
+        assert!(error.contains("\n  transaction 1 of 5 is disputed"));
+        assert!(error.contains("\n  authorization 2 holds 1"));
+
+        bank.account_mut(client).held = Decimal::new(6, 0);
+        bank.dispute_index.insert((client, TransactionId(9)));
+        assert!(matches!(
+            bank.check_account_invariants(client),
+            Err(InvariantViolation { invariant, .. }) if invariant.contains("9 has no history")
+        ));
+        Ok(())
+    }
+}
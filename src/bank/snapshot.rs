@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    Account, AccountName, Bank, BatchId, ClientId, DisputeEntry, FeeEntry, RecordedTransaction,
+    Timestamp, Transaction, TransactionId, TransactionState,
+};
+use crate::error::{TransactorError, TransactorError::*};
+
+const FORMAT_VERSION: u32 = 1;
+
+/// The first line of a snapshot, identifying the format.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checkpoint: Option<InputCheckpoint>,
+    /// The time the state is as of, see `Bank::as_of`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    as_of: Option<u64>,
+    /// The next occurrence of each standing order, see `Bank::apply_standing_order`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    standing_orders: BTreeMap<String, u64>,
+}
+
+/// How far through an input file the state in a snapshot goes, for a later run to resume from.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InputCheckpoint {
+    /// The input file, as it was named to the run.
+    pub input: String,
+    /// The line of the first record not applied.
+    pub line: u64,
+    /// The byte offset of the first record not applied.
+    pub offset: u64,
+}
+
+/// One account in a snapshot with its full transaction history, one per line after the header.
+#[derive(Serialize, Deserialize)]
+struct AccountSnapshot {
+    client: u16,
+    /// The name of a sub-account, absent for the main account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account: Option<String>,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    frozen: bool,
+    freeze_reason: Option<String>,
+    currency: Option<String>,
+    version: u64,
+    last_batch: Option<String>,
+    transactions: Vec<TransactionSnapshot>,
+    #[serde(default)]
+    authorizations: Vec<AuthorizationSnapshot>,
+    #[serde(default)]
+    chargebacks: u32,
+    /// Absent from snapshots saved before representments were told apart from the chargebacks
+    /// of the period, whose count was of those standing.
+    #[serde(default)]
+    standing_chargebacks: Option<u32>,
+    #[serde(default)]
+    recovery: Decimal,
+    #[serde(default)]
+    recovered: Decimal,
+    /// Absent from snapshots saved before accounts had a status, whose locked accounts are
+    /// frozen.
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fees: Vec<FeeSnapshot>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    holds: Vec<HoldSnapshot>,
+}
+
+impl AccountSnapshot {
+    /// The snapshot with every amount normalized, so amounts equal in value are written the same,
+    /// and without batch ids, which default to the path of the input.
+    fn normalized(mut self) -> Self {
+        self.last_batch = None;
+        self.available = self.available.normalize();
+        self.held = self.held.normalize();
+        self.recovery = self.recovery.normalize();
+        self.recovered = self.recovered.normalize();
+        for transaction in &mut self.transactions {
+            transaction.amount = transaction.amount.normalize();
+            for dispute in &mut transaction.disputes {
+                dispute.batch = None;
+            }
+        }
+        for authorization in &mut self.authorizations {
+            authorization.amount = authorization.amount.normalize();
+        }
+        for fee in &mut self.fees {
+            fee.amount = fee.amount.normalize();
+        }
+        for hold in &mut self.holds {
+            hold.amount = hold.amount.normalize();
+        }
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransactionSnapshot {
+    tx: u32,
+    amount: Decimal,
+    currency: Option<String>,
+    timestamp: Option<u64>,
+    state: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    disputes: Vec<DisputeEntrySnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DisputeEntrySnapshot {
+    state: String,
+    reason: Option<String>,
+    batch: Option<String>,
+}
+
+impl DisputeEntrySnapshot {
+    fn new(entry: &DisputeEntry) -> Self {
+        Self {
+            state: entry.state.to_string(),
+            reason: entry.reason.map(|reason| reason.to_string()),
+            batch: entry.batch.as_ref().map(|batch| batch.as_str().to_string()),
+        }
+    }
+
+    fn restore(&self) -> Result<DisputeEntry, TransactorError> {
+        Ok(DisputeEntry {
+            state: parse_field(&self.state)?,
+            reason: self.reason.as_deref().map(str::parse).transpose()?,
+            batch: self.batch.as_deref().map(BatchId::new),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuthorizationSnapshot {
+    tx: u32,
+    amount: Decimal,
+    currency: Option<String>,
+    timestamp: Option<u64>,
+}
+
+impl AuthorizationSnapshot {
+    fn new(authorized: &Transaction) -> Self {
+        Self {
+            tx: authorized.transaction_id.0,
+            amount: authorized.amount,
+            currency: authorized.currency.map(|currency| currency.to_string()),
+            timestamp: authorized.timestamp.map(|timestamp| timestamp.0),
+        }
+    }
+
+    fn restore(&self) -> Result<Transaction, TransactorError> {
+        restore_transaction(self.tx, self.amount, &self.currency, self.timestamp)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FeeSnapshot {
+    tx: u32,
+    operation: String,
+    amount: Decimal,
+}
+
+impl FeeSnapshot {
+    fn new(entry: &FeeEntry) -> Self {
+        Self {
+            tx: entry.transaction.0,
+            operation: entry.operation.to_string(),
+            amount: entry.amount,
+        }
+    }
+
+    fn restore(&self) -> Result<FeeEntry, TransactorError> {
+        Ok(FeeEntry {
+            transaction: TransactionId(self.tx),
+            operation: parse_field(&self.operation)?,
+            amount: self.amount,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct HoldSnapshot {
+    tx: u32,
+    amount: Decimal,
+}
+
+impl Bank {
+    /// Write the state of every account, including transaction history spilled to disk, as json
+    /// lines ordered by client. `restore` reads it back.
+    pub fn save_state<W: Write>(&self, writer: W) -> Result<(), TransactorError> {
+        self.write_snapshot(writer, None)
+    }
+
+    /// Write the state as `save_state` does, recording that it covers the input up to the
+    /// checkpoint. State and position are saved together so they cannot disagree.
+    pub fn save_checkpoint<W: Write>(
+        &self,
+        writer: W,
+        checkpoint: &InputCheckpoint,
+    ) -> Result<(), TransactorError> {
+        self.write_snapshot(writer, Some(checkpoint.clone()))
+    }
+
+    fn write_snapshot<W: Write>(
+        &self,
+        mut writer: W,
+        checkpoint: Option<InputCheckpoint>,
+    ) -> Result<(), TransactorError> {
+        let header = Header {
+            format: "transactor-state".to_string(),
+            version: FORMAT_VERSION,
+            checkpoint,
+            as_of: self.as_of.map(|as_of| as_of.0),
+            standing_orders: self.standing_orders.clone(),
+        };
+        write_line(&mut writer, &header)?;
+        for account in self.snapshot_order() {
+            write_line(&mut writer, &self.account_snapshot(account)?)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// A sha256 hash, in hex, of every account as `save_state` writes it with its amounts
+    /// normalized and batch ids left out. Two runs of the same input reach the same hash wherever
+    /// they run, so they can be checked to agree by comparing it.
+    pub fn state_hash(&self) -> Result<String, TransactorError> {
+        let mut hasher = Sha256::new();
+        for account in self.snapshot_order() {
+            write_line(&mut hasher, &self.account_snapshot(account)?.normalized())?;
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Every account, ordered by client with the main account first.
+    fn snapshot_order(&self) -> Vec<&Account> {
+        let mut accounts: Vec<&Account> = self.client_accounts.values().collect();
+        accounts.extend(self.sub_accounts());
+        accounts
+            .sort_by_key(|account| (account.client_id.0, !account.name.is_main(), account.name));
+        accounts
+    }
+
+    fn account_snapshot(&self, account: &Account) -> Result<AccountSnapshot, TransactorError> {
+        let mut transactions = Vec::with_capacity(account.transaction_order.len());
+        for transaction_id in &account.transaction_order {
+            let recorded = match (account.transaction_history.get(transaction_id), &self.spill) {
+                (Some(recorded), _) => Some(*recorded),
+                (None, Some(spill)) => spill.store.read(account.client_id, *transaction_id)?,
+                (None, None) => None,
+            };
+            if let Some(recorded) = recorded {
+                transactions.push(TransactionSnapshot {
+                    tx: transaction_id.0,
+                    amount: recorded.transaction.amount,
+                    currency: recorded
+                        .transaction
+                        .currency
+                        .map(|currency| currency.to_string()),
+                    timestamp: recorded.transaction.timestamp.map(|timestamp| timestamp.0),
+                    state: recorded.state.to_string(),
+                    reason: recorded.reason.map(|reason| reason.to_string()),
+                    disputes: account
+                        .dispute_history(*transaction_id)
+                        .iter()
+                        .map(DisputeEntrySnapshot::new)
+                        .collect(),
+                });
+            }
+        }
+        let mut authorizations: Vec<AuthorizationSnapshot> = account
+            .authorizations
+            .values()
+            .map(AuthorizationSnapshot::new)
+            .collect();
+        authorizations.sort_by_key(|authorized| authorized.tx);
+        let mut holds: Vec<HoldSnapshot> = account
+            .holds
+            .iter()
+            .map(|(transaction_id, amount)| HoldSnapshot {
+                tx: transaction_id.0,
+                amount: *amount,
+            })
+            .collect();
+        holds.sort_by_key(|hold| hold.tx);
+        Ok(AccountSnapshot {
+            client: account.client_id.0,
+            account: Some(account.name.to_string()).filter(|_| !account.name.is_main()),
+            available: account.available,
+            held: account.held,
+            locked: account.is_locked(),
+            frozen: account.is_frozen(),
+            freeze_reason: account.freeze_reason().map(|reason| reason.to_string()),
+            currency: account.currency.map(|currency| currency.to_string()),
+            version: account.version,
+            last_batch: account
+                .last_batch
+                .as_ref()
+                .map(|batch| batch.as_str().to_string()),
+            transactions,
+            authorizations,
+            chargebacks: account.chargebacks,
+            standing_chargebacks: Some(account.standing_chargebacks),
+            recovery: account.recovery,
+            recovered: account.recovered,
+            status: Some(account.status.to_string()),
+            fees: account.fees.iter().map(FeeSnapshot::new).collect(),
+            holds,
+        })
+    }
+
+    /// Open the accounts saved by `save_state`, as they were when saved. The bank keeps its own
+    /// configuration, so a snapshot can be restored into a bank spilling to disk or indexing
+    /// transaction ids. Fails if an account in the snapshot already exists.
+    ///
+    /// Returns how far through its input the state goes if it was saved with `save_checkpoint`.
+    pub fn restore<R: BufRead>(
+        &mut self,
+        reader: R,
+    ) -> Result<Option<InputCheckpoint>, TransactorError> {
+        let mut lines = reader.lines();
+        let header: Header = match lines.next() {
+            Some(line) => parse_line(&line?, 1)?,
+            None => return Err(InvalidSnapshot("the snapshot is empty".to_string())),
+        };
+        if header.format != "transactor-state" || header.version != FORMAT_VERSION {
+            return Err(InvalidSnapshot(format!(
+                "unsupported format {} version {}",
+                header.format, header.version
+            )));
+        }
+        for (index, line) in lines.enumerate() {
+            let snapshot: AccountSnapshot = parse_line(&line?, index + 2)?;
+            let name = match &snapshot.account {
+                Some(name) => parse_field(name)?,
+                None => AccountName::MAIN,
+            };
+            self.in_account(ClientId(snapshot.client), name, |bank| {
+                bank.restore_account(snapshot)
+            })?;
+        }
+        if let Some(as_of) = header.as_of {
+            self.as_of = Some(Timestamp(as_of));
+        }
+        self.standing_orders.extend(header.standing_orders);
+        Ok(header.checkpoint)
+    }
+
+    fn restore_account(&mut self, snapshot: AccountSnapshot) -> Result<(), TransactorError> {
+        let client_id = ClientId(snapshot.client);
+        if self.client_accounts.contains_key(&client_id) {
+            return Err(AccountExists { client: client_id });
+        }
+        let mut account = Account::with_balances(
+            client_id,
+            snapshot.available,
+            snapshot.held,
+            snapshot.locked,
+        )?;
+        account.version = snapshot.version;
+        account.chargebacks = snapshot.chargebacks;
+        account.standing_chargebacks = snapshot
+            .standing_chargebacks
+            .unwrap_or(snapshot.chargebacks);
+        account.recovery = snapshot.recovery;
+        account.recovered = snapshot.recovered;
+        account.fees = snapshot
+            .fees
+            .iter()
+            .map(FeeSnapshot::restore)
+            .collect::<Result<_, _>>()?;
+        if let Some(status) = &snapshot.status {
+            account.status = parse_field(status)?;
+        }
+        account.last_batch = snapshot.last_batch.as_deref().map(BatchId::new);
+        account.currency = snapshot.currency.as_deref().map(parse_field).transpose()?;
+        if snapshot.frozen {
+            account.frozen = Some(
+                snapshot
+                    .freeze_reason
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?,
+            );
+        }
+        for saved in snapshot.transactions {
+            let transaction_id = TransactionId(saved.tx);
+            let transaction =
+                restore_transaction(saved.tx, saved.amount, &saved.currency, saved.timestamp)?;
+            let state: TransactionState = parse_field(&saved.state)?;
+            let reason = saved.reason.as_deref().map(str::parse).transpose()?;
+            account.transaction_history.insert(
+                transaction_id,
+                RecordedTransaction {
+                    transaction,
+                    state,
+                    reason,
+                },
+            );
+            account.transaction_order.push(transaction_id);
+            if !saved.disputes.is_empty() {
+                let history = saved
+                    .disputes
+                    .iter()
+                    .map(DisputeEntrySnapshot::restore)
+                    .collect::<Result<_, _>>()?;
+                account.dispute_history.insert(transaction_id, history);
+            }
+            if state == TransactionState::Disputed {
+                account.open_disputes += 1;
+            }
+        }
+        account.holds = snapshot
+            .holds
+            .iter()
+            .map(|hold| (TransactionId(hold.tx), hold.amount))
+            .collect();
+        for saved in &snapshot.authorizations {
+            let authorized = saved.restore()?;
+            account
+                .authorizations
+                .insert(authorized.transaction_id, authorized);
+        }
+        self.adopt_account(account)
+    }
+}
+
+fn restore_transaction(
+    tx: u32,
+    amount: Decimal,
+    currency: &Option<String>,
+    timestamp: Option<u64>,
+) -> Result<Transaction, TransactorError> {
+    let mut transaction = Transaction::new(TransactionId(tx), amount);
+    if let Some(currency) = currency {
+        transaction = transaction.with_currency(parse_field(currency)?);
+    }
+    if let Some(timestamp) = timestamp {
+        transaction = transaction.with_timestamp(Timestamp(timestamp));
+    }
+    Ok(transaction)
+}
+
+fn write_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<(), TransactorError> {
+    serde_json::to_writer(&mut *writer, value).map_err(std::io::Error::from)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+fn parse_line<'a, T: Deserialize<'a>>(line: &'a str, number: usize) -> Result<T, TransactorError> {
+    serde_json::from_str(line).map_err(|e| InvalidSnapshot(format!("line {}: {}", number, e)))
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str) -> Result<T, TransactorError> {
+    field
+        .parse()
+        .map_err(|_| InvalidSnapshot(format!("unreadable value {}", field)))
+}
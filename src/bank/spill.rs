@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_decimal::Decimal;
+
+use super::{
+    ClientId, ReasonCode, RecordedTransaction, Timestamp, Transaction, TransactionId,
+    TransactionState,
+};
+
+const RECORD_SIZE: u64 = 48;
+const PRESENT: u8 = 1;
+const HAS_TIMESTAMP: u8 = 2;
+
+static NEXT_STORE: AtomicUsize = AtomicUsize::new(0);
+
+/// An on-disk store of recorded transactions that have been evicted from memory.
+///
+/// Transaction ids are only unique per client, so each record is kept in a slot of its own found
+/// through an index of client and transaction id. The index costs a few bytes for each spilled
+/// transaction rather than the whole record, and looking up a transaction is a single seek and
+/// read. The slots of recalled transactions are reused by those spilled after them.
+///
+/// Each record is laid out as:
+/// * byte 0: bit 0 set if the slot holds a transaction, bit 1 set if it has a timestamp
+/// * bytes 1-2: client id, little endian
+/// * byte 3: transaction state
+/// * bytes 4-19: amount, as `Decimal::serialize`
+/// * byte 20: 1 if the transaction has a currency, followed by its three letter code
+/// * bytes 24-31: timestamp, little endian
+/// * bytes 32-47: dispute reason code, padded with zeros
+pub(super) struct SpillStore {
+    path: PathBuf,
+    file: File,
+    slots: HashMap<(ClientId, TransactionId), u64>,
+    /// Slots emptied by recalled transactions, to be filled before the file grows.
+    free_slots: Vec<u64>,
+    next_slot: u64,
+    #[cfg(feature = "failure-injection")]
+    pub(super) faults: Option<crate::fault::FaultInjector>,
+}
+
+impl SpillStore {
+    pub(super) fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "transactions-{}-{}.spill",
+            std::process::id(),
+            NEXT_STORE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            slots: HashMap::new(),
+            free_slots: Vec::new(),
+            next_slot: 0,
+            #[cfg(feature = "failure-injection")]
+            faults: None,
+        })
+    }
+
+    pub(super) fn write(
+        &mut self,
+        client_id: ClientId,
+        recorded: &RecordedTransaction,
+    ) -> io::Result<()> {
+        let mut record = [0u8; RECORD_SIZE as usize];
+        record[0] = PRESENT;
+        record[1..3].copy_from_slice(&client_id.0.to_le_bytes());
+        record[3] = encode_state(recorded.state);
+        record[4..20].copy_from_slice(&recorded.transaction.amount.serialize());
+        if let Some(currency) = recorded.transaction.currency {
+            record[20] = PRESENT;
+            record[21..24].copy_from_slice(currency.to_string().as_bytes());
+        }
+        if let Some(Timestamp(timestamp)) = recorded.transaction.timestamp {
+            record[0] |= HAS_TIMESTAMP;
+            record[24..32].copy_from_slice(&timestamp.to_le_bytes());
+        }
+        if let Some(reason) = recorded.reason {
+            let code = reason.as_str().as_bytes();
+            record[32..32 + code.len()].copy_from_slice(code);
+        }
+        let key = (client_id, recorded.transaction.transaction_id);
+        let slot = match self.slots.get(&key) {
+            Some(slot) => *slot,
+            None => match self.free_slots.pop() {
+                Some(slot) => slot,
+                None => {
+                    self.next_slot += 1;
+                    self.next_slot - 1
+                }
+            },
+        };
+        self.write_slot(slot, &record)?;
+        self.slots.insert(key, slot);
+        Ok(())
+    }
+
+    /// The client and id of every transaction spilled.
+    pub(super) fn spilled(&self) -> impl Iterator<Item = (ClientId, TransactionId)> + '_ {
+        self.slots.keys().copied()
+    }
+
+    /// Read a spilled transaction, None if the client has no spilled transaction of that id.
+    pub(super) fn read(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> io::Result<Option<RecordedTransaction>> {
+        self.inject_fault()?;
+        let slot = match self.slots.get(&(client_id, transaction_id)) {
+            Some(slot) => *slot,
+            None => return Ok(None),
+        };
+        let mut record = [0u8; RECORD_SIZE as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * RECORD_SIZE))?;
+        file.read_exact(&mut record)?;
+        if record[0] & PRESENT == 0 || record[1..3] != client_id.0.to_le_bytes() {
+            return Err(corrupt(transaction_id));
+        }
+        let mut amount = [0u8; 16];
+        amount.copy_from_slice(&record[4..20]);
+        let mut transaction = Transaction::new(transaction_id, Decimal::deserialize(amount));
+        if record[20] == PRESENT {
+            let currency = std::str::from_utf8(&record[21..24])
+                .ok()
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| corrupt(transaction_id))?;
+            transaction = transaction.with_currency(currency);
+        }
+        if record[0] & HAS_TIMESTAMP != 0 {
+            let mut timestamp = [0u8; 8];
+            timestamp.copy_from_slice(&record[24..32]);
+            transaction = transaction.with_timestamp(Timestamp(u64::from_le_bytes(timestamp)));
+        }
+        let reason = match record[32..48].iter().position(|byte| *byte == 0) {
+            Some(0) => None,
+            end => {
+                let code = &record[32..end.map_or(48, |end| 32 + end)];
+                let code = std::str::from_utf8(code).map_err(|_| corrupt(transaction_id))?;
+                Some(
+                    code.parse::<ReasonCode>()
+                        .map_err(|_| corrupt(transaction_id))?,
+                )
+            }
+        };
+        Ok(Some(RecordedTransaction {
+            transaction,
+            state: decode_state(record[3]).ok_or_else(|| corrupt(transaction_id))?,
+            reason,
+        }))
+    }
+
+    pub(super) fn remove(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> io::Result<()> {
+        if let Some(slot) = self.slots.get(&(client_id, transaction_id)).copied() {
+            self.write_slot(slot, &[0u8; RECORD_SIZE as usize])?;
+            self.slots.remove(&(client_id, transaction_id));
+            self.free_slots.push(slot);
+        }
+        Ok(())
+    }
+
+    fn write_slot(&mut self, slot: u64, record: &[u8]) -> io::Result<()> {
+        self.inject_fault()?;
+        self.file.seek(SeekFrom::Start(slot * RECORD_SIZE))?;
+        self.file.write_all(record)
+    }
+}
+
+impl SpillStore {
+    #[cfg(feature = "failure-injection")]
+    fn inject_fault(&self) -> io::Result<()> {
+        match &self.faults {
+            Some(faults) if faults.should_fail() => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "injected storage timeout",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "failure-injection"))]
+    fn inject_fault(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SpillStore {
+    fn drop(&mut self) {
+        // The store only lives as long as the bank, nothing useful can be done if removal fails
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn corrupt(transaction_id: TransactionId) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Corrupt spilled record for transaction {}",
+            transaction_id.0
+        ),
+    )
+}
+
+fn encode_state(state: TransactionState) -> u8 {
+    match state {
+        TransactionState::Posted => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::Resolved => 2,
+        TransactionState::ChargedBack => 3,
+        TransactionState::Represented => 4,
+    }
+}
+
+fn decode_state(state: u8) -> Option<TransactionState> {
+    match state {
+        0 => Some(TransactionState::Posted),
+        1 => Some(TransactionState::Disputed),
+        2 => Some(TransactionState::Resolved),
+        3 => Some(TransactionState::ChargedBack),
+        4 => Some(TransactionState::Represented),
+        _ => None,
+    }
+}
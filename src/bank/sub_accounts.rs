@@ -0,0 +1,419 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::prelude::*;
+
+use super::{Account, Bank, ClientId, Outcome, Transaction};
+use crate::error::{TransactorError, TransactorError::*};
+use crate::metrics::Operation;
+
+/// The name of one of a client's accounts, such as `savings`, up to 16 ascii letters, digits, `-`
+/// or `_`. Every client has a `main` account, which takes everything not given an account.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct AccountName {
+    name: [u8; AccountName::MAX_LEN],
+    len: u8,
+}
+
+impl AccountName {
+    pub const MAX_LEN: usize = 16;
+    pub const MAIN: AccountName = AccountName {
+        name: *b"main\0\0\0\0\0\0\0\0\0\0\0\0",
+        len: 4,
+    };
+
+    pub fn as_str(&self) -> &str {
+        // Only ascii is ever stored
+        std::str::from_utf8(&self.name[..self.len as usize]).unwrap_or_default()
+    }
+
+    pub fn is_main(&self) -> bool {
+        *self == Self::MAIN
+    }
+}
+
+impl Default for AccountName {
+    fn default() -> Self {
+        Self::MAIN
+    }
+}
+
+impl fmt::Display for AccountName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AccountName {
+    type Err = TransactorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = !s.is_empty()
+            && s.len() <= Self::MAX_LEN
+            && s.bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+        if !valid {
+            return Err(InvalidAccountName(s.to_string()));
+        }
+        let mut name = [0; Self::MAX_LEN];
+        name[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Self {
+            name,
+            len: s.len() as u8,
+        })
+    }
+}
+
+impl Bank {
+    /// Run `op` against one of the client's accounts: every operation on the client within it
+    /// sees that account in place of the main one, with its own balances, transactions and
+    /// disputes. Transaction ids are still unique across all of the client's accounts.
+    ///
+    /// Fails without running `op` when spilling transaction history to disk, which tracks
+    /// transactions by client alone.
+    pub fn in_account<T>(
+        &mut self,
+        client_id: ClientId,
+        name: AccountName,
+        op: impl FnOnce(&mut Bank) -> Result<T, TransactorError>,
+    ) -> Result<T, TransactorError> {
+        if name.is_main() {
+            return op(self);
+        }
+        if self.spill.is_some() {
+            return Err(InvalidConfig(
+                "sub-accounts cannot be used while spilling transaction history to disk"
+                    .to_string(),
+            ));
+        }
+        // The main account is parked with the others for the duration
+        let accounts = self.sub_accounts.entry(client_id).or_default();
+        if let Some(main) = self.client_accounts.remove(&client_id) {
+            accounts.insert(AccountName::MAIN, main);
+        }
+        if let Some(selected) = accounts.remove(&name) {
+            self.client_accounts.insert(client_id, selected);
+        }
+        let result = op(self);
+        let accounts = self.sub_accounts.entry(client_id).or_default();
+        // The account is opened by the operation if it did not exist, as the main account is
+        if let Some(mut selected) = self.client_accounts.remove(&client_id) {
+            selected.name = name;
+            accounts.insert(name, selected);
+        }
+        if let Some(main) = accounts.remove(&AccountName::MAIN) {
+            self.client_accounts.insert(client_id, main);
+        }
+        if accounts.is_empty() {
+            self.sub_accounts.remove(&client_id);
+        }
+        result
+    }
+
+    /// Every named sub-account, ordered by client and then by name.
+    pub fn sub_accounts(&self) -> Vec<&Account> {
+        let mut accounts: Vec<&Account> = self
+            .sub_accounts
+            .values()
+            .flat_map(|accounts| accounts.values())
+            .collect();
+        accounts.sort_by_key(|account| (account.client_id.0, account.name));
+        accounts
+    }
+
+    /// One of the client's accounts, the same as `get_account` for the main account.
+    pub fn get_sub_account(&self, client_id: ClientId, name: AccountName) -> Option<&Account> {
+        match name.is_main() {
+            true => self.get_account(client_id),
+            false => self.sub_accounts.get(&client_id)?.get(&name),
+        }
+    }
+
+    /// Move funds from one of the client's accounts to another, recorded under the id of the
+    /// transaction as a withdrawal from one and a deposit to the other. The positive amount is
+    /// moved whole or not at all, ignored when the source has too little available or either
+    /// account refuses it.
+    pub fn move_funds(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+        from: AccountName,
+        to: AccountName,
+    ) -> Result<Outcome, TransactorError> {
+        if from == to {
+            return Err(SameAccountMove(from));
+        }
+        // The destination is checked first so the withdrawal is only made if the deposit can be
+        if let Some(refused) = self.in_account(client_id, to, |bank| {
+            bank.deposit_refused(client_id, &transaction)
+        })? {
+            return Ok(refused);
+        }
+        let withdrawal = Transaction {
+            amount: -transaction.amount,
+            ..transaction
+        };
+        match self.in_account(client_id, from, |bank| bank.transact(client_id, withdrawal))? {
+            Outcome::Applied => {}
+            ignored => return Ok(ignored),
+        }
+        self.in_account(client_id, to, |bank| {
+            bank.measured(Operation::Deposit, |bank| {
+                bank.post_transaction(client_id, transaction, Operation::Deposit, Decimal::zero())
+            })
+        })
+    }
+
+    /// Why the account would not take the deposit, None if it would. Fails as the deposit would.
+    fn deposit_refused(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+    ) -> Result<Option<Outcome>, TransactorError> {
+        self.check_amount(client_id, transaction)?;
+        if let Some(reason) = self.refused(client_id, Operation::Deposit) {
+            return self
+                .refusal_policy
+                .refuse(
+                    reason,
+                    AccountLocked {
+                        client: client_id,
+                        tx: transaction.transaction_id,
+                    },
+                )
+                .map(Some);
+        }
+        if self.is_used(client_id, transaction.transaction_id)? {
+            return Err(TransactionIdReuse);
+        }
+        let account = self.account_mut(client_id);
+        match (account.currency, transaction.currency) {
+            (Some(expected), Some(found)) if expected != found => {
+                Err(CurrencyMismatch { expected, found })
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{IgnoreReason, OverdraftPolicy, TransactionId, TransactionState};
+
+    #[test]
+    fn sub_accounts_keep_their_own_balances_and_disputes() -> Result<(), TransactorError> {
+        let client = ClientId(1);
+        let savings: AccountName = "savings".parse()?;
+        let mut bank = Bank::new();
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(10, 0)),
+        )?;
+        bank.in_account(client, savings, |bank| {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(2), Decimal::new(5, 0)),
+            )
+        })?;
+        // Ids are shared by all of the client's accounts
+        assert!(matches!(
+            bank.in_account(client, savings, |bank| bank.transact(
+                client,
+                Transaction::new(TransactionId(1), Decimal::new(1, 0))
+            )),
+            Err(TransactionIdReuse)
+        ));
+
+        let moved = Transaction::new(TransactionId(3), Decimal::new(4, 0));
+        assert_eq!(
+            bank.move_funds(client, moved, AccountName::MAIN, savings)?,
+            Outcome::Applied
+        );
+        let too_much = Transaction::new(TransactionId(4), Decimal::new(7, 0));
+        assert_eq!(
+            bank.move_funds(client, too_much, AccountName::MAIN, savings)?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            bank.get_account(client).unwrap().available(),
+            Decimal::new(6, 0)
+        );
+        let account = bank.get_sub_account(client, savings).unwrap();
+        assert_eq!(account.available(), Decimal::new(9, 0));
+        assert_eq!(account.name(), savings);
+
+        // A dispute only finds the transaction in the account it names
+        assert_eq!(
+            bank.dispute_transaction(client, TransactionId(2))?,
+            Outcome::Ignored(IgnoreReason::UnknownTransaction)
+        );
+        bank.in_account(client, savings, |bank| {
+            bank.dispute_transaction(client, TransactionId(2))
+        })?;
+        let account = bank.get_sub_account(client, savings).unwrap();
+        assert_eq!(account.held(), Decimal::new(5, 0));
+        assert_eq!(bank.get_account(client).unwrap().held(), Decimal::zero());
+        assert_eq!(bank.sub_accounts().len(), 1);
+
+        assert!(matches!(
+            bank.move_funds(client, moved, savings, savings),
+            Err(SameAccountMove(_))
+        ));
+        assert!("a savings account".parse::<AccountName>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn moves_are_bounded_by_the_source_and_disputes_by_their_account() -> Result<(), TransactorError>
+    {
+        let client = ClientId(1);
+        let savings: AccountName = "savings".parse()?;
+        let mut bank = Bank::new().with_overdraft_policy(OverdraftPolicy::new(Decimal::new(5, 0)));
+        bank.in_account(client, savings, |bank| {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(1), Decimal::new(10, 0)),
+            )
+        })?;
+        // The source may be overdrawn down to its limit, and no further
+        let moved = Transaction::new(TransactionId(2), Decimal::new(12, 0));
+        assert_eq!(
+            bank.move_funds(client, moved, savings, AccountName::MAIN)?,
+            Outcome::Applied
+        );
+        let too_much = Transaction::new(TransactionId(3), Decimal::new(4, 0));
+        assert_eq!(
+            bank.move_funds(client, too_much, savings, AccountName::MAIN)?,
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        let account = bank.get_sub_account(client, savings).unwrap();
+        assert_eq!(account.available(), Decimal::new(-2, 0));
+        assert_eq!(
+            bank.get_account(client).unwrap().available(),
+            Decimal::new(12, 0)
+        );
+
+        // The deposit is disputed where it was made, though its funds have since moved
+        bank.in_account(client, savings, |bank| {
+            bank.dispute_transaction(client, TransactionId(1))
+        })?;
+        let account = bank.get_sub_account(client, savings).unwrap();
+        assert_eq!(account.available(), Decimal::new(-12, 0));
+        assert_eq!(account.held(), Decimal::new(10, 0));
+        bank.in_account(client, savings, |bank| {
+            bank.chargeback(client, TransactionId(1))
+        })?;
+        let account = bank.get_sub_account(client, savings).unwrap();
+        assert_eq!(account.total()?, Decimal::new(-12, 0));
+        assert!(account.is_locked());
+        let main = bank.get_account(client).unwrap();
+        assert_eq!(main.available(), Decimal::new(12, 0));
+        assert_eq!(main.held(), Decimal::zero());
+        Ok(())
+    }
+
+    #[test]
+    fn merges_take_over_sub_accounts_and_check_them_for_clashes() -> Result<(), TransactorError> {
+        let savings: AccountName = "savings".parse()?;
+        let deposit = |id, amount| Transaction::new(TransactionId(id), Decimal::new(amount, 0));
+        let mut first = Bank::new();
+        first.in_account(ClientId(1), savings, |bank| {
+            bank.transact(ClientId(1), deposit(1, 5))
+        })?;
+        let mut second = Bank::new();
+        second.transact(ClientId(2), deposit(2, 3))?;
+        second.in_account(ClientId(2), savings, |bank| {
+            bank.transact(ClientId(2), deposit(3, 4))?;
+            bank.dispute_transaction(ClientId(2), TransactionId(3))
+        })?;
+        first.merge(second)?;
+        let account = first.get_sub_account(ClientId(2), savings).unwrap();
+        assert_eq!(account.held(), Decimal::new(4, 0));
+        assert_eq!(
+            first.get_account(ClientId(2)).unwrap().available(),
+            Decimal::new(3, 0)
+        );
+        first.check_invariants()?;
+        // The dispute carried over can still be resolved
+        assert_eq!(
+            first.in_account(ClientId(2), savings, |bank| bank
+                .resolve_disputed_transaction(ClientId(2), TransactionId(3)))?,
+            Outcome::Applied
+        );
+
+        // A client with only a sub-account here still clashes
+        let mut same_client = Bank::new();
+        same_client.transact(ClientId(1), deposit(4, 1))?;
+        assert!(matches!(
+            first.merge(same_client),
+            Err(AccountExists { .. })
+        ));
+        // As does an id used by a sub-account on either side
+        let mut same_transaction = Bank::new();
+        same_transaction.in_account(ClientId(3), savings, |bank| {
+            bank.transact(ClientId(3), deposit(1, 1))
+        })?;
+        assert!(matches!(
+            first.merge(same_transaction),
+            Err(DuplicateTransaction { .. })
+        ));
+        assert!(first.get_account(ClientId(3)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn closing_a_period_archives_sub_accounts_too() -> Result<(), TransactorError> {
+        let client = ClientId(1);
+        let savings: AccountName = "savings".parse()?;
+        let mut bank = Bank::new();
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(2, 0)),
+        )?;
+        bank.in_account(client, savings, |bank| {
+            for id in 2..=4 {
+                bank.transact(
+                    client,
+                    Transaction::new(TransactionId(id), Decimal::new(5, 0)),
+                )?;
+            }
+            bank.dispute_transaction(client, TransactionId(2))?;
+            bank.chargeback(client, TransactionId(2))?;
+            bank.dispute_transaction(client, TransactionId(3))
+        })?;
+
+        let archived: Vec<(u32, TransactionState)> = bank
+            .close_period()
+            .iter()
+            .map(|found| (found.transaction.transaction_id.0, found.state))
+            .collect();
+        assert_eq!(
+            archived,
+            vec![
+                (1, TransactionState::Posted),
+                (2, TransactionState::ChargedBack),
+                (4, TransactionState::Posted),
+            ]
+        );
+        let account = bank.get_sub_account(client, savings).unwrap();
+        assert_eq!(account.transaction_count(), 1);
+        assert_eq!(account.chargebacks(), 0);
+        assert_eq!(account.open_disputes(), 1);
+        assert!(account.dispute_history(TransactionId(2)).is_empty());
+        assert_eq!(account.dispute_history(TransactionId(3)).len(), 1);
+        bank.check_invariants()?;
+        // The archived chargeback is no longer known, the open dispute still is
+        bank.in_account(client, savings, |bank| {
+            assert_eq!(
+                bank.resolve_disputed_transaction(client, TransactionId(2))?,
+                Outcome::Ignored(IgnoreReason::UnknownTransaction)
+            );
+            assert_eq!(
+                bank.resolve_disputed_transaction(client, TransactionId(3))?,
+                Outcome::Applied
+            );
+            Ok(())
+        })
+    }
+}
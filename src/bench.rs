@@ -0,0 +1,209 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bank::Bank;
+use crate::error::TransactorError;
+use crate::input::{InputReader, InputRecord, InputSchema};
+
+/// A synthetic input of `rows` rows spread over `clients` clients, for benchmarking without a
+/// real input at hand. Most rows are deposits and withdrawals, with disputes of earlier deposits
+/// and resolves and chargebacks of earlier disputes mixed in. The same seed gives the same input.
+pub fn generate_workload(rows: usize, clients: u16, seed: u64) -> Vec<u8> {
+    let clients = u64::from(clients.max(1));
+    // xorshift never leaves a zero state
+    let mut state = seed.max(1);
+    let mut random = move |below: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % below
+    };
+    let mut deposits: Vec<(u64, usize)> = Vec::new();
+    let mut disputes: Vec<(u64, usize)> = Vec::new();
+    let mut workload = String::from("type,client,tx,amount\n");
+    for tx in 1..=rows {
+        let roll = random(100);
+        let row = match roll {
+            0..=9 if !deposits.is_empty() => {
+                let (client, deposit) = deposits[random(deposits.len() as u64) as usize];
+                disputes.push((client, deposit));
+                format!("dispute,{},{},\n", client, deposit)
+            }
+            10..=14 if !disputes.is_empty() => {
+                let (client, disputed) =
+                    disputes.swap_remove(random(disputes.len() as u64) as usize);
+                let kind = if roll == 14 { "chargeback" } else { "resolve" };
+                format!("{},{},{},\n", kind, client, disputed)
+            }
+            _ => {
+                let client = random(clients) + 1;
+                let amount = random(10_000_000);
+                if roll < 45 {
+                    let (units, cents) = (amount / 10_000, amount % 100);
+                    format!("withdrawal,{},{},{}.{:02}\n", client, tx, units, cents)
+                } else {
+                    deposits.push((client, tx));
+                    let (units, fraction) = (amount / 10_000, amount % 10_000);
+                    format!("deposit,{},{},{}.{:04}\n", client, tx, units, fraction)
+                }
+            }
+        };
+        workload.push_str(&row);
+    }
+    workload.into_bytes()
+}
+
+/// Runs inputs through a bank in separate phases, timing each, to measure the effect of changes
+/// such as parallel parsing without an external harness.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    inputs: Vec<Arc<[u8]>>,
+    schema: InputSchema,
+    parse_threads: usize,
+}
+
+/// The time each phase of one benchmark run took.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BenchRun {
+    pub rows: u64,
+    /// Rows that could not be read or that the bank rejected.
+    pub failed: u64,
+    pub parse: Duration,
+    pub apply: Duration,
+    pub write: Duration,
+}
+
+impl Benchmark {
+    /// Benchmark the inputs, each a whole csv file with its header, applied in order.
+    pub fn new(inputs: Vec<Vec<u8>>, schema: InputSchema) -> Self {
+        Self {
+            inputs: inputs.into_iter().map(Arc::from).collect(),
+            schema,
+            parse_threads: 0,
+        }
+    }
+
+    /// Parse on `threads` threads as `InputReader::parallel` does, 0 parses on this thread.
+    pub fn with_parse_threads(mut self, threads: usize) -> Self {
+        self.parse_threads = threads;
+        self
+    }
+
+    /// Run once: parse every row, then apply them all to the bank and then write the accounts
+    /// with `write`.
+    pub fn run<F>(&self, bank: &mut Bank, write: F) -> Result<BenchRun, TransactorError>
+    where
+        F: FnOnce(&Bank) -> Result<(), TransactorError>,
+    {
+        let mut run = BenchRun::default();
+        let started = Instant::now();
+        let mut records: Vec<Result<InputRecord, TransactorError>> = Vec::new();
+        for input in &self.inputs {
+            let reader = InputReader::from_reader(Cursor::new(Arc::clone(input)), self.schema)?;
+            match self.parse_threads {
+                0 => records.extend(reader),
+                threads => records.extend(reader.parallel(threads)),
+            }
+        }
+        run.parse = started.elapsed();
+
+        let started = Instant::now();
+        for result in records {
+            run.rows += 1;
+            let applied = result.and_then(|record| record.command.apply_in(bank, record.account));
+            run.failed += u64::from(applied.is_err());
+        }
+        run.apply = started.elapsed();
+
+        let started = Instant::now();
+        write(bank)?;
+        run.write = started.elapsed();
+        Ok(run)
+    }
+}
+
+impl BenchRun {
+    pub fn elapsed(&self) -> Duration {
+        self.parse + self.apply + self.write
+    }
+
+    pub fn rows_per_second(&self) -> f64 {
+        match self.elapsed().as_secs_f64() {
+            seconds if seconds > 0.0 => self.rows as f64 / seconds,
+            _ => 0.0,
+        }
+    }
+
+    /// The mean of each phase over the runs, None without any.
+    pub fn mean(runs: &[BenchRun]) -> Option<BenchRun> {
+        let count = u32::try_from(runs.len()).ok().filter(|count| *count > 0)?;
+        let total = |phase: fn(&BenchRun) -> Duration| runs.iter().map(phase).sum::<Duration>();
+        Some(BenchRun {
+            rows: runs.iter().map(|run| run.rows).sum::<u64>() / u64::from(count),
+            failed: runs.iter().map(|run| run.failed).sum::<u64>() / u64::from(count),
+            parse: total(|run| run.parse) / count,
+            apply: total(|run| run.apply) / count,
+            write: total(|run| run.write) / count,
+        })
+    }
+}
+
+impl fmt::Display for BenchRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rows ({} failed) in {:.3}s, {:.0} rows/s: parse {:.3}s, apply {:.3}s, write {:.3}s",
+            self.rows,
+            self.failed,
+            self.elapsed().as_secs_f64(),
+            self.rows_per_second(),
+            self.parse.as_secs_f64(),
+            self.apply.as_secs_f64(),
+            self.write.as_secs_f64()
+        )
+    }
+}
+
+/// The most memory this process has had resident in bytes, None where the platform does not say.
+pub fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::ClientId;
+
+    #[test]
+    fn generated_workloads_are_timed_by_phase() -> Result<(), TransactorError> {
+        let workload = generate_workload(2000, 10, 7);
+        assert_eq!(workload, generate_workload(2000, 10, 7));
+        let benchmark = Benchmark::new(vec![workload], InputSchema::V1).with_parse_threads(2);
+        let mut bank = Bank::new();
+        let mut written = false;
+        let run = benchmark.run(&mut bank, |_| {
+            written = true;
+            Ok(())
+        })?;
+        assert!(written);
+        assert_eq!(run.rows, 2000);
+        assert!(run.failed < 100);
+        assert!(bank.get_account(ClientId(10)).is_some());
+        assert_eq!(BenchRun::mean(&[run, run]), Some(run));
+        assert_eq!(BenchRun::mean(&[]), None);
+        Ok(())
+    }
+}
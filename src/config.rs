@@ -0,0 +1,831 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+use rust_decimal::prelude::*;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::bank::{
+    Bank, ClientDirectory, ClientId, DisputePolicy, FeeRule, FeeSchedule, LockedAccountPolicy,
+    OverdraftPolicy, RecoveryPolicy, RefusalPolicy, RepresentmentPolicy, RiskLimits, StatusRule,
+    StatusRules,
+};
+use crate::currency::{PrecisionTable, Rounding};
+use crate::error::{RecordPosition, TransactorError, TransactorError::*};
+use crate::input::ImportFormat;
+use crate::report::Partitioning;
+
+/// The settings of a run. The engine settings are read from a TOML file keyed by the long names
+/// of the command line flags, in a table for each part of the engine, for example
+///
+/// ```toml
+/// fees = ["withdrawal=0.25+1%", "chargeback=15"]
+///
+/// [disputes]
+/// dispute-policy = "deposits-only"
+///
+/// [accounts]
+/// refusal-policy = "error"
+/// status-rules = ["frozen=dispute,resolve,chargeback"]
+///
+/// [limits]
+/// overdraft-limit = "100.00"
+/// max-chargebacks = 2
+/// max-amount = "1000000"
+///
+/// [currencies]
+/// currency-config = "currencies.csv"
+/// ```
+///
+/// Every setting is optional, those left out keep the defaults of `Bank`. Relative paths are
+/// taken as they are, from the working directory. The settings of where a run reads from and
+/// writes to are only given on the command line, they are kept here with the rest so that
+/// `validate` can check them against each other.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub disputes: DisputeSettings,
+    #[serde(default)]
+    pub accounts: AccountSettings,
+    #[serde(default)]
+    pub limits: LimitSettings,
+    #[serde(default)]
+    pub transactions: TransactionSettings,
+    #[serde(default)]
+    pub currencies: CurrencySettings,
+    /// The fee charged on each operation, see `FeeRule`.
+    #[serde(default, deserialize_with = "parsed_list")]
+    pub fees: Option<Vec<FeeRule>>,
+    /// A csv file of client,name,email,risk_tier with metadata of clients for reports.
+    pub clients: Option<PathBuf>,
+    #[serde(skip)]
+    pub input: InputSettings,
+    #[serde(skip)]
+    pub output: OutputSettings,
+    #[serde(skip)]
+    pub sla: SlaSettings,
+    #[serde(skip)]
+    pub operations: OperationSettings,
+    /// Whether the run is checked against the reference model, which only models the default
+    /// rules starting from no accounts.
+    #[serde(skip)]
+    pub verify: bool,
+}
+
+/// How disputes and the chargebacks ending them are handled.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DisputeSettings {
+    #[serde(default, deserialize_with = "parsed")]
+    pub dispute_policy: Option<DisputePolicy>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub representment_policy: Option<RepresentmentPolicy>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub recovery_policy: Option<RecoveryPolicy>,
+    /// How many times one transaction may be disputed.
+    pub max_dispute_cycles: Option<u32>,
+}
+
+impl DisputeSettings {
+    fn overridden_by(self, overrides: Self) -> Self {
+        Self {
+            dispute_policy: overrides.dispute_policy.or(self.dispute_policy),
+            representment_policy: overrides.representment_policy.or(self.representment_policy),
+            recovery_policy: overrides.recovery_policy.or(self.recovery_policy),
+            max_dispute_cycles: overrides.max_dispute_cycles.or(self.max_dispute_cycles),
+        }
+    }
+}
+
+/// What accounts refuse, and how.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AccountSettings {
+    #[serde(default, deserialize_with = "parsed")]
+    pub refusal_policy: Option<RefusalPolicy>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub locked_account_policy: Option<LockedAccountPolicy>,
+    /// The operations accounts in a status accept, see `StatusRule`.
+    #[serde(default, deserialize_with = "parsed_list")]
+    pub status_rules: Option<Vec<StatusRule>>,
+}
+
+impl AccountSettings {
+    fn overridden_by(self, overrides: Self) -> Self {
+        Self {
+            refusal_policy: overrides.refusal_policy.or(self.refusal_policy),
+            locked_account_policy: overrides
+                .locked_account_policy
+                .or(self.locked_account_policy),
+            status_rules: overrides.status_rules.or(self.status_rules),
+        }
+    }
+}
+
+/// How far accounts may go, overdrawn or with suspicious activity, and how large a transaction
+/// may be.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LimitSettings {
+    pub overdraft_limit: Option<Decimal>,
+    /// A csv file of client,limit giving accounts their own overdraft limit.
+    pub overdraft_limits: Option<PathBuf>,
+    pub max_chargebacks: Option<u32>,
+    pub max_open_disputes: Option<u32>,
+    /// The largest amount of a single deposit, withdrawal or authorization.
+    pub max_amount: Option<Decimal>,
+}
+
+impl LimitSettings {
+    fn overridden_by(self, overrides: Self) -> Self {
+        Self {
+            overdraft_limit: overrides.overdraft_limit.or(self.overdraft_limit),
+            overdraft_limits: overrides.overdraft_limits.or(self.overdraft_limits),
+            max_chargebacks: overrides.max_chargebacks.or(self.max_chargebacks),
+            max_open_disputes: overrides.max_open_disputes.or(self.max_open_disputes),
+            max_amount: overrides.max_amount.or(self.max_amount),
+        }
+    }
+}
+
+/// How transaction ids are checked and transaction history is kept.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TransactionSettings {
+    pub tx_index: Option<bool>,
+    pub global_tx_ids: Option<bool>,
+    pub spill_dir: Option<PathBuf>,
+    pub spill_threshold: Option<usize>,
+}
+
+impl TransactionSettings {
+    fn overridden_by(self, overrides: Self) -> Self {
+        Self {
+            tx_index: overrides.tx_index.or(self.tx_index),
+            global_tx_ids: overrides.global_tx_ids.or(self.global_tx_ids),
+            spill_dir: overrides.spill_dir.or(self.spill_dir),
+            spill_threshold: overrides.spill_threshold.or(self.spill_threshold),
+        }
+    }
+}
+
+/// The precision of amounts in each currency.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CurrencySettings {
+    /// A csv file of currency,decimal_places,rounding.
+    pub currency_config: Option<PathBuf>,
+    /// How amounts are rounded for output, overriding the rounding of each currency.
+    #[serde(default, deserialize_with = "parsed")]
+    pub rounding: Option<Rounding>,
+}
+
+impl CurrencySettings {
+    fn overridden_by(self, overrides: Self) -> Self {
+        Self {
+            currency_config: overrides.currency_config.or(self.currency_config),
+            rounding: overrides.rounding.or(self.rounding),
+        }
+    }
+}
+
+/// Where a run reads its transactions and starting accounts from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputSettings {
+    /// Whether any input file is given.
+    pub files: bool,
+    pub state: Option<PathBuf>,
+    pub initial_balances: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: bool,
+    pub dispute_outcomes: Option<PathBuf>,
+    pub import: Option<ImportFormat>,
+    pub import_client: Option<u16>,
+    pub avro_schema: Option<PathBuf>,
+    pub avro_registry: Option<String>,
+    pub protobuf: bool,
+    /// Only rows timestamped from this time, in seconds since the unix epoch, are applied.
+    pub from: Option<u64>,
+    /// Only rows timestamped before this time are applied.
+    pub to: Option<u64>,
+}
+
+/// Where a run writes its account report and the records of the run to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OutputSettings {
+    /// Whether only the accounts changed from those of the state are reported.
+    pub changed_only: bool,
+    pub partition_by: Option<Partitioning>,
+    pub partition_dir: Option<PathBuf>,
+    pub journal: Option<PathBuf>,
+    pub history: Option<PathBuf>,
+    /// The most rows that may fail and be skipped.
+    pub max_errors: Option<usize>,
+    pub error_report: Option<PathBuf>,
+}
+
+/// How long disputes may stay open and where those open too long are reported.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SlaSettings {
+    /// The seconds a dispute may stay open.
+    pub dispute_sla: Option<u64>,
+    pub report: Option<PathBuf>,
+    pub webhook: Option<String>,
+}
+
+/// The operations applied besides those of the input.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OperationSettings {
+    /// A csv file of standing orders, taken up to `as_of`.
+    pub recurring: Option<PathBuf>,
+    pub as_of: Option<u64>,
+    pub admin_ops: Option<PathBuf>,
+    /// Whether the admin operations are only previewed.
+    pub admin_dry_run: bool,
+    /// The seconds an authorization may stay unsettled.
+    pub authorization_expiry: Option<u64>,
+}
+
+impl Config {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(config: &str) -> Result<Self, TransactorError> {
+        toml::from_str(config).map_err(|e| InvalidConfig(format!("invalid config file: {}", e)))
+    }
+
+    /// These settings with every engine setting given in `overrides` taking its place. The
+    /// settings only given on the command line are those of `overrides`.
+    pub fn overridden_by(self, overrides: Config) -> Self {
+        Self {
+            disputes: self.disputes.overridden_by(overrides.disputes),
+            accounts: self.accounts.overridden_by(overrides.accounts),
+            limits: self.limits.overridden_by(overrides.limits),
+            transactions: self.transactions.overridden_by(overrides.transactions),
+            currencies: self.currencies.overridden_by(overrides.currencies),
+            fees: overrides.fees.or(self.fees),
+            clients: overrides.clients.or(self.clients),
+            input: overrides.input,
+            output: overrides.output,
+            sla: overrides.sla,
+            operations: overrides.operations,
+            verify: overrides.verify,
+        }
+    }
+
+    /// Check the settings that depend on each other are given together, failing with the first
+    /// combination that is not allowed, named by its command line flags.
+    pub fn validate(&self) -> Result<(), TransactorError> {
+        let (input, output, operations) = (&self.input, &self.output, &self.operations);
+        let avro = input.avro_schema.is_some() || input.avro_registry.is_some();
+        let checks = [
+            (
+                input.checkpoint.is_some() && (!input.files || input.dispute_outcomes.is_some()),
+                "--checkpoint requires an input file and no --dispute-outcomes",
+            ),
+            (
+                input.resume && input.checkpoint.is_none(),
+                "--resume requires --checkpoint",
+            ),
+            (
+                input.dispute_outcomes.is_some() && !input.files,
+                "--dispute-outcomes requires an input file",
+            ),
+            (
+                input.import.is_some() && input.resume,
+                "--import numbers the lines of a whole run so takes no --resume",
+            ),
+            (
+                input.import.is_some() && input.import_client.is_none(),
+                "--import requires --import-client",
+            ),
+            (
+                input.import.is_none() && input.import_client.is_some(),
+                "--import-client requires --import",
+            ),
+            (
+                input.avro_schema.is_some() && input.avro_registry.is_some(),
+                "--avro-schema and --avro-registry cannot be used together",
+            ),
+            (
+                avro && input.import.is_some(),
+                "--import reads statements, not Avro messages",
+            ),
+            (
+                input.protobuf && (input.import.is_some() || avro),
+                "--protobuf cannot be used with --import, --avro-schema or --avro-registry",
+            ),
+            (
+                output.changed_only
+                    && (input.state.is_none() || input.resume || output.partition_by.is_some()),
+                "--output-changed-only requires --state and no --resume or --partition-output-by",
+            ),
+            (
+                output.partition_dir.is_some() && output.partition_by.is_none(),
+                "--partition-dir requires --partition-output-by",
+            ),
+            (
+                output.journal.is_some()
+                    && (input.resume
+                        || operations.recurring.is_some()
+                        || operations.admin_ops.is_some()),
+                "--journal only posts the input rows of a whole run, so takes no --resume, \
+                 --recurring or --admin-ops",
+            ),
+            (
+                output.history.is_some() && input.resume,
+                "--history holds the rows of a whole run, so takes no --resume",
+            ),
+            (
+                output.error_report.is_some() && output.max_errors.is_none(),
+                "--error-report requires --max-errors",
+            ),
+            (
+                self.sla.report.is_some() && self.sla.dispute_sla.is_none(),
+                "--dispute-sla-report requires --dispute-sla",
+            ),
+            (
+                self.sla.webhook.is_some() && self.sla.dispute_sla.is_none(),
+                "--sla-webhook requires --dispute-sla",
+            ),
+            (
+                operations.recurring.is_some() && operations.as_of.is_none(),
+                "--recurring requires --as-of",
+            ),
+            (
+                operations.recurring.is_none() && operations.as_of.is_some(),
+                "--as-of requires --recurring",
+            ),
+            (
+                operations.admin_dry_run && operations.admin_ops.is_none(),
+                "--admin-dry-run requires --admin-ops",
+            ),
+            (
+                self.verify && !self.is_modelled(),
+                "--verify only models the default rules starting from no accounts",
+            ),
+        ];
+        if let Some((_, message)) = checks.iter().find(|(refused, _)| *refused) {
+            return Err(InvalidConfig(message.to_string()));
+        }
+        match (input.from, input.to) {
+            (Some(from), Some(to)) if from >= to => Err(InvalidConfig(format!(
+                "--from {} is not before --to {}",
+                from, to
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether any limit on suspicious activity is set.
+    pub fn has_risk_limits(&self) -> bool {
+        self.limits.max_chargebacks.is_some() || self.limits.max_open_disputes.is_some()
+    }
+
+    /// Whether a bank with these settings follows the default rules, differing only in how it
+    /// stores history and the precision of currencies.
+    pub fn has_default_rules(&self) -> bool {
+        let (disputes, accounts, limits) = (&self.disputes, &self.accounts, &self.limits);
+        disputes.dispute_policy.unwrap_or_default() == DisputePolicy::default()
+            && accounts.refusal_policy.unwrap_or_default() == RefusalPolicy::default()
+            && disputes.representment_policy.unwrap_or_default() == RepresentmentPolicy::default()
+            && accounts.locked_account_policy.unwrap_or_default() == LockedAccountPolicy::default()
+            && limits.overdraft_limit.is_none_or(|limit| limit.is_zero())
+            && limits.overdraft_limits.is_none()
+            && !self.has_risk_limits()
+            && limits.max_amount.is_none()
+            && disputes.max_dispute_cycles.is_none()
+            && accounts.status_rules.is_none()
+            && self.fees.is_none()
+            && self.transactions.tx_index != Some(true)
+            && self.transactions.global_tx_ids != Some(true)
+    }
+
+    /// Whether the reference model can follow a run with these settings: the default rules,
+    /// starting from no accounts and applying every row of the input alone.
+    fn is_modelled(&self) -> bool {
+        self.has_default_rules()
+            && self.input.state.is_none()
+            && self.input.initial_balances.is_none()
+            && !self.input.resume
+            && self.input.from.is_none()
+            && self.operations.recurring.is_none()
+            && self.operations.authorization_expiry.is_none()
+    }
+
+    /// A bank with these settings. This fails if a file named by the settings cannot be read, or
+    /// an overdraft limit or the max amount is negative.
+    pub fn bank(&self) -> Result<Bank, TransactorError> {
+        let (disputes, accounts, limits) = (&self.disputes, &self.accounts, &self.limits);
+        let mut bank = Bank::new()
+            .with_dispute_policy(disputes.dispute_policy.unwrap_or_default())
+            .with_refusal_policy(accounts.refusal_policy.unwrap_or_default())
+            .with_representment_policy(disputes.representment_policy.unwrap_or_default())
+            .with_locked_account_policy(accounts.locked_account_policy.unwrap_or_default())
+            .with_recovery_policy(disputes.recovery_policy.unwrap_or_default())
+            .with_overdraft_policy(self.overdraft_policy()?);
+        if self.has_risk_limits() {
+            bank = bank.with_risk_limits(RiskLimits {
+                max_chargebacks: limits.max_chargebacks,
+                max_open_disputes: limits.max_open_disputes,
+            });
+        }
+        if let Some(max_amount) = limits.max_amount {
+            if max_amount < Decimal::zero() {
+                return Err(InvalidConfig(format!(
+                    "max amount {} is negative, it is the largest amount of one transaction",
+                    max_amount
+                )));
+            }
+            bank = bank.with_max_amount(max_amount);
+        }
+        if let Some(max_dispute_cycles) = disputes.max_dispute_cycles {
+            bank = bank.with_max_dispute_cycles(max_dispute_cycles);
+        }
+        if let Some(status_rules) = &accounts.status_rules {
+            let rules = status_rules
+                .iter()
+                .cloned()
+                .fold(StatusRules::default(), StatusRules::with_rule);
+            bank = bank.with_status_rules(rules);
+        }
+        if let Some(fees) = &self.fees {
+            let schedule = fees
+                .iter()
+                .copied()
+                .fold(FeeSchedule::default(), FeeSchedule::with_rule);
+            bank = bank.with_fee_schedule(schedule);
+        }
+        bank = bank.with_precision(self.precision()?);
+        let transactions = &self.transactions;
+        if transactions.tx_index == Some(true) {
+            bank = bank.with_transaction_index();
+        }
+        if transactions.global_tx_ids == Some(true) {
+            bank = bank.with_global_transaction_ids();
+        }
+        if let Some(clients) = &self.clients {
+            bank = bank.with_client_directory(ClientDirectory::from_path(clients)?);
+        }
+        if let Some(spill_dir) = &transactions.spill_dir {
+            let threshold = transactions.spill_threshold.unwrap_or(1_000_000);
+            bank = bank.with_spill(spill_dir, threshold)?;
+        }
+        Ok(bank)
+    }
+
+    /// The precision of each currency, 4 decimal places for all of them unless a currency config
+    /// is set, rounded with the rounding if one is set.
+    pub fn precision(&self) -> Result<PrecisionTable, TransactorError> {
+        let table = match &self.currencies.currency_config {
+            Some(currency_config) => PrecisionTable::from_reader(File::open(currency_config)?)?,
+            None => PrecisionTable::new(),
+        };
+        Ok(match self.currencies.rounding {
+            Some(rounding) => table.with_rounding(rounding),
+            None => table,
+        })
+    }
+
+    fn overdraft_policy(&self) -> Result<OverdraftPolicy, TransactorError> {
+        let default_limit = self.limits.overdraft_limit.unwrap_or_default();
+        if default_limit < Decimal::zero() {
+            return Err(negative_limit(default_limit));
+        }
+        let mut policy = OverdraftPolicy::new(default_limit);
+        let filename = match &self.limits.overdraft_limits {
+            Some(filename) => filename,
+            None => return Ok(policy),
+        };
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(filename)?;
+        let headers = reader.headers()?.clone();
+        let mut row = StringRecord::new();
+        while reader.read_record(&mut row)? {
+            let mut position = RecordPosition::new(row.position().map_or(0, |p| p.line()));
+            let record: OverdraftLimitRecord = row
+                .deserialize(Some(&headers))
+                .map_err(|e| TransactorError::from(e).at(position))?;
+            position.client = Some(ClientId(record.client));
+            if record.limit < Decimal::zero() {
+                return Err(negative_limit(record.limit).at(position));
+            }
+            policy = policy.with_limit(ClientId(record.client), record.limit);
+        }
+        Ok(policy)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OverdraftLimitRecord {
+    client: u16,
+    limit: Decimal,
+}
+
+fn negative_limit(limit: Decimal) -> TransactorError {
+    InvalidConfig(format!(
+        "overdraft limit {} is negative, limits are how far below zero an account may go",
+        limit
+    ))
+}
+
+/// Deserialize a setting written the way its command line flag is.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Deserialize a list of settings each written the way its command line flag is.
+fn parsed_list<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    let values = Vec::<String>::deserialize(deserializer)?;
+    values
+        .iter()
+        .map(|value| value.parse())
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn settings_given_on_the_command_line_override_the_file() -> Result<(), TransactorError> {
+        let file = Config::from_toml(
+            r#"
+            [disputes]
+            dispute-policy = "deposits-only"
+            representment-policy = "keep-locked"
+
+            [accounts]
+            refusal-policy = "error"
+            locked-account-policy = "block-withdrawals-only"
+            status-rules = ["frozen=dispute,resolve"]
+
+            [limits]
+            overdraft-limit = "10.5"
+            max-chargebacks = 2
+
+            [currencies]
+            rounding = "half-up"
+            "#,
+        )?;
+        assert_eq!(file.limits.overdraft_limit, Some(Decimal::new(105, 1)));
+        let config = file.overridden_by(Config {
+            accounts: AccountSettings {
+                refusal_policy: Some(RefusalPolicy::Ignore),
+                ..AccountSettings::default()
+            },
+            limits: LimitSettings {
+                overdraft_limit: Some(Decimal::new(20, 0)),
+                ..LimitSettings::default()
+            },
+            ..Config::default()
+        });
+        assert_eq!(
+            config.disputes.dispute_policy,
+            Some(DisputePolicy::DepositsOnly)
+        );
+        assert_eq!(config.accounts.refusal_policy, Some(RefusalPolicy::Ignore));
+        assert_eq!(
+            config.disputes.representment_policy,
+            Some(RepresentmentPolicy::KeepLocked)
+        );
+        assert_eq!(config.limits.overdraft_limit, Some(Decimal::new(20, 0)));
+        assert_eq!(
+            config.accounts.locked_account_policy,
+            Some(LockedAccountPolicy::BlockWithdrawalsOnly)
+        );
+        assert_eq!(config.limits.max_chargebacks, Some(2));
+        assert!(config
+            .accounts
+            .status_rules
+            .as_ref()
+            .is_some_and(|rules| rules[0].accepted.len() == 2));
+        assert_eq!(
+            config.precision()?.precision(None).rounding,
+            Rounding::HalfUp
+        );
+        config.bank()?;
+
+        assert!(Config::from_toml("[disputes]\ndispute-policy = \"some\"").is_err());
+        assert!(Config::from_toml("unknown = 1").is_err());
+        // Settings belong to the table of their part of the engine
+        assert!(Config::from_toml("dispute-policy = \"deposits-only\"").is_err());
+        assert!(Config::from_toml("[limits]\nrefusal-policy = \"error\"").is_err());
+        let negative = Config {
+            limits: LimitSettings {
+                overdraft_limit: Some(Decimal::new(-1, 0)),
+                ..LimitSettings::default()
+            },
+            ..Config::default()
+        };
+        assert!(negative.bank().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn settings_that_depend_on_each_other_are_checked() {
+        let refused = |config: Config, flag: &str| match config.validate() {
+            Err(InvalidConfig(message)) => message.starts_with(flag),
+            _ => false,
+        };
+        let input = InputSettings {
+            files: true,
+            ..InputSettings::default()
+        };
+        assert!(Config::default().validate().is_ok());
+
+        let changed_only = |input: InputSettings, partition_by: Option<Partitioning>| Config {
+            input,
+            output: OutputSettings {
+                changed_only: true,
+                partition_by,
+                ..OutputSettings::default()
+            },
+            ..Config::default()
+        };
+        assert!(refused(
+            changed_only(input.clone(), None),
+            "--output-changed-only"
+        ));
+        let with_state = InputSettings {
+            state: Some("state.json".into()),
+            ..input.clone()
+        };
+        assert!(changed_only(with_state.clone(), None).validate().is_ok());
+        let by_currency = "currency".parse().ok();
+        assert!(refused(
+            changed_only(with_state.clone(), by_currency),
+            "--output-changed-only"
+        ));
+        let resumed = InputSettings {
+            resume: true,
+            checkpoint: Some("run.checkpoint".into()),
+            ..with_state
+        };
+        assert!(refused(
+            changed_only(resumed.clone(), None),
+            "--output-changed-only"
+        ));
+
+        let partitioned = |partition_by: Option<Partitioning>| Config {
+            output: OutputSettings {
+                partition_by,
+                partition_dir: Some("out".into()),
+                ..OutputSettings::default()
+            },
+            ..Config::default()
+        };
+        assert!(refused(partitioned(None), "--partition-dir"));
+        assert!(partitioned("client_shard:4".parse().ok())
+            .validate()
+            .is_ok());
+
+        let sla = |sla: SlaSettings| Config {
+            sla,
+            ..Config::default()
+        };
+        let report = SlaSettings {
+            report: Some("open.csv".into()),
+            ..SlaSettings::default()
+        };
+        assert!(refused(sla(report.clone()), "--dispute-sla-report"));
+        let webhook = SlaSettings {
+            webhook: Some("http://localhost/sla".to_string()),
+            ..SlaSettings::default()
+        };
+        assert!(refused(sla(webhook), "--sla-webhook"));
+        assert!(sla(SlaSettings {
+            dispute_sla: Some(3600),
+            ..report
+        })
+        .validate()
+        .is_ok());
+
+        let with_input = |input: InputSettings| Config {
+            input,
+            ..Config::default()
+        };
+        let checkpointed = InputSettings {
+            checkpoint: Some("run.checkpoint".into()),
+            ..InputSettings::default()
+        };
+        assert!(refused(with_input(checkpointed), "--checkpoint"));
+        assert!(refused(
+            with_input(InputSettings {
+                resume: true,
+                ..input.clone()
+            }),
+            "--resume"
+        ));
+        assert!(with_input(resumed.clone()).validate().is_ok());
+        let imported = InputSettings {
+            import: Some(ImportFormat::Ofx),
+            ..input.clone()
+        };
+        assert!(refused(with_input(imported.clone()), "--import requires"));
+        assert!(refused(
+            with_input(InputSettings {
+                import_client: Some(1),
+                ..resumed
+            }),
+            "--import"
+        ));
+        assert!(refused(
+            with_input(InputSettings {
+                protobuf: true,
+                import_client: Some(1),
+                ..imported
+            }),
+            "--protobuf"
+        ));
+        assert!(refused(
+            with_input(InputSettings {
+                avro_schema: Some("transaction.avsc".into()),
+                avro_registry: Some("http://localhost:8081".to_string()),
+                ..input.clone()
+            }),
+            "--avro-schema"
+        ));
+        assert!(refused(
+            with_input(InputSettings {
+                from: Some(200),
+                to: Some(100),
+                ..input.clone()
+            }),
+            "--from"
+        ));
+
+        let operations = |operations: OperationSettings| Config {
+            input: input.clone(),
+            operations,
+            ..Config::default()
+        };
+        let recurring = OperationSettings {
+            recurring: Some("orders.csv".into()),
+            ..OperationSettings::default()
+        };
+        assert!(refused(operations(recurring.clone()), "--recurring"));
+        assert!(refused(
+            operations(OperationSettings {
+                as_of: Some(100),
+                ..OperationSettings::default()
+            }),
+            "--as-of"
+        ));
+        assert!(refused(
+            operations(OperationSettings {
+                admin_dry_run: true,
+                ..OperationSettings::default()
+            }),
+            "--admin-dry-run"
+        ));
+        let journal = Config {
+            output: OutputSettings {
+                journal: Some("journal.csv".into()),
+                ..OutputSettings::default()
+            },
+            ..operations(OperationSettings {
+                as_of: Some(100),
+                ..recurring
+            })
+        };
+        assert!(refused(journal, "--journal"));
+        assert!(refused(
+            Config {
+                output: OutputSettings {
+                    error_report: Some("errors.json".into()),
+                    ..OutputSettings::default()
+                },
+                ..Config::default()
+            },
+            "--error-report"
+        ));
+
+        let verified = |limits: LimitSettings| Config {
+            input: input.clone(),
+            limits,
+            verify: true,
+            ..Config::default()
+        };
+        assert!(verified(LimitSettings::default()).validate().is_ok());
+        assert!(refused(
+            verified(LimitSettings {
+                max_amount: Some(Decimal::new(100, 0)),
+                ..LimitSettings::default()
+            }),
+            "--verify"
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::error::{TransactorError, TransactorError::*};
+
+/// A three letter ISO 4217 currency code such as `USD`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [a, b, c] if s.bytes().all(|byte| byte.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(format!("Invalid currency code {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only ascii letters can be parsed into a currency so this is always valid utf-8
+        f.write_str(std::str::from_utf8(&self.0).unwrap_or("???"))
+    }
+}
+
+/// How an amount is rounded when it has more decimal places than are being kept.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Rounding {
+    /// Round half to even.
+    #[default]
+    Bankers,
+    /// Round half away from zero.
+    HalfUp,
+    /// Drop the extra decimal places.
+    Truncate,
+}
+
+impl Rounding {
+    pub fn round(self, amount: Decimal, decimal_places: u32) -> Decimal {
+        let strategy = match self {
+            Rounding::Bankers => RoundingStrategy::BankersRounding,
+            Rounding::HalfUp => RoundingStrategy::RoundHalfUp,
+            Rounding::Truncate => RoundingStrategy::RoundDown,
+        };
+        amount.round_dp_with_strategy(decimal_places, strategy)
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bankers" => Ok(Rounding::Bankers),
+            "half-up" => Ok(Rounding::HalfUp),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(format!(
+                "Unknown rounding {}, expected one of: bankers, half-up, truncate",
+                s
+            )),
+        }
+    }
+}
+
+/// The number of decimal places amounts in a currency may have and how they are rounded for output.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Precision {
+    pub decimal_places: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for Precision {
+    /// Four decimal places, as the input specification allows.
+    fn default() -> Self {
+        Self {
+            decimal_places: 4,
+            rounding: Rounding::Bankers,
+        }
+    }
+}
+
+impl Precision {
+    /// Check an input amount does not have more decimal places than this precision allows.
+    /// Trailing zeros are not counted, so `1.50` is valid for a currency with one decimal place.
+    pub fn validate(&self, amount: Decimal) -> Result<(), TransactorError> {
+        if amount.normalize().scale() > self.decimal_places {
+            Err(ExcessPrecision {
+                amount,
+                decimal_places: self.decimal_places,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        self.rounding.round(amount, self.decimal_places)
+    }
+}
+
+/// The precision of each configured currency, along with a default for amounts without a
+/// currency or in a currency that is not configured.
+#[derive(Clone, Debug, Default)]
+pub struct PrecisionTable {
+    default: Precision,
+    currencies: HashMap<Currency, Precision>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrecisionRecord {
+    currency: String,
+    decimal_places: u32,
+    rounding: Option<String>,
+}
+
+impl PrecisionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_currency(mut self, currency: Currency, precision: Precision) -> Self {
+        self.currencies.insert(currency, precision);
+        self
+    }
+
+    /// Round every amount with `rounding`, overriding the rounding of every configured currency.
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.default.rounding = rounding;
+        for precision in self.currencies.values_mut() {
+            precision.rounding = rounding;
+        }
+        self
+    }
+
+    /// Read a table from csv with the columns `currency,decimal_places,rounding`, where rounding is
+    /// optional and one of `bankers`, `half-up` or `truncate`.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, TransactorError> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let mut table = Self::new();
+        for result in reader.deserialize() {
+            let record: PrecisionRecord = result?;
+            let currency = record.currency.parse().map_err(InvalidConfig)?;
+            let rounding = match record.rounding.as_deref() {
+                Some(rounding) if !rounding.is_empty() => {
+                    rounding.parse().map_err(InvalidConfig)?
+                }
+                _ => Rounding::default(),
+            };
+            table = table.with_currency(
+                currency,
+                Precision {
+                    decimal_places: record.decimal_places,
+                    rounding,
+                },
+            );
+        }
+        Ok(table)
+    }
+
+    /// Check an input amount against the precision of its currency. Only explicitly configured
+    /// currencies are validated, other amounts are accepted as is and rounded on output.
+    pub fn validate(
+        &self,
+        amount: Decimal,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactorError> {
+        match currency.and_then(|currency| self.currencies.get(&currency)) {
+            Some(precision) => precision.validate(amount),
+            None => Ok(()),
+        }
+    }
+
+    pub fn precision(&self, currency: Option<Currency>) -> Precision {
+        currency
+            .and_then(|currency| self.currencies.get(&currency).copied())
+            .unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn currency_codes_are_three_letters() {
+        assert_eq!("jpy".parse::<Currency>().unwrap().to_string(), "JPY");
+        assert!("JP".parse::<Currency>().is_err());
+        assert!("JP1".parse::<Currency>().is_err());
+    }
+
+    #[test]
+    fn table_uses_configured_precision_and_falls_back_to_default() -> Result<(), TransactorError> {
+        let table = PrecisionTable::from_reader(
+            "currency,decimal_places,rounding\nJPY,0,half-up\nBHD,3,\n".as_bytes(),
+        )?;
+        let jpy = table.precision(Some("JPY".parse().unwrap()));
+        assert_eq!(jpy.decimal_places, 0);
+        assert_eq!(jpy.round(Decimal::new(25, 1)), Decimal::new(3, 0));
+        assert_eq!(
+            table.precision(Some("BHD".parse().unwrap())).decimal_places,
+            3
+        );
+        assert_eq!(
+            table.precision(Some("USD".parse().unwrap())),
+            Precision::default()
+        );
+        assert_eq!(table.precision(None), Precision::default());
+        Ok(())
+    }
+
+    #[test]
+    fn table_rounding_overrides_every_currency() -> Result<(), TransactorError> {
+        let jpy: Currency = "JPY".parse().unwrap();
+        let table = PrecisionTable::from_reader("currency,decimal_places\nJPY,0\n".as_bytes())?
+            .with_rounding(Rounding::HalfUp);
+        assert_eq!(
+            table.precision(Some(jpy)).round(Decimal::new(25, 1)),
+            Decimal::new(3, 0)
+        );
+        assert_eq!(
+            table.precision(None).round(Decimal::new(125, 5)),
+            Decimal::new(13, 4)
+        );
+        let table = table.with_rounding(Rounding::Truncate);
+        assert_eq!(
+            table.precision(Some(jpy)).round(Decimal::new(29, 1)),
+            Decimal::new(2, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_rejects_excess_decimal_places() {
+        let precision = Precision {
+            decimal_places: 1,
+            rounding: Rounding::Bankers,
+        };
+        assert!(precision.validate(Decimal::new(150, 2)).is_ok());
+        assert!(precision.validate(Decimal::new(155, 2)).is_err());
+    }
+
+    #[test]
+    fn table_only_validates_configured_currencies() {
+        let jpy: Currency = "JPY".parse().unwrap();
+        let table = PrecisionTable::new().with_currency(
+            jpy,
+            Precision {
+                decimal_places: 0,
+                rounding: Rounding::Bankers,
+            },
+        );
+        let amount = Decimal::new(123456, 5);
+        assert!(table.validate(amount, Some(jpy)).is_err());
+        assert!(table.validate(amount, Some("USD".parse().unwrap())).is_ok());
+        assert!(table.validate(amount, None).is_ok());
+    }
+}
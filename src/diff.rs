@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::bank::{AccountName, Bank, ClientId};
+use crate::currency::PrecisionTable;
+use crate::error::{RecordPosition, TransactorError, TransactorError::*};
+use crate::report::{ReportValue, ReportWriter};
+
+/// The balances of an account as an account report gives them.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReportedBalances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportRecord {
+    client: u16,
+    account: Option<String>,
+    available: Decimal,
+    held: Decimal,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+}
+
+/// An account report read back in, such as the output of an earlier run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AccountReport {
+    accounts: BTreeMap<(u16, AccountName), ReportedBalances>,
+}
+
+impl AccountReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_account(
+        mut self,
+        client_id: ClientId,
+        name: AccountName,
+        balances: ReportedBalances,
+    ) -> Self {
+        self.accounts.insert((client_id.0, name), balances);
+        self
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Read a csv account report. Only the client, available and held columns are needed, the
+    /// total is their sum if left out, accounts are unlocked if locked is left out and rows are
+    /// of the main account if account is. Other columns are ignored.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, TransactorError> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut row = StringRecord::new();
+        let mut report = Self::new();
+        while reader.read_record(&mut row)? {
+            let mut position = RecordPosition::new(row.position().map_or(0, |p| p.line()));
+            let record: ReportRecord = row
+                .deserialize(Some(&headers))
+                .map_err(|e| TransactorError::from(e).at(position))?;
+            position.client = Some(ClientId(record.client));
+            let name = match record.account.as_deref() {
+                Some(name) if !name.is_empty() => {
+                    name.parse().map_err(|e: TransactorError| e.at(position))?
+                }
+                _ => AccountName::MAIN,
+            };
+            if report.accounts.contains_key(&(record.client, name)) {
+                return Err(InvalidConfig(format!(
+                    "account {} of client {} is reported more than once",
+                    name, record.client
+                ))
+                .at(position));
+            }
+            let total = match record.total {
+                Some(total) => total,
+                None => record
+                    .available
+                    .checked_add(record.held)
+                    .ok_or_else(|| Overflow.at(position))?,
+            };
+            let balances = ReportedBalances {
+                available: record.available,
+                held: record.held,
+                total,
+                locked: record.locked.unwrap_or_default(),
+            };
+            report = report.with_account(ClientId(record.client), name, balances);
+        }
+        Ok(report)
+    }
+
+    /// The balances of every account of the bank, rounded as account reports round them.
+    pub fn of(bank: &Bank, precision: &PrecisionTable) -> Result<Self, TransactorError> {
+        let mut report = Self::new();
+        for account in bank.get_accounts().chain(bank.sub_accounts()) {
+            let precision = precision.precision(account.currency());
+            let balances = ReportedBalances {
+                available: precision.round(account.available()),
+                held: precision.round(account.held()),
+                total: precision.round(account.total()?),
+                locked: account.is_locked(),
+            };
+            report = report.with_account(account.client_id, account.name(), balances);
+        }
+        Ok(report)
+    }
+
+    pub fn get(&self, client_id: ClientId, name: AccountName) -> Option<&ReportedBalances> {
+        self.accounts.get(&(client_id.0, name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+/// How an account differs between two reports.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccountChange {
+    /// Only in the later report.
+    Added,
+    /// Only in the earlier report.
+    Removed,
+    Changed,
+}
+
+impl fmt::Display for AccountChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccountChange::Added => "added",
+            AccountChange::Removed => "removed",
+            AccountChange::Changed => "changed",
+        })
+    }
+}
+
+/// The difference in an account from one report to another, an account missing from either
+/// counting as having no funds.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AccountDelta {
+    pub client: ClientId,
+    pub account: AccountName,
+    pub change: AccountChange,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// Whether the account was locked before, None if it was not reported.
+    pub locked_before: Option<bool>,
+    /// Whether the account is locked after, None if it is not reported.
+    pub locked_after: Option<bool>,
+}
+
+/// The accounts that differ from `before` to `after`, ordered by client and then account.
+pub fn diff_reports(
+    before: &AccountReport,
+    after: &AccountReport,
+) -> Result<Vec<AccountDelta>, TransactorError> {
+    let mut keys: Vec<&(u16, AccountName)> = before
+        .accounts
+        .keys()
+        .chain(after.accounts.keys())
+        .collect();
+    keys.sort();
+    keys.dedup();
+    let mut deltas = Vec::new();
+    for key in keys {
+        let (old, new) = (before.accounts.get(key), after.accounts.get(key));
+        let change = match (old, new) {
+            (Some(old), Some(new)) if old == new => continue,
+            (Some(_), Some(_)) => AccountChange::Changed,
+            (None, _) => AccountChange::Added,
+            (_, None) => AccountChange::Removed,
+        };
+        let (from, to) = (
+            old.copied().unwrap_or_default(),
+            new.copied().unwrap_or_default(),
+        );
+        let delta = |from: Decimal, to: Decimal| to.checked_sub(from).ok_or(Overflow);
+        deltas.push(AccountDelta {
+            client: ClientId(key.0),
+            account: key.1,
+            change,
+            available: delta(from.available, to.available)?,
+            held: delta(from.held, to.held)?,
+            total: delta(from.total, to.total)?,
+            locked_before: old.map(|old| old.locked),
+            locked_after: new.map(|new| new.locked),
+        });
+    }
+    Ok(deltas)
+}
+
+/// Write a row for each delta, with an account column after the client if any delta is of a
+/// sub-account.
+pub fn write_deltas(
+    deltas: &[AccountDelta],
+    report: &mut dyn ReportWriter,
+) -> Result<(), TransactorError> {
+    let with_account = deltas.iter().any(|delta| !delta.account.is_main());
+    let mut columns = vec!["client"];
+    if with_account {
+        columns.push("account");
+    }
+    columns.extend([
+        "change",
+        "available",
+        "held",
+        "total",
+        "locked_before",
+        "locked_after",
+    ]);
+    report.begin(&columns)?;
+    for delta in deltas {
+        let mut row = vec![ReportValue::Integer(delta.client.0.into())];
+        if with_account {
+            row.push(ReportValue::Text(delta.account.to_string()));
+        }
+        let locked = |locked: Option<bool>| locked.map_or(ReportValue::Empty, ReportValue::Bool);
+        row.extend([
+            ReportValue::Text(delta.change.to_string()),
+            ReportValue::Number(delta.available.normalize()),
+            ReportValue::Number(delta.held.normalize()),
+            ReportValue::Number(delta.total.normalize()),
+            locked(delta.locked_before),
+            locked(delta.locked_after),
+        ]);
+        report.row(&row)?;
+    }
+    report.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Transaction, TransactionId};
+    use crate::report::CsvReportWriter;
+
+    #[test]
+    fn reports_differ_by_the_accounts_that_changed() -> Result<(), TransactorError> {
+        let before = AccountReport::from_reader(
+            "client,available,held,total,locked\n\
+             1,10,0,10,false\n\
+             2,5,1,6,false\n\
+             3,1,0,1,false\n"
+                .as_bytes(),
+        )?;
+        let after = AccountReport::from_reader(
+            "client,available,held,currency\n\
+             1,10,0,USD\n\
+             2,2.5,0,USD\n\
+             4,7,0,USD\n"
+                .as_bytes(),
+        )?;
+        let deltas = diff_reports(&before, &after)?;
+        assert_eq!(deltas.len(), 3);
+        assert_eq!(deltas[0].change, AccountChange::Changed);
+        assert_eq!(deltas[0].total, Decimal::new(-35, 1));
+
+        let mut output = Vec::new();
+        write_deltas(&deltas, &mut CsvReportWriter::new(&mut output))?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,change,available,held,total,locked_before,locked_after\n\
+             2,changed,-2.5,-1,-3.5,false,false\n\
+             3,removed,-1,0,-1,false,\n\
+             4,added,7,0,7,,false\n"
+        );
+
+        let repeated = "client,available,held\n1,1,0\n1,2,0\n";
+        assert!(matches!(
+            AccountReport::from_reader(repeated.as_bytes()),
+            Err(Record { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bank_reconciles_with_the_report_of_its_balances() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        bank.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(15, 1)),
+        )?;
+        bank.freeze(ClientId(1), None)?;
+        let actual = AccountReport::of(&bank, &PrecisionTable::new())?;
+
+        let expected = "client,available,held,total,locked
+1,1.5000,0,1.5,true
+";
+        let expected = AccountReport::from_reader(expected.as_bytes())?;
+        assert!(diff_reports(&expected, &actual)?.is_empty());
+
+        let unlocked = AccountReport::from_reader(
+            "client,available,held
+1,1.5,0
+"
+            .as_bytes(),
+        )?;
+        let deltas = diff_reports(&unlocked, &actual)?;
+        assert_eq!(deltas.len(), 1);
+        assert!(deltas[0].total.is_zero());
+        assert_eq!(deltas[0].locked_after, Some(true));
+        Ok(())
+    }
+}
@@ -1,13 +1,472 @@
+use std::fmt;
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
 use thiserror::Error;
 
+use crate::bank::{AccountName, ClientId, TransactionId};
+use crate::currency::Currency;
+use crate::input::InputSchema;
+use TransactorError::*;
+
 #[derive(Error, Debug)]
 pub enum TransactorError {
     #[error("Overflow handling transaction")]
     Overflow,
-    #[error("Invalid data: {0}")]
-    InvalidData(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Missing amount in deposit or withdrawal")]
+    MissingAmount,
+    #[error("Found amount in non-transaction type record")]
+    UnexpectedAmount,
+    #[error("Negative amount {0} attempted")]
+    NegativeAmount(Decimal),
+    #[error("Invalid currency code {0}")]
+    InvalidCurrency(String),
+    #[error("Amount {amount} has more than {decimal_places} decimal places")]
+    ExcessPrecision {
+        amount: Decimal,
+        decimal_places: u32,
+    },
+    #[error("Invalid reason code {0}, expected up to 16 letters, digits, - or _")]
+    InvalidReasonCode(String),
+    #[error("Invalid account name {0}, expected up to 16 letters, digits, - or _")]
+    InvalidAccountName(String),
+    #[error("Missing to_account in move record")]
+    MissingToAccount,
+    #[error("Move from and to the same account {0}")]
+    SameAccountMove(AccountName),
+    #[error("Record type {0} does not take a reason")]
+    UnexpectedReason(&'static str),
+    #[error("Missing transaction id in {0} record")]
+    MissingTransactionId(&'static str),
+    #[error("Unknown record type {name}, expected one of: {expected}")]
+    UnknownRecordType { name: String, expected: String },
+    #[error("Unknown input columns {columns}, expected only: {expected}")]
+    UnknownColumns { columns: String, expected: String },
+    #[error("Input schema {schema} requires the column {column}")]
+    MissingColumn { column: String, schema: InputSchema },
+    #[error("Column {column} is not part of input schema {schema}")]
+    ColumnNotInSchema { column: String, schema: InputSchema },
+    #[error("Record has {found} fields but the header has {expected}")]
+    FieldCount { expected: usize, found: usize },
+    #[error("Record type {0} is not allowed in this input")]
+    UnexpectedRecordType(&'static str),
     #[error("Two transactions attempted with the same id")]
     TransactionIdReuse,
-    #[error("CSV parsing error")]
-    CsvError(#[from] csv::Error),
+    #[error("Account is at version {actual} but version {expected} was expected")]
+    VersionConflict { expected: u64, actual: u64 },
+    #[error("Transaction in {found} attempted on an account in {expected}")]
+    CurrencyMismatch { expected: Currency, found: Currency },
+    #[error("Client {} referenced transaction {} which belongs to client {}", .client.0, .transaction.0, .owner.0)]
+    ForeignTransaction {
+        client: ClientId,
+        owner: ClientId,
+        transaction: TransactionId,
+    },
+    #[error("Client {} cannot withdraw {requested} in transaction {} with {available} available", .client.0, .tx.0)]
+    InsufficientFunds {
+        client: ClientId,
+        tx: TransactionId,
+        requested: Decimal,
+        available: Decimal,
+    },
+    #[error("Client {} made transaction {} of {amount}, more than the largest amount allowed of {limit}", .client.0, .tx.0)]
+    AmountTooLarge {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        limit: Decimal,
+    },
+    #[error("Client {} is locked so transaction {} was refused", .client.0, .tx.0)]
+    AccountLocked { client: ClientId, tx: TransactionId },
+    #[error("Client {} already has an account", .client.0)]
+    AccountExists { client: ClientId },
+    #[error("Client {} has transaction {} whose id is already used", .client.0, .tx.0)]
+    DuplicateTransaction { client: ClientId, tx: TransactionId },
+    #[error("Client {} referred to transaction {} which it has not made", .client.0, .tx.0)]
+    DanglingReference { client: ClientId, tx: TransactionId },
+    #[error("Invariant broken for client {}: {invariant}\n  {dump}", .client.0)]
+    InvariantViolation {
+        client: ClientId,
+        invariant: String,
+        dump: String,
+    },
+    #[error("The journal does not balance: {debits} debited but {credits} credited")]
+    UnbalancedJournal { debits: Decimal, credits: Decimal },
+    #[error("The bank and the reference model disagree: {0}")]
+    VerificationFailed(String),
+    #[error("More than {limit} rows failed, stopping as the input looks broken")]
+    TooManyErrors { limit: usize },
+    #[error("Validation found {0} issues in the input")]
+    ValidationFailed(usize),
+    #[error("Reconciliation found {0} accounts that differ from the expected balances")]
+    ReconciliationFailed(usize),
+    #[error(
+        "Interrupted before line {line} of {input}, which carries on from byte offset {offset}"
+    )]
+    Interrupted {
+        input: String,
+        line: u64,
+        offset: u64,
+    },
+    #[error("Row breaks the rule {rule}")]
+    RuleViolation { rule: String },
+    #[error("Invalid state snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("Invalid ledger: {0}")]
+    InvalidLedger(String),
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+    #[error("Invalid bank statement: {0}")]
+    InvalidStatement(String),
+    #[error("Invalid Avro input: {0}")]
+    InvalidAvro(String),
+    #[error("Invalid protobuf input: {0}")]
+    InvalidProtobuf(String),
+    #[error("{position}: {source}")]
+    Record {
+        position: RecordPosition,
+        source: Box<TransactorError>,
+    },
+    #[error("CSV parsing error: {0}")]
+    CsvError(#[source] csv::Error),
+    #[error("IO error: {0}")]
+    IoError(#[source] std::io::Error),
+    #[error("Remote input error: {0}")]
+    RemoteError(String),
+    #[error("Zip archive error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+    #[error("Parquet or Arrow file error: {0}")]
+    ColumnarError(String),
+}
+
+impl TransactorError {
+    /// Attach the position of the input record that caused this error.
+    pub fn at(self, position: RecordPosition) -> Self {
+        TransactorError::Record {
+            position,
+            source: Box::new(self),
+        }
+    }
+
+    /// The status a run that fails with this error exits with, by the class of the failure:
+    /// * 2: the input or another file could not be read or written
+    /// * 3: the input is malformed, such as a row that cannot be parsed or an unknown column
+    /// * 4: the bank refused a row, such as a reused transaction id or insufficient funds
+    /// * 5: an amount overflowed
+    /// * 6: the configuration or command line is invalid
+    /// * 7: an invariant check or the reference model found a bug
+    /// * 8: the run was interrupted and can be resumed from its checkpoint
+    /// * 9: the balances reached differ from those expected by a reconciliation
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Record { source, .. } => source.exit_code(),
+            CsvError(e) if e.is_io_error() => match e.kind() {
+                csv::ErrorKind::Io(e) => carried(e).map_or(2, Self::exit_code),
+                _ => 2,
+            },
+            IoError(e) => carried(e).map_or(2, Self::exit_code),
+            RemoteError(_) | ZipError(_) | ColumnarError(_) => 2,
+            CsvError(_)
+            | MissingAmount
+            | UnexpectedAmount
+            | NegativeAmount(_)
+            | InvalidCurrency(_)
+            | ExcessPrecision { .. }
+            | InvalidReasonCode(_)
+            | InvalidAccountName(_)
+            | MissingToAccount
+            | SameAccountMove(_)
+            | UnexpectedReason(_)
+            | MissingTransactionId(_)
+            | UnknownRecordType { .. }
+            | UnknownColumns { .. }
+            | MissingColumn { .. }
+            | ColumnNotInSchema { .. }
+            | FieldCount { .. }
+            | UnexpectedRecordType(_)
+            | InvalidSnapshot(_)
+            | InvalidLedger(_)
+            | InvalidProof(_)
+            | InvalidStatement(_)
+            | InvalidAvro(_)
+            | InvalidProtobuf(_)
+            | ValidationFailed(_)
+            | TooManyErrors { .. } => 3,
+            TransactionIdReuse
+            | VersionConflict { .. }
+            | CurrencyMismatch { .. }
+            | ForeignTransaction { .. }
+            | InsufficientFunds { .. }
+            | AmountTooLarge { .. }
+            | AccountLocked { .. }
+            | AccountExists { .. }
+            | DuplicateTransaction { .. }
+            | DanglingReference { .. }
+            | RuleViolation { .. } => 4,
+            Overflow => 5,
+            InvalidConfig(_) => 6,
+            InvariantViolation { .. } | UnbalancedJournal { .. } | VerificationFailed(_) => 7,
+            Interrupted { .. } => 8,
+            ReconciliationFailed(_) => 9,
+        }
+    }
+
+    /// Whether the error is of a single row, which a run can skip and carry on past, rather than
+    /// of the input, the bank or the run as a whole.
+    pub fn is_row_error(&self) -> bool {
+        match self {
+            Record { source, .. } => source.is_row_error(),
+            CsvError(e) => !e.is_io_error(),
+            InvalidConfig(_)
+            | InvariantViolation { .. }
+            | UnbalancedJournal { .. }
+            | VerificationFailed(_)
+            | TooManyErrors { .. }
+            | ValidationFailed(_)
+            | ReconciliationFailed(_)
+            | Interrupted { .. }
+            | InvalidSnapshot(_)
+            | InvalidLedger(_)
+            | InvalidProof(_)
+            | InvalidStatement(_)
+            | InvalidAvro(_)
+            | InvalidProtobuf(_)
+            | IoError(_)
+            | RemoteError(_)
+            | ZipError(_)
+            | ColumnarError(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl From<csv::Error> for TransactorError {
+    /// Hands back the error a csv reader was given by its input, such as a malformed Avro or
+    /// protobuf record, rather than reporting it as a csv problem.
+    fn from(e: csv::Error) -> Self {
+        match e.kind() {
+            csv::ErrorKind::Io(io) if carried(io).is_some() => match e.into_kind() {
+                csv::ErrorKind::Io(io) => io.into(),
+                _ => unreachable!("checked to be an io error above"),
+            },
+            _ => CsvError(e),
+        }
+    }
+}
+
+impl From<io::Error> for TransactorError {
+    /// Hands back the error an io error carries, if any, as itself.
+    fn from(e: io::Error) -> Self {
+        if carried(&e).is_none() {
+            return IoError(e);
+        }
+        match e
+            .into_inner()
+            .map(|inner| inner.downcast::<TransactorError>())
+        {
+            Some(Ok(carried)) => *carried,
+            _ => unreachable!("checked to carry an error above"),
+        }
+    }
+}
+
+/// The error an io error carries, such as one raised decoding Avro or protobuf input as it is
+/// read.
+fn carried(e: &io::Error) -> Option<&TransactorError> {
+    e.get_ref()?.downcast_ref()
+}
+
+/// One row that failed, as written by `RowErrors::write_json`.
+#[derive(Debug, Serialize)]
+struct RowError {
+    line: Option<u64>,
+    client: Option<u16>,
+    tx: Option<u32>,
+    error: String,
+}
+
+/// The errors of rows skipped so a run could carry on past them, up to a limit past which the
+/// input is taken to be broken as a whole.
+#[derive(Debug)]
+pub struct RowErrors {
+    limit: usize,
+    errors: Vec<TransactorError>,
+}
+
+impl RowErrors {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Keep the error of a skipped row. Errors that are not of a single row are given back, as is
+    /// `TooManyErrors` once more than the limit have been kept.
+    pub fn push(&mut self, error: TransactorError) -> Result<(), TransactorError> {
+        if !error.is_row_error() {
+            return Err(error);
+        }
+        self.errors.push(error);
+        match self.errors.len() > self.limit {
+            true => Err(TooManyErrors { limit: self.limit }),
+            false => Ok(()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Write the errors as a json object of the limit and the errors kept, each with the line,
+    /// client and transaction of the row where known.
+    pub fn write_json<W: io::Write>(&self, mut writer: W) -> Result<(), TransactorError> {
+        let errors: Vec<RowError> = self.errors.iter().map(RowError::new).collect();
+        let report = serde_json::json!({ "limit": self.limit, "errors": errors });
+        serde_json::to_writer(&mut writer, &report).map_err(io::Error::from)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl RowError {
+    fn new(error: &TransactorError) -> Self {
+        match error {
+            Record { position, source } => Self {
+                line: Some(position.line),
+                client: position.client.map(|client| client.0),
+                tx: position.transaction.map(|transaction| transaction.0),
+                error: source.to_string(),
+            },
+            CsvError(e) => Self {
+                line: e.position().map(csv::Position::line),
+                client: None,
+                tx: None,
+                error: error.to_string(),
+            },
+            _ => Self {
+                line: None,
+                client: None,
+                tx: None,
+                error: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Where in an input file a record is, along with the client and transaction it refers to when
+/// they could be read.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RecordPosition {
+    pub line: u64,
+    pub client: Option<ClientId>,
+    pub transaction: Option<TransactionId>,
+    /// The byte offset the record starts at, reading can resume from here. 0 where not known.
+    pub offset: u64,
+}
+
+impl RecordPosition {
+    pub fn new(line: u64) -> Self {
+        Self {
+            line,
+            client: None,
+            transaction: None,
+            offset: 0,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_record(mut self, client: ClientId, transaction: TransactionId) -> Self {
+        self.client = Some(client);
+        self.transaction = Some(transaction);
+        self
+    }
+}
+
+impl fmt::Display for RecordPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}", self.line)?;
+        if let Some(client) = self.client {
+            write!(f, ", client {}", client.0)?;
+        }
+        if let Some(transaction) = self.transaction {
+            write!(f, ", transaction {}", transaction.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_errors_report_their_position() {
+        let error = TransactorError::MissingAmount
+            .at(RecordPosition::new(4).with_record(ClientId(2), TransactionId(7)));
+        assert_eq!(
+            error.to_string(),
+            "Line 4, client 2, transaction 7: Missing amount in deposit or withdrawal"
+        );
+        let error = TransactorError::TransactionIdReuse.at(RecordPosition::new(9));
+        assert_eq!(
+            error.to_string(),
+            "Line 9: Two transactions attempted with the same id"
+        );
+    }
+
+    #[test]
+    fn errors_exit_with_the_code_of_their_class() {
+        let io = IoError(io::Error::new(io::ErrorKind::NotFound, "missing"));
+        assert_eq!(io.exit_code(), 2);
+        assert_eq!(MissingAmount.at(RecordPosition::new(2)).exit_code(), 3);
+        assert_eq!(TransactionIdReuse.at(RecordPosition::new(2)).exit_code(), 4);
+        assert_eq!(Overflow.exit_code(), 5);
+        assert_eq!(InvalidConfig(String::new()).exit_code(), 6);
+        let unbalanced = UnbalancedJournal {
+            debits: Decimal::new(2, 0),
+            credits: Decimal::new(1, 0),
+        };
+        assert_eq!(unbalanced.exit_code(), 7);
+        assert_eq!(ReconciliationFailed(1).exit_code(), 9);
+    }
+
+    #[test]
+    fn row_errors_are_kept_up_to_the_limit() -> Result<(), TransactorError> {
+        let mut row_errors = RowErrors::new(1);
+        row_errors.push(
+            TransactionIdReuse
+                .at(RecordPosition::new(3).with_record(ClientId(1), TransactionId(2))),
+        )?;
+        assert!(matches!(
+            row_errors.push(VerificationFailed("differs".to_string())),
+            Err(VerificationFailed(_))
+        ));
+        assert!(matches!(
+            row_errors.push(MissingAmount.at(RecordPosition::new(4))),
+            Err(TooManyErrors { limit: 1 })
+        ));
+        assert_eq!(row_errors.len(), 2);
+
+        let mut json = Vec::new();
+        row_errors.write_json(&mut json)?;
+        assert_eq!(
+            String::from_utf8_lossy(&json),
+            "{\"errors\":[{\"client\":1,\"error\":\"Two transactions attempted with the same id\",\"line\":3,\"tx\":2},\
+             {\"client\":null,\"error\":\"Missing amount in deposit or withdrawal\",\"line\":4,\"tx\":null}],\"limit\":1}\n"
+        );
+        Ok(())
+    }
 }
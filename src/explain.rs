@@ -0,0 +1,175 @@
+use std::fmt;
+
+use crate::bank::{AccountName, Bank, ClientId, Outcome, TransactionId, TransactionState};
+use crate::currency::PrecisionTable;
+use crate::error::{RecordPosition, TransactorError, TransactorError::*};
+use crate::input::{Command, InputRecord};
+
+/// Replays input to find every row touching one transaction id, for working out after the fact
+/// why a transaction ended up as it did.
+///
+/// Unlike a run, rows that fail are noted and the replay carries on, so duplicates and rows that
+/// cannot be read show up alongside the rest. Freezes and unfreezes of the accounts the
+/// transaction was made on are included too, as they decide whether later rows are ignored.
+#[derive(Clone, Debug)]
+pub struct Explainer {
+    transaction: TransactionId,
+    precision: PrecisionTable,
+    /// The clients with a row about the transaction so far.
+    clients: Vec<ClientId>,
+}
+
+/// A row touching the transaction being explained and what came of it.
+#[derive(Debug)]
+pub struct Event {
+    pub position: RecordPosition,
+    /// The type of the row, None if it could not be read.
+    pub kind: Option<&'static str>,
+    pub result: Result<Outcome, TransactorError>,
+    /// The state of the transaction on the clients account after the row.
+    pub state: Option<TransactionState>,
+}
+
+impl Explainer {
+    pub fn new(transaction: TransactionId, precision: PrecisionTable) -> Self {
+        Self {
+            transaction,
+            precision,
+            clients: Vec::new(),
+        }
+    }
+
+    /// Apply the next row of the input to the bank, returning what happened if it touched the
+    /// transaction.
+    pub fn replay(
+        &mut self,
+        bank: &mut Bank,
+        result: Result<InputRecord, TransactorError>,
+    ) -> Option<Event> {
+        let record = match result {
+            Ok(record) => record,
+            Err(Record { position, source }) => {
+                return (position.transaction == Some(self.transaction)).then_some(Event {
+                    position,
+                    kind: None,
+                    result: Err(*source),
+                    state: None,
+                })
+            }
+            Err(_) => return None,
+        };
+        let client = record.command.client();
+        let touches = match record.command.transaction_id() {
+            Some(transaction) => transaction == self.transaction,
+            None => self.clients.contains(&client),
+        };
+        let kind = record.command.name();
+        let result = self.apply(bank, record.command, record.account);
+        if !touches {
+            return None;
+        }
+        if !self.clients.contains(&client) {
+            self.clients.push(client);
+        }
+        Some(Event {
+            position: record.position,
+            kind: Some(kind),
+            result,
+            state: bank.transaction_state(client, self.transaction),
+        })
+    }
+
+    /// The clients with a row about the transaction, in the order they were first seen.
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.iter().copied()
+    }
+
+    fn apply(
+        &self,
+        bank: &mut Bank,
+        command: Command,
+        account: AccountName,
+    ) -> Result<Outcome, TransactorError> {
+        if let Command::Deposit { transaction, .. }
+        | Command::Withdrawal { transaction, .. }
+        | Command::Authorize { transaction, .. }
+        | Command::Hold { transaction, .. }
+        | Command::Move { transaction, .. } = command
+        {
+            self.precision
+                .validate(transaction.amount(), transaction.currency())?;
+        }
+        command.apply_in(bank, account)
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.kind, &self.result) {
+            (None, Err(e)) => return write!(f, "{}: unreadable, {}", self.position, e),
+            (Some(kind), Ok(Outcome::Applied)) => write!(f, "{}: {} applied", self.position, kind),
+            (Some(kind), Ok(Outcome::Ignored(reason))) => {
+                write!(f, "{}: {} ignored, {}", self.position, kind, reason)
+            }
+            (kind, Err(e)) => write!(
+                f,
+                "{}: {} rejected, {}",
+                self.position,
+                kind.unwrap_or("row"),
+                e
+            ),
+            (None, Ok(_)) => write!(f, "{}: applied", self.position),
+        }?;
+        match self.state {
+            Some(state) => write!(f, ", transaction {}", state),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn every_row_about_the_transaction_is_explained() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,7,10\n\
+            deposit,1,8,5\n\
+            deposit,1,7,3\n\
+            dispute,1,7,\n\
+            chargeback,1,7,\n\
+            resolve,1,7,\n\
+            freeze,2,,\n\
+            freeze,1,,\n\
+            dispute,1,7,4\n";
+        let mut bank = Bank::new();
+        let mut explainer = Explainer::new(TransactionId(7), PrecisionTable::new());
+        let events: Vec<String> = InputReader::from_reader(input.as_bytes(), InputSchema::V1)?
+            .filter_map(|result| explainer.replay(&mut bank, result))
+            .map(|event| event.to_string())
+            .collect();
+        assert_eq!(events.len(), 7);
+        assert_eq!(
+            events[0],
+            "Line 2, client 1, transaction 7: deposit applied, transaction posted"
+        );
+        assert!(events[1].starts_with("Line 4, client 1, transaction 7: deposit rejected, "));
+        assert_eq!(
+            events[3],
+            "Line 6, client 1, transaction 7: chargeback applied, transaction chargedback"
+        );
+        assert_eq!(
+            events[4],
+            "Line 7, client 1, transaction 7: resolve ignored, not-disputed, transaction chargedback"
+        );
+        assert_eq!(
+            events[5],
+            "Line 9, client 1: freeze ignored, account-locked, transaction chargedback"
+        );
+        assert!(events[6].starts_with("Line 10, client 1, transaction 7: unreadable, "));
+        assert_eq!(explainer.clients().collect::<Vec<_>>(), vec![ClientId(1)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Simulated failures for resilience testing, only built with the `failure-injection` feature.
+use std::cell::Cell;
+use std::io;
+
+use rust_decimal::prelude::*;
+
+/// Decides at random, at a fixed rate, whether an operation should fail. The sequence of
+/// decisions is determined by the seed so a failing run can be reproduced.
+#[derive(Clone, Debug)]
+pub struct FaultInjector {
+    rate: f64,
+    state: Cell<u64>,
+}
+
+impl FaultInjector {
+    /// Fail a `rate` fraction of operations, between 0 and 1.
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self {
+            rate,
+            // xorshift never leaves a zero state
+            state: Cell::new(seed.max(1)),
+        }
+    }
+
+    pub fn should_fail(&self) -> bool {
+        let mut x = self.state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.set(x);
+        (x as f64 / u64::MAX as f64) < self.rate
+    }
+}
+
+/// The failure rates of each injection point, parsed from a comma separated list such as
+/// `malformed=0.01,sink=0.001,storage=0.05,seed=42`. Unset rates are zero.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Input rows replaced with a row that cannot be parsed.
+    pub malformed_record_rate: f64,
+    /// Writes to an output sink that fail.
+    pub sink_failure_rate: f64,
+    /// Reads and writes of spilled history that time out.
+    pub storage_failure_rate: f64,
+    pub seed: u64,
+}
+
+impl FaultConfig {
+    /// The environment variable the command line reads the configuration from.
+    pub const ENV_VAR: &'static str = "TRANSACTOR_FAULTS";
+
+    /// Read the configuration from `TRANSACTOR_FAULTS`, None if it is not set.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var(Self::ENV_VAR) {
+            Ok(config) => config.parse().map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn malformed_records(&self) -> FaultInjector {
+        FaultInjector::new(self.malformed_record_rate, self.seed)
+    }
+
+    pub fn sink_failures(&self) -> FaultInjector {
+        FaultInjector::new(self.sink_failure_rate, self.seed.wrapping_add(1))
+    }
+
+    pub fn storage_failures(&self) -> FaultInjector {
+        FaultInjector::new(self.storage_failure_rate, self.seed.wrapping_add(2))
+    }
+}
+
+impl FromStr for FaultConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for setting in s.split(',').filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=value but found {}", setting))?;
+            let rate = || {
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|rate| (0.0..=1.0).contains(rate))
+                    .ok_or_else(|| format!("Invalid failure rate {}", value))
+            };
+            match name.trim() {
+                "malformed" => config.malformed_record_rate = rate()?,
+                "sink" => config.sink_failure_rate = rate()?,
+                "storage" => config.storage_failure_rate = rate()?,
+                "seed" => {
+                    config.seed = value
+                        .parse()
+                        .map_err(|_| format!("Invalid seed {}", value))?
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown failure {}, expected one of: malformed, sink, storage, seed",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A writer whose writes fail at random, for testing how errors writing output are handled.
+pub struct FaultyWriter<W> {
+    inner: W,
+    faults: FaultInjector,
+}
+
+impl<W: io::Write> FaultyWriter<W> {
+    pub fn new(inner: W, faults: FaultInjector) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl<W: io::Write> io::Write for FaultyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.faults.should_fail() {
+            return Err(io::Error::other("injected sink failure"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, Transaction, TransactionId};
+    use crate::error::TransactorError;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn config_parses_rates_and_rejects_unknown_failures() {
+        let config: FaultConfig = "malformed=0.5,seed=7".parse().unwrap();
+        assert_eq!(config.malformed_record_rate, 0.5);
+        assert_eq!(config.sink_failure_rate, 0.0);
+        assert_eq!(config.seed, 7);
+        assert!("malformed=2".parse::<FaultConfig>().is_err());
+        assert!("network=0.1".parse::<FaultConfig>().is_err());
+    }
+
+    #[test]
+    fn malformed_records_are_reported_as_parse_errors() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n";
+        let records: Vec<_> = InputReader::from_reader(input.as_bytes(), InputSchema::Auto)?
+            .with_faults(FaultInjector::new(1.0, 1))
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|record| matches!(record, Err(TransactorError::Record { .. }))));
+        Ok(())
+    }
+
+    #[test]
+    fn storage_failures_surface_as_io_errors() -> Result<(), TransactorError> {
+        let mut bank = Bank::new()
+            .with_spill(&std::env::temp_dir().join("transactor-fault-test"), 1)?
+            .with_storage_faults(FaultInjector::new(1.0, 1));
+        let result = bank.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+        );
+        assert!(matches!(result, Err(TransactorError::IoError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Entry points for fuzzing the input path, only built with the `fuzzing` feature. The cargo-fuzz
+//! targets in `fuzz/` call these.
+use std::fmt::Write;
+
+use arbitrary::Arbitrary;
+
+use crate::bank::Bank;
+use crate::currency::PrecisionTable;
+use crate::input::{Command, InputReader, InputSchema};
+
+/// A row of input built by the fuzzer, so rows reach the bank more often than from raw bytes.
+/// Clients and transaction ids are small so rows often refer to each other.
+#[derive(Arbitrary, Clone, Debug)]
+pub struct FuzzRecord {
+    pub kind: FuzzKind,
+    pub client: u8,
+    pub tx: u8,
+    pub amount: FuzzAmount,
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub enum FuzzKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Represent,
+    Freeze,
+    Unfreeze,
+    Close,
+    Authorize,
+    Capture,
+    Void,
+    /// A type the input does not have.
+    Other(String),
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub enum FuzzAmount {
+    Missing,
+    /// The digits of `units` with a decimal point `scale` places from the right, so scales past
+    /// what a decimal holds are tried too.
+    Decimal {
+        units: i64,
+        scale: u8,
+    },
+    Text(String),
+}
+
+impl FuzzKind {
+    fn name(&self) -> &str {
+        match self {
+            FuzzKind::Deposit => "deposit",
+            FuzzKind::Withdrawal => "withdrawal",
+            FuzzKind::Dispute => "dispute",
+            FuzzKind::Resolve => "resolve",
+            FuzzKind::Chargeback => "chargeback",
+            FuzzKind::Represent => "represent",
+            FuzzKind::Freeze => "freeze",
+            FuzzKind::Unfreeze => "unfreeze",
+            FuzzKind::Close => "close",
+            FuzzKind::Authorize => "authorize",
+            FuzzKind::Capture => "capture",
+            FuzzKind::Void => "void",
+            FuzzKind::Other(name) => name,
+        }
+    }
+}
+
+impl FuzzAmount {
+    fn write(&self, row: &mut String) {
+        match self {
+            FuzzAmount::Missing => {}
+            FuzzAmount::Decimal { units, scale } => {
+                let digits = units.unsigned_abs().to_string();
+                let scale = usize::from(*scale % 40);
+                let digits = format!("{:0>width$}", digits, width = scale + 1);
+                let (whole, fraction) = digits.split_at(digits.len() - scale);
+                let sign = if *units < 0 { "-" } else { "" };
+                // Writing to a string cannot fail
+                let _ = match fraction {
+                    "" => write!(row, "{}{}", sign, whole),
+                    fraction => write!(row, "{}{}.{}", sign, whole, fraction),
+                };
+            }
+            FuzzAmount::Text(text) => row.push_str(text),
+        }
+    }
+}
+
+/// The csv input with a header and a row for each record.
+pub fn render(records: &[FuzzRecord]) -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for record in records {
+        let _ = write!(
+            input,
+            "{},{},{},",
+            record.kind.name(),
+            record.client,
+            record.tx
+        );
+        record.amount.write(&mut input);
+        input.push('\n');
+    }
+    input
+}
+
+/// Read the bytes as input and apply every row that can be read to a new bank, as a run does but
+/// carrying on past rows that fail. Panics if the input breaks an invariant of an account, see
+/// `Bank::check_account_invariants` and `Bank::check_funds_conserved`, so the fuzzer finds it
+/// along with any panic in reading or applying.
+pub fn fuzz_input(data: &[u8]) {
+    let reader = match InputReader::from_reader(data, InputSchema::Auto) {
+        Ok(reader) => reader,
+        Err(_) => return,
+    };
+    let precision = PrecisionTable::new();
+    let mut bank = Bank::new();
+    for record in reader.flatten() {
+        if let Command::Deposit { transaction, .. }
+        | Command::Withdrawal { transaction, .. }
+        | Command::Authorize { transaction, .. }
+        | Command::Hold { transaction, .. }
+        | Command::Move { transaction, .. } = record.command
+        {
+            if precision
+                .validate(transaction.amount(), transaction.currency())
+                .is_err()
+            {
+                continue;
+            }
+        }
+        let client = record.command.client();
+        let _ = record.command.apply_in(&mut bank, record.account);
+        if let Err(e) = bank.check_account_invariants(client) {
+            panic!("{} after {}", e, record.position);
+        }
+    }
+    if let Err(e) = bank.check_funds_conserved() {
+        panic!("{}", e);
+    }
+}
+
+/// Render the records as input and fuzz it, see `fuzz_input`.
+pub fn fuzz_records(records: &[FuzzRecord]) {
+    fuzz_input(render(records).as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use arbitrary::Unstructured;
+
+    use super::*;
+    use crate::error::TransactorError;
+
+    #[test]
+    fn fixtures_and_generated_records_are_fuzzed() -> Result<(), TransactorError> {
+        for entry in fs::read_dir("resources/test_input")? {
+            fuzz_input(&fs::read(entry?.path())?);
+        }
+        fuzz_input(b"type,client,tx,amount\ndeposit,1,1,1e30\ndeposit,1,2,\xff\nwithdrawal,1,3,99999999999999999999999999999999\n");
+
+        let records = vec![
+            FuzzRecord {
+                kind: FuzzKind::Deposit,
+                client: 1,
+                tx: 1,
+                amount: FuzzAmount::Decimal {
+                    units: -12345,
+                    scale: 7,
+                },
+            },
+            FuzzRecord {
+                kind: FuzzKind::Other("refund".to_string()),
+                client: 1,
+                tx: 2,
+                amount: FuzzAmount::Text("1,5".to_string()),
+            },
+        ];
+        assert_eq!(
+            render(&records),
+            "type,client,tx,amount\ndeposit,1,1,-0.0012345\nrefund,1,2,1,5\n"
+        );
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let mut noise = Unstructured::new(&noise);
+        let mut records = Vec::new();
+        while !noise.is_empty() {
+            records.push(noise.arbitrary::<FuzzRecord>().unwrap());
+        }
+        assert!(!records.is_empty());
+        fuzz_records(&records);
+        Ok(())
+    }
+}
@@ -0,0 +1,1460 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use csv::{ByteRecord, Position, Reader, StringRecord};
+use rust_decimal::prelude::*;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::bank::{
+    AccountName, Bank, BatchId, ClientId, Outcome, ReasonCode, Timestamp, Transaction,
+    TransactionId, Versioned,
+};
+use crate::currency::{Currency, Rounding};
+use crate::error::{RecordPosition, TransactorError, TransactorError::*};
+
+#[cfg(feature = "avro")]
+mod avro;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod compression;
+mod filter;
+mod format;
+#[cfg(feature = "iso20022")]
+mod iso20022;
+mod parallel;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+#[cfg(feature = "remote")]
+mod remote;
+mod source;
+mod statement;
+#[cfg(feature = "async")]
+mod stream;
+
+#[cfg(feature = "avro")]
+pub use avro::{AvroRows, AvroSchemas};
+pub use compression::{Compression, InputFile};
+pub use filter::{ClientFilter, ClientRanges, TimeWindow, WindowPosition};
+pub use format::{ColumnMap, InputFormat};
+pub use parallel::ParallelInputReader;
+#[cfg(feature = "protobuf")]
+pub use protobuf::{ProtobufRows, TransactionMessage, TransactionType};
+pub use source::{read_input_list, ColumnarFormat, InputSource};
+pub use statement::{read_statement, ImportFormat, StatementImport, StatementLine};
+#[cfg(feature = "async")]
+pub use stream::AsyncInputReader;
+
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+const OPTIONAL_COLUMNS: [&str; 5] = ["amount", "currency", "reason", "account", "to_account"];
+const V2_COLUMNS: [&str; 2] = ["timestamp", "batch"];
+
+/// The supported shapes of the transaction input.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum InputSchema {
+    /// `type,client,tx,amount` with an optional `currency` column. The amount column may be left
+    /// out of input without deposits or withdrawals, such as a feed of dispute outcomes.
+    V1,
+    /// The v1 columns plus `timestamp`, in seconds since the unix epoch, and `batch`. The values
+    /// may be empty but both columns must be present.
+    V2,
+    /// Pick v1 or v2 from the header row.
+    #[default]
+    Auto,
+}
+
+impl fmt::Display for InputSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputSchema::V1 => "v1",
+            InputSchema::V2 => "v2",
+            InputSchema::Auto => "auto",
+        })
+    }
+}
+
+impl FromStr for InputSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(InputSchema::V1),
+            "v2" => Ok(InputSchema::V2),
+            "auto" => Ok(InputSchema::Auto),
+            _ => Err(format!(
+                "Unknown input schema {}, expected one of: v1, v2, auto",
+                s
+            )),
+        }
+    }
+}
+
+impl InputSchema {
+    /// Check the header row fits this schema, resolving auto to the schema it fits. Columns the
+    /// input does not know are ignored, unless `strict` when they are refused.
+    fn resolve(self, headers: &StringRecord, strict: bool) -> Result<InputSchema, TransactorError> {
+        let unknown: Vec<&str> = headers
+            .iter()
+            .filter(|column| {
+                !REQUIRED_COLUMNS.contains(column)
+                    && !OPTIONAL_COLUMNS.contains(column)
+                    && !V2_COLUMNS.contains(column)
+            })
+            .collect();
+        if strict && !unknown.is_empty() {
+            let known: Vec<&str> = REQUIRED_COLUMNS
+                .iter()
+                .chain(&OPTIONAL_COLUMNS)
+                .chain(&V2_COLUMNS)
+                .copied()
+                .collect();
+            return Err(UnknownColumns {
+                columns: unknown.join(", "),
+                expected: known.join(", "),
+            });
+        }
+        let has_column = |column: &str| headers.iter().any(|header| header == column);
+        let schema = match self {
+            InputSchema::Auto if V2_COLUMNS.iter().any(|column| has_column(column)) => {
+                InputSchema::V2
+            }
+            InputSchema::Auto => InputSchema::V1,
+            schema => schema,
+        };
+        let (required, forbidden): (&[&str], &[&str]) = match schema {
+            InputSchema::V2 => (&V2_COLUMNS, &[]),
+            _ => (&[], &V2_COLUMNS),
+        };
+        for column in REQUIRED_COLUMNS.iter().chain(required) {
+            if !has_column(column) {
+                return Err(MissingColumn {
+                    column: column.to_string(),
+                    schema,
+                });
+            }
+        }
+        if let Some(column) = forbidden.iter().find(|column| has_column(column)) {
+            return Err(ColumnNotInSchema {
+                column: column.to_string(),
+                schema,
+            });
+        }
+        Ok(schema)
+    }
+}
+
+/// What to do with an amount given on a dispute, resolve or chargeback row. The amount is
+/// redundant as these rows refer to a transaction which has its own.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DisputeAmountPolicy {
+    /// Fail on the row.
+    #[default]
+    Reject,
+    /// Drop the amount.
+    Ignore,
+    /// Keep the amount on the record so it can be checked against the transaction.
+    Verify,
+}
+
+impl fmt::Display for DisputeAmountPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisputeAmountPolicy::Reject => "reject",
+            DisputeAmountPolicy::Ignore => "ignore",
+            DisputeAmountPolicy::Verify => "verify",
+        })
+    }
+}
+
+impl FromStr for DisputeAmountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(DisputeAmountPolicy::Reject),
+            "ignore" => Ok(DisputeAmountPolicy::Ignore),
+            "verify" => Ok(DisputeAmountPolicy::Verify),
+            _ => Err(format!(
+                "Unknown dispute amount policy {}, expected one of: reject, ignore, verify",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do with an amount with more decimal places than `InputReader::with_max_precision`
+/// allows.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ExcessPrecisionPolicy {
+    /// Fail on the row.
+    #[default]
+    Reject,
+    /// Drop the extra decimal places.
+    Truncate,
+}
+
+impl fmt::Display for ExcessPrecisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExcessPrecisionPolicy::Reject => "reject",
+            ExcessPrecisionPolicy::Truncate => "truncate",
+        })
+    }
+}
+
+impl FromStr for ExcessPrecisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(ExcessPrecisionPolicy::Reject),
+            "truncate" => Ok(ExcessPrecisionPolicy::Truncate),
+            _ => Err(format!(
+                "Unknown excess precision policy {}, expected one of: reject, truncate",
+                s
+            )),
+        }
+    }
+}
+
+/// An operation requested by the transaction input.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Deposit {
+        client: ClientId,
+        transaction: Transaction,
+    },
+    /// The transaction amount is negative.
+    Withdrawal {
+        client: ClientId,
+        transaction: Transaction,
+    },
+    /// The reason, such as `fraud`, is read from the optional `reason` column.
+    Dispute {
+        client: ClientId,
+        transaction: TransactionId,
+        reason: Option<ReasonCode>,
+    },
+    Resolve {
+        client: ClientId,
+        transaction: TransactionId,
+    },
+    /// The reason is read from the optional `reason` column, replacing that of the dispute.
+    Chargeback {
+        client: ClientId,
+        transaction: TransactionId,
+        reason: Option<ReasonCode>,
+    },
+    /// Credit back a charged back transaction, as a merchant contesting the chargeback does.
+    Represent {
+        client: ClientId,
+        transaction: TransactionId,
+    },
+    /// Lock the account, the reason is read from the optional `reason` column.
+    Freeze {
+        client: ClientId,
+        reason: Option<ReasonCode>,
+    },
+    Unfreeze {
+        client: ClientId,
+    },
+    /// Close the account at the request of the customer, see `Bank::close_account`.
+    Close {
+        client: ClientId,
+    },
+    /// Hold the funds of the transaction, whose amount is positive, until it is captured or voided.
+    Authorize {
+        client: ClientId,
+        transaction: Transaction,
+    },
+    Capture {
+        client: ClientId,
+        transaction: TransactionId,
+    },
+    Void {
+        client: ClientId,
+        transaction: TransactionId,
+    },
+    /// Hold the positive amount of the transaction administratively until it is released, see
+    /// `Bank::hold`.
+    Hold {
+        client: ClientId,
+        transaction: Transaction,
+    },
+    Release {
+        client: ClientId,
+        transaction: TransactionId,
+    },
+    /// Move the positive amount of the transaction between two of the client's accounts, from
+    /// the one in the `account` column to the one in the `to_account` column.
+    Move {
+        client: ClientId,
+        transaction: Transaction,
+        from: AccountName,
+        to: AccountName,
+    },
+}
+
+impl Command {
+    /// The input record type the command was read from.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Deposit { .. } => "deposit",
+            Command::Withdrawal { .. } => "withdrawal",
+            Command::Dispute { .. } => "dispute",
+            Command::Resolve { .. } => "resolve",
+            Command::Chargeback { .. } => "chargeback",
+            Command::Represent { .. } => "represent",
+            Command::Freeze { .. } => "freeze",
+            Command::Unfreeze { .. } => "unfreeze",
+            Command::Close { .. } => "close",
+            Command::Authorize { .. } => "authorize",
+            Command::Capture { .. } => "capture",
+            Command::Void { .. } => "void",
+            Command::Hold { .. } => "hold",
+            Command::Release { .. } => "release",
+            Command::Move { .. } => "move",
+        }
+    }
+
+    pub fn client(&self) -> ClientId {
+        match self {
+            Command::Deposit { client, .. }
+            | Command::Withdrawal { client, .. }
+            | Command::Dispute { client, .. }
+            | Command::Resolve { client, .. }
+            | Command::Chargeback { client, .. }
+            | Command::Represent { client, .. }
+            | Command::Freeze { client, .. }
+            | Command::Unfreeze { client }
+            | Command::Close { client }
+            | Command::Authorize { client, .. }
+            | Command::Capture { client, .. }
+            | Command::Void { client, .. }
+            | Command::Hold { client, .. }
+            | Command::Release { client, .. }
+            | Command::Move { client, .. } => *client,
+        }
+    }
+
+    /// Apply the command to the bank.
+    pub fn apply(self, bank: &mut Bank) -> Result<Outcome, TransactorError> {
+        match self {
+            Command::Deposit {
+                client,
+                transaction,
+            }
+            | Command::Withdrawal {
+                client,
+                transaction,
+            } => bank.transact(client, transaction),
+            Command::Dispute {
+                client,
+                transaction,
+                reason,
+            } => bank.dispute_with_reason(client, transaction, reason),
+            Command::Resolve {
+                client,
+                transaction,
+            } => bank.resolve_disputed_transaction(client, transaction),
+            Command::Chargeback {
+                client,
+                transaction,
+                reason,
+            } => bank.chargeback_with_reason(client, transaction, reason),
+            Command::Represent {
+                client,
+                transaction,
+            } => bank.represent(client, transaction),
+            Command::Freeze { client, reason } => bank.freeze(client, reason),
+            Command::Unfreeze { client } => bank.unfreeze(client),
+            Command::Close { client } => bank.close_account(client),
+            Command::Authorize {
+                client,
+                transaction,
+            } => bank.authorize(client, transaction),
+            Command::Capture {
+                client,
+                transaction,
+            } => bank.capture(client, transaction),
+            Command::Void {
+                client,
+                transaction,
+            } => bank.void(client, transaction),
+            Command::Hold {
+                client,
+                transaction,
+            } => bank.hold(client, transaction),
+            Command::Release {
+                client,
+                transaction,
+            } => bank.release(client, transaction),
+            Command::Move {
+                client,
+                transaction,
+                from,
+                to,
+            } => bank.move_funds(client, transaction, from, to),
+        }
+    }
+
+    /// Apply the command to the bank, returning the version of the account after it along with
+    /// what it did.
+    pub fn apply_versioned(self, bank: &mut Bank) -> Result<Versioned, TransactorError> {
+        let client = self.client();
+        let outcome = self.apply(bank)?;
+        Ok(Versioned {
+            outcome,
+            version: bank.account_version(client).unwrap_or(0),
+        })
+    }
+
+    /// Apply the command to one of the client's accounts, see `Bank::in_account`. A move names
+    /// its own accounts.
+    pub fn apply_in(
+        self,
+        bank: &mut Bank,
+        account: AccountName,
+    ) -> Result<Outcome, TransactorError> {
+        bank.in_account(self.client(), account, |bank| self.apply(bank))
+    }
+
+    /// The transaction the command creates or refers to, None for commands on the account as a
+    /// whole.
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        match self {
+            Command::Deposit { transaction, .. }
+            | Command::Withdrawal { transaction, .. }
+            | Command::Authorize { transaction, .. }
+            | Command::Hold { transaction, .. }
+            | Command::Move { transaction, .. } => Some(transaction.transaction_id()),
+            Command::Dispute { transaction, .. }
+            | Command::Resolve { transaction, .. }
+            | Command::Chargeback { transaction, .. }
+            | Command::Represent { transaction, .. }
+            | Command::Capture { transaction, .. }
+            | Command::Void { transaction, .. }
+            | Command::Release { transaction, .. } => Some(*transaction),
+            Command::Freeze { .. } | Command::Unfreeze { .. } | Command::Close { .. } => None,
+        }
+    }
+
+    /// The reason code given with a freeze, dispute or chargeback.
+    pub fn reason(&self) -> Option<ReasonCode> {
+        match self {
+            Command::Freeze { reason, .. }
+            | Command::Dispute { reason, .. }
+            | Command::Chargeback { reason, .. } => *reason,
+            _ => None,
+        }
+    }
+}
+
+/// A command read from the input along with where it was read from and the v2 metadata.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InputRecord {
+    pub position: RecordPosition,
+    pub command: Command,
+    /// The account of the client the command is on, from the optional `account` column.
+    pub account: AccountName,
+    pub timestamp: Option<Timestamp>,
+    pub batch: Option<BatchId>,
+    /// The amount given on a row referring to an earlier transaction, such as a dispute, only kept
+    /// under `DisputeAmountPolicy::Verify`.
+    pub stated_amount: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionRecord<'a> {
+    /// The type as written, see `TransactionRecordType::from_name`.
+    #[serde(borrow)]
+    r#type: &'a str,
+    client: u16,
+    tx: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    amount: Option<Decimal>,
+    #[serde(borrow)]
+    currency: Option<&'a str>,
+    timestamp: Option<u64>,
+    #[serde(borrow)]
+    batch: Option<&'a str>,
+    #[serde(borrow)]
+    reason: Option<&'a str>,
+    #[serde(borrow)]
+    account: Option<&'a str>,
+    #[serde(borrow)]
+    to_account: Option<&'a str>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum TransactionRecordType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Represent,
+    Freeze,
+    Unfreeze,
+    Close,
+    Authorize,
+    Capture,
+    Void,
+    Hold,
+    Release,
+    Move,
+}
+
+/// Other names upstream systems give record types, read with lenient types once the name is
+/// lowercased and stripped of `_`, `-` and spaces.
+const TYPE_ALIASES: [(&str, TransactionRecordType); 6] = [
+    ("withdraw", TransactionRecordType::Withdrawal),
+    ("auth", TransactionRecordType::Authorize),
+    ("authorise", TransactionRecordType::Authorize),
+    ("authorization", TransactionRecordType::Authorize),
+    ("authorisation", TransactionRecordType::Authorize),
+    ("representment", TransactionRecordType::Represent),
+];
+
+impl TransactionRecordType {
+    const ALL: [TransactionRecordType; 15] = [
+        TransactionRecordType::Deposit,
+        TransactionRecordType::Withdrawal,
+        TransactionRecordType::Dispute,
+        TransactionRecordType::Resolve,
+        TransactionRecordType::Chargeback,
+        TransactionRecordType::Represent,
+        TransactionRecordType::Freeze,
+        TransactionRecordType::Unfreeze,
+        TransactionRecordType::Close,
+        TransactionRecordType::Authorize,
+        TransactionRecordType::Capture,
+        TransactionRecordType::Void,
+        TransactionRecordType::Hold,
+        TransactionRecordType::Release,
+        TransactionRecordType::Move,
+    ];
+
+    /// The type with this name. Unless `lenient` only the exact lowercase names are read,
+    /// otherwise case, `_`, `-` and spaces are ignored and the names in `TYPE_ALIASES` read too,
+    /// so `WITHDRAW` and `charge_back` are a withdrawal and a chargeback.
+    fn from_name(name: &str, lenient: bool) -> Result<Self, TransactorError> {
+        let exact = Self::ALL.iter().find(|kind| kind.name() == name);
+        if let Some(kind) = exact {
+            return Ok(*kind);
+        }
+        let unknown = || {
+            let names: Vec<&str> = Self::ALL.iter().map(|kind| kind.name()).collect();
+            UnknownRecordType {
+                name: name.to_string(),
+                expected: names.join(", "),
+            }
+        };
+        if !lenient {
+            return Err(unknown());
+        }
+        let normalized: String = name
+            .chars()
+            .filter(|c| !matches!(c, '_' | '-' | ' '))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        Self::ALL
+            .iter()
+            .map(|kind| (kind.name(), *kind))
+            .chain(TYPE_ALIASES.iter().copied())
+            .find(|(alias, _)| *alias == normalized)
+            .map(|(_, kind)| kind)
+            .ok_or_else(unknown)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TransactionRecordType::Deposit => "deposit",
+            TransactionRecordType::Withdrawal => "withdrawal",
+            TransactionRecordType::Dispute => "dispute",
+            TransactionRecordType::Resolve => "resolve",
+            TransactionRecordType::Chargeback => "chargeback",
+            TransactionRecordType::Represent => "represent",
+            TransactionRecordType::Freeze => "freeze",
+            TransactionRecordType::Unfreeze => "unfreeze",
+            TransactionRecordType::Close => "close",
+            TransactionRecordType::Authorize => "authorize",
+            TransactionRecordType::Capture => "capture",
+            TransactionRecordType::Void => "void",
+            TransactionRecordType::Hold => "hold",
+            TransactionRecordType::Release => "release",
+            TransactionRecordType::Move => "move",
+        }
+    }
+}
+
+/// Reads commands from csv transaction input. Every error is reported with the position of the
+/// record that caused it.
+pub struct InputReader<R> {
+    rows: RowReader<R>,
+    parser: RecordParser,
+    schema: InputSchema,
+    row: ByteRecord,
+}
+
+impl InputReader<InputFile> {
+    /// Read a file, decompressing it if its extension says it is compressed.
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        schema: InputSchema,
+    ) -> Result<Self, TransactorError> {
+        let compression = Compression::from_extension(&path);
+        Self::open(path, schema, compression)
+    }
+
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        schema: InputSchema,
+        compression: Compression,
+    ) -> Result<Self, TransactorError> {
+        Self::from_reader(InputFile::open(path, compression)?, schema)
+    }
+
+    /// Carry on reading from the record at `position`. Uncompressed files are sought straight to
+    /// it, anything else has to be read up to it.
+    pub fn resume_from(&mut self, position: RecordPosition) -> Result<(), TransactorError> {
+        match self.rows.reader.get_ref().can_seek() {
+            true => self.seek(position),
+            false => self.skip_to(position),
+        }
+    }
+}
+
+impl<R: io::Read> InputReader<R> {
+    pub fn from_reader(reader: R, schema: InputSchema) -> Result<Self, TransactorError> {
+        Self::from_reader_with_format(reader, schema, &InputFormat::default())
+    }
+
+    /// Read input laid out as `format` says, see `InputFormat`.
+    pub fn from_reader_with_format(
+        reader: R,
+        schema: InputSchema,
+        format: &InputFormat,
+    ) -> Result<Self, TransactorError> {
+        let mut reader = format.reader_builder().from_reader(reader);
+        let (schema, parser) = RecordParser::for_input(reader.headers()?, schema, format)?;
+        Ok(Self {
+            rows: RowReader {
+                reader,
+                #[cfg(feature = "failure-injection")]
+                faults: None,
+            },
+            parser,
+            schema,
+            row: ByteRecord::new(),
+        })
+    }
+
+    /// Choose what to do with amounts on dispute, resolve and chargeback rows, by default they
+    /// are rejected.
+    pub fn with_dispute_amounts(mut self, policy: DisputeAmountPolicy) -> Self {
+        self.parser.dispute_amounts = policy;
+        self
+    }
+
+    /// Read the type of a row ignoring case, `_`, `-` and spaces, and accept aliases such as
+    /// `withdraw`, for upstream systems that do not write the exact names. By default only the
+    /// exact lowercase names are read.
+    pub fn with_lenient_types(mut self) -> Self {
+        self.parser.lenient_types = true;
+        self
+    }
+
+    /// Check amounts have at most `decimal_places` decimal places as they are read, rejecting the
+    /// row or truncating the amount if not. By default any precision is read.
+    pub fn with_max_precision(
+        mut self,
+        decimal_places: u32,
+        policy: ExcessPrecisionPolicy,
+    ) -> Self {
+        self.parser.max_precision = Some(decimal_places);
+        self.parser.excess_precision = policy;
+        self
+    }
+
+    /// Only read the rows of the clients the filter allows, skipping the rest before they are
+    /// parsed. By default every row is read.
+    pub fn with_client_filter(mut self, filter: ClientFilter) -> Self {
+        self.parser.client_filter = Some(filter);
+        self
+    }
+
+    /// Replace rows at random with a row that cannot be parsed.
+    #[cfg(feature = "failure-injection")]
+    pub fn with_faults(mut self, faults: crate::fault::FaultInjector) -> Self {
+        self.rows.faults = Some(faults);
+        self
+    }
+
+    /// Skip the records before `position`, for input that cannot be sought.
+    pub fn skip_to(&mut self, position: RecordPosition) -> Result<(), TransactorError> {
+        while self.rows.reader.position().byte() < position.offset {
+            if !self.rows.reader.read_byte_record(&mut self.row)? {
+                return Err(InvalidConfig(format!(
+                    "the input ends before byte offset {}",
+                    position.offset
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The schema the input was read with, never auto.
+    pub fn schema(&self) -> InputSchema {
+        self.schema
+    }
+
+    fn read_record(&mut self) -> Result<Option<InputRecord>, TransactorError> {
+        loop {
+            if !self.rows.read(&mut self.row)? {
+                return Ok(None);
+            }
+            if !self.parser.skips(&self.row) {
+                return self.parser.parse(&self.row).map(Some);
+            }
+        }
+    }
+}
+
+impl<R: io::Read + io::Seek> InputReader<R> {
+    /// Carry on reading from the record at `position`, such as the first record an earlier run
+    /// did not apply. The offset must be the start of a record of this input.
+    pub fn seek(&mut self, position: RecordPosition) -> Result<(), TransactorError> {
+        let mut start = Position::new();
+        start.set_byte(position.offset).set_line(position.line);
+        self.rows.reader.seek(start)?;
+        Ok(())
+    }
+}
+
+impl<R: io::Read + Send + 'static> InputReader<R> {
+    /// Parse the input on `threads` threads, see `ParallelInputReader`.
+    pub fn parallel(self, threads: usize) -> ParallelInputReader {
+        ParallelInputReader::new(self, threads)
+    }
+}
+
+impl<R: io::Read> Iterator for InputReader<R> {
+    type Item = Result<InputRecord, TransactorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Reads the raw rows of the input, before they are parsed.
+struct RowReader<R> {
+    reader: Reader<R>,
+    #[cfg(feature = "failure-injection")]
+    faults: Option<crate::fault::FaultInjector>,
+}
+
+impl<R: io::Read> RowReader<R> {
+    fn read(&mut self, row: &mut ByteRecord) -> Result<bool, TransactorError> {
+        if !self.reader.read_byte_record(row)? {
+            return Ok(false);
+        }
+        #[cfg(feature = "failure-injection")]
+        if self
+            .faults
+            .as_ref()
+            .is_some_and(|faults| faults.should_fail())
+        {
+            let mut malformed: ByteRecord = row.iter().map(|_| &b"malformed"[..]).collect();
+            malformed.set_position(row.position().cloned());
+            *row = malformed;
+        }
+        Ok(true)
+    }
+}
+
+/// Turns raw rows into records, shared between the threads of a `ParallelInputReader`.
+#[derive(Clone)]
+struct RecordParser {
+    headers: ByteRecord,
+    columns: Option<Columns>,
+    dispute_amounts: DisputeAmountPolicy,
+    /// The most decimal places an amount may have, any number if None.
+    max_precision: Option<u32>,
+    excess_precision: ExcessPrecisionPolicy,
+    lenient_types: bool,
+    client_filter: Option<ClientFilter>,
+}
+
+impl RecordParser {
+    fn new(headers: ByteRecord) -> Self {
+        Self {
+            columns: Columns::new(&headers),
+            headers,
+            dispute_amounts: DisputeAmountPolicy::default(),
+            max_precision: None,
+            excess_precision: ExcessPrecisionPolicy::default(),
+            lenient_types: false,
+            client_filter: None,
+        }
+    }
+
+    /// The schema of the input and the parser of its rows, given its first row: the header row,
+    /// or the first record of input without one. Every reader of csv input starts here so they
+    /// all read the same input alike.
+    fn for_input(
+        first: &StringRecord,
+        schema: InputSchema,
+        format: &InputFormat,
+    ) -> Result<(InputSchema, Self), TransactorError> {
+        let headers = match format.has_headers {
+            true => format.headers(first),
+            false => format.positional_headers(schema, first.len()),
+        };
+        let schema = schema
+            .resolve(&headers, format.strict_schema)
+            .map_err(|e| e.at(RecordPosition::new(1)))?;
+        Ok((schema, Self::new(headers.into_byte_record())))
+    }
+
+    /// Whether the row is of a client the filter leaves out. Rows whose client cannot be read are
+    /// kept, to fail as they would unfiltered.
+    fn skips(&self, row: &ByteRecord) -> bool {
+        let (filter, columns) = match (&self.client_filter, &self.columns) {
+            (Some(filter), Some(columns)) => (filter, columns),
+            _ => return false,
+        };
+        row.get(columns.client)
+            .and_then(|client| std::str::from_utf8(client).ok())
+            .and_then(|client| client.trim().parse().ok())
+            .is_some_and(|client| !filter.allows(ClientId(client)))
+    }
+
+    fn parse(&self, row: &ByteRecord) -> Result<InputRecord, TransactorError> {
+        let position = match row.position() {
+            Some(position) => RecordPosition::new(position.line()).with_offset(position.byte()),
+            None => RecordPosition::new(0),
+        };
+        if row.len() != self.headers.len() {
+            return Err(FieldCount {
+                expected: self.headers.len(),
+                found: row.len(),
+            }
+            .at(position));
+        }
+        let mut record = match self.parse_fields(row) {
+            Some(record) => record,
+            None => row
+                .deserialize(Some(&self.headers))
+                .map_err(|e| TransactorError::from(e).at(position))?,
+        };
+        let mut position = position;
+        position.client = Some(ClientId(record.client));
+        position.transaction = record.tx.map(TransactionId);
+        let timestamp = record.timestamp.map(Timestamp);
+        let batch = record
+            .batch
+            .filter(|batch| !batch.is_empty())
+            .map(BatchId::new);
+        if let (Some(amount), Some(decimal_places)) = (record.amount, self.max_precision) {
+            if amount.normalize().scale() > decimal_places {
+                record.amount = match self.excess_precision {
+                    ExcessPrecisionPolicy::Reject => {
+                        return Err(ExcessPrecision {
+                            amount,
+                            decimal_places,
+                        }
+                        .at(position))
+                    }
+                    ExcessPrecisionPolicy::Truncate => {
+                        Some(Rounding::Truncate.round(amount, decimal_places))
+                    }
+                };
+            }
+        }
+        let amount = record.amount;
+        let account = parse_account_name(record.account).map_err(|e| e.at(position))?;
+        let command = TransactionRecordType::from_name(record.r#type, self.lenient_types)
+            .and_then(|kind| parse_command(kind, record, timestamp, self.dispute_amounts))
+            .map_err(|e| e.at(position))?;
+        // A move carries the accounts it is between
+        let account = match command {
+            Command::Move { .. } => AccountName::MAIN,
+            _ => account,
+        };
+        let stated_amount = match command {
+            Command::Deposit { .. }
+            | Command::Withdrawal { .. }
+            | Command::Authorize { .. }
+            | Command::Hold { .. }
+            | Command::Move { .. } => None,
+            _ if self.dispute_amounts == DisputeAmountPolicy::Verify => amount,
+            _ => None,
+        };
+        Ok(InputRecord {
+            position,
+            command,
+            account,
+            timestamp,
+            batch,
+            stated_amount,
+        })
+    }
+
+    /// Read the fields straight from the row, which is much cheaper than going through serde.
+    /// This only handles plainly formatted rows, None means the row has to be deserialized which
+    /// either accepts it or reports what is wrong with it.
+    fn parse_fields<'a>(&self, row: &'a ByteRecord) -> Option<TransactionRecord<'a>> {
+        let columns = self.columns.as_ref()?;
+        if row.len() != self.headers.len() {
+            return None;
+        }
+        let text = |index: usize| std::str::from_utf8(&row[index]).ok();
+        // Some(None) for a column that is missing or empty, None if the field is not text
+        let optional = |index: Option<usize>| match index {
+            Some(index) => text(index).map(|field| Some(field).filter(|field| !field.is_empty())),
+            None => Some(None),
+        };
+        Some(TransactionRecord {
+            r#type: text(columns.kind)?,
+            client: text(columns.client)?.parse().ok()?,
+            tx: match optional(Some(columns.tx))? {
+                Some(tx) => Some(tx.parse().ok()?),
+                None => None,
+            },
+            amount: match optional(columns.amount)? {
+                Some(amount) => Some(Decimal::from_str(amount).ok()?),
+                None => None,
+            },
+            currency: optional(columns.currency)?,
+            timestamp: match optional(columns.timestamp)? {
+                Some(timestamp) => Some(timestamp.parse().ok()?),
+                None => None,
+            },
+            batch: optional(columns.batch)?,
+            reason: optional(columns.reason)?,
+            account: optional(columns.account)?,
+            to_account: optional(columns.to_account)?,
+        })
+    }
+}
+
+/// Where each column is in a row.
+#[derive(Clone)]
+struct Columns {
+    kind: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    currency: Option<usize>,
+    timestamp: Option<usize>,
+    batch: Option<usize>,
+    reason: Option<usize>,
+    account: Option<usize>,
+    to_account: Option<usize>,
+}
+
+impl Columns {
+    /// Find the columns in the header row, None if a required column is missing or a column is
+    /// repeated.
+    fn new(headers: &ByteRecord) -> Option<Self> {
+        if (0..headers.len())
+            .any(|index| headers.iter().skip(index + 1).any(|h| h == &headers[index]))
+        {
+            return None;
+        }
+        let find = |name: &str| headers.iter().position(|header| header == name.as_bytes());
+        Some(Self {
+            kind: find("type")?,
+            client: find("client")?,
+            tx: find("tx")?,
+            amount: find("amount"),
+            currency: find("currency"),
+            timestamp: find("timestamp"),
+            batch: find("batch"),
+            reason: find("reason"),
+            account: find("account"),
+            to_account: find("to_account"),
+        })
+    }
+}
+
+/// The largest exponent of an amount in scientific notation, past which it cannot be a decimal.
+const MAX_EXPONENT: u32 = 28;
+
+/// Read an amount, in plain or scientific notation. Exponents too large for any decimal are
+/// rejected before they are applied, rather than overflowing while scaling the amount.
+fn parse_amount(text: &str) -> Result<Decimal, String> {
+    let invalid = || format!("Invalid amount {}", text);
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(e) => (
+            &text[..e],
+            text[e + 1..].parse::<i64>().map_err(|_| invalid())?,
+        ),
+        None => return Decimal::from_str(text).map_err(|_| invalid()),
+    };
+    if exponent.unsigned_abs() > u64::from(MAX_EXPONENT) {
+        return Err(format!(
+            "Invalid amount {}, the exponent must be between -{} and {}",
+            text, MAX_EXPONENT, MAX_EXPONENT
+        ));
+    }
+    let mut amount = Decimal::from_str(mantissa).map_err(|_| invalid())?;
+    if exponent < 0 {
+        let scale = amount.scale() + exponent.unsigned_abs() as u32;
+        amount.set_scale(scale).map_err(|_| invalid())?;
+    } else {
+        for _ in 0..exponent {
+            amount = amount.checked_mul(Decimal::from(10)).ok_or_else(invalid)?;
+        }
+    }
+    Ok(amount)
+}
+
+fn deserialize_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Decimal>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) => parse_amount(&text).map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn parse_command(
+    kind: TransactionRecordType,
+    record: TransactionRecord,
+    timestamp: Option<Timestamp>,
+    dispute_amounts: DisputeAmountPolicy,
+) -> Result<Command, TransactorError> {
+    let client = ClientId(record.client);
+    let reason = record.reason.filter(|reason| !reason.is_empty());
+    match kind {
+        TransactionRecordType::Freeze
+        | TransactionRecordType::Unfreeze
+        | TransactionRecordType::Close
+            if record.amount.is_some() =>
+        {
+            return Err(UnexpectedAmount)
+        }
+        TransactionRecordType::Freeze => {
+            return Ok(Command::Freeze {
+                client,
+                reason: reason.map(str::parse).transpose()?,
+            })
+        }
+        TransactionRecordType::Unfreeze if reason.is_none() => {
+            return Ok(Command::Unfreeze { client })
+        }
+        TransactionRecordType::Close if reason.is_none() => return Ok(Command::Close { client }),
+        TransactionRecordType::Dispute | TransactionRecordType::Chargeback => {}
+        _ if reason.is_some() => return Err(UnexpectedReason(kind.name())),
+        _ => {}
+    }
+    let transaction = record
+        .tx
+        .map(TransactionId)
+        .ok_or_else(|| MissingTransactionId(kind.name()))?;
+    match kind {
+        TransactionRecordType::Deposit => Ok(Command::Deposit {
+            client,
+            transaction: parse_transaction(&record, transaction, false, timestamp)?,
+        }),
+        TransactionRecordType::Withdrawal => Ok(Command::Withdrawal {
+            client,
+            transaction: parse_transaction(&record, transaction, true, timestamp)?,
+        }),
+        TransactionRecordType::Authorize => Ok(Command::Authorize {
+            client,
+            transaction: parse_transaction(&record, transaction, false, timestamp)?,
+        }),
+        TransactionRecordType::Hold => Ok(Command::Hold {
+            client,
+            transaction: parse_transaction(&record, transaction, false, timestamp)?,
+        }),
+        TransactionRecordType::Move => {
+            let from = parse_account_name(record.account)?;
+            let to = match record.to_account.filter(|to| !to.is_empty()) {
+                Some(to) => to.parse()?,
+                None => return Err(MissingToAccount),
+            };
+            if from == to {
+                return Err(SameAccountMove(from));
+            }
+            Ok(Command::Move {
+                client,
+                transaction: parse_transaction(&record, transaction, false, timestamp)?,
+                from,
+                to,
+            })
+        }
+        _ if record.amount.is_some() && dispute_amounts == DisputeAmountPolicy::Reject => {
+            Err(UnexpectedAmount)
+        }
+        TransactionRecordType::Dispute => Ok(Command::Dispute {
+            client,
+            transaction,
+            reason: reason.map(str::parse).transpose()?,
+        }),
+        TransactionRecordType::Resolve => Ok(Command::Resolve {
+            client,
+            transaction,
+        }),
+        TransactionRecordType::Chargeback => Ok(Command::Chargeback {
+            client,
+            transaction,
+            reason: reason.map(str::parse).transpose()?,
+        }),
+        TransactionRecordType::Represent => Ok(Command::Represent {
+            client,
+            transaction,
+        }),
+        TransactionRecordType::Capture => Ok(Command::Capture {
+            client,
+            transaction,
+        }),
+        TransactionRecordType::Void => Ok(Command::Void {
+            client,
+            transaction,
+        }),
+        TransactionRecordType::Release => Ok(Command::Release {
+            client,
+            transaction,
+        }),
+        TransactionRecordType::Freeze
+        | TransactionRecordType::Unfreeze
+        | TransactionRecordType::Close => {
+            unreachable!("account commands are parsed without a transaction")
+        }
+    }
+}
+
+/// The account named in the `account` column, the main account if it is missing or empty.
+fn parse_account_name(account: Option<&str>) -> Result<AccountName, TransactorError> {
+    match account.filter(|account| !account.is_empty()) {
+        Some(account) => account.parse(),
+        None => Ok(AccountName::MAIN),
+    }
+}
+
+/// Build the transaction for a deposit, withdrawal, authorization or move, withdrawals are recorded
+/// with a negative amount.
+fn parse_transaction(
+    record: &TransactionRecord,
+    transaction_id: TransactionId,
+    withdrawal: bool,
+    timestamp: Option<Timestamp>,
+) -> Result<Transaction, TransactorError> {
+    let amount = record.amount.ok_or(MissingAmount)?;
+    if amount < Decimal::zero() {
+        return Err(NegativeAmount(amount));
+    }
+    let amount = if withdrawal { -amount } else { amount };
+    let mut transaction = Transaction::new(transaction_id, amount);
+    match record.currency {
+        Some(currency) if !currency.is_empty() => {
+            transaction = transaction.with_currency(
+                currency
+                    .parse::<Currency>()
+                    .map_err(|_| InvalidCurrency(currency.to_string()))?,
+            );
+        }
+        _ => {}
+    }
+    if let Some(timestamp) = timestamp {
+        transaction = transaction.with_timestamp(timestamp);
+    }
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(input: &str, schema: InputSchema) -> Result<Vec<InputRecord>, TransactorError> {
+        InputReader::from_reader(input.as_bytes(), schema)?.collect()
+    }
+
+    #[test]
+    fn auto_schema_accepts_v1_and_v2_shapes() -> Result<(), TransactorError> {
+        let v1 = read(
+            "type, client, tx, amount\ndeposit, 1, 1, 1.5\nwithdrawal, 1, 2, 0.5\n",
+            InputSchema::Auto,
+        )?;
+        assert_eq!(
+            v1[1].command,
+            Command::Withdrawal {
+                client: ClientId(1),
+                transaction: Transaction::new(TransactionId(2), Decimal::new(-5, 1)),
+            }
+        );
+        assert_eq!(v1[1].position.line, 3);
+        assert_eq!(v1[1].position.offset, 44);
+
+        let v2 = read(
+            "type,client,tx,amount,currency,timestamp,batch\ndeposit,1,1,1.5,USD,100,b1\ndispute,1,1,,,200,\n",
+            InputSchema::Auto,
+        )?;
+        assert_eq!(
+            v2[0].command,
+            Command::Deposit {
+                client: ClientId(1),
+                transaction: Transaction::new(TransactionId(1), Decimal::new(15, 1))
+                    .with_currency("USD".parse().unwrap())
+                    .with_timestamp(Timestamp(100)),
+            }
+        );
+        assert_eq!(v2[0].batch, Some(BatchId::new("b1")));
+        assert_eq!(v2[1].timestamp, Some(Timestamp(200)));
+        assert_eq!(v2[1].batch, None);
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_past_the_max_precision_or_exponent_are_caught() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.123456789\ndeposit,1,2,25e-5\n";
+        let amounts = |policy| -> Result<Vec<Decimal>, TransactorError> {
+            InputReader::from_reader(input.as_bytes(), InputSchema::V1)?
+                .with_max_precision(4, policy)
+                .map(|result| result.map(|record| amounts_of(&record.command)))
+                .collect()
+        };
+        assert_eq!(
+            amounts(ExcessPrecisionPolicy::Truncate)?,
+            vec![Decimal::new(11234, 4), Decimal::new(2, 4)]
+        );
+        let rejected = amounts(ExcessPrecisionPolicy::Reject).unwrap_err();
+        assert!(rejected
+            .to_string()
+            .starts_with("Line 2, client 1, transaction 1: Amount"));
+
+        assert_eq!(parse_amount("-1.5E3"), Ok(Decimal::new(-1500, 0)));
+        assert!(parse_amount("1e30")
+            .unwrap_err()
+            .contains("between -28 and 28"));
+        assert!(parse_amount("9e28").is_err());
+        let absurd = read(
+            "type,client,tx,amount\ndeposit,1,1,1e99999\n",
+            InputSchema::V1,
+        );
+        assert!(absurd
+            .unwrap_err()
+            .to_string()
+            .contains("between -28 and 28"));
+        Ok(())
+    }
+
+    fn amounts_of(command: &Command) -> Decimal {
+        match command {
+            Command::Deposit { transaction, .. } => transaction.amount(),
+            _ => Decimal::zero(),
+        }
+    }
+
+    #[test]
+    fn lenient_types_read_other_cases_and_aliases() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\nDeposit,1,1,2\nWITHDRAW,1,2,1\ndispute,1,1,\ncharge_back,1,1,\n";
+        let strict = read(input, InputSchema::V1).unwrap_err().to_string();
+        assert!(strict.starts_with("Line 2, client 1, transaction 1: Unknown record type Deposit"));
+        assert!(strict.ends_with("expected one of: deposit, withdrawal, dispute, resolve, chargeback, represent, freeze, unfreeze, close, authorize, capture, void, hold, release, move"));
+
+        let records: Vec<InputRecord> =
+            InputReader::from_reader(input.as_bytes(), InputSchema::V1)?
+                .with_lenient_types()
+                .collect::<Result<_, _>>()?;
+        assert!(matches!(
+            records
+                .iter()
+                .map(|record| record.command)
+                .collect::<Vec<_>>()[..],
+            [
+                Command::Deposit { .. },
+                Command::Withdrawal { .. },
+                Command::Dispute { .. },
+                Command::Chargeback { .. }
+            ]
+        ));
+        let refund = InputReader::from_reader(
+            "type,client,tx,amount\nrefund,1,3,1\n".as_bytes(),
+            InputSchema::V1,
+        )?
+        .with_lenient_types()
+        .next();
+        assert!(matches!(refund, Some(Err(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn reading_resumes_from_a_record_position() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,2\ndeposit,1,3,3\n";
+        let second = read(input, InputSchema::Auto)?[1].position;
+        let mut reader = InputReader::from_reader(io::Cursor::new(input), InputSchema::Auto)?;
+        reader.seek(second)?;
+        let resumed: Vec<InputRecord> = reader.collect::<Result<_, _>>()?;
+        assert_eq!(resumed.len(), 2);
+        assert_eq!(resumed[0].position, second);
+
+        let mut reader = InputReader::from_reader(input.as_bytes(), InputSchema::Auto)?;
+        reader.skip_to(second)?;
+        assert_eq!(
+            reader.next().transpose()?.map(|record| record.position),
+            Some(second)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rows_the_fast_path_cannot_read_are_deserialized() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount,currency\ndeposit,1,1,1.5,usd\ndeposit,1,2,1e2,\ndeposit,1,3,x,\n";
+        let mut reader = InputReader::from_reader(input.as_bytes(), InputSchema::Auto)?;
+        let mut row = ByteRecord::new();
+        reader.rows.read(&mut row)?;
+        assert!(reader.parser.parse_fields(&row).is_some());
+        assert_eq!(
+            reader.parser.parse(&row)?.command,
+            Command::Deposit {
+                client: ClientId(1),
+                transaction: Transaction::new(TransactionId(1), Decimal::new(15, 1))
+                    .with_currency("USD".parse().unwrap()),
+            }
+        );
+        reader.rows.read(&mut row)?;
+        assert!(reader.parser.parse_fields(&row).is_none());
+        assert_eq!(
+            reader.parser.parse(&row)?.command,
+            Command::Deposit {
+                client: ClientId(1),
+                transaction: Transaction::new(TransactionId(2), Decimal::new(100, 0)),
+            }
+        );
+        reader.rows.read(&mut row)?;
+        assert!(matches!(
+            reader.parser.parse(&row),
+            Err(Record { source, .. }) if matches!(*source, CsvError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_on_disputes_follow_the_policy() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\ndispute,1,1,2.5\n";
+        let read_with = |policy| {
+            InputReader::from_reader(input.as_bytes(), InputSchema::Auto)
+                .map(|reader| reader.with_dispute_amounts(policy).collect::<Vec<_>>())
+        };
+        assert!(matches!(
+            &read_with(DisputeAmountPolicy::Reject)?[..],
+            [Err(Record { source, .. })] if matches!(**source, UnexpectedAmount)
+        ));
+        let ignored = read_with(DisputeAmountPolicy::Ignore)?.remove(0)?;
+        assert_eq!(ignored.stated_amount, None);
+        let verified = read_with(DisputeAmountPolicy::Verify)?.remove(0)?;
+        assert_eq!(verified.stated_amount, Some(Decimal::new(25, 1)));
+        assert_eq!(
+            verified.command,
+            Command::Dispute {
+                client: ClientId(1),
+                transaction: TransactionId(1),
+                reason: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn freezes_and_disputes_take_a_reason() -> Result<(), TransactorError> {
+        let records = read(
+            "type,client,tx,amount,reason\nfreeze,1,,,aml-hit\nunfreeze,1,,,\ndispute,1,3,,fraud\nchargeback,1,3,,\n",
+            InputSchema::Auto,
+        )?;
+        let commands: Vec<Command> = records.iter().map(|record| record.command).collect();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Freeze {
+                    client: ClientId(1),
+                    reason: Some("aml-hit".parse()?),
+                },
+                Command::Unfreeze {
+                    client: ClientId(1)
+                },
+                Command::Dispute {
+                    client: ClientId(1),
+                    transaction: TransactionId(3),
+                    reason: Some("fraud".parse()?),
+                },
+                Command::Chargeback {
+                    client: ClientId(1),
+                    transaction: TransactionId(3),
+                    reason: None,
+                },
+            ]
+        );
+        assert!(matches!(
+            read("type,client,tx,amount,reason\ndeposit,1,1,1.0,aml-hit\n", InputSchema::Auto),
+            Err(Record { source, .. }) if matches!(*source, UnexpectedReason("deposit"))
+        ));
+        assert!(matches!(
+            read("type,client,tx,amount\ndispute,1,,\n", InputSchema::Auto),
+            Err(Record { source, .. }) if matches!(*source, MissingTransactionId("dispute"))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_column_mixes_are_rejected() {
+        let partial_v2 = "type,client,tx,amount,timestamp\n";
+        assert!(matches!(
+            read(partial_v2, InputSchema::Auto),
+            Err(Record { source, .. }) if matches!(*source, MissingColumn { .. })
+        ));
+        assert!(matches!(
+            read("type,client,tx,amount,timestamp,batch\n", InputSchema::V1),
+            Err(Record { source, .. }) if matches!(*source, ColumnNotInSchema { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_columns_are_ignored_unless_strict() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount,audit_user,audit_time\ndeposit,1,1,2,ops,monday\n";
+        assert_eq!(read(input, InputSchema::Auto)?.len(), 1);
+
+        let strict = InputFormat::default().with_strict_schema();
+        let error =
+            InputReader::from_reader_with_format(input.as_bytes(), InputSchema::Auto, &strict)
+                .err()
+                .map(|e| e.to_string());
+        assert_eq!(
+            error.as_deref(),
+            Some("Line 1: Unknown input columns audit_user, audit_time, expected only: type, client, tx, amount, currency, reason, account, to_account, timestamp, batch")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rows_of_filtered_clients_are_skipped() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1\n\
+            deposit,2,2,1\n\
+            deposit,7,3,1\n\
+            deposit,x,4,1\n\
+            deposit,12,5,1\n";
+        let only: ClientRanges = "1,5-12".parse().map_err(InvalidConfig)?;
+        let filter = ClientFilter::new()
+            .with_only(only)
+            .with_excluded("7".parse().map_err(InvalidConfig)?);
+        let results: Vec<_> = InputReader::from_reader(input.as_bytes(), InputSchema::V1)?
+            .with_client_filter(filter)
+            .collect();
+        // An unreadable client is never skipped, it fails as it would unfiltered
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+        let clients: Vec<_> = results
+            .into_iter()
+            .flatten()
+            .map(|record| record.command.client().0)
+            .collect();
+        assert_eq!(clients, vec![1, 12]);
+        assert!("3-1".parse::<ClientRanges>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1038 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+use csv::Writer;
+use flate2::read::DeflateDecoder;
+use serde_json::{Map, Number, Value};
+
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The first bytes of an Avro object container file.
+const CONTAINER_MAGIC: [u8; 4] = [b'O', b'b', b'j', 1];
+
+/// The records read from framed messages before they are handed on as rows.
+const MESSAGES_PER_READ: usize = 1024;
+
+/// An Avro schema, as far as reading values written with it needs.
+#[derive(Clone, Debug)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    /// Bytes, or fixed bytes if it has a size, of a two's complement unscaled number.
+    Decimal {
+        scale: u32,
+        size: Option<usize>,
+    },
+    /// A long count of the units in a second since the epoch, read as seconds.
+    Timestamp(i64),
+    /// An int count of days since the epoch, read as seconds.
+    Date,
+}
+
+impl Schema {
+    fn from_json(text: &str) -> Result<Self, TransactorError> {
+        let json: Value =
+            serde_json::from_str(text).map_err(|e| InvalidAvro(format!("schema: {}", e)))?;
+        Self::parse(&json, &mut HashMap::new(), None)
+    }
+
+    /// The schema in its json form, named types it defines are added to `names` so later parts
+    /// of the schema can refer to them.
+    fn parse(
+        json: &Value,
+        names: &mut HashMap<String, Schema>,
+        namespace: Option<&str>,
+    ) -> Result<Self, TransactorError> {
+        let object = match json {
+            Value::String(name) => return Self::named(name, names, namespace),
+            Value::Array(branches) => {
+                return branches
+                    .iter()
+                    .map(|branch| Self::parse(branch, names, namespace))
+                    .collect::<Result<_, _>>()
+                    .map(Schema::Union)
+            }
+            Value::Object(object) => object,
+            _ => return Err(InvalidAvro(format!("{} is not a schema", json))),
+        };
+        let text = |key: &str| object.get(key).and_then(Value::as_str);
+        let namespace = text("namespace").or(namespace);
+        let schema = match text("type") {
+            Some("record") | Some("error") => {
+                let fields = match object.get("fields") {
+                    Some(Value::Array(fields)) => fields,
+                    _ => return Err(InvalidAvro("a record has no fields".to_string())),
+                };
+                let fields = fields
+                    .iter()
+                    .map(|field| {
+                        let name = field.get("name").and_then(Value::as_str);
+                        match (name, field.get("type")) {
+                            (Some(name), Some(kind)) => {
+                                Ok((name.to_string(), Self::parse(kind, names, namespace)?))
+                            }
+                            _ => Err(InvalidAvro(format!(
+                                "the field {} has no name or type",
+                                field
+                            ))),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+                Schema::Record(fields)
+            }
+            Some("enum") => Schema::Enum(
+                object
+                    .get("symbols")
+                    .and_then(Value::as_array)
+                    .map(|symbols| {
+                        symbols
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .ok_or_else(|| InvalidAvro("an enum has no symbols".to_string()))?,
+            ),
+            Some("array") => match object.get("items") {
+                Some(items) => Schema::Array(Box::new(Self::parse(items, names, namespace)?)),
+                None => return Err(InvalidAvro("an array has no items".to_string())),
+            },
+            Some("map") => match object.get("values") {
+                Some(values) => Schema::Map(Box::new(Self::parse(values, names, namespace)?)),
+                None => return Err(InvalidAvro("a map has no values".to_string())),
+            },
+            Some("fixed") => match object.get("size").and_then(Value::as_u64) {
+                Some(size) => Schema::Fixed(size as usize),
+                None => return Err(InvalidAvro("a fixed has no size".to_string())),
+            },
+            _ => match object.get("type") {
+                Some(kind) => Self::parse(kind, names, namespace)?,
+                None => return Err(InvalidAvro(format!("{} has no type", json))),
+            },
+        };
+        let schema = match (text("logicalType"), schema) {
+            (Some("decimal"), Schema::Bytes) => Schema::Decimal {
+                scale: scale_of(object)?,
+                size: None,
+            },
+            (Some("decimal"), Schema::Fixed(size)) => Schema::Decimal {
+                scale: scale_of(object)?,
+                size: Some(size),
+            },
+            (Some("timestamp-millis"), Schema::Long)
+            | (Some("local-timestamp-millis"), Schema::Long) => Schema::Timestamp(1_000),
+            (Some("timestamp-micros"), Schema::Long)
+            | (Some("local-timestamp-micros"), Schema::Long) => Schema::Timestamp(1_000_000),
+            (Some("timestamp-nanos"), Schema::Long)
+            | (Some("local-timestamp-nanos"), Schema::Long) => Schema::Timestamp(1_000_000_000),
+            (Some("date"), Schema::Int) => Schema::Date,
+            (_, schema) => schema,
+        };
+        if let Some(name) = text("name") {
+            let full_name = match (name.contains('.'), namespace) {
+                (false, Some(namespace)) => format!("{}.{}", namespace, name),
+                _ => name.to_string(),
+            };
+            names.insert(full_name, schema.clone());
+            names.insert(name.to_string(), schema.clone());
+        }
+        Ok(schema)
+    }
+
+    /// A primitive type, or a named type defined earlier in the schema.
+    fn named(
+        name: &str,
+        names: &HashMap<String, Schema>,
+        namespace: Option<&str>,
+    ) -> Result<Self, TransactorError> {
+        Ok(match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            _ => namespace
+                .and_then(|namespace| names.get(&format!("{}.{}", namespace, name)))
+                .or_else(|| names.get(name))
+                .cloned()
+                .ok_or_else(|| {
+                    InvalidAvro(format!(
+                        "unknown type {}, types cannot refer to themselves",
+                        name
+                    ))
+                })?,
+        })
+    }
+
+    /// The names of the fields of a record, the columns of the rows read with it.
+    fn columns(&self) -> Result<Vec<String>, TransactorError> {
+        match self {
+            Schema::Record(fields) => Ok(fields.iter().map(|(name, _)| name.clone()).collect()),
+            _ => Err(InvalidAvro(
+                "records must be of a record schema".to_string(),
+            )),
+        }
+    }
+
+    /// Read a value of this schema. Nested records, arrays and maps are read as json.
+    fn read<R: Read>(&self, input: &mut R) -> io::Result<Value> {
+        Ok(match self {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Bool(read_byte(input)? != 0),
+            Schema::Int | Schema::Long => Value::from(read_long(input)?),
+            Schema::Float => {
+                let mut bytes = [0; 4];
+                read_exact(input, &mut bytes)?;
+                Number::from_f64(f32::from_le_bytes(bytes).into()).map_or(Value::Null, Value::from)
+            }
+            Schema::Double => {
+                let mut bytes = [0; 8];
+                read_exact(input, &mut bytes)?;
+                Number::from_f64(f64::from_le_bytes(bytes)).map_or(Value::Null, Value::from)
+            }
+            Schema::Bytes | Schema::String => {
+                Value::String(String::from_utf8_lossy(&read_bytes(input)?).into_owned())
+            }
+            Schema::Record(fields) => {
+                let mut record = Map::new();
+                for (name, schema) in fields {
+                    record.insert(name.clone(), schema.read(input)?);
+                }
+                Value::Object(record)
+            }
+            Schema::Enum(symbols) => {
+                let index = read_long(input)?;
+                match usize::try_from(index)
+                    .ok()
+                    .and_then(|index| symbols.get(index))
+                {
+                    Some(symbol) => Value::String(symbol.clone()),
+                    None => return Err(invalid(format!("no enum symbol {}", index))),
+                }
+            }
+            Schema::Array(items) => {
+                let mut values = Vec::new();
+                read_blocks(input, |input| {
+                    values.push(items.read(input)?);
+                    Ok(())
+                })?;
+                Value::Array(values)
+            }
+            Schema::Map(values) => {
+                let mut map = Map::new();
+                read_blocks(input, |input| {
+                    let key = String::from_utf8_lossy(&read_bytes(input)?).into_owned();
+                    map.insert(key, values.read(input)?);
+                    Ok(())
+                })?;
+                Value::Object(map)
+            }
+            Schema::Union(branches) => {
+                let index = read_long(input)?;
+                match usize::try_from(index)
+                    .ok()
+                    .and_then(|index| branches.get(index))
+                {
+                    Some(branch) => branch.read(input)?,
+                    None => return Err(invalid(format!("no union branch {}", index))),
+                }
+            }
+            Schema::Fixed(size) => {
+                let mut bytes = vec![0; *size];
+                read_exact(input, &mut bytes)?;
+                Value::String(String::from_utf8_lossy(&bytes).into_owned())
+            }
+            Schema::Decimal { scale, size } => {
+                let bytes = match size {
+                    Some(size) => {
+                        let mut bytes = vec![0; *size];
+                        read_exact(input, &mut bytes)?;
+                        bytes
+                    }
+                    None => read_bytes(input)?,
+                };
+                Value::String(decimal_text(&bytes, *scale)?)
+            }
+            Schema::Timestamp(per_second) => Value::from(read_long(input)?.div_euclid(*per_second)),
+            Schema::Date => Value::from(read_long(input)? * 86_400),
+        })
+    }
+}
+
+fn scale_of(object: &Map<String, Value>) -> Result<u32, TransactorError> {
+    let precision = object.get("precision").and_then(Value::as_u64);
+    Ok(object
+        .get("scale")
+        .map(|scale| scale.as_u64().filter(|scale| *scale <= 28))
+        .unwrap_or(Some(0))
+        .filter(|scale| precision.is_none_or(|precision| *scale <= precision))
+        .ok_or_else(|| {
+            InvalidAvro(
+                "a decimal scale must be from 0 to 28 and at most its precision".to_string(),
+            )
+        })? as u32)
+}
+
+/// The writer schemas of messages framed for a schema registry, each a zero byte and the four
+/// byte big-endian id of its schema before the record. Either one given schema is used whatever
+/// id a message names, or each is fetched from a Confluent compatible registry by its id and
+/// kept, which needs the remote feature.
+pub struct AvroSchemas {
+    given: Option<Schema>,
+    registry: Option<String>,
+    fetched: HashMap<u32, Schema>,
+}
+
+impl AvroSchemas {
+    /// Read every message with a schema given as json, such as the contents of an `.avsc` file.
+    pub fn with_schema(json: &str) -> Result<Self, TransactorError> {
+        Ok(Self {
+            given: Some(Schema::from_json(json)?),
+            registry: None,
+            fetched: HashMap::new(),
+        })
+    }
+
+    /// Fetch the schema of each message from the registry at `url`.
+    pub fn from_registry(url: &str) -> Result<Self, TransactorError> {
+        if cfg!(not(feature = "remote")) {
+            return Err(InvalidConfig(
+                "fetching Avro schemas from a registry needs the remote feature".to_string(),
+            ));
+        }
+        Ok(Self {
+            given: None,
+            registry: Some(url.trim_end_matches('/').to_string()),
+            fetched: HashMap::new(),
+        })
+    }
+
+    fn get(&mut self, id: u32) -> Result<&Schema, TransactorError> {
+        if let Some(schema) = &self.given {
+            return Ok(schema);
+        }
+        if !self.fetched.contains_key(&id) {
+            let schema = self.fetch(id)?;
+            self.fetched.insert(id, schema);
+        }
+        Ok(&self.fetched[&id])
+    }
+
+    #[cfg(feature = "remote")]
+    fn fetch(&self, id: u32) -> Result<Schema, TransactorError> {
+        let url = format!(
+            "{}/schemas/ids/{}",
+            self.registry.as_deref().unwrap_or(""),
+            id
+        );
+        let response: Value = serde_json::from_reader(super::remote::open(&url)?)
+            .map_err(|e| RemoteError(format!("cannot read {}: {}", url, e)))?;
+        match response.get("schema").and_then(Value::as_str) {
+            Some(schema) => Schema::from_json(schema),
+            None => Err(RemoteError(format!("{} holds no schema", url))),
+        }
+    }
+
+    #[cfg(not(feature = "remote"))]
+    fn fetch(&self, id: u32) -> Result<Schema, TransactorError> {
+        Err(InvalidConfig(format!(
+            "cannot fetch Avro schema {} from {}, fetching from a registry needs the remote feature",
+            id,
+            self.registry.as_deref().unwrap_or("")
+        )))
+    }
+}
+
+enum Records {
+    /// An object container file, holding the schema of its records and blocks of them.
+    Container {
+        schema: Schema,
+        codec: Codec,
+        sync: [u8; 16],
+    },
+    Messages(AvroSchemas),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Codec {
+    Null,
+    Deflate,
+    Snappy,
+    Zstandard,
+}
+
+/// A framed message, with the fields of its schema in order.
+struct Message {
+    columns: Vec<String>,
+    record: Map<String, Value>,
+}
+
+/// Avro records read a block or a run of messages at a time and handed on as csv rows, so they
+/// stream through the input reader like csv input. The header is the names of the fields of the
+/// records, of the first message for framed messages, whose later schemas may add or drop fields.
+/// Timestamps and dates are read as seconds since the epoch, decimals exactly and nulls as empty
+/// fields.
+pub struct AvroRows<R: Read> {
+    input: R,
+    records: Records,
+    columns: Vec<String>,
+    rows: io::Cursor<Vec<u8>>,
+    ended: bool,
+}
+
+impl<R: Read> AvroRows<R> {
+    /// The records of an Avro object container file.
+    pub fn container(mut input: R) -> Result<Self, TransactorError> {
+        let mut magic = [0; 4];
+        read_exact(&mut input, &mut magic)?;
+        if magic != CONTAINER_MAGIC {
+            return Err(InvalidAvro("not an Avro object container file".to_string()));
+        }
+        let metadata = match Schema::Map(Box::new(Schema::Bytes)).read(&mut input)? {
+            Value::Object(metadata) => metadata,
+            _ => Map::new(),
+        };
+        let schema = match metadata.get("avro.schema").and_then(Value::as_str) {
+            Some(schema) => Schema::from_json(schema)?,
+            None => return Err(InvalidAvro("the file has no schema".to_string())),
+        };
+        let codec = match metadata.get("avro.codec").and_then(Value::as_str) {
+            None | Some("null") => Codec::Null,
+            Some("deflate") => Codec::Deflate,
+            Some("snappy") => Codec::Snappy,
+            Some("zstandard") => Codec::Zstandard,
+            Some(codec) => return Err(InvalidAvro(format!("unsupported codec {}", codec))),
+        };
+        let mut sync = [0; 16];
+        read_exact(&mut input, &mut sync)?;
+        let columns = schema.columns()?;
+        Self::start(
+            input,
+            Records::Container {
+                schema,
+                codec,
+                sync,
+            },
+            columns,
+        )
+    }
+
+    /// The records of messages framed for a schema registry, one after another.
+    pub fn messages(input: R, schemas: AvroSchemas) -> Result<Self, TransactorError> {
+        let mut rows = Self {
+            input,
+            records: Records::Messages(schemas),
+            columns: Vec::new(),
+            rows: io::Cursor::new(Vec::new()),
+            ended: false,
+        };
+        // The header is only known once the schema of the first message is
+        let first = rows.read_message()?;
+        let mut writer = Writer::from_writer(Vec::new());
+        if let Some(mut message) = first {
+            writer.write_record(&message.columns)?;
+            write_row(&mut writer, &message.columns, &mut message.record)?;
+            rows.columns = message.columns;
+        }
+        rows.rows = io::Cursor::new(finish_rows(writer)?);
+        Ok(rows)
+    }
+
+    fn start(input: R, records: Records, columns: Vec<String>) -> Result<Self, TransactorError> {
+        let mut header = Writer::from_writer(Vec::new());
+        header.write_record(&columns)?;
+        Ok(Self {
+            input,
+            records,
+            columns,
+            rows: io::Cursor::new(finish_rows(header)?),
+            ended: false,
+        })
+    }
+
+    /// The next framed message, None at the end of the input.
+    fn read_message(&mut self) -> Result<Option<Message>, TransactorError> {
+        let schemas = match &mut self.records {
+            Records::Messages(schemas) => schemas,
+            Records::Container { .. } => return Ok(None),
+        };
+        let mut magic = [0; 1];
+        if self.input.read(&mut magic)? == 0 {
+            return Ok(None);
+        }
+        let mut id = [0; 4];
+        if magic[0] != 0 || self.input.read_exact(&mut id).is_err() {
+            return Err(InvalidAvro(
+                "a message is not framed for a schema registry".to_string(),
+            ));
+        }
+        let schema = schemas.get(u32::from_be_bytes(id))?;
+        let columns = schema.columns()?;
+        let record = match schema.read(&mut self.input)? {
+            Value::Object(record) => record,
+            _ => Map::new(),
+        };
+        Ok(Some(Message { columns, record }))
+    }
+
+    /// The csv rows of the next block or run of messages, None at the end of the input.
+    fn read_rows(&mut self) -> Result<Option<Vec<u8>>, TransactorError> {
+        let mut writer = Writer::from_writer(Vec::new());
+        match &self.records {
+            Records::Container {
+                schema,
+                codec,
+                sync,
+            } => {
+                let count = match read_long_or_end(&mut self.input)? {
+                    Some(count) => u64::try_from(count)
+                        .map_err(|_| invalid("a block of a negative number of records"))?,
+                    None => return Ok(None),
+                };
+                let size = read_long(&mut self.input)?;
+                let size = u64::try_from(size).map_err(|_| invalid("a negative block size"))?;
+                let mut block = Vec::new();
+                (&mut self.input).take(size).read_to_end(&mut block)?;
+                if block.len() as u64 != size {
+                    return Err(truncated().into());
+                }
+                let mut marker = [0; 16];
+                read_exact(&mut self.input, &mut marker)?;
+                if marker != *sync {
+                    return Err(InvalidAvro(
+                        "a block does not end in the sync marker".to_string(),
+                    ));
+                }
+                let mut block = io::Cursor::new(decompress(*codec, block)?);
+                let fields = match schema {
+                    Schema::Record(fields) => fields,
+                    _ => return Ok(None),
+                };
+                for _ in 0..count {
+                    for (_, field) in fields {
+                        writer.write_field(field_text(field.read(&mut block)?))?;
+                    }
+                    writer.write_record(None::<&[u8]>)?;
+                }
+            }
+            Records::Messages(_) => {
+                for read in 0..MESSAGES_PER_READ {
+                    match self.read_message()? {
+                        Some(mut message) => {
+                            write_row(&mut writer, &self.columns, &mut message.record)?
+                        }
+                        None if read == 0 => return Ok(None),
+                        None => break,
+                    }
+                }
+            }
+        }
+        finish_rows(writer).map(Some)
+    }
+}
+
+impl<R: Read> Read for AvroRows<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.rows.read(buf)?;
+            if read > 0 || buf.is_empty() || self.ended {
+                return Ok(read);
+            }
+            match self.read_rows() {
+                Ok(Some(rows)) => self.rows = io::Cursor::new(rows),
+                Ok(None) => self.ended = true,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+    }
+}
+
+/// Write the fields of a record under the columns of the header they are named by.
+fn write_row(
+    writer: &mut Writer<Vec<u8>>,
+    header: &[String],
+    record: &mut Map<String, Value>,
+) -> Result<(), TransactorError> {
+    for column in header {
+        writer.write_field(field_text(record.remove(column).unwrap_or(Value::Null)))?;
+    }
+    writer.write_record(None::<&[u8]>)?;
+    Ok(())
+}
+
+fn finish_rows(writer: Writer<Vec<u8>>) -> Result<Vec<u8>, TransactorError> {
+    writer
+        .into_inner()
+        .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()).into())
+}
+
+fn field_text(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text,
+        value => value.to_string(),
+    }
+}
+
+fn decompress(codec: Codec, block: Vec<u8>) -> Result<Vec<u8>, TransactorError> {
+    match codec {
+        Codec::Null => Ok(block),
+        Codec::Deflate => {
+            let mut data = Vec::new();
+            DeflateDecoder::new(block.as_slice())
+                .read_to_end(&mut data)
+                .map_err(|e| InvalidAvro(e.to_string()))?;
+            Ok(data)
+        }
+        // The compressed data is followed by a crc32 of the data, which is left unchecked
+        Codec::Snappy => snap::raw::Decoder::new()
+            .decompress_vec(&block[..block.len().saturating_sub(4)])
+            .map_err(|e| InvalidAvro(e.to_string())),
+        Codec::Zstandard => {
+            zstd::decode_all(block.as_slice()).map_err(|e| InvalidAvro(e.to_string()))
+        }
+    }
+}
+
+/// An unscaled big-endian two's complement number written with `scale` decimal places.
+fn decimal_text(bytes: &[u8], scale: u32) -> io::Result<String> {
+    if bytes.len() > 16 {
+        return Err(invalid("a decimal of more than 16 bytes"));
+    }
+    let negative = bytes.first().is_some_and(|byte| byte & 0x80 != 0);
+    let mut unscaled: i128 = if negative { -1 } else { 0 };
+    for byte in bytes {
+        unscaled = unscaled << 8 | i128::from(*byte);
+    }
+    let scale = scale as usize;
+    let digits = format!("{:0>width$}", unscaled.unsigned_abs(), width = scale + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - scale);
+    let sign = if negative { "-" } else { "" };
+    Ok(match fraction.is_empty() {
+        true => format!("{}{}", sign, whole),
+        false => format!("{}{}.{}", sign, whole, fraction),
+    })
+}
+
+/// Read the blocks of an array or map, calling `read` for each item.
+fn read_blocks<R: Read>(
+    input: &mut R,
+    mut read: impl FnMut(&mut R) -> io::Result<()>,
+) -> io::Result<()> {
+    loop {
+        let count = read_long(input)?;
+        if count == 0 {
+            return Ok(());
+        }
+        // A negative count is followed by the size of the block in bytes
+        if count < 0 {
+            read_long(input)?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            read(input)?;
+        }
+    }
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0; 1];
+    read_exact(input, &mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_bytes<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let length = read_long(input)?;
+    let length = u64::try_from(length).map_err(|_| invalid("a negative length"))?;
+    let mut bytes = Vec::new();
+    input.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(truncated());
+    }
+    Ok(bytes)
+}
+
+/// A zigzag encoded variable length long.
+fn read_long<R: Read>(input: &mut R) -> io::Result<i64> {
+    read_long_or_end(input)?.ok_or_else(truncated)
+}
+
+/// A long, or None if the input ends before it starts.
+fn read_long_or_end<R: Read>(input: &mut R) -> io::Result<Option<i64>> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0; 1];
+        if input.read(&mut byte)? == 0 {
+            return match shift {
+                0 => Ok(None),
+                _ => Err(truncated()),
+            };
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some((value >> 1) as i64 ^ -((value & 1) as i64)));
+        }
+    }
+    Err(invalid("a long of more than ten bytes"))
+}
+
+/// Fill `buf`, input that ends first being invalid rather than an io error.
+fn read_exact<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => truncated(),
+        _ => e,
+    })
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, InvalidAvro(message.into()))
+}
+
+fn truncated() -> io::Error {
+    invalid("the input ends part way through a value")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, Timestamp};
+    use crate::input::{InputReader, InputSchema};
+    use rust_decimal::Decimal;
+    use std::io::Write;
+
+    const SCHEMA: &str = r#"{"type": "record", "name": "Transaction", "namespace": "payments",
+        "fields": [
+            {"name": "type", "type": {"type": "enum", "name": "Kind",
+                "symbols": ["deposit", "withdrawal"]}},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": {"type": "bytes", "logicalType": "decimal",
+                "precision": 20, "scale": 4}},
+            {"name": "timestamp", "type": ["null",
+                {"type": "long", "logicalType": "timestamp-millis"}]},
+            {"name": "batch", "type": ["null", "string"]}
+        ]}"#;
+
+    fn long(out: &mut Vec<u8>, value: i64) {
+        let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+        while zigzag >= 0x80 {
+            out.push(zigzag as u8 | 0x80);
+            zigzag >>= 7;
+        }
+        out.push(zigzag as u8);
+    }
+
+    fn bytes(out: &mut Vec<u8>, value: &[u8]) {
+        long(out, value.len() as i64);
+        out.extend_from_slice(value);
+    }
+
+    /// A deposit of 2.5 and a withdrawal of 0.5 by client 1.
+    fn records() -> [Vec<u8>; 2] {
+        let mut deposit = Vec::new();
+        for value in [0, 1, 1] {
+            long(&mut deposit, value);
+        }
+        bytes(&mut deposit, &[0x61, 0xa8]);
+        long(&mut deposit, 1);
+        long(&mut deposit, 1_700_000_000_000);
+        long(&mut deposit, 0);
+        let mut withdrawal = Vec::new();
+        for value in [1, 1, 2] {
+            long(&mut withdrawal, value);
+        }
+        bytes(&mut withdrawal, &[0x13, 0x88]);
+        long(&mut withdrawal, 0);
+        long(&mut withdrawal, 1);
+        bytes(&mut withdrawal, b"day-1");
+        [deposit, withdrawal]
+    }
+
+    /// An object container file of SCHEMA, written with `codec`, holding `blocks` of a record
+    /// count and the bytes of the block.
+    fn container(codec: &[u8], blocks: &[(i64, &[u8])]) -> Vec<u8> {
+        let mut file = CONTAINER_MAGIC.to_vec();
+        long(&mut file, 2);
+        bytes(&mut file, b"avro.schema");
+        bytes(&mut file, SCHEMA.as_bytes());
+        bytes(&mut file, b"avro.codec");
+        bytes(&mut file, codec);
+        long(&mut file, 0);
+        file.extend_from_slice(&SYNC);
+        for (count, block) in blocks {
+            long(&mut file, *count);
+            bytes(&mut file, block);
+            file.extend_from_slice(&SYNC);
+        }
+        file
+    }
+
+    const SYNC: [u8; 16] = [7; 16];
+
+    fn read_all(input: impl Read) -> Result<usize, TransactorError> {
+        Ok(InputReader::from_reader(input, InputSchema::Auto)?
+            .collect::<Result<Vec<_>, _>>()?
+            .len())
+    }
+
+    fn apply(input: impl Read) -> Result<Bank, TransactorError> {
+        let mut bank = Bank::new();
+        let records =
+            InputReader::from_reader(input, InputSchema::Auto)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records[0].timestamp, Some(Timestamp(1_700_000_000)));
+        assert_eq!(
+            records[1].batch.as_ref().map(|batch| batch.as_str()),
+            Some("day-1")
+        );
+        for record in records {
+            record.command.apply(&mut bank)?;
+        }
+        Ok(bank)
+    }
+
+    #[test]
+    fn container_files_and_framed_messages_are_read() -> Result<(), TransactorError> {
+        let mut block =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        block.write_all(&records().concat())?;
+        let file = container(b"deflate", &[(2, &block.finish()?)]);
+        let bank = apply(AvroRows::container(file.as_slice())?)?;
+        let account = bank.get_account(ClientId(1)).expect("client 1 deposited");
+        assert_eq!(account.available(), Decimal::new(2, 0));
+
+        let mut messages = Vec::new();
+        for record in records() {
+            messages.extend_from_slice(&[0, 0, 0, 0, 42]);
+            messages.extend_from_slice(&record);
+        }
+        let schemas = AvroSchemas::with_schema(SCHEMA)?;
+        let bank = apply(AvroRows::messages(messages.as_slice(), schemas)?)?;
+        let account = bank.get_account(ClientId(1)).expect("client 1 deposited");
+        assert_eq!(account.available(), Decimal::new(2, 0));
+
+        let schemas = AvroSchemas::with_schema(SCHEMA)?;
+        assert!(matches!(
+            AvroRows::messages(&b"type,client"[..], schemas),
+            Err(InvalidAvro(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn records_that_do_not_decode_are_reported_as_invalid_avro() -> Result<(), TransactorError> {
+        let [deposit, mut withdrawal] = records();
+        withdrawal.truncate(withdrawal.len() - 3);
+        let mut messages = Vec::new();
+        for record in [deposit, withdrawal] {
+            messages.extend_from_slice(&[0, 0, 0, 0, 42]);
+            messages.extend_from_slice(&record);
+        }
+        let schemas = AvroSchemas::with_schema(SCHEMA)?;
+        let read = InputReader::from_reader(
+            AvroRows::messages(messages.as_slice(), schemas)?,
+            InputSchema::Auto,
+        )?
+        .collect::<Result<Vec<_>, _>>();
+        assert!(matches!(read, Err(InvalidAvro(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_blocks_are_invalid_avro() -> Result<(), TransactorError> {
+        let records = records().concat();
+        assert_eq!(
+            read_all(AvroRows::container(
+                container(b"null", &[(2, &records)]).as_slice()
+            )?)?,
+            2
+        );
+
+        let mut unsynced = container(b"null", &[(2, &records)]);
+        let end = unsynced.len() - 1;
+        unsynced[end] = 8;
+        // The block would run past the end of the file
+        let mut cut_short = container(b"null", &[(2, &records)]);
+        cut_short.truncate(cut_short.len() - 20);
+        let files = [
+            unsynced,
+            cut_short,
+            // Fewer records than the block counts
+            container(b"null", &[(3, &records)]),
+            container(b"null", &[(-2, &records)]),
+            container(b"deflate", &[(2, &records)]),
+            container(b"snappy", &[(2, &records)]),
+        ];
+        for file in files {
+            let read = read_all(AvroRows::container(file.as_slice())?);
+            assert!(matches!(read, Err(InvalidAvro(_))), "{:?}", read);
+        }
+        assert!(matches!(
+            AvroRows::container(&CONTAINER_MAGIC[..]),
+            Err(InvalidAvro(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn arrays_and_maps_are_read_in_blocks_counted_either_way() -> Result<(), TransactorError> {
+        let mut input = Vec::new();
+        // Two items, with the size of the block as the count is negative, then one more
+        long(&mut input, -2);
+        long(&mut input, 2);
+        long(&mut input, 5);
+        long(&mut input, -6);
+        long(&mut input, 1);
+        long(&mut input, 7);
+        long(&mut input, 0);
+        let schema = Schema::from_json(r#"{"type": "array", "items": "long"}"#)?;
+        assert_eq!(
+            schema.read(&mut input.as_slice())?,
+            serde_json::json!([5, -6, 7])
+        );
+
+        let mut input = Vec::new();
+        long(&mut input, -1);
+        long(&mut input, 3);
+        bytes(&mut input, b"a");
+        long(&mut input, 1);
+        long(&mut input, 0);
+        let schema = Schema::from_json(r#"{"type": "map", "values": "int"}"#)?;
+        assert_eq!(
+            schema.read(&mut input.as_slice())?,
+            serde_json::json!({"a": 1})
+        );
+        // The map ends before its closing empty block
+        input.pop();
+        assert!(matches!(
+            schema
+                .read(&mut input.as_slice())
+                .map_err(TransactorError::from),
+            Err(InvalidAvro(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn branches_and_symbols_out_of_range_are_invalid_avro() -> Result<(), TransactorError> {
+        let union = Schema::from_json(r#"["null", "long"]"#)?;
+        assert_eq!(union.read(&mut &[2, 4][..])?, serde_json::json!(2));
+        for index in [4, 1] {
+            assert!(matches!(
+                union.read(&mut &[index][..]).map_err(TransactorError::from),
+                Err(InvalidAvro(_))
+            ));
+        }
+        let symbols = Schema::from_json(r#"{"type": "enum", "name": "Kind", "symbols": ["a"]}"#)?;
+        assert!(matches!(
+            symbols.read(&mut &[2][..]).map_err(TransactorError::from),
+            Err(InvalidAvro(_))
+        ));
+
+        // A deposit whose timestamp is of a third branch of its union
+        let [mut deposit, withdrawal] = records();
+        let branch = deposit.len() - 8;
+        assert_eq!(deposit[branch], 2);
+        deposit[branch] = 4;
+        let mut messages = Vec::new();
+        for record in [withdrawal, deposit] {
+            messages.extend_from_slice(&[0, 0, 0, 0, 42]);
+            messages.extend_from_slice(&record);
+        }
+        let schemas = AvroSchemas::with_schema(SCHEMA)?;
+        let read = read_all(AvroRows::messages(messages.as_slice(), schemas)?);
+        assert!(matches!(read, Err(InvalidAvro(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn decimals_are_read_at_a_scale_within_their_precision() -> Result<(), TransactorError> {
+        let decimal = Schema::from_json(
+            r#"{"type": "bytes", "logicalType": "decimal",
+            "precision": 6, "scale": 2}"#,
+        )?;
+        assert_eq!(
+            decimal.read(&mut &[4, 0xff, 0x38][..])?,
+            serde_json::json!("-2.00")
+        );
+        let fixed = Schema::from_json(
+            r#"{"type": "fixed", "name": "Amount", "size": 2,
+            "logicalType": "decimal", "precision": 4}"#,
+        )?;
+        assert_eq!(
+            fixed.read(&mut &[0x01, 0x00][..])?,
+            serde_json::json!("256")
+        );
+        assert!(matches!(
+            decimal.read(&mut &[34][..]).map_err(TransactorError::from),
+            Err(InvalidAvro(_))
+        ));
+        for scale in ["29", "-1", "2.5", "\"2\"", "5"] {
+            let schema = format!(
+                r#"{{"type": "bytes", "logicalType": "decimal", "precision": 4, "scale": {}}}"#,
+                scale
+            );
+            assert!(
+                matches!(Schema::from_json(&schema), Err(InvalidAvro(_))),
+                "scale {}",
+                scale
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn messages_of_a_schema_the_registry_does_not_hold_are_refused() -> Result<(), TransactorError>
+    {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let registry = format!("http://{}/", listener.local_addr()?);
+        let server = std::thread::spawn(move || -> io::Result<String> {
+            let (stream, _) = listener.accept()?;
+            let mut request = BufReader::new(stream);
+            let mut request_line = String::new();
+            request.read_line(&mut request_line)?;
+            let mut line = String::new();
+            while request.read_line(&mut line)? > 2 {
+                line.clear();
+            }
+            request
+                .get_mut()
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")?;
+            Ok(request_line)
+        });
+        let mut messages = vec![0, 0, 0, 0, 7];
+        messages.extend_from_slice(&records()[0]);
+        let read = AvroRows::messages(messages.as_slice(), AvroSchemas::from_registry(&registry)?);
+        assert!(matches!(read, Err(RemoteError(_))));
+        let request_line = server.join().expect("server panicked")?;
+        assert!(request_line.starts_with("GET /schemas/ids/7 "));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "remote"))]
+    #[test]
+    fn schemas_are_only_fetched_from_a_registry_with_the_remote_feature() {
+        assert!(matches!(
+            AvroSchemas::from_registry("http://localhost:8081"),
+            Err(InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchReader};
+use arrow_cast::cast;
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_ipc::reader::FileReader;
+use arrow_schema::{ArrowError, DataType, SchemaRef, TimeUnit};
+use csv::Writer;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+use super::ColumnarFormat;
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The rows of a Parquet file read into each record batch.
+const BATCH_ROWS: usize = 8192;
+
+type Batches = Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>> + Send>;
+
+/// The record batches of a Parquet or Arrow file, read one at a time and handed on as csv rows so
+/// the file streams through the input reader like any other, only a batch is held at once. The
+/// header is the names of the columns, timestamp and date columns are read as seconds since the
+/// epoch and null values as empty fields.
+pub(crate) struct BatchRows {
+    batches: Batches,
+    rows: io::Cursor<Vec<u8>>,
+}
+
+impl BatchRows {
+    pub(crate) fn open(path: &Path, format: ColumnarFormat) -> Result<Self, TransactorError> {
+        let file = File::open(path)?;
+        let unreadable =
+            |e: &dyn fmt::Display| ColumnarError(format!("cannot read {}: {}", path.display(), e));
+        let (schema, batches): (SchemaRef, Batches) = match format {
+            ColumnarFormat::Parquet => {
+                let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+                    .and_then(|builder| builder.with_batch_size(BATCH_ROWS).build())
+                    .map_err(|e| unreadable(&e))?;
+                (reader.schema(), Box::new(reader))
+            }
+            ColumnarFormat::Arrow => {
+                let reader = FileReader::try_new(file, None).map_err(|e| unreadable(&e))?;
+                (reader.schema(), Box::new(reader))
+            }
+        };
+        let mut header = Writer::from_writer(Vec::new());
+        header.write_record(schema.fields().iter().map(|field| field.name()))?;
+        let header = header.into_inner().map_err(|e| unreadable(e.error()))?;
+        Ok(Self {
+            rows: io::Cursor::new(header),
+            batches,
+        })
+    }
+}
+
+impl Read for BatchRows {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.rows.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.batches.next() {
+                Some(batch) => {
+                    let rows = batch
+                        .and_then(|batch| csv_rows(&batch))
+                        .map_err(invalid_data)?;
+                    self.rows = io::Cursor::new(rows);
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// The rows of a record batch written as csv.
+fn csv_rows(batch: &RecordBatch) -> Result<Vec<u8>, ArrowError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .map(seconds)
+        .collect::<Result<Vec<_>, _>>()?;
+    let options = FormatOptions::default();
+    let formatters = columns
+        .iter()
+        .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut writer = Writer::from_writer(Vec::new());
+    let mut field = String::new();
+    for row in 0..batch.num_rows() {
+        for formatter in &formatters {
+            field.clear();
+            formatter.value(row).write(&mut field)?;
+            writer.write_field(&field).map_err(external)?;
+        }
+        writer.write_record(None::<&[u8]>).map_err(external)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| ArrowError::CsvError(e.error().to_string()))
+}
+
+/// Timestamp and date columns as whole seconds since the epoch, other columns as they are.
+fn seconds(column: &ArrayRef) -> Result<ArrayRef, ArrowError> {
+    let timestamps = match column.data_type() {
+        DataType::Timestamp(_, zone) => DataType::Timestamp(TimeUnit::Second, zone.clone()),
+        DataType::Date32 | DataType::Date64 => DataType::Timestamp(TimeUnit::Second, None),
+        _ => return Ok(Arc::clone(column)),
+    };
+    cast(&cast(column, &timestamps)?, &DataType::Int64)
+}
+
+fn external(e: csv::Error) -> ArrowError {
+    ArrowError::CsvError(e.to_string())
+}
+
+fn invalid_data(e: ArrowError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, ColumnarError(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, Timestamp};
+    use crate::input::{InputReader, InputSchema, InputSource};
+    use arrow_array::{Decimal128Array, StringArray, TimestampMillisecondArray, UInt16Array};
+    use arrow_schema::{Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn parquet_batches_are_read_as_input_rows() -> Result<(), TransactorError> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::UInt16, false),
+            Field::new("tx", DataType::UInt16, false),
+            Field::new("amount", DataType::Decimal128(10, 4), true),
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                true,
+            ),
+            Field::new("batch", DataType::Utf8, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["deposit", "withdrawal", "dispute"])),
+            Arc::new(UInt16Array::from(vec![1, 1, 1])),
+            Arc::new(UInt16Array::from(vec![1, 2, 1])),
+            Arc::new(
+                Decimal128Array::from(vec![Some(25_000), Some(5_000), None])
+                    .with_precision_and_scale(10, 4)
+                    .map_err(|e| ColumnarError(e.to_string()))?,
+            ),
+            Arc::new(
+                TimestampMillisecondArray::from(vec![1_700_000_000_000, 1_700_000_001_500, 0])
+                    .with_timezone("UTC"),
+            ),
+            Arc::new(StringArray::from(vec![Some("day-1"), None, None])),
+        ];
+        let batch = RecordBatch::try_new(Arc::clone(&schema), columns)
+            .map_err(|e| ColumnarError(e.to_string()))?;
+        let path = std::env::temp_dir().join("transactor-columnar-input-test.parquet");
+        let mut writer = ArrowWriter::try_new(File::create(&path)?, schema, None)
+            .map_err(|e| ColumnarError(e.to_string()))?;
+        writer
+            .write(&batch)
+            .map_err(|e| ColumnarError(e.to_string()))?;
+        writer.close().map_err(|e| ColumnarError(e.to_string()))?;
+
+        let sources = InputSource::expand(&path, None)?;
+        let records = InputReader::from_reader(sources[0].open()?, InputSchema::Auto)?
+            .collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].timestamp, Some(Timestamp(1_700_000_001)));
+        assert_eq!(
+            records[0].batch.as_ref().map(|batch| batch.as_str()),
+            Some("day-1")
+        );
+        let mut bank = Bank::new();
+        for record in records {
+            record.command.apply(&mut bank)?;
+        }
+        let account = bank
+            .get_account(ClientId(1))
+            .expect("the deposit opens an account");
+        assert_eq!(account.held(), Decimal::new(25, 1));
+        assert_eq!(account.available(), Decimal::new(-5, 1));
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use std::net::TcpStream;
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+
+use crate::error::TransactorError;
+
+/// How an input file is compressed.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression implied by the extension of a file, `.gz` for gzip and `.zst` for zstd.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "Unknown compression {}, expected one of: none, gzip, zstd",
+                s
+            )),
+        }
+    }
+}
+
+/// An input file, decompressed as it is read so compressed input never needs unpacking to disk.
+/// Only uncompressed files can be sought, not members of a zip archive, urls or streams.
+pub struct InputFile {
+    decoder: Decoder,
+}
+
+enum Decoder {
+    Plain(File),
+    Gzip(MultiGzDecoder<BufReader<File>>),
+    Zstd(zstd::stream::read::Decoder<'static, BufReader<File>>),
+    ZipStored(Take<BufReader<File>>),
+    ZipDeflated(DeflateDecoder<Take<BufReader<File>>>),
+    /// Input converted in memory, such as an imported bank statement.
+    Memory(io::Cursor<Vec<u8>>),
+    /// A url, stdin or socket, decompressed as it arrives.
+    Stream(Box<dyn Read + Send>),
+    #[cfg(feature = "parquet")]
+    Columnar(super::columnar::BatchRows),
+    #[cfg(feature = "avro")]
+    Avro(Box<dyn Read + Send>),
+    #[cfg(feature = "protobuf")]
+    Protobuf(Box<super::ProtobufRows<InputFile>>),
+}
+
+impl InputFile {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        compression: Compression,
+    ) -> Result<Self, TransactorError> {
+        let file = File::open(path)?;
+        let decoder = match compression {
+            Compression::None => Decoder::Plain(file),
+            // Concatenated gzip members, as written by parallel compressors, read as one stream
+            Compression::Gzip => Decoder::Gzip(MultiGzDecoder::new(BufReader::new(file))),
+            Compression::Zstd => Decoder::Zstd(zstd::stream::read::Decoder::with_buffer(
+                BufReader::new(file),
+            )?),
+        };
+        Ok(Self { decoder })
+    }
+
+    /// The `size` bytes of member data starting at `start` in a zip archive.
+    pub(super) fn zip_member(
+        archive: &Path,
+        start: u64,
+        size: u64,
+        deflated: bool,
+    ) -> Result<Self, TransactorError> {
+        let mut file = File::open(archive)?;
+        file.seek(SeekFrom::Start(start))?;
+        let data = BufReader::new(file).take(size);
+        let decoder = match deflated {
+            true => Decoder::ZipDeflated(DeflateDecoder::new(data)),
+            false => Decoder::ZipStored(data),
+        };
+        Ok(Self { decoder })
+    }
+
+    /// Input already held in memory.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            decoder: Decoder::Memory(io::Cursor::new(bytes)),
+        }
+    }
+
+    /// The body of a url, decompressed as it arrives.
+    #[cfg(feature = "remote")]
+    pub(super) fn url(url: &str, compression: Compression) -> Result<Self, TransactorError> {
+        Self::stream(super::remote::open(url)?, compression)
+    }
+
+    /// The standard input.
+    pub(super) fn stdin(compression: Compression) -> Result<Self, TransactorError> {
+        Self::stream(Box::new(io::stdin()), compression)
+    }
+
+    /// What the other end of a tcp connection to `address` sends until it closes it.
+    pub(super) fn socket(address: &str, compression: Compression) -> Result<Self, TransactorError> {
+        Self::stream(Box::new(TcpStream::connect(address)?), compression)
+    }
+
+    fn stream(
+        input: Box<dyn Read + Send>,
+        compression: Compression,
+    ) -> Result<Self, TransactorError> {
+        let decoder = match compression {
+            Compression::None => input,
+            Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(input))),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+        };
+        Ok(Self {
+            decoder: Decoder::Stream(decoder),
+        })
+    }
+
+    /// The rows of a Parquet or Arrow file, read a record batch at a time.
+    #[cfg(feature = "parquet")]
+    pub(super) fn columnar(
+        path: &Path,
+        format: super::ColumnarFormat,
+    ) -> Result<Self, TransactorError> {
+        Ok(Self {
+            decoder: Decoder::Columnar(super::columnar::BatchRows::open(path, format)?),
+        })
+    }
+
+    /// The records of an Avro object container file, read a block at a time.
+    #[cfg(feature = "avro")]
+    pub(super) fn avro(path: &Path) -> Result<Self, TransactorError> {
+        let rows = super::AvroRows::container(BufReader::new(File::open(path)?))?;
+        Ok(Self {
+            decoder: Decoder::Avro(Box::new(rows)),
+        })
+    }
+
+    /// The records of Avro messages framed for a schema registry, one after another in the input.
+    #[cfg(feature = "avro")]
+    pub fn avro_messages(
+        input: InputFile,
+        schemas: super::AvroSchemas,
+    ) -> Result<Self, TransactorError> {
+        let rows = super::AvroRows::messages(BufReader::new(input), schemas)?;
+        Ok(Self {
+            decoder: Decoder::Avro(Box::new(rows)),
+        })
+    }
+
+    /// The transactions of length-delimited protobuf messages, one after another in the input.
+    #[cfg(feature = "protobuf")]
+    pub fn protobuf_messages(input: InputFile) -> Result<Self, TransactorError> {
+        Ok(Self {
+            decoder: Decoder::Protobuf(Box::new(super::ProtobufRows::new(input)?)),
+        })
+    }
+
+    pub fn can_seek(&self) -> bool {
+        matches!(self.decoder, Decoder::Plain(_))
+    }
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.decoder {
+            Decoder::Plain(file) => file.read(buf),
+            Decoder::Gzip(decoder) => decoder.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+            Decoder::ZipStored(data) => data.read(buf),
+            Decoder::ZipDeflated(decoder) => decoder.read(buf),
+            Decoder::Memory(bytes) => bytes.read(buf),
+            Decoder::Stream(input) => input.read(buf),
+            #[cfg(feature = "parquet")]
+            Decoder::Columnar(rows) => rows.read(buf),
+            #[cfg(feature = "avro")]
+            Decoder::Avro(rows) => rows.read(buf),
+            #[cfg(feature = "protobuf")]
+            Decoder::Protobuf(rows) => rows.read(buf),
+        }
+    }
+}
+
+impl Seek for InputFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match &mut self.decoder {
+            Decoder::Plain(file) => file.seek(position),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compressed input cannot be sought",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn compressed_files_are_read_decompressed() -> Result<(), TransactorError> {
+        let text = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let dir = std::env::temp_dir();
+        let gzip = dir.join("transactor-compression-test.csv.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gzip)?, flate2::Compression::default());
+        encoder.write_all(text.as_bytes())?;
+        encoder.finish()?;
+        let zstd = dir.join("transactor-compression-test.csv.zst");
+        std::fs::write(&zstd, zstd::encode_all(text.as_bytes(), 0)?)?;
+
+        for path in [gzip, zstd] {
+            let mut file = InputFile::open(&path, Compression::from_extension(&path))?;
+            assert!(!file.can_seek());
+            let mut read = String::new();
+            file.read_to_string(&mut read)?;
+            assert_eq!(read, text);
+            std::fs::remove_file(path)?;
+        }
+        assert_eq!("zstd".parse(), Ok(Compression::Zstd));
+        Ok(())
+    }
+}
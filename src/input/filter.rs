@@ -0,0 +1,101 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::bank::{ClientId, Timestamp};
+
+/// A set of clients written as ids and inclusive ranges of ids, such as `1,2,10-20`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientRanges(Vec<RangeInclusive<u16>>);
+
+impl ClientRanges {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.0.iter().any(|range| range.contains(&client_id.0))
+    }
+}
+
+impl FromStr for ClientRanges {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid clients {}, expected ids and ranges such as 1,2,10-20",
+                s
+            )
+        };
+        let id = |id: &str| id.trim().parse::<u16>().map_err(|_| invalid());
+        s.split(',')
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (id(start)?, id(end)?);
+                    match start <= end {
+                        true => Ok(start..=end),
+                        false => Err(invalid()),
+                    }
+                }
+                None => id(part).map(|id| id..=id),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Which clients' rows are read from the input, every client's by default. Rows of other clients
+/// are skipped as they are read, before they are parsed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientFilter {
+    /// Only these clients, every client if None.
+    only: Option<ClientRanges>,
+    excluded: ClientRanges,
+}
+
+impl ClientFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_only(mut self, clients: ClientRanges) -> Self {
+        self.only = Some(clients);
+        self
+    }
+
+    pub fn with_excluded(mut self, clients: ClientRanges) -> Self {
+        self.excluded = clients;
+        self
+    }
+
+    pub fn allows(&self, client_id: ClientId) -> bool {
+        self.only
+            .as_ref()
+            .is_none_or(|only| only.contains(client_id))
+            && !self.excluded.contains(client_id)
+    }
+}
+
+/// Where a row falls relative to a `TimeWindow`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WindowPosition {
+    Before,
+    Within,
+    After,
+}
+
+/// A period of row timestamps, from `from` up to but not including `to`, open ended on a side
+/// left out.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TimeWindow {
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+}
+
+impl TimeWindow {
+    pub fn position(&self, timestamp: Timestamp) -> WindowPosition {
+        if self.from.is_some_and(|from| timestamp < from) {
+            WindowPosition::Before
+        } else if self.to.is_some_and(|to| timestamp >= to) {
+            WindowPosition::After
+        } else {
+            WindowPosition::Within
+        }
+    }
+}
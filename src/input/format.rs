@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+
+use super::{InputSchema, OPTIONAL_COLUMNS, REQUIRED_COLUMNS, V2_COLUMNS};
+use crate::error::{TransactorError, TransactorError::*};
+
+/// How a csv input is laid out where it differs from the input specification, for reading the
+/// exports of other systems without converting them first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InputFormat {
+    column_map: ColumnMap,
+    pub(super) has_headers: bool,
+    pub(super) delimiter: u8,
+    pub(super) strict_schema: bool,
+}
+
+impl Default for InputFormat {
+    fn default() -> Self {
+        Self {
+            column_map: ColumnMap::default(),
+            has_headers: true,
+            delimiter: b',',
+            strict_schema: false,
+        }
+    }
+}
+
+impl InputFormat {
+    /// Read the columns of the header row under the names the map gives them.
+    pub fn with_column_map(mut self, column_map: ColumnMap) -> Self {
+        self.column_map = column_map;
+        self
+    }
+
+    /// Read input without a header row, taking the columns by position: `type,client,tx,amount`
+    /// and then `timestamp,batch` for the v2 schema. The auto schema picks v2 for a first row of
+    /// more than four fields.
+    pub fn without_headers(mut self) -> Self {
+        self.has_headers = false;
+        self
+    }
+
+    /// Separate fields with `delimiter` rather than a comma, such as `b'\t'`.
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Refuse a header row with columns the input does not know, naming them, rather than
+    /// ignoring those columns.
+    pub fn with_strict_schema(mut self) -> Self {
+        self.strict_schema = true;
+        self
+    }
+
+    pub(super) fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        // Rows of the wrong width are refused by `RecordParser`, as they are for every reader
+        builder
+            .trim(Trim::All)
+            .flexible(true)
+            .has_headers(self.has_headers)
+            .delimiter(self.delimiter);
+        builder
+    }
+
+    /// The header row standing in for the columns of input without one, given the number of
+    /// fields of its first row.
+    pub(super) fn positional_headers(&self, schema: InputSchema, width: usize) -> StringRecord {
+        let v2 = match schema {
+            InputSchema::V1 => false,
+            InputSchema::V2 => true,
+            InputSchema::Auto => width > REQUIRED_COLUMNS.len() + 1,
+        };
+        let v2_columns: &[&str] = if v2 { &V2_COLUMNS } else { &[] };
+        REQUIRED_COLUMNS
+            .iter()
+            .chain(&["amount"])
+            .chain(v2_columns)
+            .collect()
+    }
+
+    /// The header row under the names the schema knows the columns by.
+    pub(super) fn headers(&self, headers: &StringRecord) -> StringRecord {
+        headers
+            .iter()
+            .map(|header| self.column_map.column(header))
+            .collect()
+    }
+}
+
+/// Names of input columns for the columns they hold, such as `txn_id` for `tx`. Columns not in
+/// the map keep their name.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ColumnMap {
+    columns: BTreeMap<String, String>,
+}
+
+impl ColumnMap {
+    /// Map each header name to the column it holds, which must be one of the input columns.
+    pub fn new<I, S, T>(columns: I) -> Result<Self, TransactorError>
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let columns: BTreeMap<String, String> = columns
+            .into_iter()
+            .map(|(header, column)| (header.into(), column.into()))
+            .collect();
+        let known = REQUIRED_COLUMNS
+            .iter()
+            .chain(&OPTIONAL_COLUMNS)
+            .chain(&V2_COLUMNS);
+        for (header, column) in &columns {
+            if !known.clone().any(|known| known == column) {
+                return Err(InvalidConfig(format!(
+                    "the column map reads {} as {}, which is not an input column",
+                    header, column
+                )));
+            }
+        }
+        Ok(Self { columns })
+    }
+
+    /// Read a map of header names to columns from a json file if its extension is `.json`,
+    /// otherwise from a toml file, such as `txn_id = "tx"` on each line.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let columns: BTreeMap<String, String> = match is_json {
+            true => serde_json::from_str(&text).map_err(|e| e.to_string()),
+            false => toml::from_str(&text).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| InvalidConfig(format!("invalid column map {}: {}", path.display(), e)))?;
+        Self::new(columns)
+    }
+
+    /// The column a header holds.
+    fn column<'a>(&'a self, header: &'a str) -> &'a str {
+        self.columns.get(header).map_or(header, String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::input::{Command, InputReader, InputSchema};
+
+    #[test]
+    fn mapped_columns_are_read_as_the_columns_they_hold() -> Result<(), TransactorError> {
+        let path = std::env::temp_dir().join("transactor-column-map-test.json");
+        fs::write(
+            &path,
+            r#"{"kind": "type", "customer": "client", "txn_id": "tx", "value": "amount"}"#,
+        )?;
+        let format = InputFormat::default().with_column_map(ColumnMap::from_path(&path)?);
+        fs::remove_file(path)?;
+
+        let input = "kind, customer, txn_id, value\ndeposit, 7, 1, 2.5\n";
+        let records =
+            InputReader::from_reader_with_format(input.as_bytes(), InputSchema::Auto, &format)?
+                .collect::<Result<Vec<_>, _>>()?;
+        assert!(matches!(
+            records[0].command,
+            Command::Deposit { client, transaction }
+                if client.0 == 7 && transaction.amount() == Decimal::new(25, 1)
+        ));
+        assert!(InputReader::from_reader(input.as_bytes(), InputSchema::Auto).is_err());
+        assert!(matches!(
+            ColumnMap::new(vec![("value", "amount"), ("fee", "charge")]),
+            Err(InvalidConfig(message)) if message.contains("reads fee as charge")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn headerless_input_is_read_by_position() -> Result<(), TransactorError> {
+        let format = InputFormat::default()
+            .without_headers()
+            .with_delimiter(b'\t');
+        let read = |input: &str| -> Result<(InputSchema, usize), TransactorError> {
+            let reader =
+                InputReader::from_reader_with_format(input.as_bytes(), InputSchema::Auto, &format)?;
+            let schema = reader.schema();
+            Ok((schema, reader.collect::<Result<Vec<_>, _>>()?.len()))
+        };
+        assert_eq!(
+            read("deposit\t1\t1\t2\nwithdrawal\t1\t2\t1\n")?,
+            (InputSchema::V1, 2)
+        );
+        assert_eq!(
+            read("deposit\t1\t1\t2\t1700000000\tmonday\n")?,
+            (InputSchema::V2, 1)
+        );
+        assert_eq!(read("")?, (InputSchema::V1, 0));
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::str::FromStr;
+
+use roxmltree::{Document, Node};
+use rust_decimal::prelude::*;
+
+use super::statement::{civil_timestamp, StatementLine};
+use crate::bank::Timestamp;
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The booked entries of a camt.053 bank to customer statement, credits paid in and debits paid
+/// out, dated when they were booked. Pending and information only entries are left out and
+/// reversals count against the direction of the entry they reverse.
+pub(super) fn read_camt053(text: &str) -> Result<Vec<StatementLine>, TransactorError> {
+    let document = parse(text)?;
+    let mut lines = Vec::new();
+    for entry in document
+        .descendants()
+        .filter(|node| node.has_tag_name("Ntry"))
+    {
+        // The status is a code of its own before version 8 and in a Cd element from then on
+        let status = child(entry, "Sts").map(|status| child(status, "Cd").unwrap_or(status));
+        if status
+            .and_then(|status| status.text())
+            .is_some_and(|code| code.trim() != "BOOK")
+        {
+            continue;
+        }
+        let (amount, currency) = match child(entry, "Amt") {
+            Some(amount) => amount_of(amount)?,
+            None => return Err(InvalidStatement("an entry has no Amt".to_string())),
+        };
+        let debit = match text_of(entry, "CdtDbtInd") {
+            Some("CRDT") => false,
+            Some("DBIT") => true,
+            indicator => {
+                return Err(InvalidStatement(format!(
+                    "an entry has the CdtDbtInd {}, expected CRDT or DBIT",
+                    indicator.unwrap_or("")
+                )))
+            }
+        };
+        let reversal = text_of(entry, "RvslInd") == Some("true");
+        lines.push(StatementLine {
+            amount: if debit != reversal { -amount } else { amount },
+            posted: child(entry, "BookgDt").map(date_of).transpose()?,
+            currency,
+        });
+    }
+    Ok(lines)
+}
+
+/// The credit transfers of a pain.001 customer credit transfer initiation, each paying its
+/// instructed amount out of the debtor's account on the execution date requested for it.
+pub(super) fn read_pain001(text: &str) -> Result<Vec<StatementLine>, TransactorError> {
+    let document = parse(text)?;
+    let mut lines = Vec::new();
+    for payment in document
+        .descendants()
+        .filter(|node| node.has_tag_name("PmtInf"))
+    {
+        let requested = child(payment, "ReqdExctnDt").map(date_of).transpose()?;
+        for transfer in payment
+            .children()
+            .filter(|node| node.has_tag_name("CdtTrfTxInf"))
+        {
+            let (amount, currency) =
+                match child(transfer, "Amt").and_then(|amount| child(amount, "InstdAmt")) {
+                    Some(instructed) => amount_of(instructed)?,
+                    None => {
+                        return Err(InvalidStatement(
+                            "a credit transfer has no InstdAmt".to_string(),
+                        ))
+                    }
+                };
+            lines.push(StatementLine {
+                amount: -amount,
+                posted: requested,
+                currency,
+            });
+        }
+    }
+    Ok(lines)
+}
+
+fn parse(text: &str) -> Result<Document<'_>, TransactorError> {
+    Document::parse(text).map_err(|e| InvalidStatement(e.to_string()))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn text_of<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+/// An amount and its currency, given by the Ccy attribute.
+fn amount_of(node: Node) -> Result<(Decimal, Option<String>), TransactorError> {
+    let text = node.text().unwrap_or("").trim();
+    let amount = Decimal::from_str(text)
+        .map_err(|_| InvalidStatement(format!("{} is not an amount", text)))?;
+    Ok((amount, node.attribute("Ccy").map(str::to_string)))
+}
+
+/// A date given by a Dt or DtTm element, or as the text of the node itself.
+fn date_of(node: Node) -> Result<Timestamp, TransactorError> {
+    let value = text_of(node, "Dt")
+        .or_else(|| text_of(node, "DtTm"))
+        .or_else(|| node.text())
+        .unwrap_or("")
+        .trim();
+    iso_date_time(value).ok_or_else(|| InvalidStatement(format!("{} is not a date", value)))
+}
+
+/// An ISO 8601 date such as `2023-11-14`, or date time such as `2023-11-14T10:30:00.000+01:00`
+/// which is utc if it has no offset.
+fn iso_date_time(value: &str) -> Option<Timestamp> {
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let mut parts = date.get(..10)?.split('-').map(|part| part.parse().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    let midnight = civil_timestamp(year, month, day)?;
+    if time.is_empty() {
+        return Some(midnight);
+    }
+    let seconds_of = |clock: &str| -> Option<i64> {
+        let mut parts = clock.split(':').map(|part| part.parse::<i64>().ok());
+        let (hours, minutes) = (parts.next()??, parts.next()??);
+        let seconds = parts.next().unwrap_or(Some(0))?;
+        Some(hours * 3600 + minutes * 60 + seconds)
+    };
+    let clock = seconds_of(time.get(..8)?)?;
+    let zone = time[8..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone.split_at(zone.len().min(1)) {
+        ("", _) | ("Z", "") => 0,
+        ("+", offset) => seconds_of(offset)?,
+        ("-", offset) => -seconds_of(offset)?,
+        _ => return None,
+    };
+    midnight.0.checked_add_signed(clock - offset).map(Timestamp)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn camt053_entries_and_pain001_transfers_are_read() -> Result<(), TransactorError> {
+        let camt = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt><Stmt>
+    <Ntry>
+      <Amt Ccy="EUR">250.00</Amt><CdtDbtInd>CRDT</CdtDbtInd>
+      <Sts><Cd>BOOK</Cd></Sts><BookgDt><Dt>2023-11-14</Dt></BookgDt>
+    </Ntry>
+    <Ntry>
+      <Amt Ccy="EUR">40.5</Amt><CdtDbtInd>DBIT</CdtDbtInd>
+      <Sts><Cd>BOOK</Cd></Sts><BookgDt><DtTm>2023-11-15T10:00:00+01:00</DtTm></BookgDt>
+    </Ntry>
+    <Ntry>
+      <Amt Ccy="EUR">10</Amt><CdtDbtInd>CRDT</CdtDbtInd><RvslInd>true</RvslInd>
+      <Sts>BOOK</Sts><BookgDt><Dt>2023-11-16</Dt></BookgDt>
+    </Ntry>
+    <Ntry>
+      <Amt Ccy="EUR">99</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts><Cd>PDNG</Cd></Sts>
+    </Ntry>
+  </Stmt></BkToCstmrStmt>
+</Document>"#;
+        let lines = read_camt053(camt)?;
+        let amounts: Vec<Decimal> = lines.iter().map(|line| line.amount).collect();
+        assert_eq!(
+            amounts,
+            [
+                Decimal::new(25000, 2),
+                Decimal::new(-405, 1),
+                Decimal::new(-10, 0)
+            ]
+        );
+        assert_eq!(lines[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(lines[0].posted, Timestamp::from_civil(2023, 11, 14, 0));
+        assert_eq!(
+            lines[1].posted,
+            Timestamp::from_civil(2023, 11, 15, 9 * 3600)
+        );
+
+        let pain = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.03">
+  <CstmrCdtTrfInitn><PmtInf>
+    <ReqdExctnDt>2023-11-20</ReqdExctnDt>
+    <CdtTrfTxInf><Amt><InstdAmt Ccy="USD">12.34</InstdAmt></Amt></CdtTrfTxInf>
+    <CdtTrfTxInf><Amt><InstdAmt Ccy="USD">5</InstdAmt></Amt></CdtTrfTxInf>
+  </PmtInf></CstmrCdtTrfInitn>
+</Document>"#;
+        let lines = read_pain001(pain)?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].amount, Decimal::new(-1234, 2));
+        assert_eq!(lines[1].posted, Timestamp::from_civil(2023, 11, 20, 0));
+
+        assert!(matches!(
+            read_camt053("<Document><Ntry><Amt>1</Amt></Ntry></Document>"),
+            Err(InvalidStatement(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use std::io;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+use std::vec;
+
+use csv::ByteRecord;
+
+use super::{InputReader, InputRecord, RecordParser};
+use crate::error::TransactorError;
+
+/// Rows read before they are handed to a parser thread.
+const CHUNK_SIZE: usize = 1024;
+/// Chunks each parser thread may have waiting to be parsed, and parsed waiting to be taken.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// A run of consecutive rows, ended early by the error that stopped the input being read.
+struct Chunk {
+    rows: Vec<ByteRecord>,
+    error: Option<TransactorError>,
+}
+
+impl Chunk {
+    fn read<R: io::Read>(reader: &mut InputReader<R>) -> Self {
+        let mut rows = Vec::with_capacity(CHUNK_SIZE);
+        while rows.len() < CHUNK_SIZE {
+            let mut row = ByteRecord::new();
+            match reader.rows.read(&mut row) {
+                Ok(true) => rows.push(row),
+                Ok(false) => break,
+                Err(error) => {
+                    return Self {
+                        rows,
+                        error: Some(error),
+                    }
+                }
+            }
+        }
+        Self { rows, error: None }
+    }
+
+    /// Whether nothing follows this chunk in the input.
+    fn is_last(&self) -> bool {
+        self.error.is_some() || self.rows.len() < CHUNK_SIZE
+    }
+
+    fn parse(self, parser: &RecordParser) -> Vec<Result<InputRecord, TransactorError>> {
+        self.rows
+            .iter()
+            .filter(|row| !parser.skips(row))
+            .map(|row| parser.parse(row))
+            .chain(self.error.map(Err))
+            .collect()
+    }
+}
+
+/// Reads transaction input with parsing spread over several threads, so the thread applying the
+/// records to the bank only has to apply them.
+///
+/// One thread reads raw rows and deals them out in chunks to the parser threads in turn, the
+/// parsed chunks are taken back in the same turn so records come out in input order. The channels
+/// between threads are bounded, so reading stops when the parsed records are not being taken.
+/// Dropping the reader stops its threads once they next try to hand on a chunk.
+pub struct ParallelInputReader {
+    parsed: Vec<Receiver<Vec<Result<InputRecord, TransactorError>>>>,
+    next: usize,
+    chunk: vec::IntoIter<Result<InputRecord, TransactorError>>,
+}
+
+impl ParallelInputReader {
+    pub(super) fn new<R: io::Read + Send + 'static>(
+        mut reader: InputReader<R>,
+        threads: usize,
+    ) -> Self {
+        let threads = threads.max(1);
+        let mut raw = Vec::with_capacity(threads);
+        let mut parsed = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let (raw_sender, raw_receiver) = sync_channel::<Chunk>(CHUNKS_IN_FLIGHT);
+            let (parsed_sender, parsed_receiver) = sync_channel(CHUNKS_IN_FLIGHT);
+            let parser = reader.parser.clone();
+            thread::spawn(move || {
+                for chunk in raw_receiver {
+                    if parsed_sender.send(chunk.parse(&parser)).is_err() {
+                        break;
+                    }
+                }
+            });
+            raw.push(raw_sender);
+            parsed.push(parsed_receiver);
+        }
+        thread::spawn(move || {
+            for sender in raw.iter().cycle() {
+                let chunk = Chunk::read(&mut reader);
+                let last = chunk.is_last();
+                if sender.send(chunk).is_err() || last {
+                    break;
+                }
+            }
+        });
+        Self {
+            parsed,
+            next: 0,
+            chunk: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for ParallelInputReader {
+    type Item = Result<InputRecord, TransactorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.chunk.next() {
+                return Some(record);
+            }
+            // Once the input is exhausted the parser that would have had the next chunk hangs up
+            let chunk = self.parsed[self.next % self.parsed.len()].recv().ok()?;
+            self.next += 1;
+            self.chunk = chunk.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::InputSchema;
+
+    #[test]
+    fn records_are_parsed_in_input_order() -> Result<(), TransactorError> {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=3 * CHUNK_SIZE as u32 + 7 {
+            input.push_str(&format!("deposit,{},{},1.0\n", tx % 5, tx));
+        }
+        input.push_str("deposit,1,bad,1.0\n");
+        let sequential: Vec<_> =
+            InputReader::from_reader(io::Cursor::new(input.clone()), InputSchema::Auto)?.collect();
+        let parallel: Vec<_> = InputReader::from_reader(io::Cursor::new(input), InputSchema::Auto)?
+            .parallel(3)
+            .collect();
+        assert_eq!(parallel.len(), sequential.len());
+        for (parallel, sequential) in parallel.iter().zip(sequential.iter()) {
+            match (parallel, sequential) {
+                (Ok(parallel), Ok(sequential)) => assert_eq!(parallel, sequential),
+                (Err(parallel), Err(sequential)) => {
+                    assert_eq!(parallel.to_string(), sequential.to_string())
+                }
+                _ => panic!("parallel and sequential reads differ"),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read};
+
+use csv::Writer;
+use prost::Message;
+
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The messages read before they are handed on as rows.
+const MESSAGES_PER_READ: usize = 1024;
+
+/// The largest message read, so a corrupt size cannot have the whole input read into memory.
+const MAX_MESSAGE_SIZE: u64 = 1 << 20;
+
+/// The columns of the rows messages are read as.
+const COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "reason",
+    "account",
+    "to_account",
+    "timestamp",
+    "batch",
+];
+
+/// The type of a transaction message, as numbered in `proto/transaction.proto`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, prost::Enumeration)]
+#[repr(i32)]
+pub enum TransactionType {
+    Unspecified = 0,
+    Deposit = 1,
+    Withdrawal = 2,
+    Dispute = 3,
+    Resolve = 4,
+    Chargeback = 5,
+    Represent = 6,
+    Freeze = 7,
+    Unfreeze = 8,
+    Close = 9,
+    Authorize = 10,
+    Capture = 11,
+    Void = 12,
+    Hold = 13,
+    Release = 14,
+    Move = 15,
+}
+
+impl TransactionType {
+    /// The name of the type in the csv input, empty if unspecified.
+    fn name(self) -> &'static str {
+        match self {
+            TransactionType::Unspecified => "",
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Represent => "represent",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Unfreeze => "unfreeze",
+            TransactionType::Close => "close",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Hold => "hold",
+            TransactionType::Release => "release",
+            TransactionType::Move => "move",
+        }
+    }
+}
+
+/// The `Transaction` message of `proto/transaction.proto`, for producers written in Rust.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TransactionMessage {
+    #[prost(enumeration = "TransactionType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(string, tag = "4")]
+    pub amount: String,
+    #[prost(string, tag = "5")]
+    pub currency: String,
+    #[prost(string, tag = "6")]
+    pub reason: String,
+    #[prost(string, tag = "7")]
+    pub account: String,
+    #[prost(string, tag = "8")]
+    pub to_account: String,
+    #[prost(uint64, optional, tag = "9")]
+    pub timestamp: Option<u64>,
+    #[prost(string, tag = "10")]
+    pub batch: String,
+}
+
+impl TransactionMessage {
+    /// The fields of the row the message is read as. Types the schema does not know are kept as
+    /// their number, for the row to be rejected like any other of an unknown type.
+    fn fields(&self) -> [String; 10] {
+        let kind = TransactionType::try_from(self.r#type);
+        let tx = match kind {
+            // Account wide types have no transaction
+            Ok(TransactionType::Freeze | TransactionType::Unfreeze | TransactionType::Close) => {
+                String::new()
+            }
+            _ => self.tx.to_string(),
+        };
+        [
+            kind.map_or_else(|_| self.r#type.to_string(), |kind| kind.name().to_string()),
+            self.client.to_string(),
+            tx,
+            self.amount.clone(),
+            self.currency.clone(),
+            self.reason.clone(),
+            self.account.clone(),
+            self.to_account.clone(),
+            self.timestamp
+                .map_or_else(String::new, |timestamp| timestamp.to_string()),
+            self.batch.clone(),
+        ]
+    }
+}
+
+/// Transaction messages, each preceded by its size as a varint, handed on as csv rows so the
+/// stream goes through the input reader like any other. The first message is the second line,
+/// as if after a header.
+pub struct ProtobufRows<R> {
+    input: BufReader<R>,
+    rows: io::Cursor<Vec<u8>>,
+    ended: bool,
+}
+
+impl<R: Read> ProtobufRows<R> {
+    pub fn new(input: R) -> Result<Self, TransactorError> {
+        let mut header = Writer::from_writer(Vec::new());
+        header.write_record(COLUMNS)?;
+        Ok(Self {
+            input: BufReader::new(input),
+            rows: io::Cursor::new(finish_rows(header)?),
+            ended: false,
+        })
+    }
+
+    /// The next message, None at the end of the input.
+    fn read_message(&mut self) -> Result<Option<TransactionMessage>, TransactorError> {
+        let size = match read_size(&mut self.input)? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        if size > MAX_MESSAGE_SIZE {
+            return Err(InvalidProtobuf(format!(
+                "a message of {} bytes is larger than the {} bytes allowed",
+                size, MAX_MESSAGE_SIZE
+            )));
+        }
+        let mut message = Vec::new();
+        (&mut self.input).take(size).read_to_end(&mut message)?;
+        if u64::try_from(message.len()) != Ok(size) {
+            return Err(InvalidProtobuf(
+                "the input ends part way through a message".to_string(),
+            ));
+        }
+        TransactionMessage::decode(message.as_slice())
+            .map(Some)
+            .map_err(|e| InvalidProtobuf(e.to_string()))
+    }
+
+    /// The csv rows of the next run of messages, None at the end of the input. A run stops short
+    /// once no more of the input has arrived, so messages from a stream are not held back
+    /// waiting for the rest of the run.
+    fn read_rows(&mut self) -> Result<Option<Vec<u8>>, TransactorError> {
+        let mut writer = Writer::from_writer(Vec::new());
+        for read in 0..MESSAGES_PER_READ {
+            if read > 0 && self.input.buffer().is_empty() {
+                break;
+            }
+            match self.read_message()? {
+                Some(message) => writer.write_record(message.fields())?,
+                None if read == 0 => return Ok(None),
+                None => break,
+            }
+        }
+        finish_rows(writer).map(Some)
+    }
+}
+
+impl<R: Read> Read for ProtobufRows<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.rows.read(buf)?;
+            if read > 0 || buf.is_empty() || self.ended {
+                return Ok(read);
+            }
+            match self.read_rows() {
+                Ok(Some(rows)) => self.rows = io::Cursor::new(rows),
+                Ok(None) => self.ended = true,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+    }
+}
+
+/// The varint size of the next message, None at the end of the input.
+fn read_size(input: &mut impl BufRead) -> Result<Option<u64>, TransactorError> {
+    let mut size = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = match input.fill_buf()?.first() {
+            Some(byte) => *byte,
+            None if shift == 0 => return Ok(None),
+            None => {
+                return Err(InvalidProtobuf(
+                    "the input ends part way through the size of a message".to_string(),
+                ))
+            }
+        };
+        input.consume(1);
+        size |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(Some(size));
+        }
+    }
+    Err(InvalidProtobuf(
+        "the size of a message is longer than a varint".to_string(),
+    ))
+}
+
+fn finish_rows(writer: Writer<Vec<u8>>) -> Result<Vec<u8>, TransactorError> {
+    writer
+        .into_inner()
+        .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string()).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, Timestamp};
+    use crate::input::{InputReader, InputSchema};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn length_delimited_messages_are_read_as_rows() -> Result<(), TransactorError> {
+        let messages = [
+            TransactionMessage {
+                r#type: TransactionType::Deposit.into(),
+                client: 1,
+                tx: 1,
+                amount: "2.5".to_string(),
+                timestamp: Some(1_700_000_000),
+                batch: "day-1".to_string(),
+                ..TransactionMessage::default()
+            },
+            TransactionMessage {
+                r#type: TransactionType::Withdrawal.into(),
+                client: 1,
+                tx: 2,
+                amount: "0.5".to_string(),
+                ..TransactionMessage::default()
+            },
+            TransactionMessage {
+                r#type: TransactionType::Freeze.into(),
+                client: 1,
+                reason: "fraud".to_string(),
+                ..TransactionMessage::default()
+            },
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            message
+                .encode_length_delimited(&mut input)
+                .map_err(|e| InvalidProtobuf(e.to_string()))?;
+        }
+        let records =
+            InputReader::from_reader(ProtobufRows::new(input.as_slice())?, InputSchema::Auto)?
+                .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].timestamp, Some(Timestamp(1_700_000_000)));
+        assert_eq!(records[1].timestamp, None);
+        assert_eq!(records[2].command.name(), "freeze");
+        let mut bank = Bank::new();
+        for record in records {
+            record.command.apply(&mut bank)?;
+        }
+        let account = bank.get_account(ClientId(1)).expect("client 1 deposited");
+        assert_eq!(account.available(), Decimal::new(2, 0));
+        assert!(account.is_locked());
+
+        input.truncate(input.len() - 2);
+        let truncated =
+            InputReader::from_reader(ProtobufRows::new(input.as_slice())?, InputSchema::Auto)?
+                .collect::<Result<Vec<_>, _>>();
+        // Malformed input however far into the stream it is found
+        assert!(matches!(truncated, Err(InvalidProtobuf(_))));
+        Ok(())
+    }
+
+    fn framed(messages: &[TransactionMessage]) -> Result<Vec<u8>, TransactorError> {
+        let mut input = Vec::new();
+        for message in messages {
+            message
+                .encode_length_delimited(&mut input)
+                .map_err(|e| InvalidProtobuf(e.to_string()))?;
+        }
+        Ok(input)
+    }
+
+    fn deposit(tx: u32) -> TransactionMessage {
+        TransactionMessage {
+            r#type: TransactionType::Deposit.into(),
+            client: 1,
+            tx,
+            amount: "1".to_string(),
+            ..TransactionMessage::default()
+        }
+    }
+
+    #[test]
+    fn every_type_is_read_as_the_record_of_its_name() -> Result<(), TransactorError> {
+        let types = [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Represent,
+            TransactionType::Freeze,
+            TransactionType::Unfreeze,
+            TransactionType::Close,
+            TransactionType::Authorize,
+            TransactionType::Capture,
+            TransactionType::Void,
+            TransactionType::Hold,
+            TransactionType::Release,
+            TransactionType::Move,
+        ];
+        let messages: Vec<_> = types
+            .iter()
+            .map(|kind| {
+                let text = |value: &str, of: &[TransactionType]| match of.contains(kind) {
+                    true => value.to_string(),
+                    false => String::new(),
+                };
+                TransactionMessage {
+                    r#type: (*kind).into(),
+                    client: 1,
+                    tx: 1,
+                    amount: text(
+                        "1",
+                        &[
+                            TransactionType::Deposit,
+                            TransactionType::Withdrawal,
+                            TransactionType::Authorize,
+                            TransactionType::Hold,
+                            TransactionType::Move,
+                        ],
+                    ),
+                    reason: text("fraud", &[TransactionType::Freeze]),
+                    account: text("main", &[TransactionType::Move]),
+                    to_account: text("savings", &[TransactionType::Move]),
+                    ..TransactionMessage::default()
+                }
+            })
+            .collect();
+        let input = framed(&messages)?;
+        let records =
+            InputReader::from_reader(ProtobufRows::new(input.as_slice())?, InputSchema::Auto)?
+                .collect::<Result<Vec<_>, _>>()?;
+        let names: Vec<_> = records.iter().map(|record| record.command.name()).collect();
+        let expected: Vec<_> = types.iter().map(|kind| kind.name()).collect();
+        assert_eq!(names, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn types_the_schema_does_not_know_are_rejected_as_rows() -> Result<(), TransactorError> {
+        let unknown = TransactionMessage {
+            r#type: 99,
+            ..deposit(2)
+        };
+        let unspecified = TransactionMessage {
+            r#type: TransactionType::Unspecified.into(),
+            ..deposit(3)
+        };
+        let input = framed(&[deposit(1), unknown, unspecified, deposit(4)])?;
+        let records: Vec<_> =
+            InputReader::from_reader(ProtobufRows::new(input.as_slice())?, InputSchema::Auto)?
+                .collect();
+        assert_eq!(records.len(), 4);
+        assert!(records[0].is_ok() && records[3].is_ok());
+        for record in &records[1..3] {
+            assert!(
+                record.as_ref().is_err_and(|e| e.exit_code() == 3),
+                "{:?}",
+                record
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn messages_that_cannot_be_read_are_invalid_protobuf() -> Result<(), TransactorError> {
+        let mut oversized = framed(&[deposit(1)])?;
+        oversized.extend_from_slice(&[0x81, 0x80, 0x80, 0x01]);
+        let mut too_long = framed(&[deposit(1)])?;
+        too_long.extend_from_slice(&[0xff; 10]);
+        let mut size_cut_short = framed(&[deposit(1)])?;
+        size_cut_short.push(0x80);
+        // A string field whose length runs past the end of the message
+        let mut undecodable = framed(&[deposit(1)])?;
+        undecodable.extend_from_slice(&[2, 0x22, 0x05]);
+        for input in [oversized, too_long, size_cut_short, undecodable] {
+            let read =
+                InputReader::from_reader(ProtobufRows::new(input.as_slice())?, InputSchema::Auto)?
+                    .collect::<Result<Vec<_>, _>>();
+            assert!(matches!(read, Err(InvalidProtobuf(_))), "{:?}", read);
+        }
+
+        let records = InputReader::from_reader(ProtobufRows::new(io::empty())?, InputSchema::Auto)?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(records.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn messages_are_handed_on_as_they_arrive() -> Result<(), TransactorError> {
+        use std::sync::mpsc;
+
+        /// Input of the chunks sent to it, blocking until the next arrives.
+        struct Stream(mpsc::Receiver<Vec<u8>>, io::Cursor<Vec<u8>>);
+
+        impl Read for Stream {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.1.position() == self.1.get_ref().len() as u64 {
+                    match self.0.recv() {
+                        Ok(chunk) => self.1 = io::Cursor::new(chunk),
+                        Err(_) => return Ok(0),
+                    }
+                }
+                self.1.read(buf)
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        sender.send(framed(&[deposit(1)])?).ok();
+        let stream = Stream(receiver, io::Cursor::new(Vec::new()));
+        let mut records = InputReader::from_reader(ProtobufRows::new(stream)?, InputSchema::Auto)?;
+        // The first deposit is read before the second is sent
+        let first = records.next().transpose()?;
+        assert_eq!(first.map(|record| record.command.name()), Some("deposit"));
+        sender.send(framed(&[deposit(2)])?).ok();
+        drop(sender);
+        assert_eq!(records.count(), 1);
+        Ok(())
+    }
+}
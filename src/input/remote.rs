@@ -0,0 +1,228 @@
+use std::env;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::bank::Timestamp;
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The sha256 of an empty body, which is what a GET request signs.
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// The body of an `http(s)://` or `s3://bucket/key` url, streamed as it is read.
+///
+/// S3 objects are fetched with the credentials in `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+/// and `AWS_SESSION_TOKEN` from the bucket in `AWS_REGION`, or unsigned if there are no
+/// credentials. `AWS_ENDPOINT_URL` points them at an S3 compatible store instead of AWS.
+pub(super) fn open(url: &str) -> Result<Box<dyn Read + Send>, TransactorError> {
+    let request = match url.strip_prefix("s3://") {
+        Some(location) => s3_request(location)?,
+        None => ureq::get(url),
+    };
+    let response = request
+        .call()
+        .map_err(|e| RemoteError(format!("cannot read {}: {}", url, e)))?;
+    Ok(Box::new(response.into_reader()))
+}
+
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            access_key: env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_key: env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+fn s3_request(location: &str) -> Result<ureq::Request, TransactorError> {
+    let (bucket, key) = location
+        .split_once('/')
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| InvalidConfig(format!("s3://{} has no bucket and key", location)))?;
+    let region = env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| "us-east-1".to_string());
+    // Custom endpoints are addressed by path as they rarely have a host name per bucket
+    let (endpoint, path) = match env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => (
+            endpoint.trim_end_matches('/').to_string(),
+            format!("/{}/{}", bucket, encode_path(key)),
+        ),
+        Err(_) => (
+            format!("https://{}.s3.{}.amazonaws.com", bucket, region),
+            format!("/{}", encode_path(key)),
+        ),
+    };
+    let mut request = ureq::get(&format!("{}{}", endpoint, path));
+    // Without credentials only public objects can be read
+    let credentials = match Credentials::from_env() {
+        Some(credentials) => credentials,
+        None => return Ok(request),
+    };
+    let host = endpoint
+        .split_once("://")
+        .map_or(&*endpoint, |(_, host)| host);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    for (name, value) in signed_headers(&credentials, &region, host, &path, now) {
+        request = request.set(name, &value);
+    }
+    Ok(request)
+}
+
+/// The headers signing a GET of `path` from S3 with AWS signature version 4. The host is signed
+/// but left out, as the http client sets it.
+fn signed_headers(
+    credentials: &Credentials,
+    region: &str,
+    host: &str,
+    path: &str,
+    now: u64,
+) -> Vec<(&'static str, String)> {
+    let time = timestamp(now);
+    let date = &time[..8];
+    // Kept in name order, as signing needs
+    let mut headers = vec![
+        ("host", host.to_string()),
+        ("x-amz-content-sha256", EMPTY_SHA256.to_string()),
+        ("x-amz-date", time.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "GET\n{}\n\n{}\n{}\n{}",
+        path, canonical_headers, signed, EMPTY_SHA256
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        time,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(&credentials.secret_key, date, region, "s3");
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+    headers.remove(0);
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, scope, signed, signature
+        ),
+    ));
+    headers
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent encode an object key, leaving the `/` between its parts.
+fn encode_path(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Seconds since the epoch as a basic format utc timestamp, such as `20150830T123600Z`.
+fn timestamp(seconds: u64) -> String {
+    let (year, month, day, seconds) = Timestamp(seconds).civil();
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::InputSource;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn signatures_follow_the_aws_examples() {
+        // The signing key example from the AWS signature version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(timestamp(1_700_000_000), "20231114T221320Z");
+        assert_eq!(encode_path("day 1/tx.csv"), "day%201/tx.csv");
+    }
+
+    #[test]
+    fn urls_are_streamed_through_the_reader() -> Result<(), TransactorError> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let server = thread::spawn(move || -> std::io::Result<()> {
+            let (stream, _) = listener.accept()?;
+            let mut request = BufReader::new(stream);
+            let mut line = String::new();
+            while request.read_line(&mut line)? > 2 {
+                line.clear();
+            }
+            let body = "type,client,tx,amount\ndeposit,1,1,2\n";
+            write!(
+                request.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        });
+        let url = format!("http://{}/day-1.csv?version=2", address);
+        let sources = InputSource::expand(&url, None)?;
+        assert_eq!(sources[0].name(), url);
+        let mut text = String::new();
+        sources[0].open()?.read_to_string(&mut text)?;
+        assert_eq!(text, "type,client,tx,amount\ndeposit,1,1,2\n");
+        server.join().expect("server panicked")?;
+        Ok(())
+    }
+}
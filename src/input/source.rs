@@ -0,0 +1,371 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use zip::{CompressionMethod, ZipArchive};
+
+use super::{Compression, InputFile};
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The input path read as the standard input.
+const STDIN: &str = "-";
+
+/// The scheme of input read from a tcp connection.
+const SOCKET: &str = "tcp://";
+
+/// The columnar file formats transactions can be read from with the parquet feature, each column
+/// holding the values of the csv column of the same name.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColumnarFormat {
+    /// An Apache Parquet file.
+    Parquet,
+    /// An Arrow IPC file, also known as Feather version 2.
+    Arrow,
+}
+
+impl ColumnarFormat {
+    /// The columnar format implied by the extension of a file: `.parquet`, or `.arrow` and
+    /// `.feather` for Arrow IPC files.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "parquet" => Some(ColumnarFormat::Parquet),
+            "arrow" | "feather" => Some(ColumnarFormat::Arrow),
+            _ => None,
+        }
+    }
+}
+
+/// A file of transactions to read, either a csv file of its own, compressed or not, a member of
+/// a zip archive, a url, a Parquet or Arrow file, an Avro object container file, or a stream read
+/// from stdin or a socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InputSource {
+    File {
+        path: PathBuf,
+        compression: Compression,
+    },
+    ZipMember {
+        archive: PathBuf,
+        name: String,
+        /// Where the member data starts in the archive and its size there.
+        start: u64,
+        size: u64,
+        deflated: bool,
+    },
+    /// An `http(s)://` or `s3://` url, only read with the remote feature.
+    Url {
+        url: String,
+        compression: Compression,
+    },
+    /// Only read with the parquet feature.
+    Columnar {
+        path: PathBuf,
+        format: ColumnarFormat,
+    },
+    /// Only read with the avro feature.
+    Avro { path: PathBuf },
+    /// The standard input, given as `-`.
+    Stdin { compression: Compression },
+    /// A tcp connection to `host:port`, given as `tcp://host:port`, read until the other end
+    /// closes it.
+    Socket {
+        address: String,
+        compression: Compression,
+    },
+}
+
+impl InputSource {
+    /// The inputs a path holds: the csv members of a `.zip` archive in name order, a Parquet,
+    /// Arrow or `.avro` file, or otherwise the file, url, stdin or socket itself, compressed as
+    /// given or as its extension says.
+    pub fn expand<P: AsRef<Path>>(
+        path: P,
+        compression: Option<Compression>,
+    ) -> Result<Vec<Self>, TransactorError> {
+        let path = path.as_ref();
+        if path.to_str() == Some(STDIN) {
+            return Ok(vec![InputSource::Stdin {
+                compression: compression.unwrap_or_default(),
+            }]);
+        }
+        if let Some(address) = path.to_str().and_then(|path| path.strip_prefix(SOCKET)) {
+            return Ok(vec![InputSource::Socket {
+                address: address.to_string(),
+                compression: compression.unwrap_or_default(),
+            }]);
+        }
+        if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+            return url_source(url, compression).map(|source| vec![source]);
+        }
+        if compression.is_none() && is_zip(path) {
+            return zip_members(path);
+        }
+        if let Some(format) = ColumnarFormat::from_extension(path).filter(|_| compression.is_none())
+        {
+            if cfg!(not(feature = "parquet")) {
+                return Err(needs_parquet(path));
+            }
+            return Ok(vec![InputSource::Columnar {
+                path: path.to_path_buf(),
+                format,
+            }]);
+        }
+        if compression.is_none() && has_extension(path, "avro") {
+            if cfg!(not(feature = "avro")) {
+                return Err(InvalidConfig(format!(
+                    "cannot read {}, reading Avro files needs the avro feature",
+                    path.display()
+                )));
+            }
+            return Ok(vec![InputSource::Avro {
+                path: path.to_path_buf(),
+            }]);
+        }
+        Ok(vec![InputSource::File {
+            path: path.to_path_buf(),
+            compression: compression.unwrap_or_else(|| Compression::from_extension(path)),
+        }])
+    }
+
+    /// The name the input is known by, for zip members the archive and member joined by `:`.
+    pub fn name(&self) -> String {
+        match self {
+            InputSource::File { path, .. } => path.display().to_string(),
+            InputSource::ZipMember { archive, name, .. } => {
+                format!("{}:{}", archive.display(), name)
+            }
+            InputSource::Url { url, .. } => url.clone(),
+            InputSource::Columnar { path, .. } | InputSource::Avro { path } => {
+                path.display().to_string()
+            }
+            InputSource::Stdin { .. } => STDIN.to_string(),
+            InputSource::Socket { address, .. } => format!("{}{}", SOCKET, address),
+        }
+    }
+
+    pub fn open(&self) -> Result<InputFile, TransactorError> {
+        match self {
+            InputSource::File { path, compression } => InputFile::open(path, *compression),
+            InputSource::ZipMember {
+                archive,
+                start,
+                size,
+                deflated,
+                ..
+            } => InputFile::zip_member(archive, *start, *size, *deflated),
+            #[cfg(feature = "remote")]
+            InputSource::Url { url, compression } => InputFile::url(url, *compression),
+            #[cfg(not(feature = "remote"))]
+            InputSource::Url { url, .. } => Err(needs_remote(url)),
+            #[cfg(feature = "parquet")]
+            InputSource::Columnar { path, format } => InputFile::columnar(path, *format),
+            #[cfg(not(feature = "parquet"))]
+            InputSource::Columnar { path, .. } => Err(needs_parquet(path)),
+            #[cfg(feature = "avro")]
+            InputSource::Avro { path } => InputFile::avro(path),
+            #[cfg(not(feature = "avro"))]
+            InputSource::Avro { path } => Err(InvalidConfig(format!(
+                "cannot read {}, reading Avro files needs the avro feature",
+                path.display()
+            ))),
+            InputSource::Stdin { compression } => InputFile::stdin(*compression),
+            InputSource::Socket {
+                address,
+                compression,
+            } => InputFile::socket(address, *compression),
+        }
+    }
+}
+
+/// The paths in a list of input files, one per line. Blank lines and lines starting with `#` are
+/// skipped, relative paths are taken from the directory of the list and urls, sockets and `-`
+/// kept as they are.
+pub fn read_input_list<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, TransactorError> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(
+            |line| match is_url(line) || line.starts_with(SOCKET) || line == STDIN {
+                true => PathBuf::from(line),
+                false => directory.join(line),
+            },
+        )
+        .collect())
+}
+
+fn is_url(input: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| input.starts_with(scheme))
+}
+
+fn is_zip(path: &Path) -> bool {
+    has_extension(path, "zip")
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|found| found.eq_ignore_ascii_case(extension))
+}
+
+fn url_source(url: &str, compression: Option<Compression>) -> Result<InputSource, TransactorError> {
+    if cfg!(not(feature = "remote")) {
+        return Err(needs_remote(url));
+    }
+    // The extension is that of the path, not of any query string
+    let path = Path::new(url.split(['?', '#']).next().unwrap_or(url));
+    if compression.is_none() && is_zip(path) {
+        return Err(InvalidConfig(format!(
+            "cannot read {}, zip archives can only be read from local files",
+            url
+        )));
+    }
+    if compression.is_none() && ColumnarFormat::from_extension(path).is_some() {
+        return Err(InvalidConfig(format!(
+            "cannot read {}, Parquet and Arrow files can only be read from local files",
+            url
+        )));
+    }
+    Ok(InputSource::Url {
+        url: url.to_string(),
+        compression: compression.unwrap_or_else(|| Compression::from_extension(path)),
+    })
+}
+
+fn needs_remote(url: &str) -> TransactorError {
+    InvalidConfig(format!(
+        "cannot read {}, reading urls needs the remote feature",
+        url
+    ))
+}
+
+fn needs_parquet(path: &Path) -> TransactorError {
+    InvalidConfig(format!(
+        "cannot read {}, reading Parquet and Arrow files needs the parquet feature",
+        path.display()
+    ))
+}
+
+/// The csv members of a zip archive in name order. Members are read straight from the archive
+/// when applied, so only stored and deflated members are supported.
+fn zip_members(path: &Path) -> Result<Vec<InputSource>, TransactorError> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut members = Vec::new();
+    for index in 0..archive.len() {
+        let member = archive.by_index_raw(index)?;
+        let is_csv = Path::new(member.name())
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        if !member.is_file() || !is_csv {
+            continue;
+        }
+        let deflated = match member.compression() {
+            CompressionMethod::Stored => false,
+            CompressionMethod::Deflated => true,
+            method => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "zip member {} is compressed with {}, only stored and deflated members can be read",
+                        member.name(),
+                        method
+                    ),
+                )
+                .into())
+            }
+        };
+        members.push(InputSource::ZipMember {
+            archive: path.to_path_buf(),
+            name: member.name().to_string(),
+            start: member.data_start(),
+            size: member.compressed_size(),
+            deflated,
+        });
+    }
+    members.sort_by_key(InputSource::name);
+    Ok(members)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn zip_members_are_read_in_name_order() -> Result<(), TransactorError> {
+        let path = std::env::temp_dir().join("transactor-source-test.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path)?);
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("b.csv", deflated)?;
+        writer.write_all(b"type,client,tx,amount\ndeposit,1,2,2\n")?;
+        writer.start_file("README", stored)?;
+        writer.write_all(b"not input")?;
+        writer.start_file("a.csv", stored)?;
+        writer.write_all(b"type,client,tx,amount\ndeposit,1,1,1\n")?;
+        writer.finish()?;
+
+        let sources = InputSource::expand(&path, None)?;
+        let names: Vec<String> = sources.iter().map(InputSource::name).collect();
+        assert_eq!(
+            names,
+            vec![
+                format!("{}:a.csv", path.display()),
+                format!("{}:b.csv", path.display())
+            ]
+        );
+        let mut text = String::new();
+        sources[1].open()?.read_to_string(&mut text)?;
+        assert_eq!(text, "type,client,tx,amount\ndeposit,1,2,2\n");
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn input_lists_are_relative_to_their_directory() -> Result<(), TransactorError> {
+        let path = std::env::temp_dir().join("transactor-input-list-test.txt");
+        fs::write(
+            &path,
+            "# monday\nday-1.csv\n\n  /data/day-2.csv.gz\ns3://dumps/day-3.csv\ntcp://feed:9000\n",
+        )?;
+        assert_eq!(
+            read_input_list(&path)?,
+            vec![
+                std::env::temp_dir().join("day-1.csv"),
+                PathBuf::from("/data/day-2.csv.gz"),
+                PathBuf::from("s3://dumps/day-3.csv"),
+                PathBuf::from("tcp://feed:9000")
+            ]
+        );
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sockets_are_read_until_closed() -> Result<(), TransactorError> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("tcp://{}", listener.local_addr()?);
+        let producer = std::thread::spawn(move || -> io::Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            stream.write_all(b"type,client,tx,amount\ndeposit,1,1,2\n")
+        });
+        let sources = InputSource::expand(&url, None)?;
+        assert_eq!(sources[0].name(), url);
+        let mut text = String::new();
+        sources[0].open()?.read_to_string(&mut text)?;
+        assert_eq!(text, "type,client,tx,amount\ndeposit,1,1,2\n");
+        producer.join().expect("producer panicked")?;
+        assert_eq!(
+            InputSource::expand("-", Some(Compression::Gzip))?,
+            vec![InputSource::Stdin {
+                compression: Compression::Gzip
+            }]
+        );
+        Ok(())
+    }
+}
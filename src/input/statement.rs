@@ -0,0 +1,346 @@
+use std::io::Read;
+use std::ops::Range;
+use std::str::FromStr;
+
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::bank::{ClientId, Timestamp, TransactionId};
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The bank statement formats that can be imported.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImportFormat {
+    /// Open Financial Exchange, either the SGML of OFX 1 or the XML of OFX 2.
+    Ofx,
+    /// Quicken Interchange Format.
+    Qif,
+    /// ISO 20022 camt.053 bank to customer statements, only read with the iso20022 feature.
+    Camt053,
+    /// ISO 20022 pain.001 customer credit transfer initiations, only read with the iso20022
+    /// feature.
+    Pain001,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ofx" => Ok(ImportFormat::Ofx),
+            "qif" => Ok(ImportFormat::Qif),
+            "camt053" => Ok(ImportFormat::Camt053),
+            "pain001" => Ok(ImportFormat::Pain001),
+            _ => Err(format!(
+                "Unknown import format {}, expected one of: ofx, qif, camt053, pain001",
+                s
+            )),
+        }
+    }
+}
+
+/// A line of a bank statement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatementLine {
+    /// The signed amount, money paid out is negative.
+    pub amount: Decimal,
+    /// When the line was posted, at midnight utc for a date alone.
+    pub posted: Option<Timestamp>,
+    /// The currency of the statement, if it gives one.
+    pub currency: Option<String>,
+}
+
+/// Read the lines of a statement in the given format.
+pub fn read_statement(
+    text: &str,
+    format: ImportFormat,
+) -> Result<Vec<StatementLine>, TransactorError> {
+    match format {
+        ImportFormat::Ofx => read_ofx(text),
+        ImportFormat::Qif => read_qif(text),
+        #[cfg(feature = "iso20022")]
+        ImportFormat::Camt053 => super::iso20022::read_camt053(text),
+        #[cfg(feature = "iso20022")]
+        ImportFormat::Pain001 => super::iso20022::read_pain001(text),
+        #[cfg(not(feature = "iso20022"))]
+        ImportFormat::Camt053 | ImportFormat::Pain001 => Err(InvalidConfig(
+            "ISO 20022 import needs the iso20022 feature".to_string(),
+        )),
+    }
+}
+
+/// A row of the csv input a statement is converted to.
+#[derive(Serialize)]
+struct InputRow<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    client: u16,
+    tx: u32,
+    amount: Decimal,
+    currency: Option<&'a str>,
+    timestamp: Option<u64>,
+    batch: Option<&'a str>,
+}
+
+/// Turns bank statements into transaction input of one client, money paid in becoming deposits
+/// and money paid out withdrawals. Statements carry no transaction ids of the kind the bank
+/// needs, so the lines of every statement converted are numbered in turn from the first id.
+#[derive(Clone, Debug)]
+pub struct StatementImport {
+    format: ImportFormat,
+    client_id: ClientId,
+    next_transaction: Option<u32>,
+}
+
+impl StatementImport {
+    pub fn new(format: ImportFormat, client_id: ClientId) -> Self {
+        Self {
+            format,
+            client_id,
+            next_transaction: Some(1),
+        }
+    }
+
+    /// Number the lines from `transaction_id` rather than 1.
+    pub fn with_first_transaction(mut self, transaction_id: TransactionId) -> Self {
+        self.next_transaction = Some(transaction_id.0);
+        self
+    }
+
+    /// Read a statement and write it out as v2 csv input.
+    pub fn convert<R: Read>(&mut self, mut reader: R) -> Result<Vec<u8>, TransactorError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for line in read_statement(&text, self.format)? {
+            let tx = self.next_transaction.ok_or(Overflow)?;
+            self.next_transaction = tx.checked_add(1);
+            writer.serialize(InputRow {
+                kind: match line.amount.is_sign_negative() {
+                    true => "withdrawal",
+                    false => "deposit",
+                },
+                client: self.client_id.0,
+                tx,
+                amount: line.amount.abs(),
+                currency: line.currency.as_deref(),
+                timestamp: line.posted.map(|posted| posted.0),
+                batch: None,
+            })?;
+        }
+        // Only writing to a vector, which cannot fail
+        Ok(writer.into_inner().unwrap_or_default())
+    }
+}
+
+/// The transactions of an OFX statement, found by their tags whether or not elements are closed
+/// as OFX 1 leaves them.
+fn read_ofx(text: &str) -> Result<Vec<StatementLine>, TransactorError> {
+    let mut lines = Vec::new();
+    let mut currency = None;
+    let mut transaction: Option<(Option<Decimal>, Option<Timestamp>)> = None;
+    // Everything before the first tag is the OFX 1 header
+    for element in text.split('<').skip(1) {
+        let (tag, value) = element.split_once('>').ok_or_else(|| {
+            InvalidStatement(format!("the tag <{} is not closed", element.trim()))
+        })?;
+        let value = value.trim();
+        match (
+            tag.trim().to_ascii_uppercase().as_str(),
+            transaction.as_mut(),
+        ) {
+            ("CURDEF", _) => currency = Some(value.to_string()),
+            ("STMTTRN", _) => transaction = Some((None, None)),
+            ("TRNAMT", Some((amount, _))) => {
+                *amount =
+                    Some(Decimal::from_str(&value.replace(',', ".")).map_err(|_| {
+                        InvalidStatement(format!("TRNAMT {} is not an amount", value))
+                    })?);
+            }
+            ("DTPOSTED", Some((_, posted))) => *posted = Some(ofx_date(value)?),
+            ("/STMTTRN", Some((amount, posted))) => {
+                let amount = amount
+                    .ok_or_else(|| InvalidStatement("a transaction has no TRNAMT".to_string()))?;
+                lines.push(StatementLine {
+                    amount,
+                    posted: *posted,
+                    currency: currency.clone(),
+                });
+                transaction = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+/// An OFX date time such as `20231114221320.000[-5:EST]`, of which everything after the date is
+/// optional and times without an offset are utc.
+fn ofx_date(value: &str) -> Result<Timestamp, TransactorError> {
+    let invalid = || InvalidStatement(format!("DTPOSTED {} is not a date", value));
+    let (date_time, offset) = match value.split_once('[') {
+        Some((date_time, zone)) => {
+            let offset = zone.trim_end_matches(']').split(':').next().unwrap_or("");
+            (date_time, offset.parse::<i64>().map_err(|_| invalid())?)
+        }
+        None => (value, 0),
+    };
+    let digits = date_time.split('.').next().unwrap_or("");
+    if digits.len() < 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // Only digits, so every part present parses
+    let number = |range: Range<usize>| {
+        digits
+            .get(range)
+            .and_then(|part| part.parse::<i64>().ok())
+            .unwrap_or(0)
+    };
+    let timestamp =
+        civil_timestamp(number(0..4), number(4..6), number(6..8)).ok_or_else(invalid)?;
+    let local = number(8..10) * 3600 + number(10..12) * 60 + number(12..14) - offset * 3600;
+    timestamp
+        .0
+        .checked_add_signed(local)
+        .map(Timestamp)
+        .ok_or_else(invalid)
+}
+
+/// The transactions of a QIF file, those of its bank, cash, card and other asset and liability
+/// accounts. Lists of categories, classes and the like are skipped.
+fn read_qif(text: &str) -> Result<Vec<StatementLine>, TransactorError> {
+    let mut lines = Vec::new();
+    let mut in_transactions = false;
+    let (mut amount, mut posted) = (None, None);
+    for (number, line) in text.lines().enumerate() {
+        let invalid = |reason: String| InvalidStatement(format!("line {}: {}", number + 1, reason));
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('!') {
+            let kind = header.strip_prefix("Type:").unwrap_or("");
+            in_transactions = ["Bank", "Cash", "CCard", "Oth A", "Oth L"]
+                .iter()
+                .any(|known| kind.trim().eq_ignore_ascii_case(known));
+            continue;
+        }
+        if !in_transactions || line.is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        let code = chars.next();
+        let value = chars.as_str().trim();
+        match code {
+            Some('T') | Some('U') => {
+                let parsed = Decimal::from_str(&value.replace(',', ""))
+                    .map_err(|_| invalid(format!("{} is not an amount", value)))?;
+                amount = amount.or(Some(parsed));
+            }
+            Some('D') => {
+                posted = Some(
+                    qif_date(value).ok_or_else(|| invalid(format!("{} is not a date", value)))?,
+                );
+            }
+            Some('^') => {
+                let amount = amount
+                    .take()
+                    .ok_or_else(|| invalid("the transaction has no amount".to_string()))?;
+                lines.push(StatementLine {
+                    amount,
+                    posted: posted.take(),
+                    currency: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+/// A QIF date, month first as Quicken writes them such as `11/14/2023`, `11/14'23` or `11-14-23`,
+/// or year first such as `2023-11-14`. Two digit years are of the 1900s unless after a `'`.
+fn qif_date(value: &str) -> Option<Timestamp> {
+    let parts: Vec<&str> = value
+        .split(|c: char| !c.is_ascii_digit())
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+    let numbers = parts
+        .iter()
+        .map(|part| part.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (year, month, day) = match (parts.first(), numbers.as_slice()) {
+        (Some(first), [year, month, day]) if first.len() == 4 => (*year, *month, *day),
+        (Some(_), [month, day, year]) => {
+            let year = match (*year, value.contains('\'')) {
+                (year, _) if year >= 100 => year,
+                (year, true) => 2000 + year,
+                (year, false) => 1900 + year,
+            };
+            (year, *month, *day)
+        }
+        _ => return None,
+    };
+    civil_timestamp(year, month, day)
+}
+
+/// Midnight utc of a date, None for dates that do not exist or come before the epoch.
+pub(super) fn civil_timestamp(year: i64, month: i64, day: i64) -> Option<Timestamp> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let timestamp = Timestamp::from_civil(year, month, day, 0)?;
+    // Days past the end of the month roll into the next
+    let (_, civil_month, _, _) = timestamp.civil();
+    (civil_month == month).then_some(timestamp)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::Bank;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn statements_import_as_deposits_and_withdrawals() -> Result<(), TransactorError> {
+        let ofx = "OFXHEADER:100\nDATA:OFXSGML\n\n\
+            <OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><CURDEF>USD\n\
+            <BANKTRANLIST>\n\
+            <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20231114<TRNAMT>100.00<FITID>a1</STMTTRN>\n\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20231115120000[-5:EST]<TRNAMT>-25.50<FITID>a2\n\
+            </STMTTRN>\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>\n";
+        let qif = "!Type:Cat\nNGroceries\n^\n\
+            !Type:Bank\nD11/16'23\nT-1,000.00\nPRent\n^\nD2023-11-17\nT12.5\n^\n";
+        let mut import = StatementImport::new(ImportFormat::Ofx, ClientId(7))
+            .with_first_transaction(TransactionId(10));
+        let converted = import.convert(ofx.as_bytes())?;
+        assert_eq!(
+            String::from_utf8(converted.clone()).unwrap(),
+            "type,client,tx,amount,currency,timestamp,batch\n\
+             deposit,7,10,100.00,USD,1699920000,\n\
+             withdrawal,7,11,25.50,USD,1700067600,\n"
+        );
+        import.format = ImportFormat::Qif;
+        let lines = read_statement(qif, ImportFormat::Qif)?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].amount, Decimal::new(-1000, 0));
+        assert_eq!(lines[0].posted, Timestamp::from_civil(2023, 11, 16, 0));
+
+        let mut bank = Bank::new();
+        for input in [converted, import.convert("!Type:Bank\nT5\n^\n".as_bytes())?] {
+            for record in InputReader::from_reader(&input[..], InputSchema::Auto)? {
+                record?.command.apply(&mut bank)?;
+            }
+        }
+        let account = bank.get_account(ClientId(7)).unwrap();
+        assert_eq!(account.available(), Decimal::new(7950, 2));
+        assert!(account
+            .transactions()
+            .any(|(transaction, _)| transaction.transaction_id() == TransactionId(12)));
+
+        assert!(matches!(
+            read_statement("!Type:Bank\nD02/30/2023\nT1\n^\n", ImportFormat::Qif),
+            Err(InvalidStatement(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use std::io;
+
+use csv::{ByteRecord, Position, StringRecord};
+use csv_core::ReadRecordResult;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{
+    ClientFilter, DisputeAmountPolicy, ExcessPrecisionPolicy, InputFormat, InputRecord,
+    InputSchema, RecordParser,
+};
+use crate::bank::Bank;
+use crate::error::TransactorError;
+
+/// Bytes read from the input at a time.
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reads commands from csv transaction input arriving on an async byte stream, such as a socket
+/// or an object store download, without blocking the thread while waiting for it. Records are
+/// read and reported exactly as `InputReader` does, only the reading is async.
+pub struct AsyncInputReader<R> {
+    input: R,
+    csv: csv_core::Reader,
+    buffer: Box<[u8]>,
+    /// The part of the buffer not yet handed to the csv reader.
+    start: usize,
+    end: usize,
+    /// The bytes handed to the csv reader so far.
+    offset: u64,
+    fields: Vec<u8>,
+    ends: Vec<usize>,
+    /// The first row of input without a header row, read to pick its schema.
+    pending: Option<ByteRecord>,
+    parser: RecordParser,
+    schema: InputSchema,
+}
+
+impl<R: AsyncRead + Unpin> AsyncInputReader<R> {
+    pub async fn from_reader(input: R, schema: InputSchema) -> Result<Self, TransactorError> {
+        Self::from_reader_with_format(input, schema, &InputFormat::default()).await
+    }
+
+    /// Read input laid out as `format` says, see `InputFormat`.
+    pub async fn from_reader_with_format(
+        input: R,
+        schema: InputSchema,
+        format: &InputFormat,
+    ) -> Result<Self, TransactorError> {
+        let mut reader = Self {
+            input,
+            csv: csv_core::ReaderBuilder::new()
+                .delimiter(format.delimiter)
+                .build(),
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            offset: 0,
+            fields: vec![0; 1024],
+            ends: vec![0; 16],
+            pending: None,
+            parser: RecordParser::new(ByteRecord::new()),
+            schema,
+        };
+        let first = reader.read_row().await?;
+        let text = StringRecord::from_byte_record(first.clone().unwrap_or_default())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.utf8_error().to_string()))?;
+        if !format.has_headers {
+            reader.pending = first;
+        }
+        (reader.schema, reader.parser) = RecordParser::for_input(&text, schema, format)?;
+        Ok(reader)
+    }
+
+    /// Choose what to do with amounts on dispute, resolve and chargeback rows, by default they
+    /// are rejected.
+    pub fn with_dispute_amounts(mut self, policy: DisputeAmountPolicy) -> Self {
+        self.parser.dispute_amounts = policy;
+        self
+    }
+
+    /// Read types leniently, see `InputReader::with_lenient_types`.
+    pub fn with_lenient_types(mut self) -> Self {
+        self.parser.lenient_types = true;
+        self
+    }
+
+    /// Check the decimal places of amounts, see `InputReader::with_max_precision`.
+    pub fn with_max_precision(
+        mut self,
+        decimal_places: u32,
+        policy: ExcessPrecisionPolicy,
+    ) -> Self {
+        self.parser.max_precision = Some(decimal_places);
+        self.parser.excess_precision = policy;
+        self
+    }
+
+    /// Skip the rows of clients the filter leaves out, see `InputReader::with_client_filter`.
+    pub fn with_client_filter(mut self, filter: ClientFilter) -> Self {
+        self.parser.client_filter = Some(filter);
+        self
+    }
+
+    /// The schema the input was read with, never auto.
+    pub fn schema(&self) -> InputSchema {
+        self.schema
+    }
+
+    /// The next record, None once the input has ended.
+    pub async fn next_record(&mut self) -> Result<Option<InputRecord>, TransactorError> {
+        let row = loop {
+            let row = match self.pending.take() {
+                Some(row) => row,
+                None => match self.read_row().await? {
+                    Some(row) => row,
+                    None => return Ok(None),
+                },
+            };
+            if !self.parser.skips(&row) {
+                break row;
+            }
+        };
+        self.parser.parse(&row).map(Some)
+    }
+
+    /// Apply every record to the bank in input order. The bank is only borrowed while a record is
+    /// applied, never across a wait for input. This stops at the first record that fails.
+    pub async fn apply_to(&mut self, bank: &mut Bank) -> Result<(), TransactorError> {
+        while let Some(record) = self.next_record().await? {
+            bank.set_batch(record.batch);
+            let position = record.position;
+            record
+                .command
+                .apply_in(bank, record.account)
+                .map_err(|e| e.at(position))?;
+        }
+        Ok(())
+    }
+
+    /// The fields of the next row, trimmed, None once the input has ended.
+    async fn read_row(&mut self) -> Result<Option<ByteRecord>, TransactorError> {
+        let mut start = Position::new();
+        start.set_byte(self.offset).set_line(self.csv.line());
+        let (mut written, mut ended) = (0, 0);
+        loop {
+            // An empty read means the input has ended, which the csv reader is told by handing
+            // it no input
+            if self.start == self.end {
+                self.start = 0;
+                self.end = self.input.read(&mut self.buffer).await?;
+            }
+            let (result, read, field_bytes, field_ends) = self.csv.read_record(
+                &self.buffer[self.start..self.end],
+                &mut self.fields[written..],
+                &mut self.ends[ended..],
+            );
+            self.start += read;
+            self.offset += read as u64;
+            written += field_bytes;
+            ended += field_ends;
+            match result {
+                ReadRecordResult::InputEmpty => {}
+                ReadRecordResult::OutputFull => {
+                    let size = self.fields.len() * 2;
+                    self.fields.resize(size, 0);
+                }
+                ReadRecordResult::OutputEndsFull => {
+                    let size = self.ends.len() * 2;
+                    self.ends.resize(size, 0);
+                }
+                ReadRecordResult::Record => {
+                    let mut row = ByteRecord::with_capacity(written, ended);
+                    let mut field_start = 0;
+                    for &field_end in &self.ends[..ended] {
+                        row.push_field(&self.fields[field_start..field_end]);
+                        field_start = field_end;
+                    }
+                    row.trim();
+                    row.set_position(Some(start));
+                    return Ok(Some(row));
+                }
+                ReadRecordResult::End => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::ClientId;
+    use crate::input::InputReader;
+    use rust_decimal::Decimal;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    /// Input arriving a few bytes at a time, as from a slow socket.
+    struct Trickle(&'static [u8]);
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let size = self.0.len().min(3).min(buf.remaining());
+            let (read, rest) = self.0.split_at(size);
+            buf.put_slice(read);
+            self.0 = rest;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn streamed_input_reads_as_a_file_does() -> Result<(), TransactorError> {
+        let input =
+            "type, client, tx, amount\ndeposit, 1, 1, 10\n\n\"withdrawal\",1,2,2.5\ndispute,1,1,\n";
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let mut reader = runtime.block_on(AsyncInputReader::from_reader(
+            Trickle(input.as_bytes()),
+            InputSchema::default(),
+        ))?;
+        let mut records = Vec::new();
+        while let Some(record) = runtime.block_on(reader.next_record())? {
+            records.push(record);
+        }
+        let expected = InputReader::from_reader(input.as_bytes(), InputSchema::default())?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records, expected);
+
+        let headerless = "deposit;1;1;10\nwithdrawal;1;2;2.5\n";
+        let format = InputFormat::default()
+            .without_headers()
+            .with_delimiter(b';');
+        let mut reader = runtime.block_on(AsyncInputReader::from_reader_with_format(
+            Trickle(headerless.as_bytes()),
+            InputSchema::default(),
+            &format,
+        ))?;
+        let mut records = Vec::new();
+        while let Some(record) = runtime.block_on(reader.next_record())? {
+            records.push(record);
+        }
+        let expected = InputReader::from_reader_with_format(
+            headerless.as_bytes(),
+            InputSchema::default(),
+            &format,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records, expected);
+
+        let short_row = &b"type,client,tx,amount\ndeposit,2,1,4\nwithdrawal,2,2\n"[..];
+        let mut bank = Bank::new();
+        let mut reader = runtime.block_on(AsyncInputReader::from_reader(
+            short_row,
+            InputSchema::default(),
+        ))?;
+        let error = runtime.block_on(reader.apply_to(&mut bank)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line 3: Record has 3 fields but the header has 4"
+        );
+        // Rows are checked by the parser both readers share, so a file fails alike
+        let file_error = InputReader::from_reader(short_row, InputSchema::default())?
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert_eq!(file_error.to_string(), error.to_string());
+        let account = bank
+            .get_account(ClientId(2))
+            .expect("the deposit was applied");
+        assert_eq!(account.available(), Decimal::new(4, 0));
+
+        let precise = "type,client,tx,amount\ndeposit,1,1,1.239\n";
+        let mut reader = runtime
+            .block_on(AsyncInputReader::from_reader(
+                Trickle(precise.as_bytes()),
+                InputSchema::default(),
+            ))?
+            .with_max_precision(2, ExcessPrecisionPolicy::Truncate);
+        let record = runtime.block_on(reader.next_record())?;
+        let expected = InputReader::from_reader(precise.as_bytes(), InputSchema::default())?
+            .with_max_precision(2, ExcessPrecisionPolicy::Truncate)
+            .next()
+            .transpose()?;
+        assert_eq!(record, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+use crate::bank::TransactionId;
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::{Command, InputReader, InputRecord};
+
+/// Resolves and chargebacks read from a separate feed, joined by transaction id to the disputes in
+/// the transaction input so each outcome is applied after the dispute it settles.
+///
+/// Finding an outcome reads ahead in the feed, holding the outcomes of other transactions until
+/// their disputes arrive. At most `max_buffered` outcomes are held, once the buffer is full reading
+/// ahead stops and outcomes still in the feed are only applied by `remaining` once the transaction
+/// input is exhausted, which is still after their disputes.
+pub struct OutcomeFeed<R> {
+    reader: InputReader<R>,
+    pending: HashMap<TransactionId, VecDeque<InputRecord>>,
+    buffered: usize,
+    max_buffered: usize,
+}
+
+impl<R: io::Read> OutcomeFeed<R> {
+    pub fn new(reader: InputReader<R>, max_buffered: usize) -> Self {
+        Self {
+            reader,
+            pending: HashMap::new(),
+            buffered: 0,
+            max_buffered: max_buffered.max(1),
+        }
+    }
+
+    /// The next outcome in the feed for a transaction, or None if it has not been found within the
+    /// buffer. This fails if the feed contains anything other than resolves and chargebacks.
+    pub fn next_outcome(
+        &mut self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<InputRecord>, TransactorError> {
+        if let Some(outcome) = self.take_pending(transaction_id) {
+            return Ok(Some(outcome));
+        }
+        while self.buffered < self.max_buffered {
+            let (outcome_id, outcome) = match self.reader.next() {
+                Some(outcome) => check_outcome(outcome?)?,
+                None => return Ok(None),
+            };
+            if outcome_id == transaction_id {
+                return Ok(Some(outcome));
+            }
+            self.buffered += 1;
+            self.pending
+                .entry(outcome_id)
+                .or_default()
+                .push_back(outcome);
+        }
+        Ok(None)
+    }
+
+    /// Every outcome not yet taken, in feed order.
+    pub fn remaining(mut self) -> impl Iterator<Item = Result<InputRecord, TransactorError>> {
+        let mut buffered: Vec<InputRecord> = self
+            .pending
+            .drain()
+            .flat_map(|(_, outcomes)| outcomes)
+            .collect();
+        buffered.sort_by_key(|outcome| outcome.position.line);
+        buffered
+            .into_iter()
+            .map(Ok)
+            .chain(self.reader.map(|outcome| Ok(check_outcome(outcome?)?.1)))
+    }
+
+    fn take_pending(&mut self, transaction_id: TransactionId) -> Option<InputRecord> {
+        let outcomes = self.pending.get_mut(&transaction_id)?;
+        let outcome = outcomes.pop_front();
+        if outcomes.is_empty() {
+            self.pending.remove(&transaction_id);
+        }
+        if outcome.is_some() {
+            self.buffered -= 1;
+        }
+        outcome
+    }
+}
+
+fn check_outcome(outcome: InputRecord) -> Result<(TransactionId, InputRecord), TransactorError> {
+    match outcome.command {
+        Command::Resolve { transaction, .. } | Command::Chargeback { transaction, .. } => {
+            Ok((transaction, outcome))
+        }
+        command => Err(UnexpectedRecordType(command.name()).at(outcome.position)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::InputSchema;
+
+    fn feed(input: &str, max_buffered: usize) -> Result<OutcomeFeed<&[u8]>, TransactorError> {
+        Ok(OutcomeFeed::new(
+            InputReader::from_reader(input.as_bytes(), InputSchema::Auto)?,
+            max_buffered,
+        ))
+    }
+
+    #[test]
+    fn outcomes_are_found_out_of_order_within_the_buffer() -> Result<(), TransactorError> {
+        let mut outcomes = feed(
+            "type,client,tx\nchargeback,1,2\nresolve,1,1\nresolve,1,3\n",
+            2,
+        )?;
+        let first = outcomes.next_outcome(TransactionId(1))?.unwrap();
+        assert_eq!(first.command.name(), "resolve");
+        assert_eq!(first.position.line, 3);
+        // The chargeback for 2 is buffered so the search for 4 can read ahead one more outcome
+        assert!(outcomes.next_outcome(TransactionId(4))?.is_none());
+        assert_eq!(
+            outcomes
+                .next_outcome(TransactionId(2))?
+                .unwrap()
+                .command
+                .name(),
+            "chargeback"
+        );
+        let remaining = outcomes.remaining().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(
+            remaining[0].command.transaction_id(),
+            Some(TransactionId(3))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn feed_rejects_anything_but_outcomes() -> Result<(), TransactorError> {
+        let mut outcomes = feed("type,client,tx,amount\ndeposit,1,1,1.0\n", 10)?;
+        assert!(outcomes.next_outcome(TransactionId(1)).is_err());
+        Ok(())
+    }
+}
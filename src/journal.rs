@@ -0,0 +1,479 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::bank::{AccountName, Bank, ClientId, Outcome, Timestamp};
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::{Command, InputRecord};
+
+/// An account of the double-entry journal. The funds of clients are what the bank owes them, so
+/// credits add to them and debits take from them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum JournalAccount {
+    /// The available funds of one of a client's accounts.
+    Available(ClientId, AccountName),
+    /// The held funds of one of a client's accounts.
+    Held(ClientId, AccountName),
+    /// Funds on their way into or out of the bank, the other side of deposits and withdrawals.
+    Suspense,
+    /// What chargebacks took from clients to return to the card networks, the other side of
+    /// chargebacks and representments.
+    Chargebacks,
+    /// The fees charged to clients.
+    FeeIncome,
+}
+
+impl JournalAccount {
+    /// The name of the account in Beancount and ledger-cli, under the root its funds belong to,
+    /// such as `Liabilities:Clients:1:Savings:Held`. Sub-account names are capitalized with any
+    /// `_` made a `-`, as Beancount allows no other names.
+    pub fn ledger_name(&self) -> String {
+        let client = |client: &ClientId, name: &AccountName, funds: &str| match name.is_main() {
+            true => format!("Liabilities:Clients:{}:{}", client.0, funds),
+            false => {
+                let mut chars = name.as_str().chars();
+                let first = chars.next().map(|c| c.to_ascii_uppercase());
+                let name: String = first.into_iter().chain(chars).collect();
+                format!(
+                    "Liabilities:Clients:{}:{}:{}",
+                    client.0,
+                    name.replace('_', "-"),
+                    funds
+                )
+            }
+        };
+        match self {
+            JournalAccount::Available(client_id, name) => client(client_id, name, "Available"),
+            JournalAccount::Held(client_id, name) => client(client_id, name, "Held"),
+            JournalAccount::Suspense => "Assets:Suspense".to_string(),
+            JournalAccount::Chargebacks => "Liabilities:Chargebacks".to_string(),
+            JournalAccount::FeeIncome => "Income:Fees".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for JournalAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let client = |f: &mut fmt::Formatter<'_>, client: &ClientId, name: &AccountName| match name
+            .is_main()
+        {
+            true => write!(f, "clients:{}", client.0),
+            false => write!(f, "clients:{}:{}", client.0, name),
+        };
+        match self {
+            JournalAccount::Available(client_id, name) => {
+                client(f, client_id, name)?;
+                f.write_str(":available")
+            }
+            JournalAccount::Held(client_id, name) => {
+                client(f, client_id, name)?;
+                f.write_str(":held")
+            }
+            JournalAccount::Suspense => f.write_str("suspense"),
+            JournalAccount::Chargebacks => f.write_str("chargebacks"),
+            JournalAccount::FeeIncome => f.write_str("income:fees"),
+        }
+    }
+}
+
+/// The funds of an account and the number of fees charged to it so far.
+#[derive(Copy, Clone, Debug, Default)]
+struct AccountBalances {
+    available: Decimal,
+    held: Decimal,
+    fees: usize,
+}
+
+/// The balances of the accounts of a client an operation may change, taken before it is applied
+/// so the journal can post what it changed.
+#[derive(Clone, Debug)]
+pub struct ClientBalances {
+    client_id: ClientId,
+    /// The balances of each account, None if it has yet to be opened.
+    accounts: Vec<(AccountName, Option<AccountBalances>)>,
+}
+
+impl ClientBalances {
+    /// The balances of the accounts of the client the record may change.
+    pub fn of_record(bank: &Bank, record: &InputRecord) -> Self {
+        match record.command {
+            Command::Move {
+                client, from, to, ..
+            } => Self::of(bank, client, &[from, to]),
+            command => Self::of(bank, command.client(), &[record.account]),
+        }
+    }
+
+    pub fn of(bank: &Bank, client_id: ClientId, names: &[AccountName]) -> Self {
+        let accounts = names
+            .iter()
+            .map(|name| {
+                let account = bank.get_sub_account(client_id, *name);
+                let balances = account.map(|account| AccountBalances {
+                    available: account.available(),
+                    held: account.held(),
+                    fees: account.fees().len(),
+                });
+                (*name, balances)
+            })
+            .collect();
+        Self {
+            client_id,
+            accounts,
+        }
+    }
+}
+
+/// One line of a csv journal.
+#[derive(Debug, Serialize)]
+struct Posting {
+    entry: u64,
+    line: u64,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    client: u16,
+    tx: Option<u32>,
+    account: String,
+    debit: Option<Decimal>,
+    credit: Option<Decimal>,
+}
+
+/// The ISO 4217 code for no currency, the commodity of plain text entries for accounts without a
+/// currency.
+const NO_CURRENCY: &str = "XXX";
+
+/// The formats a journal can be written in.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum JournalFormat {
+    /// A csv row for each posting.
+    #[default]
+    Csv,
+    /// Beancount plain text accounting, opening every account it posts to.
+    Beancount,
+    /// ledger-cli plain text accounting.
+    Ledger,
+}
+
+impl FromStr for JournalFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(JournalFormat::Csv),
+            "beancount" => Ok(JournalFormat::Beancount),
+            "ledger" => Ok(JournalFormat::Ledger),
+            _ => Err(format!(
+                "Unknown journal format {}, expected one of: csv, beancount, ledger",
+                s
+            )),
+        }
+    }
+}
+
+enum JournalOutput<W: io::Write> {
+    Csv(Box<csv::Writer<W>>),
+    Text {
+        writer: W,
+        format: JournalFormat,
+        /// The accounts opened so far, which Beancount needs before they are posted to.
+        opened: HashSet<JournalAccount>,
+    },
+}
+
+/// The operation an entry is posted for.
+struct EntryOf {
+    line: u64,
+    kind: &'static str,
+    tx: Option<u32>,
+    timestamp: Option<Timestamp>,
+}
+
+/// Writes the operations applied to the bank as a double-entry journal, an entry of balanced
+/// debit and credit postings for each. Every change to the funds of a client's account is posted
+/// to that account, against suspense for funds entering or leaving the bank, chargebacks for
+/// chargebacks and representments and fee income for fees.
+pub struct Journal<W: io::Write> {
+    output: JournalOutput<W>,
+    entries: u64,
+    debits: Decimal,
+    credits: Decimal,
+}
+
+impl<W: io::Write> Journal<W> {
+    /// A journal written as csv.
+    pub fn new(writer: W) -> Self {
+        Self::in_format(writer, JournalFormat::Csv)
+    }
+
+    /// A journal written in the given format. Plain text entries are dated by the timestamp of
+    /// their row, or 1970-01-01 if it has none.
+    pub fn in_format(writer: W, format: JournalFormat) -> Self {
+        let output = match format {
+            JournalFormat::Csv => JournalOutput::Csv(Box::new(csv::Writer::from_writer(writer))),
+            format => JournalOutput::Text {
+                writer,
+                format,
+                opened: HashSet::new(),
+            },
+        };
+        Self {
+            output,
+            entries: 0,
+            debits: Decimal::zero(),
+            credits: Decimal::zero(),
+        }
+    }
+
+    /// Post what the record changed if it was applied, from the balances before it.
+    pub fn post_record(
+        &mut self,
+        record: &InputRecord,
+        result: &Result<Outcome, TransactorError>,
+        before: &ClientBalances,
+        bank: &Bank,
+    ) -> Result<(), TransactorError> {
+        if !matches!(result, Ok(Outcome::Applied)) {
+            return Ok(());
+        }
+        let counter = match record.command {
+            Command::Chargeback { .. } | Command::Represent { .. } => JournalAccount::Chargebacks,
+            _ => JournalAccount::Suspense,
+        };
+        let entry = EntryOf {
+            line: record.position.line,
+            kind: record.command.name(),
+            tx: record.command.transaction_id().map(|tx| tx.0),
+            timestamp: record.timestamp,
+        };
+        self.post(entry, counter, before, bank)
+    }
+
+    /// Post authorizations voided for going unsettled too long by the time of the record, from
+    /// the balances before.
+    pub fn post_expiry(
+        &mut self,
+        record: &InputRecord,
+        before: &ClientBalances,
+        bank: &Bank,
+    ) -> Result<(), TransactorError> {
+        let entry = EntryOf {
+            line: record.position.line,
+            kind: "expire",
+            tx: None,
+            timestamp: record.timestamp,
+        };
+        self.post(entry, JournalAccount::Suspense, before, bank)
+    }
+
+    fn post(
+        &mut self,
+        entry: EntryOf,
+        counter: JournalAccount,
+        before: &ClientBalances,
+        bank: &Bank,
+    ) -> Result<(), TransactorError> {
+        let client_id = before.client_id;
+        // Credits are positive and debits negative
+        let mut postings = Vec::new();
+        let mut currency = None;
+        let (mut changed, mut fees) = (Decimal::zero(), Decimal::zero());
+        for (name, balances) in &before.accounts {
+            let balances = balances.unwrap_or_default();
+            let account = match bank.get_sub_account(client_id, *name) {
+                Some(account) => account,
+                None => continue,
+            };
+            currency = currency.or_else(|| account.currency());
+            let available = account
+                .available()
+                .checked_sub(balances.available)
+                .ok_or(Overflow)?;
+            let held = account.held().checked_sub(balances.held).ok_or(Overflow)?;
+            for fee in account.fees().get(balances.fees..).unwrap_or_default() {
+                fees = fees.checked_add(fee.amount).ok_or(Overflow)?;
+            }
+            changed = changed
+                .checked_add(available)
+                .and_then(|changed| changed.checked_add(held))
+                .ok_or(Overflow)?;
+            postings.push((JournalAccount::Available(client_id, *name), available));
+            postings.push((JournalAccount::Held(client_id, *name), held));
+        }
+        postings.push((JournalAccount::FeeIncome, fees));
+        let counter_amount = changed.checked_add(fees).ok_or(Overflow)?;
+        postings.push((counter, -counter_amount));
+        postings.retain(|(_, amount)| !amount.is_zero());
+        if postings.is_empty() {
+            return Ok(());
+        }
+        self.entries += 1;
+        for (_, amount) in &postings {
+            let total = match amount.is_sign_negative() {
+                true => &mut self.debits,
+                false => &mut self.credits,
+            };
+            *total = total.checked_add(amount.abs()).ok_or(Overflow)?;
+        }
+        match &mut self.output {
+            JournalOutput::Csv(writer) => {
+                for (account, amount) in postings {
+                    let amount = amount.normalize();
+                    let (debit, credit) = match amount.is_sign_negative() {
+                        true => (Some(-amount), None),
+                        false => (None, Some(amount)),
+                    };
+                    writer.serialize(Posting {
+                        entry: self.entries,
+                        line: entry.line,
+                        kind: entry.kind,
+                        client: client_id.0,
+                        tx: entry.tx,
+                        account: account.to_string(),
+                        debit,
+                        credit,
+                    })?;
+                }
+            }
+            JournalOutput::Text {
+                writer,
+                format,
+                opened,
+            } => {
+                let currency = currency.map_or(NO_CURRENCY.to_string(), |c| c.to_string());
+                let mut narration = format!("{}, client {}", entry.kind, client_id.0);
+                if let Some(tx) = entry.tx {
+                    narration.push_str(&format!(", tx {}", tx));
+                }
+                narration.push_str(&format!(", line {}", entry.line));
+                let (year, month, day, _) = entry.timestamp.unwrap_or(Timestamp(0)).civil();
+                match format {
+                    JournalFormat::Beancount => {
+                        for (account, _) in &postings {
+                            if opened.insert(*account) {
+                                writeln!(writer, "1970-01-01 open {}", account.ledger_name())?;
+                            }
+                        }
+                        writeln!(
+                            writer,
+                            "{:04}-{:02}-{:02} * \"{}\"",
+                            year, month, day, narration
+                        )?;
+                    }
+                    _ => writeln!(
+                        writer,
+                        "{:04}/{:02}/{:02} * {}",
+                        year, month, day, narration
+                    )?,
+                }
+                // Plain text accounting has debits positive and credits negative
+                for (account, amount) in postings {
+                    writeln!(
+                        writer,
+                        "  {}  {} {}",
+                        account.ledger_name(),
+                        -amount.normalize(),
+                        currency
+                    )?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The debits and the credits of every entry posted, which are always equal.
+    pub fn totals(&self) -> (Decimal, Decimal) {
+        (self.debits, self.credits)
+    }
+
+    pub fn flush(&mut self) -> Result<(), TransactorError> {
+        match &mut self.output {
+            JournalOutput::Csv(writer) => writer.flush()?,
+            JournalOutput::Text { writer, .. } => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn every_applied_operation_posts_balanced_entries() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            withdrawal,1,2,4\n\
+            dispute,1,1,\n\
+            chargeback,1,1,\n\
+            withdrawal,2,3,1\n";
+        let mut bank = Bank::new();
+        let mut output = Vec::new();
+        let mut journal = Journal::new(&mut output);
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let before = ClientBalances::of_record(&bank, &record);
+            let result = record.command.apply(&mut bank);
+            journal.post_record(&record, &result, &before, &bank)?;
+        }
+        assert_eq!(journal.totals(), (Decimal::new(34, 0), Decimal::new(34, 0)));
+        journal.flush()?;
+        drop(journal);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "entry,line,type,client,tx,account,debit,credit\n\
+             1,2,deposit,1,1,clients:1:available,,10\n\
+             1,2,deposit,1,1,suspense,10,\n\
+             2,3,withdrawal,1,2,clients:1:available,4,\n\
+             2,3,withdrawal,1,2,suspense,,4\n\
+             3,4,dispute,1,1,clients:1:available,10,\n\
+             3,4,dispute,1,1,clients:1:held,,10\n\
+             4,5,chargeback,1,1,clients:1:held,10,\n\
+             4,5,chargeback,1,1,chargebacks,,10\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn beancount_journals_are_dated_and_open_their_accounts() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount,timestamp,batch\n\
+            deposit,1,1,10,1700000000,a\n\
+            withdrawal,1,2,2.50,1700086400,a\n";
+        let mut bank = Bank::new();
+        let mut output = Vec::new();
+        let mut journal = Journal::in_format(&mut output, JournalFormat::Beancount);
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let before = ClientBalances::of_record(&bank, &record);
+            let result = record.command.apply(&mut bank);
+            journal.post_record(&record, &result, &before, &bank)?;
+        }
+        journal.flush()?;
+        drop(journal);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "1970-01-01 open Liabilities:Clients:1:Available
+1970-01-01 open Assets:Suspense
+2023-11-14 * \"deposit, client 1, tx 1, line 2\"
+  Liabilities:Clients:1:Available  -10 XXX
+  Assets:Suspense  10 XXX
+
+2023-11-15 * \"withdrawal, client 1, tx 2, line 3\"
+  Liabilities:Clients:1:Available  2.5 XXX
+  Assets:Suspense  -2.5 XXX
+
+"
+        );
+        let savings: AccountName = "rainy_day".parse()?;
+        assert_eq!(
+            JournalAccount::Held(ClientId(3), savings).ledger_name(),
+            "Liabilities:Clients:3:Rainy-day:Held"
+        );
+        Ok(())
+    }
+}
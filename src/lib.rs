@@ -0,0 +1,28 @@
+pub mod audit;
+pub mod bank;
+pub mod bench;
+pub mod config;
+pub mod currency;
+pub mod diff;
+pub mod error;
+pub mod explain;
+#[cfg(feature = "failure-injection")]
+pub mod fault;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod input;
+pub mod join;
+pub mod journal;
+pub mod metrics;
+pub mod model;
+pub mod output;
+pub mod pace;
+pub mod recurring;
+pub mod report;
+pub mod rules;
+pub mod shutdown;
+pub mod sla;
+pub mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod validate;
@@ -1,137 +1,2331 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Instant;
+
 use argh::FromArgs;
-use csv::{ReaderBuilder, Trim, Writer};
+use csv::{ReaderBuilder, StringRecord, Trim, Writer};
 use rust_decimal::prelude::*;
 use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, error, info, warn, Level};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
 
-mod bank;
-mod error;
-
-use crate::bank::{Bank, ClientId, Transaction, TransactionId};
-use crate::error::TransactorError;
-use crate::error::TransactorError::*;
+use transactor::audit::{
+    prove_transaction, verify_ledger, AuditLog, History, InclusionProof, LedgerReader,
+    LedgerWriter, MerkleTree,
+};
+use transactor::bank::{
+    Account, AccountName, AccountView, Bank, BankObserver, BatchId, ClientId, ClientMetadata,
+    DisputePolicy, FeeRule, InputCheckpoint, LockedAccountPolicy, Outcome, RecoveryPolicy,
+    RefusalPolicy, RepresentmentPolicy, RiskLimit, StatusRule, Timestamp, TransactionFilter,
+    TransactionId, TransactionKind, TransactionState,
+};
+use transactor::bench::{generate_workload, peak_rss, BenchRun, Benchmark};
+use transactor::config::{
+    AccountSettings, Config, CurrencySettings, DisputeSettings, InputSettings, LimitSettings,
+    OperationSettings, OutputSettings, SlaSettings, TransactionSettings,
+};
+use transactor::currency::PrecisionTable;
+use transactor::diff::{diff_reports, write_deltas, AccountReport};
+use transactor::error::TransactorError::*;
+use transactor::error::{RecordPosition, RowErrors, TransactorError};
+use transactor::explain::Explainer;
+#[cfg(feature = "failure-injection")]
+use transactor::fault::{FaultConfig, FaultyWriter};
+#[cfg(feature = "avro")]
+use transactor::input::AvroSchemas;
+use transactor::input::{
+    read_input_list, ClientFilter, ClientRanges, ColumnMap, Command, Compression,
+    DisputeAmountPolicy, ExcessPrecisionPolicy, ImportFormat, InputFile, InputFormat, InputReader,
+    InputRecord, InputSchema, InputSource, StatementImport, TimeWindow, WindowPosition,
+};
+use transactor::join::OutcomeFeed;
+use transactor::journal::{ClientBalances, Journal, JournalFormat};
+use transactor::metrics::{MetricsServer, PUBLISH_INTERVAL};
+use transactor::model::ReferenceModel;
+use transactor::output::AtomicFile;
+use transactor::pace::{Pace, Pacer};
+use transactor::recurring::RecurringSchedule;
+#[cfg(feature = "pdf")]
+use transactor::report::write_statement_pdf;
+use transactor::report::{
+    write_accounts, write_changed_accounts, write_open_disputes, write_partitioned_accounts,
+    write_recoveries, write_statement, write_statement_html, write_transaction_matches,
+    AccountColumns, AccountOrder, AccountVersions, AggregateReport, OutputFormat, Partitioning,
+    Rounding, StatementFormat, StatementTemplate,
+};
+use transactor::rules::{RuleAction, RuleEngine};
+use transactor::shutdown::Shutdown;
+use transactor::sla::{DisputeTimers, SlaWebhook};
+use transactor::stats::RunStats;
+use transactor::validate::Validator;
 
 #[derive(FromArgs)]
 /// A program for enacting a CSV files of transactions over multiple accounts
 struct Arguments {
     #[argh(positional)]
-    /// A csv file of transactions. Nb: the filename must be UTF-8 encoded
-    input_file: String,
+    /// csv files of transactions, applied in order. Nb: the filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+    #[argh(option)]
+    /// a file listing more input files one per line, applied in order after any given as
+    /// arguments, relative paths are from the directory of the list
+    input_list: Option<String>,
+    #[argh(option)]
+    /// a TOML file of settings keyed by the long names of these flags, such as
+    /// dispute-policy = "deposits-only", flags given here override the file
+    config: Option<String>,
+    #[argh(option)]
+    /// which transactions may be disputed: all (default) or deposits-only
+    dispute_policy: Option<DisputePolicy>,
+    #[argh(option)]
+    /// what happens to withdrawals exceeding the available funds and transactions on locked
+    /// accounts: ignore (default) or error, which fails the run
+    refusal_policy: Option<RefusalPolicy>,
+    #[argh(option)]
+    /// whether a represented chargeback unlocks the account once none of its chargebacks stand:
+    /// unlock (default) or keep-locked
+    representment_policy: Option<RepresentmentPolicy>,
+    #[argh(option)]
+    /// which transactions locked accounts refuse: block-all (default) or block-withdrawals-only,
+    /// which still takes deposits on frozen and under review accounts
+    locked_account_policy: Option<LockedAccountPolicy>,
+    #[argh(option)]
+    /// how the debt a chargeback of funds already withdrawn leaves is recovered: deposits-first
+    /// (default), paying it down with deposits, or track-only
+    recovery_policy: Option<RecoveryPolicy>,
+    #[argh(option)]
+    /// a csv file to write the debts chargebacks left and are still owed to, with what has been
+    /// recovered of them
+    recovery_report: Option<String>,
+    #[argh(option)]
+    /// how far below zero withdrawals may take an accounts available funds, defaults to 0
+    overdraft_limit: Option<Decimal>,
+    #[argh(option)]
+    /// a csv file of client,limit giving accounts their own overdraft limit in place of
+    /// --overdraft-limit
+    overdraft_limits: Option<String>,
+    #[argh(option)]
+    /// a csv file of client,name,email,risk_tier with metadata of clients, shown in the report
+    /// when asked for with --output-columns and on statements with --details
+    clients: Option<String>,
+    #[argh(option)]
+    /// put an account under review once it has had more than this many chargebacks, represented
+    /// or not, reported on stderr
+    max_chargebacks: Option<u32>,
+    #[argh(option)]
+    /// put an account under review once it has more than this many transactions in dispute at
+    /// once, reported on stderr
+    max_open_disputes: Option<u32>,
+    #[argh(option)]
+    /// ignore disputes of a transaction already disputed this many times, however those disputes
+    /// ended
+    max_dispute_cycles: Option<u32>,
+    #[argh(option)]
+    /// the operations accounts in a status accept, such as frozen=dispute,resolve,chargeback,
+    /// repeated for each status. Statuses are active, frozen, closed and under-review
+    status_rule: Vec<StatusRule>,
+    #[argh(option)]
+    /// a fee taken with each withdrawal or chargeback, such as withdrawal=0.25+1% for a flat
+    /// 0.25 and 1% of the amount, repeated for each operation
+    fee: Vec<FeeRule>,
+    #[argh(option)]
+    /// fail on a deposit, withdrawal or authorization of more than this amount, such as a
+    /// mistyped 1000000000000
+    max_amount: Option<Decimal>,
+    #[argh(option)]
+    /// a correlation id recorded against every account change, defaults to the input file name
+    batch_id: Option<String>,
+    #[argh(option)]
+    /// a csv file of client,amount,interval,start standing orders, a negative amount for a
+    /// withdrawal, taken at each interval after the time of --state up to --as-of
+    recurring: Option<String>,
+    #[argh(option)]
+    /// the time in seconds since the unix epoch the run brings the accounts up to, needed by
+    /// --recurring
+    as_of: Option<u64>,
+    #[argh(option)]
+    /// only apply rows timestamped at or after this time in seconds since the unix epoch, earlier
+    /// deposits and withdrawals are only recorded so disputes of them are found
+    from: Option<u64>,
+    #[argh(option)]
+    /// only apply rows timestamped before this time in seconds since the unix epoch
+    to: Option<u64>,
+    #[argh(option)]
+    /// a csv file of administrative operations (e.g. unlock) applied after the input file
+    admin_ops: Option<String>,
+    #[argh(switch)]
+    /// instead of the account report, print how the admin operations would change each account
+    /// without applying them
+    admin_dry_run: bool,
+    #[argh(option)]
+    /// a csv file of currency,decimal_places,rounding giving the precision amounts in each
+    /// currency may have and how they are rounded for output, defaults to 4 decimal places
+    currency_config: Option<String>,
+    #[argh(option)]
+    /// how amounts are rounded for output: bankers (default), half-up or truncate, overriding
+    /// the rounding of each currency in the currency config
+    rounding: Option<Rounding>,
+    #[argh(option)]
+    /// a directory to spill older transaction history into, bounding memory use on large inputs
+    spill_dir: Option<String>,
+    #[argh(option)]
+    /// the number of transactions kept in memory when spilling, defaults to 1000000
+    spill_threshold: Option<usize>,
+    #[argh(switch)]
+    /// index every transaction id so disputes referencing another clients transaction are
+    /// reported as suspicious
+    tx_index: bool,
+    #[argh(switch)]
+    /// reject a transaction id already used by any client, not just by the same client
+    global_tx_ids: bool,
+    #[argh(option)]
+    /// how the input files are compressed: none, gzip or zstd, by default from their extension,
+    /// .gz or .zst
+    compression: Option<Compression>,
+    #[argh(option, default = "InputSchema::Auto")]
+    /// the shape of the input: v1 (type,client,tx,amount[,currency]), v2 (v1 plus timestamp and
+    /// batch columns) or auto (default) to pick from the header row
+    input_schema: InputSchema,
+    #[argh(option)]
+    /// a toml or json file mapping the header names of the input to the columns they hold, such
+    /// as txn_id = "tx", for inputs that name their columns differently
+    column_map: Option<String>,
+    #[argh(option)]
+    /// read the inputs as bank statements rather than csv: ofx, qif, or camt053 and pain001
+    /// when built with the iso20022 feature, each line becoming a deposit or withdrawal of
+    /// --import-client
+    import: Option<ImportFormat>,
+    #[argh(option)]
+    /// the client whose account imported statements are applied to
+    import_client: Option<u16>,
+    #[argh(option, default = "1")]
+    /// the transaction id of the first line of the imported statements, the rest numbered on in
+    /// order, defaults to 1
+    import_first_tx: u32,
+    #[argh(option)]
+    /// read the inputs as Avro messages framed for a schema registry, as Kafka producers write
+    /// them, decoded with the schema in this .avsc file whatever schema id they name. Needs the
+    /// avro feature
+    avro_schema: Option<String>,
+    #[argh(option)]
+    /// read the inputs as Avro messages framed for a schema registry, decoded with the schemas
+    /// they name fetched from the registry at this url. Needs the avro and remote features
+    avro_registry: Option<String>,
+    #[argh(switch)]
+    /// read the inputs as length-delimited protobuf Transaction messages, as described by
+    /// proto/transaction.proto, rather than csv. Needs the protobuf feature
+    protobuf: bool,
+    #[argh(switch)]
+    /// refuse input with columns other than those of the input specification, by default
+    /// unknown columns such as trailing audit columns are ignored
+    strict_schema: bool,
+    #[argh(switch)]
+    /// read input without a header row, taking type, client, tx and amount, and then timestamp
+    /// and batch for the v2 schema, by their position
+    no_header: bool,
+    #[argh(option, from_str_fn(parse_delimiter))]
+    /// the character separating the fields of the input rather than a comma, such as ';' or '\t'
+    /// for tab separated input
+    delimiter: Option<u8>,
+    #[argh(option, default = "DisputeAmountPolicy::Reject")]
+    /// what to do with an amount on a dispute, resolve or chargeback row: reject (default) the
+    /// row, ignore the amount or verify it, warning if it is not the amount of the transaction
+    dispute_amounts: DisputeAmountPolicy,
+    #[argh(option)]
+    /// the most decimal places an amount may have when read, such as 4 as the input
+    /// specification allows, by default amounts of any precision are read and only checked
+    /// against the currency config
+    max_precision: Option<u32>,
+    #[argh(option, default = "ExcessPrecisionPolicy::Reject")]
+    /// what to do with an amount past --max-precision: reject (default) the row or truncate the
+    /// amount
+    excess_precision: ExcessPrecisionPolicy,
+    #[argh(switch)]
+    /// read the type of a row ignoring case, underscores, dashes and spaces, and accept aliases
+    /// such as withdraw, rather than only the exact lowercase names
+    lenient_types: bool,
+    #[argh(option)]
+    /// only apply the rows of these clients, given as ids and ranges such as 1,2,10-20, skipping
+    /// the rest as they are read
+    only_clients: Option<ClientRanges>,
+    #[argh(option)]
+    /// skip the rows of these clients as they are read, given as ids and ranges such as 1,2,10-20
+    exclude_clients: Option<ClientRanges>,
+    #[argh(option)]
+    /// a csv file of client, available, held and locked, optionally with total and currency, to
+    /// open accounts with before processing, such as the account report of a previous run
+    initial_balances: Option<String>,
+    #[argh(option, default = "0")]
+    /// the number of threads parsing the input while another applies it, defaults to 0 to parse
+    /// and apply on the same thread
+    parse_threads: usize,
+    #[argh(option)]
+    /// a file to write a json line to for every transaction input row, recording whether it was
+    /// applied, ignored or rejected and the resulting balances of the account
+    audit_log: Option<String>,
+    #[argh(option)]
+    /// a ledger file to append an entry to for every operation applied, each holding the hash of
+    /// the one before so later changes to it can be found with verify-ledger. An existing ledger
+    /// is checked and its chain carried on
+    ledger: Option<String>,
+    #[argh(option)]
+    /// a csv file to write a double-entry journal to, posting every operation applied as
+    /// balanced debits and credits of the available and held funds of clients, suspense,
+    /// chargebacks and fee income
+    journal: Option<String>,
+    #[argh(option, default = "JournalFormat::Csv")]
+    /// the format of --journal: csv (default), beancount, or ledger for ledger-cli
+    journal_format: JournalFormat,
+    #[argh(option)]
+    /// a file to write the full history of the run to, a row for every input row given to the
+    /// bank with its type, client, tx, amount, currency, timestamp, batch and whether it was
+    /// applied, ignored or rejected
+    history: Option<String>,
+    #[argh(option, default = "OutputFormat::Csv")]
+    /// the format of --history, as for --output-format
+    history_format: OutputFormat,
+    #[argh(option)]
+    /// a csv file of resolves and chargebacks joined to the disputes in the input by transaction
+    /// id, each is applied straight after the dispute it settles
+    dispute_outcomes: Option<String>,
+    #[argh(option, default = "100_000")]
+    /// the number of dispute outcomes held while searching the feed for the outcome of a dispute,
+    /// defaults to 100000
+    outcome_buffer: usize,
+    #[argh(switch)]
+    /// print a summary of the operations handled and their outcomes to stderr
+    metrics_summary: bool,
+    #[argh(option)]
+    /// a file to write the operation metrics to in the Prometheus text format, for example for the
+    /// node exporter textfile collector
+    metrics_file: Option<String>,
+    #[argh(option)]
+    /// an address such as 127.0.0.1:9100 to serve the operation metrics on at /metrics while the
+    /// input is read, for runs reading a socket or stdin
+    metrics_listen: Option<String>,
+    #[argh(switch)]
+    /// print totals of the run to stderr: rows by type and outcome, accounts created and locked,
+    /// funds held, elapsed time and throughput
+    stats_summary: bool,
+    #[argh(option)]
+    /// a file to write the totals of the run to as json, as printed by --stats-summary
+    stats: Option<String>,
+    #[argh(option)]
+    /// check every account is consistent, failing with the state of the first that is not:
+    /// every (after each row applied, checking its account) or end (once the input is applied)
+    check_invariants: Option<InvariantChecks>,
+    #[argh(switch)]
+    /// apply the input to a simple reference model of the bank as well, failing on the first row
+    /// or final account they disagree on. Only the default rules are modelled, starting from no
+    /// accounts without --state, --initial-balances, --resume or --authorization-expiry
+    verify: bool,
+    #[argh(option, default = "AccountColumns::default()")]
+    /// the comma separated columns of the account report, from client, available, held, total,
+    /// locked, status, currency, version, last_batch, fees, account, name, email and risk_tier,
+    /// defaults to client,available,held,total,locked
+    output_columns: AccountColumns,
+    #[argh(option, default = "OutputFormat::Csv")]
+    /// the format of the account report: csv (default), json, table, or parquet and arrow when
+    /// built with the parquet feature
+    output_format: OutputFormat,
+    #[argh(option, default = "AccountOrder::Client")]
+    /// the order of the account report: client (default) or total, smallest first
+    sort: AccountOrder,
+    #[argh(option)]
+    /// split the account report into a file per partition under --partition-dir instead of
+    /// writing it to stdout, by a comma separated list of currency and client_shard:N
+    partition_output_by: Option<Partitioning>,
+    #[argh(switch)]
+    /// only report the accounts that changed from those loaded with --state, or were opened, for
+    /// loaders applying deltas
+    output_changed_only: bool,
+    #[argh(option)]
+    /// write the output to this file instead of stdout, the file is only replaced once the run
+    /// has succeeded and all of the output has been written
+    output: Option<String>,
+    #[argh(option)]
+    /// the directory partitioned account reports are written to, defaults to partitions
+    partition_dir: Option<String>,
+    #[argh(option)]
+    /// the seconds a dispute may stay open before it is escalated, timed by the input timestamps,
+    /// escalations are reported on stderr, in the audit log and to --sla-webhook
+    dispute_sla: Option<u64>,
+    #[argh(option)]
+    /// skip rows that fail rather than stopping at the first, unless more than this many do, and
+    /// write the errors of the rows skipped as json to stderr or --error-report
+    max_errors: Option<usize>,
+    #[argh(option)]
+    /// a file to write the errors of rows skipped with --max-errors to, rather than stderr
+    error_report: Option<String>,
+    #[argh(option)]
+    /// a csv file to write the disputes still open to, with their age and sla status, requires
+    /// --dispute-sla
+    dispute_sla_report: Option<String>,
+    #[argh(option)]
+    /// a url each dispute sla escalation is posted to as json, requires --dispute-sla and the
+    /// remote feature
+    sla_webhook: Option<String>,
+    #[argh(option)]
+    /// a TOML file of velocity and limit rules on deposits and withdrawals, rows breaking a
+    /// rule are rejected or flagged in the audit log, or on stderr without one
+    rules: Option<String>,
+    #[argh(option)]
+    /// the seconds an authorization may stay unsettled before it is voided, timed by the input
+    /// timestamps, authorizations without a timestamp never expire
+    authorization_expiry: Option<u64>,
+    #[argh(option)]
+    /// replay the input at the pace of its timestamps: realtime or a speed up such as 10x
+    pace: Option<Pace>,
+    #[argh(option)]
+    /// a state snapshot saved by --save-state to start from, the input file may then be left out
+    /// to report on the snapshot as it is
+    state: Option<String>,
+    #[argh(option)]
+    /// a file to save the final state of every account to, including its transaction history,
+    /// for later runs to start from with --state
+    save_state: Option<String>,
+    #[argh(switch)]
+    /// log a hash of the final state of every account whatever the log level, the same for every
+    /// run reaching the same state, to check runs on different machines agree
+    state_hash: bool,
+    #[argh(option)]
+    /// a file to save the state reached and the position in the input to every
+    /// --checkpoint-interval rows and on SIGINT or SIGTERM, removed once the run completes
+    checkpoint: Option<String>,
+    #[argh(option, default = "100_000")]
+    /// the rows read between checkpoints, defaults to 100000
+    checkpoint_interval: u64,
+    #[argh(switch)]
+    /// carry on from the --checkpoint file if there is one, in place of --state and
+    /// --initial-balances, skipping the input it covers
+    resume: bool,
+    #[argh(switch, short = 'v')]
+    /// log more to stderr, -v for each input read and checkpoint saved and -vv for every
+    /// operation with its client, transaction and outcome, only warnings are logged by default
+    verbose: u8,
+    #[argh(option, default = "LogFormat::Text")]
+    /// the format of the log on stderr: text (default) or json
+    log_format: LogFormat,
+    #[argh(subcommand)]
+    command: Option<Subcommand>,
+}
+
+/// When `--check-invariants` checks the accounts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum InvariantChecks {
+    Every,
+    End,
+}
+
+impl FromStr for InvariantChecks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "every" => Ok(InvariantChecks::Every),
+            "end" => Ok(InvariantChecks::End),
+            _ => Err(format!(
+                "Unknown invariant checks {}, expected one of: every, end",
+                s
+            )),
+        }
+    }
+}
+
+/// How the log on stderr is written.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Unknown log format {}, expected one of: text, json",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Subcommand {
+    Report(ReportCommand),
+    Search(SearchCommand),
+    ClosePeriod(ClosePeriodCommand),
+    Validate(ValidateCommand),
+    Explain(ExplainCommand),
+    Bench(BenchCommand),
+    Diff(DiffCommand),
+    Reconcile(ReconcileCommand),
+    VerifyLedger(VerifyLedgerCommand),
+    Prove(ProveCommand),
+    VerifyProof(VerifyProofCommand),
+}
+
+impl Subcommand {
+    fn input_files(&self) -> &[String] {
+        match self {
+            Subcommand::Report(report) => &report.input_files,
+            Subcommand::Search(search) => &search.input_files,
+            Subcommand::ClosePeriod(close) => &close.input_files,
+            Subcommand::Validate(validate) => &validate.input_files,
+            Subcommand::Explain(explain) => &explain.input_files,
+            Subcommand::Bench(bench) => &bench.input_files,
+            Subcommand::Diff(_)
+            | Subcommand::VerifyLedger(_)
+            | Subcommand::Prove(_)
+            | Subcommand::VerifyProof(_) => &[],
+            Subcommand::Reconcile(reconcile) => &reconcile.input_files,
+        }
+    }
+}
+
+#[derive(FromArgs)]
+/// Print the statement of every transaction on one clients account, or a summary of the run
+#[argh(subcommand, name = "report")]
+struct ReportCommand {
+    #[argh(option)]
+    /// the client whose statement is printed
+    client: Option<u16>,
+    #[argh(switch)]
+    /// print a summary of the run rather than a statement: the available, held and locked funds
+    /// of every account, the accounts in each status, the input rows applied of each type with
+    /// their amounts and the debits and credits of --journal, which must balance
+    aggregate: bool,
+    #[argh(option, default = "StatementFormat::Csv")]
+    /// the statement format: csv (default), html, or pdf when built with the pdf feature
+    format: StatementFormat,
+    #[argh(option)]
+    /// the title of html and pdf statements
+    title: Option<String>,
+    #[argh(option)]
+    /// the location of a logo shown on html statements
+    logo: Option<String>,
+    #[argh(option)]
+    /// the comma separated columns of html and pdf statements, from tx, amount, state, balance,
+    /// currency and timestamp, defaults to tx,amount,state,balance
+    fields: Option<String>,
+    #[argh(option)]
+    /// the comma separated client details shown on html and pdf statements, from name, email
+    /// and risk_tier, read from --clients
+    details: Option<String>,
+    #[argh(positional)]
+    /// csv files of transactions, applied in order, may be left out with --state. Nb: the
+    /// filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Print every transaction matching all of the given criteria
+#[argh(subcommand, name = "search")]
+struct SearchCommand {
+    #[argh(option)]
+    /// only transactions on this clients account
+    client: Option<u16>,
+    #[argh(option)]
+    /// only transactions of at least this amount
+    min_amount: Option<Decimal>,
+    #[argh(option)]
+    /// only transactions of at most this amount
+    max_amount: Option<Decimal>,
+    #[argh(option, long = "type")]
+    /// only transactions of this type: deposit or withdrawal
+    kind: Option<TransactionKind>,
+    #[argh(option)]
+    /// only transactions in this state: posted, disputed, resolved or chargedback
+    state: Option<TransactionState>,
+    #[argh(option)]
+    /// only transactions timestamped at or after this time in seconds since the unix epoch
+    from: Option<u64>,
+    #[argh(option)]
+    /// only transactions timestamped before this time in seconds since the unix epoch
+    to: Option<u64>,
+    #[argh(positional)]
+    /// csv files of transactions, applied in order, may be left out with --state. Nb: the
+    /// filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Process the period's transactions, archive their history to a file and print the closing
+/// balances that carry forward to the next period
+#[argh(subcommand, name = "close-period")]
+struct ClosePeriodCommand {
+    #[argh(option)]
+    /// the csv file the period's transaction history is archived to
+    archive: String,
+    #[argh(positional)]
+    /// csv files of transactions, applied in order, may be left out with --state. Nb: the
+    /// filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Check the input for rows that cannot be read, duplicate transaction ids, references to
+/// transactions never made and amounts with too many decimal places, without applying it. Every
+/// issue is printed with its line and the run fails if there are any
+#[argh(subcommand, name = "validate")]
+struct ValidateCommand {
+    #[argh(positional)]
+    /// csv files of transactions, checked in order as one input. Nb: the filenames must be UTF-8
+    /// encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Replay the input and print every row touching one transaction with its line and what came of
+/// it, whether applied, ignored and why, or rejected, along with freezes of its account
+#[argh(subcommand, name = "explain")]
+struct ExplainCommand {
+    #[argh(option)]
+    /// the transaction id to explain
+    tx: u32,
+    #[argh(positional)]
+    /// csv files of transactions, replayed in order. Nb: the filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Time runs of a workload, generated or read from the input files, printing rows per second,
+/// the time spent parsing, applying and writing in each run and the peak memory used. Rows are
+/// parsed with --parse-threads and the bank follows the --config rules
+#[argh(subcommand, name = "bench")]
+struct BenchCommand {
+    #[argh(option, default = "3")]
+    /// the number of runs, defaults to 3
+    runs: u32,
+    #[argh(option, default = "100_000")]
+    /// the number of rows of the generated workload, defaults to 100000
+    rows: usize,
+    #[argh(option, default = "1000")]
+    /// the number of clients of the generated workload, defaults to 1000
+    clients: u16,
+    #[argh(option, default = "1")]
+    /// the seed of the generated workload, the same seed gives the same rows
+    seed: u64,
+    #[argh(option)]
+    /// write the generated workload to this file, to rerun it elsewhere
+    save_workload: Option<String>,
+    #[argh(positional)]
+    /// csv files of transactions to use as the workload instead of generating one. Nb: the
+    /// filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Compare two csv account reports, such as the output of two runs, and print how each account
+/// that differs changed: added, removed or changed, with the change in its available, held and
+/// total funds and whether it was locked before and after, in --output-format
+#[argh(subcommand, name = "diff")]
+struct DiffCommand {
+    #[argh(positional)]
+    /// the earlier account report
+    before: String,
+    #[argh(positional)]
+    /// the later account report
+    after: String,
+}
+
+#[derive(FromArgs)]
+/// Process the transactions and compare the balances reached with those of an expected account
+/// report, printing each account that differs as diff does, the expected report taken as before.
+/// The run fails if any account differs
+#[argh(subcommand, name = "reconcile")]
+struct ReconcileCommand {
+    #[argh(option)]
+    /// a csv account report of the expected balances, such as from an upstream ledger
+    expected: String,
+    #[argh(positional)]
+    /// csv files of transactions, applied in order, may be left out with --state. Nb: the
+    /// filenames must be UTF-8 encoded
+    input_files: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// Check every entry of a ledger written with --ledger still holds its own hash and the hash of
+/// the entry before, printing the number of entries, the hash of the last and the Merkle root of
+/// them all to publish for verify-proof. The run fails at the first entry that does not
+#[argh(subcommand, name = "verify-ledger")]
+struct VerifyLedgerCommand {
+    #[argh(positional)]
+    /// the ledger file
+    ledger: String,
+}
+
+#[derive(FromArgs)]
+/// Print a proof as json that the deposit or withdrawal making a transaction is in a ledger
+/// written with --ledger, which verify-proof checks against the Merkle root of the ledger
+#[argh(subcommand, name = "prove")]
+struct ProveCommand {
+    #[argh(option)]
+    /// the transaction id to prove
+    tx: u32,
+    #[argh(positional)]
+    /// the ledger file
+    ledger: String,
+}
+
+#[derive(FromArgs)]
+/// Check a proof printed by prove leads to the published Merkle root of the ledger, failing if
+/// it does not
+#[argh(subcommand, name = "verify-proof")]
+struct VerifyProofCommand {
+    #[argh(option)]
+    /// the published Merkle root of the ledger, as printed by verify-ledger
+    root: String,
+    #[argh(positional)]
+    /// the proof file
+    proof: String,
 }
 
 fn main() {
-    let arguments: Arguments = argh::from_env();
-    std::process::exit(match enact_transactions(arguments.input_file) {
+    let arguments = parse_arguments();
+    init_logging(&arguments);
+    std::process::exit(match run(arguments) {
         Ok(_) => 0,
         Err(e) => {
-            eprintln!("Failed to handle given file {}", e);
-            1
+            error!(error = %e, "failed to handle given file");
+            e.exit_code()
         }
     })
 }
 
+/// The command line arguments, as `argh::from_env` parses them except that repeated short
+/// switches such as `-vv` are taken as `-v -v`.
+fn parse_arguments() -> Arguments {
+    let strings: Vec<String> = std::env::args().collect();
+    let mut args = Vec::with_capacity(strings.len());
+    for arg in &strings[1..] {
+        match arg.strip_prefix('-') {
+            Some(verbose) if verbose.len() > 1 && verbose.chars().all(|c| c == 'v') => {
+                args.extend(verbose.chars().map(|_| "-v"))
+            }
+            _ => args.push(arg.as_str()),
+        }
+    }
+    let command = Path::new(&strings[0])
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&strings[0]);
+    Arguments::from_args(&[command], &args).unwrap_or_else(|early_exit| {
+        println!("{}", early_exit.output);
+        std::process::exit(match early_exit.status {
+            Ok(()) => 0,
+            // As for an InvalidConfig error
+            Err(()) => 6,
+        })
+    })
+}
+
+/// The target of the event logging the hash asked for by `--state-hash`, which is logged at any
+/// level.
+const STATE_HASH_TARGET: &str = "state_hash";
+
+/// Log to stderr at the level and in the format asked for.
+fn init_logging(arguments: &Arguments) {
+    let level = match arguments.verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let mut filter = Targets::new().with_default(level);
+    if arguments.state_hash {
+        filter = filter.with_target(STATE_HASH_TARGET, Level::DEBUG);
+    }
+    // The levels are left to the filter
+    let log = tracing_subscriber::fmt()
+        .with_max_level(Level::TRACE)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false);
+    match arguments.log_format {
+        LogFormat::Text => log.finish().with(filter).init(),
+        LogFormat::Json => log.json().finish().with(filter).init(),
+    }
+}
+
+fn run(arguments: Arguments) -> Result<(), TransactorError> {
+    let started = Instant::now();
+    if let Some(Subcommand::Diff(diff)) = &arguments.command {
+        let before = AccountReport::from_path(&diff.before)?;
+        let after = AccountReport::from_path(&diff.after)?;
+        let deltas = diff_reports(&before, &after)?;
+        return write_output(&arguments, |output| {
+            write_deltas(&deltas, &mut *arguments.output_format.writer(output))
+        });
+    }
+    if let Some(Subcommand::VerifyLedger(verify)) = &arguments.command {
+        let hashes = LedgerReader::new(BufReader::new(File::open(&verify.ledger)?))
+            .map(|entry| entry.map(|entry| entry.hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        let root = MerkleTree::new(&hashes)?.root();
+        return write_output(&arguments, |mut output| {
+            match (hashes.last(), root) {
+                (Some(last), Some(root)) => writeln!(
+                    output,
+                    "{} entries verified, the last with hash {} and the Merkle root {}",
+                    hashes.len(),
+                    last,
+                    root
+                )?,
+                _ => writeln!(output, "The ledger is empty")?,
+            }
+            Ok(())
+        });
+    }
+    if let Some(Subcommand::Prove(prove)) = &arguments.command {
+        let ledger = BufReader::new(File::open(&prove.ledger)?);
+        let proof = prove_transaction(ledger, TransactionId(prove.tx))?
+            .ok_or_else(|| InvalidConfig(format!("--tx {} is not in the ledger", prove.tx)))?;
+        return write_output(&arguments, |mut output| {
+            serde_json::to_writer_pretty(&mut output, &proof).map_err(io::Error::from)?;
+            writeln!(output)?;
+            Ok(())
+        });
+    }
+    if let Some(Subcommand::VerifyProof(verify)) = &arguments.command {
+        let proof: InclusionProof =
+            serde_json::from_reader(BufReader::new(File::open(&verify.proof)?))
+                .map_err(|e| InvalidProof(e.to_string()))?;
+        proof.verify(&verify.root)?;
+        return write_output(&arguments, |mut output| {
+            writeln!(
+                output,
+                "Entry {} is in the ledger with the Merkle root {}",
+                proof.entry.seq, verify.root
+            )?;
+            Ok(())
+        });
+    }
+    let input_files = match &arguments.command {
+        Some(command) => command.input_files(),
+        None => &arguments.input_files,
+    };
+    let mut sources = Vec::new();
+    for input_file in input_files {
+        sources.extend(InputSource::expand(input_file, arguments.compression)?);
+    }
+    if let Some(input_list) = &arguments.input_list {
+        for input_file in read_input_list(input_list)? {
+            sources.extend(InputSource::expand(input_file, arguments.compression)?);
+        }
+    }
+    let benchmarking = matches!(arguments.command, Some(Subcommand::Bench(_)));
+    if sources.is_empty()
+        && arguments.state.is_none()
+        && arguments.recurring.is_none()
+        && !benchmarking
+    {
+        return Err(InvalidConfig("No input file given".to_string()));
+    }
+    let config = match &arguments.config {
+        Some(config) => Config::from_path(config)?,
+        None => Config::default(),
+    }
+    .overridden_by(command_line_config(&arguments, !sources.is_empty()));
+    config.validate()?;
+    let precision = config.precision()?;
+    if let Some(Subcommand::Validate(_)) = &arguments.command {
+        let mut validator = Validator::new(precision);
+        if config.transactions.global_tx_ids == Some(true) {
+            validator = validator.with_global_transaction_ids();
+        }
+        return validate_input(&sources, validator, &arguments);
+    }
+    if let Some(Subcommand::Bench(bench)) = &arguments.command {
+        return run_benchmark(&sources, bench, &config, &precision, &arguments);
+    }
+    let mut model = arguments.verify.then(ReferenceModel::new);
+    let mut bank = config.bank()?;
+    if config.has_risk_limits() {
+        bank = bank.with_observer(RiskAlerts);
+    }
+    if arguments.metrics_summary
+        || arguments.metrics_file.is_some()
+        || arguments.metrics_listen.is_some()
+    {
+        bank = bank.with_metrics();
+    }
+    #[cfg(feature = "failure-injection")]
+    if let Some(faults) = FaultConfig::from_env().map_err(InvalidConfig)? {
+        bank = bank.with_storage_faults(faults.storage_failures());
+    }
+    let resume_from = match &arguments.checkpoint {
+        Some(checkpoint) if arguments.resume && Path::new(checkpoint).exists() => {
+            Some(restore_checkpoint(checkpoint, &mut bank)?)
+        }
+        _ => None,
+    };
+    if resume_from.is_none() {
+        if let Some(initial_balances) = &arguments.initial_balances {
+            open_initial_accounts(initial_balances, &mut bank)?;
+        }
+        if let Some(state) = &arguments.state {
+            bank.restore(BufReader::new(File::open(state)?))?;
+        }
+    }
+    arguments.output_format.check_available()?;
+    if arguments.history.is_some() {
+        arguments.history_format.check_available()?;
+    }
+    if let Some(Subcommand::Report(report)) = &arguments.command {
+        if report.client.is_some() == report.aggregate {
+            return Err(InvalidConfig(
+                "report takes one of --client or --aggregate".to_string(),
+            ));
+        }
+    }
+    let loaded = arguments
+        .output_changed_only
+        .then(|| AccountVersions::of(&bank));
+    if let Some(Subcommand::Explain(explain)) = &arguments.command {
+        let transaction = TransactionId(explain.tx);
+        return explain_transaction(&sources, bank, transaction, precision, &arguments);
+    }
+    let accounts_before = bank.get_accounts().count();
+    let mut stats = RunStats::new();
+    let mut aggregate = match &arguments.command {
+        Some(Subcommand::Report(report)) if report.aggregate => Some(AggregateReport::new()),
+        _ => None,
+    };
+    let shutdown = Shutdown::on_signals()?;
+    let interrupted = if !sources.is_empty() {
+        process_input(
+            &sources,
+            &mut bank,
+            &precision,
+            &arguments,
+            &shutdown,
+            resume_from,
+            Tracking {
+                stats: &mut stats,
+                model: model.as_mut(),
+                aggregate: aggregate.as_mut(),
+            },
+        )?
+    } else {
+        None
+    };
+    if let (Some(model), None) = (&model, &interrupted) {
+        let differences = model.differences(&bank);
+        if !differences.is_empty() {
+            return Err(VerificationFailed(differences.join("\n  ")));
+        }
+    }
+    if let (Some(recurring), Some(as_of), None) =
+        (&arguments.recurring, arguments.as_of, &interrupted)
+    {
+        let schedule = RecurringSchedule::from_path(recurring)?;
+        bank.set_batch(Some(BatchId::new(recurring)));
+        schedule.apply(&mut bank, Timestamp(as_of))?;
+    }
+    if interrupted.is_some() {
+        // Admin operations follow the whole input, so they are left to the run finishing it
+    } else if let Some(admin_ops) = &arguments.admin_ops {
+        if arguments.admin_dry_run {
+            // The operations are applied in memory to compute the preview but the resulting state
+            // is never reported
+            write_metrics(&bank, &arguments)?;
+            stats.finish(&bank, accounts_before, started.elapsed());
+            write_stats(&stats, &arguments)?;
+            let before = account_balances(&bank);
+            enact_admin_operations(admin_ops, &mut bank)?;
+            return write_output(&arguments, |output| {
+                write_account_changes(&before, &bank, &precision, output)
+            });
+        }
+        bank.set_batch(Some(BatchId::new(admin_ops)));
+        enact_admin_operations(admin_ops, &mut bank)?;
+    }
+    if arguments.check_invariants.is_some() {
+        bank.check_invariants()?;
+    }
+    write_metrics(&bank, &arguments)?;
+    stats.finish(&bank, accounts_before, started.elapsed());
+    write_stats(&stats, &arguments)?;
+    if let Some(report) = &arguments.recovery_report {
+        write_recoveries(
+            &bank.outstanding_recoveries(),
+            &precision,
+            File::create(report)?,
+        )?;
+    }
+    match &arguments.command {
+        Some(Subcommand::Report(report)) => match (&mut aggregate, report.client) {
+            (Some(aggregate), _) => {
+                aggregate.count_accounts(&bank)?;
+                write_output(&arguments, |output| {
+                    aggregate.write(&mut *arguments.output_format.writer(output))
+                })?;
+                aggregate.check()
+            }
+            (None, Some(client)) => match bank.get_account(ClientId(client)) {
+                Some(account) => write_output(&arguments, |output| {
+                    let metadata = bank.clients().get(account.client_id);
+                    write_report(account, metadata, &precision, report, output)
+                }),
+                None => Ok(()),
+            },
+            (None, None) => unreachable!("report takes one of --client or --aggregate"),
+        },
+        Some(Subcommand::Search(search)) => {
+            let filter = TransactionFilter {
+                client: search.client.map(ClientId),
+                min_amount: search.min_amount,
+                max_amount: search.max_amount,
+                kind: search.kind,
+                state: search.state,
+                from: search.from.map(Timestamp),
+                to: search.to.map(Timestamp),
+            };
+            let matches = bank.find_transactions(&filter)?;
+            write_output(&arguments, |output| {
+                write_transaction_matches(&matches, &precision, output)
+            })
+        }
+        Some(Subcommand::ClosePeriod(close)) => {
+            let archived = bank.close_period();
+            write_transaction_matches(&archived, &precision, File::create(&close.archive)?)?;
+            write_account_report(&bank, loaded.as_ref(), &precision, &arguments)
+        }
+        Some(Subcommand::Reconcile(reconcile)) => {
+            let expected = AccountReport::from_path(&reconcile.expected)?;
+            let deltas = diff_reports(&expected, &AccountReport::of(&bank, &precision)?)?;
+            write_output(&arguments, |output| {
+                write_deltas(&deltas, &mut *arguments.output_format.writer(output))
+            })?;
+            match deltas.len() {
+                0 => Ok(()),
+                differences => Err(ReconciliationFailed(differences)),
+            }
+        }
+        Some(Subcommand::Validate(_))
+        | Some(Subcommand::Explain(_))
+        | Some(Subcommand::Bench(_))
+        | Some(Subcommand::Diff(_))
+        | Some(Subcommand::VerifyLedger(_))
+        | Some(Subcommand::Prove(_))
+        | Some(Subcommand::VerifyProof(_)) => {
+            unreachable!("only commands applying the input get this far")
+        }
+        None => write_account_report(&bank, loaded.as_ref(), &precision, &arguments),
+    }?;
+    if let Some(save_state) = &arguments.save_state {
+        let mut file = AtomicFile::create(save_state)?;
+        bank.save_state(&mut file)?;
+        file.commit()?;
+    }
+    if arguments.state_hash {
+        debug!(target: STATE_HASH_TARGET, hash = %bank.state_hash()?, "state hash");
+    }
+    if let (Some(checkpoint), None) = (&arguments.checkpoint, &interrupted) {
+        if Path::new(checkpoint).exists() {
+            fs::remove_file(checkpoint)?;
+        }
+    }
+    match interrupted {
+        Some(stopped) => Err(Interrupted {
+            input: stopped.input,
+            line: stopped.line,
+            offset: stopped.offset,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Print every issue the validator finds in the input with the input it is in, and a summary of
+/// how many rows were checked.
+fn validate_input(
+    sources: &[InputSource],
+    mut validator: Validator,
+    arguments: &Arguments,
+) -> Result<(), TransactorError> {
+    let (mut rows, mut issues) = (0, 0);
+    let mut import = statement_import(arguments)?;
+    for source in sources {
+        let reader = open_source(source, import.as_mut(), arguments);
+        let found: Box<dyn Iterator<Item = TransactorError>> = match reader {
+            Ok(reader) => Box::new(reader.flat_map(|result| {
+                rows += 1;
+                validator.check(result)
+            })),
+            // Nothing more of an input can be checked without its header
+            Err(e) => Box::new(std::iter::once(e)),
+        };
+        for issue in found {
+            println!("{}: {}", source.name(), issue);
+            issues += 1;
+        }
+    }
+    println!("{} rows checked, {} issues found", rows, issues);
+    match issues {
+        0 => Ok(()),
+        issues => Err(ValidationFailed(issues)),
+    }
+}
+
+/// Run the benchmark, printing the timings of each run, their mean and the peak memory used.
+fn run_benchmark(
+    sources: &[InputSource],
+    bench: &BenchCommand,
+    config: &Config,
+    precision: &PrecisionTable,
+    arguments: &Arguments,
+) -> Result<(), TransactorError> {
+    let inputs = if sources.is_empty() {
+        let workload = generate_workload(bench.rows, bench.clients, bench.seed);
+        if let Some(save_workload) = &bench.save_workload {
+            fs::write(save_workload, &workload)?;
+        }
+        vec![workload]
+    } else {
+        let mut inputs = Vec::with_capacity(sources.len());
+        for source in sources {
+            let mut input = Vec::new();
+            source.open()?.read_to_end(&mut input)?;
+            inputs.push(input);
+        }
+        inputs
+    };
+    let benchmark =
+        Benchmark::new(inputs, arguments.input_schema).with_parse_threads(arguments.parse_threads);
+    let mut runs = Vec::new();
+    for run in 1..=bench.runs {
+        let mut bank = config.bank()?;
+        let timings = benchmark.run(&mut bank, |bank| {
+            write_accounts(
+                bank,
+                precision,
+                &arguments.output_columns,
+                arguments.sort,
+                &mut *arguments.output_format.writer(io::sink()),
+            )
+        })?;
+        println!("run {}: {}", run, timings);
+        runs.push(timings);
+    }
+    if let Some(mean) = BenchRun::mean(&runs) {
+        println!("mean: {}", mean);
+    }
+    match peak_rss() {
+        Some(bytes) => println!("peak rss: {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+        None => println!("peak rss: unknown"),
+    }
+    Ok(())
+}
+
+/// Print every row of the input touching the transaction being explained, with the input it is
+/// in, and then where the transaction ended up.
+fn explain_transaction(
+    sources: &[InputSource],
+    mut bank: Bank,
+    transaction: TransactionId,
+    precision: PrecisionTable,
+    arguments: &Arguments,
+) -> Result<(), TransactorError> {
+    let mut explainer = Explainer::new(transaction, precision);
+    let mut events = 0;
+    let mut import = statement_import(arguments)?;
+    for source in sources {
+        let reader = open_source(source, import.as_mut(), arguments)?;
+        for result in reader {
+            if let Some(event) = explainer.replay(&mut bank, result) {
+                println!("{}: {}", source.name(), event);
+                events += 1;
+            }
+        }
+    }
+    if events == 0 {
+        println!("No row touches transaction {}", transaction.0);
+    }
+    for client in explainer.clients() {
+        let state = bank
+            .transaction_state(client, transaction)
+            .map_or("was never recorded".to_string(), |state| {
+                format!("ends {}", state)
+            });
+        let locked = bank.get_account(client).is_some_and(Account::is_locked);
+        println!(
+            "Client {}: transaction {} {}{}",
+            client.0,
+            transaction.0,
+            state,
+            if locked { ", account locked" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+/// Open the accounts saved in a checkpoint, returning where in the input to carry on from.
+fn restore_checkpoint(
+    checkpoint: &str,
+    bank: &mut Bank,
+) -> Result<InputCheckpoint, TransactorError> {
+    bank.restore(BufReader::new(File::open(checkpoint)?))?
+        .ok_or_else(|| InvalidSnapshot(format!("{} is not a checkpoint", checkpoint)))
+}
+
+/// Saves the state reached, with the position in the input, to the checkpoint file.
+struct Checkpoints<'a> {
+    path: &'a str,
+    /// The name of the input being read.
+    input: String,
+    interval: u64,
+    rows: u64,
+}
+
+impl Checkpoints<'_> {
+    /// Count a row about to be applied, whether a checkpoint is due before it.
+    fn is_due(&mut self) -> bool {
+        let due = self.rows > 0 && self.rows.is_multiple_of(self.interval);
+        self.rows += 1;
+        due
+    }
+
+    fn save(&self, bank: &Bank, position: RecordPosition) -> Result<(), TransactorError> {
+        let mut file = AtomicFile::create(self.path)?;
+        bank.save_checkpoint(
+            &mut file,
+            &InputCheckpoint {
+                input: self.input.clone(),
+                line: position.line,
+                offset: position.offset,
+            },
+        )?;
+        file.commit()?;
+        info!(path = self.path, input = %self.input, line = position.line, "checkpoint saved");
+        Ok(())
+    }
+}
+
+/// What is kept track of alongside the accounts while applying the input.
+struct Tracking<'a> {
+    stats: &'a mut RunStats,
+    /// The model checked against the bank with --verify.
+    model: Option<&'a mut ReferenceModel>,
+    /// The summary of the run for report --aggregate.
+    aggregate: Option<&'a mut AggregateReport>,
+}
+
+/// Apply each input in turn and then any dispute outcomes to the bank, writing the dispute SLA
+/// report if asked to. Reading carries on from `resume_from` if given. On a shutdown request this
+/// stops early, returning the position of the first record not applied.
+fn process_input(
+    sources: &[InputSource],
+    bank: &mut Bank,
+    precision: &PrecisionTable,
+    arguments: &Arguments,
+    shutdown: &Shutdown,
+    resume_from: Option<InputCheckpoint>,
+    tracking: Tracking,
+) -> Result<Option<InputCheckpoint>, TransactorError> {
+    let (first, mut resume_at) = match &resume_from {
+        Some(checkpoint) => {
+            let first = sources
+                .iter()
+                .position(|source| source.name() == checkpoint.input)
+                .ok_or_else(|| {
+                    InvalidConfig(format!(
+                        "the checkpoint is of {}, which is not one of the inputs",
+                        checkpoint.input
+                    ))
+                })?;
+            let position = RecordPosition::new(checkpoint.line).with_offset(checkpoint.offset);
+            (first, Some(position))
+        }
+        None => (0, None),
+    };
+    let mut outcomes = match &arguments.dispute_outcomes {
+        Some(dispute_outcomes) => Some(OutcomeFeed::new(
+            open_input(
+                InputFile::open(
+                    dispute_outcomes,
+                    arguments
+                        .compression
+                        .unwrap_or_else(|| Compression::from_extension(dispute_outcomes)),
+                )?,
+                arguments,
+            )?,
+            arguments.outcome_buffer,
+        )),
+        None => None,
+    };
+    let window = match (arguments.from, arguments.to) {
+        (None, None) => None,
+        (from, to) => Some(TimeWindow {
+            from: from.map(Timestamp),
+            to: to.map(Timestamp),
+        }),
+    };
+    let mut enactor = Enactor {
+        bank,
+        precision,
+        default_batch: BatchId::new(""),
+        window,
+        audit: match &arguments.audit_log {
+            // A resumed run adds to the audit log of the run it carries on from
+            Some(audit_log) => Some(AuditLog::new(sink(BufWriter::new(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(resume_from.is_some())
+                    .truncate(resume_from.is_none())
+                    .open(audit_log)?,
+            )))),
+            None => None,
+        },
+        ledger: arguments.ledger.as_deref().map(open_ledger).transpose()?,
+        journal: match &arguments.journal {
+            Some(journal) => Some(Journal::in_format(
+                sink(BufWriter::new(File::create(journal)?)),
+                arguments.journal_format,
+            )),
+            None => None,
+        },
+        history: match &arguments.history {
+            Some(history) => Some(History::new(
+                arguments
+                    .history_format
+                    .writer(sink(BufWriter::new(File::create(history)?))),
+            )?),
+            None => None,
+        },
+        timers: arguments.dispute_sla.map(DisputeTimers::new),
+        sla_webhook: arguments
+            .sla_webhook
+            .as_deref()
+            .map(SlaWebhook::new)
+            .transpose()?,
+        authorization_expiry: arguments.authorization_expiry,
+        rules: arguments
+            .rules
+            .as_ref()
+            .map(RuleEngine::from_path)
+            .transpose()?,
+        pacer: arguments.pace.map(Pacer::new),
+        stats: tracking.stats,
+        model: tracking.model,
+        aggregate: tracking.aggregate,
+        check_invariants: arguments.check_invariants == Some(InvariantChecks::Every),
+        row_errors: arguments.max_errors.map(RowErrors::new),
+        error_report: arguments.error_report.as_deref(),
+        metrics_server: match &arguments.metrics_listen {
+            Some(address) => {
+                let server = MetricsServer::bind(address)?;
+                info!(address = %server.address(), "serving metrics");
+                Some(server)
+            }
+            None => None,
+        },
+    };
+    let mut checkpoints = arguments
+        .checkpoint
+        .as_deref()
+        .map(|checkpoint| Checkpoints {
+            path: checkpoint,
+            input: String::new(),
+            interval: arguments.checkpoint_interval.max(1),
+            rows: 0,
+        });
+    let mut import = statement_import(arguments)?;
+    let mut interrupted = None;
+    for source in &sources[first..] {
+        let name = source.name();
+        info!(input = %name, "reading input");
+        enactor.default_batch = BatchId::new(arguments.batch_id.as_deref().unwrap_or(&name));
+        let mut reader = open_source(source, import.as_mut(), arguments)?;
+        if let Some(position) = resume_at.take() {
+            reader.resume_from(position)?;
+        }
+        if let Some(checkpoints) = checkpoints.as_mut() {
+            checkpoints.input = name.clone();
+        }
+        let stopped = enact_transactions(
+            reader,
+            arguments.parse_threads,
+            outcomes.as_mut(),
+            &mut enactor,
+            shutdown,
+            checkpoints.as_mut(),
+        )?;
+        if let Some(position) = stopped {
+            interrupted = Some(InputCheckpoint {
+                input: name,
+                line: position.line,
+                offset: position.offset,
+            });
+            break;
+        }
+    }
+    if let (Some(outcomes), None) = (outcomes, &interrupted) {
+        for outcome in outcomes.remaining() {
+            enactor.enact(outcome).or_else(|e| enactor.skip(e))?;
+        }
+    }
+    if let Some(timers) = enactor.finish()? {
+        if let Some(report) = &arguments.dispute_sla_report {
+            write_open_disputes(&timers.open_disputes(), File::create(report)?)?;
+        }
+    }
+    Ok(interrupted)
+}
+
+/// A single byte character, or `\t` or `tab` for a tab.
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "Invalid delimiter {}, expected a single character or \\t",
+            value
+        )),
+    }
+}
+
+/// The import of bank statements asked for with --import.
+fn statement_import(arguments: &Arguments) -> Result<Option<StatementImport>, TransactorError> {
+    match (arguments.import, arguments.import_client) {
+        (Some(format), Some(client)) => Ok(Some(
+            StatementImport::new(format, ClientId(client))
+                .with_first_transaction(TransactionId(arguments.import_first_tx)),
+        )),
+        // Checked by `Config::validate`
+        _ => Ok(None),
+    }
+}
+
+/// Open an input with the options of the command line, converting it from a bank statement
+/// first with --import, or decoding it as Avro messages with --avro-schema or --avro-registry or
+/// as protobuf messages with --protobuf.
+fn open_source(
+    source: &InputSource,
+    import: Option<&mut StatementImport>,
+    arguments: &Arguments,
+) -> Result<InputReader<InputFile>, TransactorError> {
+    let input = source.open()?;
+    let input = match import {
+        Some(import) => InputFile::from_bytes(import.convert(input)?),
+        // Avro files hold their own schema and are not framed
+        None if matches!(source, InputSource::Avro { .. }) => input,
+        None if arguments.protobuf => protobuf_messages(input)?,
+        None => avro_messages(input, arguments)?,
+    };
+    open_input(input, arguments)
+}
+
+/// The input decoded as Avro messages framed for a schema registry, if it is.
+#[cfg(feature = "avro")]
+fn avro_messages(input: InputFile, arguments: &Arguments) -> Result<InputFile, TransactorError> {
+    let schemas = match (&arguments.avro_schema, &arguments.avro_registry) {
+        (Some(schema), _) => AvroSchemas::with_schema(&fs::read_to_string(schema)?)?,
+        (None, Some(registry)) => AvroSchemas::from_registry(registry)?,
+        (None, None) => return Ok(input),
+    };
+    InputFile::avro_messages(input, schemas)
+}
+
+#[cfg(not(feature = "avro"))]
+fn avro_messages(input: InputFile, arguments: &Arguments) -> Result<InputFile, TransactorError> {
+    match arguments.avro_schema.is_some() || arguments.avro_registry.is_some() {
+        true => Err(InvalidConfig(
+            "reading Avro messages needs the avro feature".to_string(),
+        )),
+        false => Ok(input),
+    }
+}
+
+#[cfg(feature = "protobuf")]
+fn protobuf_messages(input: InputFile) -> Result<InputFile, TransactorError> {
+    InputFile::protobuf_messages(input)
+}
+
+#[cfg(not(feature = "protobuf"))]
+fn protobuf_messages(_input: InputFile) -> Result<InputFile, TransactorError> {
+    Err(InvalidConfig(
+        "reading protobuf messages needs the protobuf feature".to_string(),
+    ))
+}
+
+/// Read the input with the format and options of the command line.
+fn open_input<R: io::Read>(
+    input: R,
+    arguments: &Arguments,
+) -> Result<InputReader<R>, TransactorError> {
+    let mut format = InputFormat::default();
+    if arguments.no_header {
+        format = format.without_headers();
+    }
+    if arguments.strict_schema {
+        format = format.with_strict_schema();
+    }
+    if let Some(delimiter) = arguments.delimiter {
+        format = format.with_delimiter(delimiter);
+    }
+    if let Some(column_map) = &arguments.column_map {
+        format = format.with_column_map(ColumnMap::from_path(column_map)?);
+    }
+    let reader = InputReader::from_reader_with_format(input, arguments.input_schema, &format)?;
+    let mut reader = reader.with_dispute_amounts(arguments.dispute_amounts);
+    if arguments.lenient_types {
+        reader = reader.with_lenient_types();
+    }
+    if arguments.only_clients.is_some() || arguments.exclude_clients.is_some() {
+        let mut filter = ClientFilter::new();
+        if let Some(only) = &arguments.only_clients {
+            filter = filter.with_only(only.clone());
+        }
+        if let Some(excluded) = &arguments.exclude_clients {
+            filter = filter.with_excluded(excluded.clone());
+        }
+        reader = reader.with_client_filter(filter);
+    }
+    Ok(match arguments.max_precision {
+        Some(decimal_places) => {
+            reader.with_max_precision(decimal_places, arguments.excess_precision)
+        }
+        None => reader,
+    })
+}
+
+/// Write the final state of every account to stdout, or split into partitions if asked to. Only
+/// the accounts changed since `loaded` are written if given.
+fn write_account_report(
+    bank: &Bank,
+    loaded: Option<&AccountVersions>,
+    precision: &PrecisionTable,
+    arguments: &Arguments,
+) -> Result<(), TransactorError> {
+    match &arguments.partition_output_by {
+        Some(partitioning) => write_partitioned_accounts(
+            bank,
+            precision,
+            &arguments.output_columns,
+            arguments.output_format,
+            partitioning,
+            Path::new(arguments.partition_dir.as_deref().unwrap_or("partitions")),
+        ),
+        None => write_output(arguments, |output| {
+            let report = &mut *arguments.output_format.writer(output);
+            let columns = &arguments.output_columns;
+            match loaded {
+                Some(loaded) => {
+                    write_changed_accounts(bank, loaded, precision, columns, arguments.sort, report)
+                }
+                None => write_accounts(bank, precision, columns, arguments.sort, report),
+            }
+        }),
+    }
+}
+
+/// Operations only accepted from the administrative operations file, never from the transaction feed.
 #[derive(Debug, Deserialize)]
-struct TransactionRecord {
-    r#type: TransactionRecordType,
+struct AdminRecord {
+    r#type: AdminRecordType,
     client: u16,
-    tx: u32,
-    amount: Option<Decimal>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
-enum TransactionRecordType {
-    DEPOSIT,
-    WITHDRAWAL,
-    DISPUTE,
-    RESOLVE,
-    CHARGEBACK,
+enum AdminRecordType {
+    Unlock,
 }
 
 #[derive(Debug, Serialize)]
-struct AccountRecord {
+struct AccountChangeRecord {
+    client: u16,
+    available_before: Decimal,
+    available_after: Decimal,
+    held_before: Decimal,
+    held_after: Decimal,
+    locked_before: bool,
+    locked_after: bool,
+}
+
+/// Apply every command in the input to the bank. With a dispute outcome feed the outcome of each
+/// dispute is applied straight after it, outcomes whose dispute is not found within the feed buffer
+/// are applied once the input is exhausted.
+fn enact_transactions(
+    reader: InputReader<InputFile>,
+    parse_threads: usize,
+    mut outcomes: Option<&mut OutcomeFeed<InputFile>>,
+    enactor: &mut Enactor,
+    shutdown: &Shutdown,
+    mut checkpoints: Option<&mut Checkpoints>,
+) -> Result<Option<RecordPosition>, TransactorError> {
+    #[cfg(feature = "failure-injection")]
+    let reader = match fault_config() {
+        Some(faults) => reader.with_faults(faults.malformed_records()),
+        None => reader,
+    };
+    let records: Box<dyn Iterator<Item = Result<InputRecord, TransactorError>> + Send> =
+        match parse_threads {
+            0 => Box::new(reader),
+            threads => Box::new(reader.parallel(threads)),
+        };
+    let mut records = match enactor.metrics_server {
+        Some(_) => Records::read_ahead(records),
+        None => Records::Inline(records),
+    };
+    while let Some(result) = records.next(enactor) {
+        let due = checkpoints
+            .as_mut()
+            .is_some_and(|checkpoints| checkpoints.is_due());
+        let stopping = shutdown.is_requested();
+        // Rows that could not be read at all have no position to carry on from
+        let position = match &result {
+            Ok(record) => Some(record.position),
+            Err(Record { position, .. }) => Some(*position),
+            Err(_) => None,
+        };
+        if let Some(position) = position.filter(|_| due || stopping) {
+            if let Some(checkpoints) = &checkpoints {
+                checkpoints.save(enactor.bank, position)?;
+            }
+            if stopping {
+                return Ok(Some(position));
+            }
+        }
+        let disputed = match enactor.enact(result) {
+            Ok(disputed) => disputed,
+            Err(e) => enactor.skip(e)?,
+        };
+        if let (Some(outcomes), Some(disputed)) = (outcomes.as_mut(), disputed) {
+            if let Some(outcome) = outcomes.next_outcome(disputed).transpose() {
+                enactor.enact(outcome).or_else(|e| enactor.skip(e))?;
+            }
+        }
+        enactor.publish_metrics(false);
+    }
+    Ok(None)
+}
+
+/// The records of an input, read as they are applied or read ahead on a thread of their own so
+/// the metrics served can be brought up to date while the input has nothing more to read, such as
+/// a stream gone quiet.
+enum Records {
+    Inline(Box<dyn Iterator<Item = Result<InputRecord, TransactorError>> + Send>),
+    ReadAhead(Receiver<Result<InputRecord, TransactorError>>),
+}
+
+impl Records {
+    /// The records read ahead at most.
+    const READ_AHEAD: usize = 1024;
+
+    fn read_ahead(
+        records: Box<dyn Iterator<Item = Result<InputRecord, TransactorError>> + Send>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(Self::READ_AHEAD);
+        thread::spawn(move || {
+            for record in records {
+                // Nothing is waiting for the records once the run stops early
+                if sender.send(record).is_err() {
+                    break;
+                }
+            }
+        });
+        Records::ReadAhead(receiver)
+    }
+
+    /// The next record, publishing the metrics whenever one is a while coming.
+    fn next(&mut self, enactor: &mut Enactor) -> Option<Result<InputRecord, TransactorError>> {
+        match self {
+            Records::Inline(records) => records.next(),
+            Records::ReadAhead(receiver) => loop {
+                match receiver.recv_timeout(PUBLISH_INTERVAL) {
+                    Ok(record) => return Some(record),
+                    Err(RecvTimeoutError::Timeout) => enactor.publish_metrics(true),
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            },
+        }
+    }
+}
+
+/// Applies input rows to the bank, recording them in the audit log if there is one.
+struct Enactor<'a> {
+    bank: &'a mut Bank,
+    precision: &'a PrecisionTable,
+    /// The batch of rows without a batch of their own.
+    default_batch: BatchId,
+    /// The period of the rows applied, every row is if None.
+    window: Option<TimeWindow>,
+    audit: Option<AuditLog<Box<dyn Write>>>,
+    ledger: Option<LedgerWriter<Box<dyn Write>>>,
+    journal: Option<Journal<Box<dyn Write>>>,
+    history: Option<History<'static>>,
+    timers: Option<DisputeTimers>,
+    /// Where escalations are posted as well as reported, see `--sla-webhook`.
+    sla_webhook: Option<SlaWebhook>,
+    /// The seconds an authorization may stay unsettled.
+    authorization_expiry: Option<u64>,
+    rules: Option<RuleEngine>,
+    pacer: Option<Pacer>,
+    stats: &'a mut RunStats,
+    model: Option<&'a mut ReferenceModel>,
+    aggregate: Option<&'a mut AggregateReport>,
+    /// Whether to check the account of each row after it is applied.
+    check_invariants: bool,
+    /// The errors of rows skipped rather than ending the run, None if every error ends it.
+    row_errors: Option<RowErrors>,
+    /// Where to write the row errors, stderr if None.
+    error_report: Option<&'a str>,
+    metrics_server: Option<MetricsServer>,
+}
+
+impl Enactor<'_> {
+    /// Whether the row falls outside the window and is not applied. Deposits and withdrawals
+    /// before it are recorded as history without changing any balance, other rows outside it are
+    /// skipped.
+    fn outside_window(
+        &mut self,
+        window: TimeWindow,
+        record: &InputRecord,
+    ) -> Result<bool, TransactorError> {
+        let timestamp = record.timestamp.ok_or_else(|| {
+            InvalidConfig("--from and --to need every row to have a timestamp".to_string())
+                .at(record.position)
+        })?;
+        match window.position(timestamp) {
+            WindowPosition::Within => Ok(false),
+            WindowPosition::After => Ok(true),
+            WindowPosition::Before => {
+                if let Command::Deposit {
+                    client,
+                    transaction,
+                }
+                | Command::Withdrawal {
+                    client,
+                    transaction,
+                } = record.command
+                {
+                    self.bank
+                        .in_account(client, record.account, |bank| {
+                            bank.record_history(client, transaction)
+                        })
+                        .map_err(|e| e.at(record.position))?;
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Apply one input row, returning the transaction it disputed if it opened a dispute.
+    fn enact(
+        &mut self,
+        result: Result<InputRecord, TransactorError>,
+    ) -> Result<Option<TransactionId>, TransactorError> {
+        let mut record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                if let Some(audit) = self.audit.as_mut() {
+                    audit.record_unreadable(&e)?;
+                }
+                return Err(e);
+            }
+        };
+        let _row = debug_span!("row", line = record.position.line).entered();
+        if let Some(window) = self.window {
+            if self.outside_window(window, &record)? {
+                return Ok(None);
+            }
+        }
+        if let Some(pacer) = self.pacer.as_mut() {
+            pacer.wait(record.timestamp);
+        }
+        if let Some(stated) = record.stated_amount {
+            verify_stated_amount(&record, stated, self.bank);
+        }
+        if let (Some(timers), Some(timestamp)) = (self.timers.as_mut(), record.timestamp) {
+            for breach in timers.advance(timestamp) {
+                warn!(
+                    position = %record.position,
+                    client = breach.client.0,
+                    tx = breach.transaction.0,
+                    opened = breach.opened.0,
+                    "dispute SLA breached"
+                );
+                if let Some(audit) = self.audit.as_mut() {
+                    audit.record_escalation(&breach, record.position.line)?;
+                }
+                // An alert that cannot be delivered is no reason to stop applying the input
+                if let Some(Err(e)) = self
+                    .sla_webhook
+                    .as_ref()
+                    .map(|webhook| webhook.send(&breach))
+                {
+                    warn!(error = %e, tx = breach.transaction.0, "dispute SLA escalation not posted");
+                }
+            }
+        }
+        let batch = record
+            .batch
+            .get_or_insert_with(|| self.default_batch.clone())
+            .clone();
+        self.bank.set_batch(Some(batch));
+        if let (Some(expiry), Some(timestamp)) = (self.authorization_expiry, record.timestamp) {
+            let cutoff = Timestamp(timestamp.0.saturating_sub(expiry));
+            let mut expiring = Vec::new();
+            if self.journal.is_some() {
+                let mut clients: Vec<ClientId> = self
+                    .bank
+                    .expiring_authorizations(cutoff)
+                    .into_iter()
+                    .map(|(client, _)| client)
+                    .collect();
+                clients.dedup();
+                for client in clients {
+                    expiring.push(ClientBalances::of(self.bank, client, &[AccountName::MAIN]));
+                }
+            }
+            for (client, transaction) in self.bank.expire_authorizations(cutoff)? {
+                if let Some(audit) = self.audit.as_mut() {
+                    audit.record_expiry(client, transaction, record.position.line)?;
+                }
+            }
+            if let Some(journal) = self.journal.as_mut() {
+                for before in &expiring {
+                    journal.post_expiry(&record, before, self.bank)?;
+                }
+            }
+        }
+        let violations = match &self.rules {
+            Some(rules) => {
+                rules.check(&record.command, record.timestamp, self.bank, record.account)
+            }
+            None => Vec::new(),
+        };
+        let rejected_by = violations
+            .iter()
+            .find(|violation| violation.action == RuleAction::Reject);
+        let before = self
+            .journal
+            .as_ref()
+            .map(|_| ClientBalances::of_record(self.bank, &record));
+        let result = match rejected_by {
+            Some(violation) => Err(RuleViolation {
+                rule: violation.rule.clone(),
+            }),
+            None => enact_command(record.command, record.account, self.bank, self.precision),
+        };
+        self.stats.record(record.command.name(), &result);
+        if let Some(aggregate) = self.aggregate.as_mut() {
+            aggregate.record(&record, &result)?;
+        }
+        if let (Some(model), None) = (self.model.as_mut(), rejected_by) {
+            // Amounts too precise for their currency never reach the bank, nor the model
+            if !record.account.is_main() {
+                return Err(InvalidConfig(
+                    "--verify only models the main account of each client".to_string(),
+                )
+                .at(record.position));
+            }
+            if !matches!(result, Err(ExcessPrecision { .. })) {
+                model
+                    .verify(record.command, &result)
+                    .map_err(|e| e.at(record.position))?;
+            }
+        }
+        if let (true, Ok(Outcome::Applied)) = (self.check_invariants, &result) {
+            self.bank
+                .check_account_invariants(record.command.client())
+                .map_err(|e| e.at(record.position))?;
+        }
+        if let (Some(rules), Ok(Outcome::Applied)) = (self.rules.as_mut(), &result) {
+            rules.record(&record.command, record.timestamp);
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(&record, &result, self.bank)?;
+        }
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.record(&record, &result)?;
+        }
+        if let (Some(journal), Some(before)) = (self.journal.as_mut(), &before) {
+            journal.post_record(&record, &result, before, self.bank)?;
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.record(&record, &result)?;
+        }
+        if rejected_by.is_none() {
+            for violation in &violations {
+                match self.audit.as_mut() {
+                    Some(audit) => audit.record_flag(&record, &violation.rule)?,
+                    None => {
+                        warn!(position = %record.position, rule = %violation.rule, "flagged by rule")
+                    }
+                }
+            }
+        }
+        if let (Some(timers), Ok(Outcome::Applied)) = (self.timers.as_mut(), &result) {
+            match record.command {
+                Command::Dispute {
+                    client,
+                    transaction,
+                    ..
+                } => timers.opened(client, transaction, record.timestamp),
+                Command::Resolve {
+                    client,
+                    transaction,
+                }
+                | Command::Chargeback {
+                    client,
+                    transaction,
+                    ..
+                } => timers.closed(client, transaction),
+                _ => {}
+            }
+        }
+        let disputed = match (&record.command, &result) {
+            (Command::Dispute { transaction, .. }, Ok(Outcome::Applied)) => Some(*transaction),
+            _ => None,
+        };
+        report_suspicious(result, record.position).map_err(|e| e.at(record.position))?;
+        Ok(disputed)
+    }
+
+    /// Skip the row the error is of if row errors are being kept, see `RowErrors::push`. The
+    /// errors are written once they pass the limit, as the run ends there.
+    fn skip(&mut self, error: TransactorError) -> Result<Option<TransactionId>, TransactorError> {
+        let row_errors = match self.row_errors.as_mut() {
+            Some(row_errors) => row_errors,
+            None => return Err(error),
+        };
+        let message = error.to_string();
+        match row_errors.push(error) {
+            Err(e @ TooManyErrors { .. }) => {
+                self.write_row_errors()?;
+                Err(e)
+            }
+            Err(e) => Err(e),
+            Ok(()) => {
+                warn!(error = %message, skipped = row_errors.len(), "row skipped");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Publish the metrics to the metrics server if there is one, once they are due unless
+    /// `always`.
+    fn publish_metrics(&mut self, always: bool) {
+        if let Some(server) = self.metrics_server.as_mut() {
+            if always || server.is_due() {
+                if let Some(metrics) = self.bank.metrics() {
+                    server.publish(&metrics);
+                }
+            }
+        }
+    }
+
+    fn write_row_errors(&self) -> Result<(), TransactorError> {
+        let row_errors = match &self.row_errors {
+            Some(row_errors) => row_errors,
+            None => return Ok(()),
+        };
+        match self.error_report {
+            Some(path) => row_errors.write_json(BufWriter::new(File::create(path)?)),
+            None => row_errors.write_json(io::stderr().lock()),
+        }
+    }
+
+    /// Publish the metrics a last time, flush the audit log, ledger, journal and history, handing
+    /// the totals of the journal to the summary of the run, and write the row errors, returning the
+    /// dispute timers for reporting.
+    fn finish(mut self) -> Result<Option<DisputeTimers>, TransactorError> {
+        self.publish_metrics(true);
+        self.write_row_errors()?;
+        if let Some(mut audit) = self.audit {
+            audit.flush()?;
+        }
+        if let Some(mut ledger) = self.ledger {
+            ledger.flush()?;
+        }
+        if let Some(mut journal) = self.journal {
+            if let Some(aggregate) = self.aggregate.as_mut() {
+                aggregate.journal = Some(journal.totals());
+            }
+            journal.flush()?;
+        }
+        if let Some(history) = self.history {
+            history.finish()?;
+        }
+        Ok(self.timers)
+    }
+}
+
+/// Open the ledger to append to, carrying on the chain of the entries already in it once they
+/// have been checked.
+fn open_ledger(path: &str) -> Result<LedgerWriter<Box<dyn Write>>, TransactorError> {
+    let head = match File::open(path) {
+        Ok(file) => verify_ledger(BufReader::new(file))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(LedgerWriter::continuing(sink(BufWriter::new(file)), head))
+}
+
+fn enact_command(
+    command: Command,
+    account: AccountName,
+    bank: &mut Bank,
+    precision: &PrecisionTable,
+) -> Result<Outcome, TransactorError> {
+    if let Command::Deposit { transaction, .. }
+    | Command::Withdrawal { transaction, .. }
+    | Command::Authorize { transaction, .. }
+    | Command::Hold { transaction, .. }
+    | Command::Move { transaction, .. } = command
+    {
+        precision.validate(transaction.amount(), transaction.currency())?;
+    }
+    command.apply_in(bank, account)
+}
+
+/// Logs accounts put under review for going over a risk limit.
+struct RiskAlerts;
+
+impl BankObserver for RiskAlerts {
+    fn risk_limit_exceeded(&mut self, account: &Account, limit: RiskLimit) {
+        warn!(
+            client = account.client_id.0,
+            %limit,
+            "risk limit exceeded, account under review"
+        );
+    }
+}
+
+/// The settings given as flags, which override those in the config file.
+fn command_line_config(arguments: &Arguments, files: bool) -> Config {
+    let path = |path: &Option<String>| path.as_ref().map(PathBuf::from);
+    Config {
+        disputes: DisputeSettings {
+            dispute_policy: arguments.dispute_policy,
+            representment_policy: arguments.representment_policy,
+            recovery_policy: arguments.recovery_policy,
+            max_dispute_cycles: arguments.max_dispute_cycles,
+        },
+        accounts: AccountSettings {
+            refusal_policy: arguments.refusal_policy,
+            locked_account_policy: arguments.locked_account_policy,
+            status_rules: Some(arguments.status_rule.clone()).filter(|rules| !rules.is_empty()),
+        },
+        limits: LimitSettings {
+            overdraft_limit: arguments.overdraft_limit,
+            overdraft_limits: path(&arguments.overdraft_limits),
+            max_chargebacks: arguments.max_chargebacks,
+            max_open_disputes: arguments.max_open_disputes,
+            max_amount: arguments.max_amount,
+        },
+        transactions: TransactionSettings {
+            tx_index: arguments.tx_index.then_some(true),
+            global_tx_ids: arguments.global_tx_ids.then_some(true),
+            spill_dir: path(&arguments.spill_dir),
+            spill_threshold: arguments.spill_threshold,
+        },
+        currencies: CurrencySettings {
+            currency_config: path(&arguments.currency_config),
+            rounding: arguments.rounding,
+        },
+        fees: Some(arguments.fee.clone()).filter(|fees| !fees.is_empty()),
+        clients: path(&arguments.clients),
+        input: InputSettings {
+            files,
+            state: path(&arguments.state),
+            initial_balances: path(&arguments.initial_balances),
+            checkpoint: path(&arguments.checkpoint),
+            resume: arguments.resume,
+            dispute_outcomes: path(&arguments.dispute_outcomes),
+            import: arguments.import,
+            import_client: arguments.import_client,
+            avro_schema: path(&arguments.avro_schema),
+            avro_registry: arguments.avro_registry.clone(),
+            protobuf: arguments.protobuf,
+            from: arguments.from,
+            to: arguments.to,
+        },
+        output: OutputSettings {
+            changed_only: arguments.output_changed_only,
+            partition_by: arguments.partition_output_by.clone(),
+            partition_dir: path(&arguments.partition_dir),
+            journal: path(&arguments.journal),
+            history: path(&arguments.history),
+            max_errors: arguments.max_errors,
+            error_report: path(&arguments.error_report),
+        },
+        sla: SlaSettings {
+            dispute_sla: arguments.dispute_sla,
+            report: path(&arguments.dispute_sla_report),
+            webhook: arguments.sla_webhook.clone(),
+        },
+        operations: OperationSettings {
+            recurring: path(&arguments.recurring),
+            as_of: arguments.as_of,
+            admin_ops: path(&arguments.admin_ops),
+            admin_dry_run: arguments.admin_dry_run,
+            authorization_expiry: arguments.authorization_expiry,
+        },
+        verify: arguments.verify,
+    }
+}
+
+/// An account's starting state, in the shape of the account report so a previous run's report
+/// can be used. Any other report columns are ignored.
+#[derive(Debug, Deserialize)]
+struct InitialBalanceRecord {
     client: u16,
     available: Decimal,
     held: Decimal,
-    total: Decimal,
+    total: Option<Decimal>,
     locked: bool,
+    currency: Option<String>,
+}
+
+/// Open the accounts listed in the initial balances file. A total, if given, must be the sum of
+/// the available and held funds.
+fn open_initial_accounts(filename: &str, bank: &mut Bank) -> Result<(), TransactorError> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(filename)?;
+    let headers = reader.headers()?.clone();
+    let mut row = StringRecord::new();
+    while reader.read_record(&mut row)? {
+        let mut position = RecordPosition::new(row.position().map_or(0, |p| p.line()));
+        let record: InitialBalanceRecord = row
+            .deserialize(Some(&headers))
+            .map_err(|e| TransactorError::from(e).at(position))?;
+        position.client = Some(ClientId(record.client));
+        let total = record
+            .available
+            .checked_add(record.held)
+            .ok_or_else(|| Overflow.at(position))?;
+        if record.total.is_some_and(|stated| stated != total) {
+            return Err(InvalidConfig(format!(
+                "total is not the sum of available {} and held {}",
+                record.available, record.held
+            ))
+            .at(position));
+        }
+        let currency = match record.currency.as_deref() {
+            Some(currency) if !currency.is_empty() => Some(
+                currency
+                    .parse()
+                    .map_err(|_| InvalidCurrency(currency.to_string()).at(position))?,
+            ),
+            _ => None,
+        };
+        bank.open_account(
+            ClientId(record.client),
+            record.available,
+            record.held,
+            record.locked,
+            currency,
+        )
+        .map_err(|e| e.at(position))?;
+    }
+    Ok(())
 }
 
-fn enact_transactions(filename: String) -> Result<(), TransactorError> {
+fn enact_admin_operations(filename: &str, bank: &mut Bank) -> Result<(), TransactorError> {
     let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(filename)?;
-    let mut bank: Bank = Bank::new();
-    for result in reader.deserialize() {
-        let record: TransactionRecord = result?;
+    let headers = reader.headers()?.clone();
+    let mut row = StringRecord::new();
+    while reader.read_record(&mut row)? {
+        let mut position = RecordPosition::new(row.position().map_or(0, |p| p.line()));
+        let record: AdminRecord = row
+            .deserialize(Some(&headers))
+            .map_err(|e| TransactorError::from(e).at(position))?;
+        position.client = Some(ClientId(record.client));
         match record.r#type {
-            TransactionRecordType::DEPOSIT => {
-                let amount = record.amount.ok_or_else(missing_data)?;
-                if amount < Decimal::zero() {
-                    return Err(InvalidData(
-                        "Deposit of negative amount attempted".to_string(),
-                    ));
-                } else {
-                    bank.transact(
-                        ClientId(record.client),
-                        Transaction::new(TransactionId(record.tx), amount),
-                    )?;
-                }
-            }
-            TransactionRecordType::WITHDRAWAL => {
-                let amount = record.amount.ok_or_else(missing_data)?;
-                if amount < Decimal::zero() {
-                    return Err(InvalidData(
-                        "Withdrawal of a negative amount attempted".to_string(),
-                    ));
-                } else {
-                    bank.transact(
-                        ClientId(record.client),
-                        Transaction::new(TransactionId(record.tx), -amount),
-                    )?;
-                }
+            AdminRecordType::Unlock => {
+                bank.unlock(ClientId(record.client))
+                    .map_err(|e| e.at(position))?;
             }
-            TransactionRecordType::DISPUTE => {
-                let (client, transaction) = parse_dispute_type_record(record)?;
-                bank.dispute_transaction(client, transaction)?;
-            }
-            TransactionRecordType::RESOLVE => {
-                let (client, transaction) = parse_dispute_type_record(record)?;
-                bank.resolve_disputed_transaction(client, transaction)?;
-            }
-            TransactionRecordType::CHARGEBACK => {
-                let (client, transaction) = parse_dispute_type_record(record)?;
-                bank.chargeback(client, transaction)?;
-            }
-        }
-    }
-    let mut writer = Writer::from_writer(std::io::stdout());
-    for account in bank.get_accounts() {
-        writer.serialize(AccountRecord {
-            client: account.client_id.0,
-            available: account.available.round_dp(4).normalize(),
-            held: account.held.round_dp(4).normalize(),
-            total: account
-                .available
-                .checked_add(account.held)
-                .ok_or_else(|| Overflow)?
-                .round_dp(4)
-                .normalize(),
-            locked: account.locked,
-        })?;
+        }
     }
     Ok(())
 }
 
-fn parse_dispute_type_record(
-    record: TransactionRecord,
-) -> Result<(ClientId, TransactionId), TransactorError> {
-    if record.amount.is_some() {
-        return Err(InvalidData(
-            "Found amount in non-transaction type record".to_string(),
-        ));
-    } else {
-        Ok((ClientId(record.client), TransactionId(record.tx)))
+/// Warn when the amount given on a dispute, resolve or chargeback row is not the amount of the
+/// transaction it refers to. Rows referring to unknown transactions are left for the bank to ignore.
+fn verify_stated_amount(record: &InputRecord, stated: Decimal, bank: &Bank) {
+    let recorded = record
+        .command
+        .transaction_id()
+        .and_then(|transaction| bank.transaction(record.command.client(), transaction));
+    if let Some(recorded) = recorded {
+        if recorded.amount().abs() != stated.abs() {
+            warn!(
+                position = %record.position,
+                %stated,
+                recorded = %recorded.amount().abs(),
+                "stated amount does not match the transaction amount"
+            );
+        }
+    }
+}
+
+/// Disputes referencing another clients transaction and rows rejected by a rule are suspicious
+/// rather than fatal, they are reported and the row is otherwise ignored.
+fn report_suspicious(
+    result: Result<Outcome, TransactorError>,
+    position: RecordPosition,
+) -> Result<(), TransactorError> {
+    match result {
+        Err(e @ ForeignTransaction { .. }) | Err(e @ RuleViolation { .. }) => {
+            warn!(position = %position, error = %e, "suspicious record ignored");
+            Ok(())
+        }
+        result => result.map(|_| ()),
+    }
+}
+
+/// Write a clients statement in the format asked for.
+fn write_report(
+    account: &Account,
+    metadata: Option<&ClientMetadata>,
+    precision: &PrecisionTable,
+    report: &ReportCommand,
+    output: Box<dyn Write + '_>,
+) -> Result<(), TransactorError> {
+    let mut template = StatementTemplate::default();
+    if let Some(title) = &report.title {
+        template.title = title.clone();
+    }
+    template.logo = report.logo.clone();
+    if let Some(fields) = &report.fields {
+        template = template.with_fields(fields).map_err(InvalidConfig)?;
+    }
+    if let Some(details) = &report.details {
+        template = template.with_details(details).map_err(InvalidConfig)?;
+    }
+    match report.format {
+        StatementFormat::Csv => write_statement(account, precision, output),
+        StatementFormat::Html => {
+            write_statement_html(account, metadata, precision, &template, output)
+        }
+        #[cfg(feature = "pdf")]
+        StatementFormat::Pdf => {
+            write_statement_pdf(account, metadata, precision, &template, output)
+        }
+        #[cfg(not(feature = "pdf"))]
+        StatementFormat::Pdf => Err(InvalidConfig(
+            "PDF statements need the pdf feature".to_string(),
+        )),
+    }
+}
+
+/// Write the output of the run to `--output` if given, only moving the file into place once
+/// everything has been written, otherwise to stdout.
+fn write_output(
+    arguments: &Arguments,
+    write: impl FnOnce(Box<dyn Write + '_>) -> Result<(), TransactorError>,
+) -> Result<(), TransactorError> {
+    match &arguments.output {
+        Some(path) => {
+            let mut file = AtomicFile::create(path)?;
+            write(sink(&mut file))?;
+            file.commit()
+        }
+        None => write(sink(std::io::stdout())),
+    }
+}
+
+/// An output sink, failing writes at random if failures are being injected.
+fn sink<'a, W: Write + 'a>(writer: W) -> Box<dyn Write + 'a> {
+    #[cfg(feature = "failure-injection")]
+    if let Some(faults) = fault_config() {
+        return Box::new(FaultyWriter::new(writer, faults.sink_failures()));
+    }
+    Box::new(writer)
+}
+
+/// The failures to inject, the configuration has already been validated by `run`.
+#[cfg(feature = "failure-injection")]
+fn fault_config() -> Option<FaultConfig> {
+    FaultConfig::from_env().ok().flatten()
+}
+
+/// Print the metrics summary and write the metrics file if either was asked for. The summary is
+/// output for the user as much as the account report is, so it is printed as it is rather than
+/// logged, whatever the log level and format.
+fn write_metrics(bank: &Bank, arguments: &Arguments) -> Result<(), TransactorError> {
+    if let Some(metrics) = bank.metrics() {
+        if arguments.metrics_summary {
+            eprintln!("{}", metrics);
+        }
+        if let Some(metrics_file) = &arguments.metrics_file {
+            std::fs::write(metrics_file, metrics.render_prometheus())?;
+        }
     }
+    Ok(())
+}
+
+/// Print the totals of the run and write the stats file if either was asked for. Like the metrics
+/// summary the totals are printed for the user rather than logged.
+fn write_stats(stats: &RunStats, arguments: &Arguments) -> Result<(), TransactorError> {
+    if arguments.stats_summary {
+        eprintln!("{}", stats);
+    }
+    if let Some(path) = &arguments.stats {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, stats).map_err(std::io::Error::from)?;
+        writeln!(file)?;
+    }
+    Ok(())
 }
 
-fn missing_data() -> TransactorError {
-    InvalidData("Missing field in input".to_string())
+/// The balances and lock status of every account, by client.
+fn account_balances(bank: &Bank) -> HashMap<ClientId, AccountView> {
+    bank.get_accounts()
+        .map(|account| (account.client_id, account.view()))
+        .collect()
+}
+
+/// Write every account whose balances or lock status differ between `before` and `after`, in
+/// client order.
+fn write_account_changes(
+    before: &HashMap<ClientId, AccountView>,
+    after: &Bank,
+    precision: &PrecisionTable,
+    output: Box<dyn Write + '_>,
+) -> Result<(), TransactorError> {
+    let mut changed: Vec<AccountChangeRecord> = after
+        .get_accounts()
+        .filter_map(|account| {
+            let new = account.view();
+            let old = before
+                .get(&new.client_id)
+                .copied()
+                .unwrap_or_else(|| Account::new(new.client_id).view());
+            if (old.available, old.held, old.locked) == (new.available, new.held, new.locked) {
+                return None;
+            }
+            let precision = precision.precision(new.currency);
+            Some(AccountChangeRecord {
+                client: new.client_id.0,
+                available_before: precision.round(old.available).normalize(),
+                available_after: precision.round(new.available).normalize(),
+                held_before: precision.round(old.held).normalize(),
+                held_after: precision.round(new.held).normalize(),
+                locked_before: old.locked,
+                locked_after: new.locked,
+            })
+        })
+        .collect();
+    changed.sort_by_key(|change| change.client);
+    let mut writer = Writer::from_writer(output);
+    for change in changed {
+        writer.serialize(change)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,374 @@
+use std::fmt::{self, Write};
+use std::io::{self, BufRead, BufReader, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::bank::Outcome;
+use crate::error::TransactorError;
+
+/// Upper bounds of the operation latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 9] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01,
+];
+
+/// A bank operation counted by the metrics.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Operation {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Represent,
+    Unlock,
+    Freeze,
+    Unfreeze,
+    Close,
+    Authorize,
+    Capture,
+    Void,
+    Hold,
+    Release,
+}
+
+impl Operation {
+    const ALL: [Operation; 15] = [
+        Operation::Deposit,
+        Operation::Withdrawal,
+        Operation::Dispute,
+        Operation::Resolve,
+        Operation::Chargeback,
+        Operation::Represent,
+        Operation::Unlock,
+        Operation::Freeze,
+        Operation::Unfreeze,
+        Operation::Close,
+        Operation::Authorize,
+        Operation::Capture,
+        Operation::Void,
+        Operation::Hold,
+        Operation::Release,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Deposit => "deposit",
+            Operation::Withdrawal => "withdrawal",
+            Operation::Dispute => "dispute",
+            Operation::Resolve => "resolve",
+            Operation::Chargeback => "chargeback",
+            Operation::Represent => "represent",
+            Operation::Unlock => "unlock",
+            Operation::Freeze => "freeze",
+            Operation::Unfreeze => "unfreeze",
+            Operation::Close => "close",
+            Operation::Authorize => "authorize",
+            Operation::Capture => "capture",
+            Operation::Void => "void",
+            Operation::Hold => "hold",
+            Operation::Release => "release",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Operation::ALL
+            .iter()
+            .copied()
+            .find(|operation| operation.name() == s)
+            .ok_or_else(|| format!("Unknown operation {}", s))
+    }
+}
+
+/// The longest the metrics served are left behind the rows applied.
+pub const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest a scrape is waited on to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OUTCOMES: [&str; 3] = ["applied", "ignored", "rejected"];
+
+/// Counters and latencies of the operations handled by a bank, see `Bank::with_metrics`.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// Operation counts indexed by operation and then applied, ignored or rejected.
+    operations: [[u64; 3]; Operation::ALL.len()],
+    /// Counted from the accounts when the metrics are taken, see `Bank::metrics`.
+    locked_accounts: u64,
+    /// Cumulative counts of operations at or under each latency bucket.
+    latency_buckets: [u64; 9],
+    latency_count: u64,
+    latency_sum: Duration,
+    latency_max: Duration,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of times an operation had an outcome, one of applied, ignored or rejected.
+    pub fn count(&self, operation: Operation, outcome: &str) -> u64 {
+        OUTCOMES
+            .iter()
+            .position(|name| *name == outcome)
+            .map_or(0, |index| self.operations[operation as usize][index])
+    }
+
+    /// The number of accounts currently locked.
+    pub fn locked_accounts(&self) -> u64 {
+        self.locked_accounts
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        operation: Operation,
+        result: &Result<Outcome, TransactorError>,
+        elapsed: Duration,
+    ) {
+        let outcome = match result {
+            Ok(Outcome::Applied) => 0,
+            Ok(Outcome::Ignored(_)) => 1,
+            Err(_) => 2,
+        };
+        self.operations[operation as usize][outcome] += 1;
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.latency_buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.latency_count += 1;
+        self.latency_sum += elapsed;
+        self.latency_max = self.latency_max.max(elapsed);
+    }
+
+    pub(crate) fn set_locked_accounts(&mut self, locked_accounts: u64) {
+        self.locked_accounts = locked_accounts;
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut output = String::new();
+        // Writing to a String cannot fail
+        let _ = self.write_prometheus(&mut output);
+        output
+    }
+
+    fn write_prometheus(&self, output: &mut String) -> fmt::Result {
+        writeln!(
+            output,
+            "# HELP transactor_operations_total Operations handled by type and outcome."
+        )?;
+        writeln!(output, "# TYPE transactor_operations_total counter")?;
+        for operation in Operation::ALL.iter() {
+            for (index, outcome) in OUTCOMES.iter().enumerate() {
+                writeln!(
+                    output,
+                    "transactor_operations_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                    operation.name(),
+                    outcome,
+                    self.operations[*operation as usize][index]
+                )?;
+            }
+        }
+        writeln!(
+            output,
+            "# HELP transactor_locked_accounts Accounts currently locked."
+        )?;
+        writeln!(output, "# TYPE transactor_locked_accounts gauge")?;
+        writeln!(
+            output,
+            "transactor_locked_accounts {}",
+            self.locked_accounts
+        )?;
+        writeln!(
+            output,
+            "# HELP transactor_operation_duration_seconds Time taken to handle an operation."
+        )?;
+        writeln!(
+            output,
+            "# TYPE transactor_operation_duration_seconds histogram"
+        )?;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.latency_buckets.iter()) {
+            writeln!(
+                output,
+                "transactor_operation_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            )?;
+        }
+        writeln!(
+            output,
+            "transactor_operation_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.latency_count
+        )?;
+        writeln!(
+            output,
+            "transactor_operation_duration_seconds_sum {}",
+            self.latency_sum.as_secs_f64()
+        )?;
+        writeln!(
+            output,
+            "transactor_operation_duration_seconds_count {}",
+            self.latency_count
+        )
+    }
+}
+
+impl fmt::Display for Metrics {
+    /// A human readable summary of the operations handled.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12}{:>10}{:>10}{:>10}",
+            "operation", "applied", "ignored", "rejected"
+        )?;
+        for operation in Operation::ALL.iter() {
+            let [applied, ignored, rejected] = self.operations[*operation as usize];
+            writeln!(
+                f,
+                "{:<12}{:>10}{:>10}{:>10}",
+                operation.name(),
+                applied,
+                ignored,
+                rejected
+            )?;
+        }
+        writeln!(f, "locked accounts: {}", self.locked_accounts)?;
+        let mean = match self.latency_count {
+            0 => Duration::from_secs(0),
+            count => Duration::from_secs_f64(self.latency_sum.as_secs_f64() / count as f64),
+        };
+        write!(f, "latency: mean {:?}, max {:?}", mean, self.latency_max)
+    }
+}
+
+/// Serves the metrics last published in the Prometheus text format at `/metrics` over HTTP, for
+/// runs reading a stream long enough to be scraped while they go.
+pub struct MetricsServer {
+    address: SocketAddr,
+    exposition: Arc<Mutex<String>>,
+    published: Option<Instant>,
+}
+
+impl MetricsServer {
+    /// Listen on `address`, such as `127.0.0.1:9100`, answering scrapes from a thread of its own
+    /// for as long as the process runs.
+    pub fn bind(address: &str) -> Result<Self, TransactorError> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let exposition = Arc::new(Mutex::new(Metrics::new().render_prometheus()));
+        let served = Arc::clone(&exposition);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                // A scrape that fails only fails that scrape
+                if let Err(e) = stream.and_then(|stream| respond(stream, &served)) {
+                    debug!(error = %e, "metrics scrape failed");
+                }
+            }
+        });
+        Ok(Self {
+            address,
+            exposition,
+            published: None,
+        })
+    }
+
+    /// The address scrapes are answered on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Whether the metrics served are old enough to publish again.
+    pub fn is_due(&self) -> bool {
+        self.published
+            .is_none_or(|published| published.elapsed() >= PUBLISH_INTERVAL)
+    }
+
+    /// Serve `metrics` from now on.
+    pub fn publish(&mut self, metrics: &Metrics) {
+        *lock(&self.exposition) = metrics.render_prometheus();
+        self.published = Some(Instant::now());
+    }
+}
+
+fn lock(exposition: &Mutex<String>) -> std::sync::MutexGuard<'_, String> {
+    // A panic while holding the lock cannot leave a string half written
+    exposition
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Answer one HTTP request, the metrics for `GET /metrics` and not found for anything else.
+fn respond(mut stream: TcpStream, exposition: &Mutex<String>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = BufReader::new(&stream);
+    let mut request_line = String::new();
+    request.read_line(&mut request_line)?;
+    // The headers are read so closing the connection does not reset it under the client
+    let mut header = String::new();
+    while request.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", lock(exposition).clone()),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    fn get(address: SocketAddr, path: &str) -> Result<String, TransactorError> {
+        let mut stream = TcpStream::connect(address)?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn the_metrics_last_published_are_served() -> Result<(), TransactorError> {
+        let mut server = MetricsServer::bind("127.0.0.1:0")?;
+        assert!(server.is_due());
+        let response = get(server.address(), "/metrics")?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("transactor_locked_accounts 0"));
+
+        let mut metrics = Metrics::new();
+        metrics.set_locked_accounts(2);
+        server.publish(&metrics);
+        assert!(!server.is_due());
+        let response = get(server.address(), "/metrics")?;
+        assert!(response.contains("transactor_locked_accounts 2"));
+
+        let response = get(server.address(), "/")?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        Ok(())
+    }
+}
@@ -0,0 +1,622 @@
+use rust_decimal::prelude::*;
+
+use crate::bank::{
+    AccountStatus, Bank, ClientId, IgnoreReason, Outcome, Transaction, TransactionId,
+    TransactionState,
+};
+use crate::currency::Currency;
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::Command;
+
+/// A deliberately simple bank following the default rules, for checking the real one against.
+///
+/// Everything is kept in flat lists and every question is answered by scanning them, so it is
+/// slow but can be checked by reading it. Running the same commands through both and comparing
+/// outcomes and final accounts guards the real bank's indexes, spilling and sharding against
+/// changing what it does. Only the default policies are modelled: any transaction can be
+/// disputed, refusals are ignored, there is no overdraft or risk limit and transaction ids are
+/// unique per client.
+#[derive(Clone, Debug, Default)]
+pub struct ReferenceModel {
+    accounts: Vec<ModelAccount>,
+    /// Every posted transaction, including captured authorizations.
+    transactions: Vec<(ClientId, Transaction, TransactionState)>,
+    /// Pending authorizations, with positive amounts.
+    authorizations: Vec<(ClientId, Transaction)>,
+    /// Administrative holds not yet released, with positive amounts.
+    holds: Vec<(ClientId, TransactionId, Decimal)>,
+}
+
+#[derive(Clone, Debug)]
+struct ModelAccount {
+    client_id: ClientId,
+    available: Decimal,
+    held: Decimal,
+    status: AccountStatus,
+    /// Whether the account is frozen by a freeze rather than a chargeback.
+    frozen: bool,
+    currency: Option<Currency>,
+}
+
+impl ReferenceModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a command, as `Command::apply` does to a bank.
+    pub fn apply(&mut self, command: Command) -> Result<Outcome, TransactorError> {
+        match command {
+            Command::Deposit {
+                client,
+                transaction,
+            }
+            | Command::Withdrawal {
+                client,
+                transaction,
+            } => self.transact(client, transaction),
+            Command::Dispute {
+                client,
+                transaction,
+                ..
+            } => self.dispute(client, transaction),
+            Command::Resolve {
+                client,
+                transaction,
+            } => self.settle(client, transaction, false),
+            Command::Chargeback {
+                client,
+                transaction,
+                ..
+            } => self.settle(client, transaction, true),
+            Command::Represent {
+                client,
+                transaction,
+            } => self.represent(client, transaction),
+            Command::Freeze { client, .. } => self.freeze(client),
+            Command::Unfreeze { client } => self.unfreeze(client),
+            Command::Close { client } => self.close(client),
+            Command::Authorize {
+                client,
+                transaction,
+            } => self.authorize(client, transaction),
+            Command::Capture {
+                client,
+                transaction,
+            } => self.capture(client, transaction),
+            Command::Void {
+                client,
+                transaction,
+            } => self.void(client, transaction),
+            Command::Hold {
+                client,
+                transaction,
+            } => self.hold(client, transaction),
+            Command::Release {
+                client,
+                transaction,
+            } => self.release(client, transaction),
+            Command::Move { .. } => Err(InvalidConfig(
+                "the reference model only models the main account of each client".to_string(),
+            )),
+        }
+    }
+
+    /// Apply a command and check the bank got the same result from it, failing with
+    /// `VerificationFailed` if not. Errors are compared by their message.
+    pub fn verify(
+        &mut self,
+        command: Command,
+        bank_result: &Result<Outcome, TransactorError>,
+    ) -> Result<(), TransactorError> {
+        let model_result = self.apply(command);
+        let same = match (bank_result, &model_result) {
+            (Ok(bank), Ok(model)) => bank == model,
+            (Err(bank), Err(model)) => bank.to_string() == model.to_string(),
+            _ => false,
+        };
+        if same {
+            return Ok(());
+        }
+        Err(VerificationFailed(format!(
+            "the {} was {} by the bank but {} by the model",
+            command.name(),
+            describe(bank_result),
+            describe(&model_result)
+        )))
+    }
+
+    /// Every way the accounts of the bank differ from the model, accounts in client order and then
+    /// transactions in the order they were made. Accounts are compared by balances and whether
+    /// they are locked, transactions by amount and state.
+    pub fn differences(&self, bank: &Bank) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut accounts: Vec<&ModelAccount> = self.accounts.iter().collect();
+        accounts.sort_by_key(|account| account.client_id.0);
+        for model in accounts {
+            let client = model.client_id.0;
+            let account = match bank.get_account(model.client_id) {
+                Some(account) => account,
+                None => {
+                    differences.push(format!("client {} has no account in the bank", client));
+                    continue;
+                }
+            };
+            let compared = [
+                ("available", account.available(), model.available),
+                ("held", account.held(), model.held),
+            ];
+            for (name, bank, model) in compared {
+                if bank != model {
+                    differences.push(format!(
+                        "client {} has {} {} in the bank but {} in the model",
+                        client, name, bank, model
+                    ));
+                }
+            }
+            if account.status() != model.status {
+                differences.push(format!(
+                    "client {} is {} in the bank but {} in the model",
+                    client,
+                    account.status(),
+                    model.status
+                ));
+            }
+            if account.is_frozen() != model.frozen {
+                differences.push(format!(
+                    "client {} has freeze {} in the bank but {} in the model",
+                    client,
+                    account.is_frozen(),
+                    model.frozen
+                ));
+            }
+        }
+        let mut extra: Vec<u16> = bank
+            .get_accounts()
+            .map(|account| account.client_id)
+            .filter(|client_id| self.find(*client_id).is_none())
+            .map(|client_id| client_id.0)
+            .collect();
+        extra.sort_unstable();
+        for client in extra {
+            differences.push(format!("client {} has no account in the model", client));
+        }
+        for (client_id, transaction, state) in &self.transactions {
+            let id = transaction.transaction_id();
+            let recorded = bank
+                .transaction(*client_id, id)
+                .map(|recorded| recorded.amount())
+                .zip(bank.transaction_state(*client_id, id));
+            if recorded != Some((transaction.amount(), *state)) {
+                differences.push(format!(
+                    "client {} transaction {} is {} in the bank but {} of {} in the model",
+                    client_id.0,
+                    id.0,
+                    recorded.map_or("missing".to_string(), |(amount, state)| format!(
+                        "{} of {}",
+                        state, amount
+                    )),
+                    state,
+                    transaction.amount()
+                ));
+            }
+        }
+        for (client_id, authorized) in &self.authorizations {
+            let id = authorized.transaction_id();
+            let pending = bank
+                .get_account(*client_id)
+                .and_then(|account| account.authorization(id))
+                .map(|pending| pending.amount());
+            if pending != Some(authorized.amount()) {
+                differences.push(format!(
+                    "client {} authorization {} of {} is not pending in the bank",
+                    client_id.0,
+                    id.0,
+                    authorized.amount()
+                ));
+            }
+        }
+        differences
+    }
+
+    fn transact(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        let used = self.is_used(client_id, transaction.transaction_id());
+        let account = self.account(client_id);
+        if account.status != AccountStatus::Active {
+            return Ok(Outcome::Ignored(account.lock_reason()));
+        }
+        if used {
+            return Err(TransactionIdReuse);
+        }
+        check_currency(account, &transaction)?;
+        let available = account
+            .available
+            .checked_add(transaction.amount())
+            .ok_or(Overflow)?;
+        if transaction.amount() <= Decimal::zero() && available < Decimal::zero() {
+            return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
+        }
+        account.available = available;
+        account.currency = account.currency.or(transaction.currency());
+        self.transactions
+            .push((client_id, transaction, TransactionState::Posted));
+        Ok(Outcome::Applied)
+    }
+
+    fn dispute(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.account(client_id);
+        let index = match self.posted(client_id, transaction_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+        };
+        let (_, transaction, state) = self.transactions[index];
+        if state != TransactionState::Posted && state != TransactionState::Resolved {
+            return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+        }
+        move_to_held(self.account(client_id), transaction.amount().abs())?;
+        self.transactions[index].2 = TransactionState::Disputed;
+        Ok(Outcome::Applied)
+    }
+
+    /// Resolve or charge back a disputed transaction.
+    fn settle(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        chargeback: bool,
+    ) -> Result<Outcome, TransactorError> {
+        self.account(client_id);
+        let index = match self.posted(client_id, transaction_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+        };
+        let (_, transaction, state) = self.transactions[index];
+        if state != TransactionState::Disputed {
+            return Ok(Outcome::Ignored(IgnoreReason::NotDisputed));
+        }
+        let amount = transaction.amount().abs();
+        let account = self.account(client_id);
+        if chargeback {
+            account.held = account.held.checked_sub(amount).ok_or(Overflow)?;
+            if account.status != AccountStatus::Closed {
+                account.status = AccountStatus::Frozen;
+                account.frozen = false;
+            }
+            self.transactions[index].2 = TransactionState::ChargedBack;
+        } else {
+            move_to_held(account, -amount)?;
+            self.transactions[index].2 = TransactionState::Resolved;
+        }
+        Ok(Outcome::Applied)
+    }
+
+    /// Credit back a charged back transaction, unlocking the account once none of its
+    /// transactions stand charged back.
+    fn represent(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        self.account(client_id);
+        let index = match self.posted(client_id, transaction_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+        };
+        let (_, transaction, state) = self.transactions[index];
+        if state != TransactionState::ChargedBack {
+            return Ok(Outcome::Ignored(IgnoreReason::NotChargedBack));
+        }
+        let account = self.account(client_id);
+        account.available = account
+            .available
+            .checked_add(transaction.amount().abs())
+            .ok_or(Overflow)?;
+        self.transactions[index].2 = TransactionState::Represented;
+        let charged_back = self.transactions.iter().any(|(client, _, state)| {
+            *client == client_id && *state == TransactionState::ChargedBack
+        });
+        let account = self.account(client_id);
+        if !charged_back && account.status == AccountStatus::Frozen && !account.frozen {
+            account.status = AccountStatus::Active;
+        }
+        Ok(Outcome::Applied)
+    }
+
+    fn freeze(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        let account = self.account(client_id);
+        if account.status != AccountStatus::Active {
+            return Ok(Outcome::Ignored(account.lock_reason()));
+        }
+        account.status = AccountStatus::Frozen;
+        account.frozen = true;
+        Ok(Outcome::Applied)
+    }
+
+    fn close(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        let index = match self.find(client_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount)),
+        };
+        let account = &mut self.accounts[index];
+        if account.status == AccountStatus::Closed {
+            return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+        }
+        if !account.held.is_zero() {
+            return Ok(Outcome::Ignored(IgnoreReason::FundsHeld));
+        }
+        account.status = AccountStatus::Closed;
+        account.frozen = false;
+        Ok(Outcome::Applied)
+    }
+
+    fn unfreeze(&mut self, client_id: ClientId) -> Result<Outcome, TransactorError> {
+        match self.find(client_id) {
+            Some(index) if self.accounts[index].frozen => {
+                self.accounts[index].status = AccountStatus::Active;
+                self.accounts[index].frozen = false;
+                Ok(Outcome::Applied)
+            }
+            _ => Ok(Outcome::Ignored(IgnoreReason::NotFrozen)),
+        }
+    }
+
+    fn authorize(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        let used = self.is_used(client_id, transaction.transaction_id());
+        let account = self.account(client_id);
+        if account.status != AccountStatus::Active {
+            return Ok(Outcome::Ignored(account.lock_reason()));
+        }
+        if used {
+            return Err(TransactionIdReuse);
+        }
+        check_currency(account, &transaction)?;
+        let remaining = account
+            .available
+            .checked_sub(transaction.amount())
+            .ok_or(Overflow)?;
+        if remaining < Decimal::zero() {
+            return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
+        }
+        move_to_held(account, transaction.amount())?;
+        account.currency = account.currency.or(transaction.currency());
+        self.authorizations.push((client_id, transaction));
+        Ok(Outcome::Applied)
+    }
+
+    fn capture(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        let index = match self.pending(client_id, transaction_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized)),
+        };
+        let (_, authorized) = self.authorizations[index];
+        let account = self.account(client_id);
+        if account.status != AccountStatus::Active {
+            return Ok(Outcome::Ignored(account.lock_reason()));
+        }
+        account.held = account
+            .held
+            .checked_sub(authorized.amount())
+            .ok_or(Overflow)?;
+        self.authorizations.remove(index);
+        let mut withdrawal = Transaction::new(transaction_id, -authorized.amount());
+        if let Some(currency) = authorized.currency() {
+            withdrawal = withdrawal.with_currency(currency);
+        }
+        self.transactions
+            .push((client_id, withdrawal, TransactionState::Posted));
+        Ok(Outcome::Applied)
+    }
+
+    fn void(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        let index = match self.pending(client_id, transaction_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized)),
+        };
+        let (_, authorized) = self.authorizations[index];
+        move_to_held(self.account(client_id), -authorized.amount())?;
+        self.authorizations.remove(index);
+        Ok(Outcome::Applied)
+    }
+
+    fn hold(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Outcome, TransactorError> {
+        if self.is_used(client_id, transaction.transaction_id()) {
+            return Err(TransactionIdReuse);
+        }
+        let account = self.account(client_id);
+        check_currency(account, &transaction)?;
+        if account.available < transaction.amount() {
+            return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
+        }
+        move_to_held(account, transaction.amount())?;
+        self.holds.push((
+            client_id,
+            transaction.transaction_id(),
+            transaction.amount(),
+        ));
+        Ok(Outcome::Applied)
+    }
+
+    fn release(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<Outcome, TransactorError> {
+        let index = match self.held(client_id, transaction_id) {
+            Some(index) => index,
+            None => return Ok(Outcome::Ignored(IgnoreReason::NotHeld)),
+        };
+        let (_, _, amount) = self.holds[index];
+        move_to_held(self.account(client_id), -amount)?;
+        self.holds.remove(index);
+        Ok(Outcome::Applied)
+    }
+
+    /// The account of a client, opened if the client has not been seen.
+    fn account(&mut self, client_id: ClientId) -> &mut ModelAccount {
+        let index = match self.find(client_id) {
+            Some(index) => index,
+            None => {
+                self.accounts.push(ModelAccount {
+                    client_id,
+                    available: Decimal::zero(),
+                    held: Decimal::zero(),
+                    status: AccountStatus::Active,
+                    frozen: false,
+                    currency: None,
+                });
+                self.accounts.len() - 1
+            }
+        };
+        &mut self.accounts[index]
+    }
+
+    fn find(&self, client_id: ClientId) -> Option<usize> {
+        self.accounts
+            .iter()
+            .position(|account| account.client_id == client_id)
+    }
+
+    fn posted(&self, client_id: ClientId, transaction_id: TransactionId) -> Option<usize> {
+        self.transactions
+            .iter()
+            .position(|(client, transaction, _)| {
+                *client == client_id && transaction.transaction_id() == transaction_id
+            })
+    }
+
+    fn pending(&self, client_id: ClientId, transaction_id: TransactionId) -> Option<usize> {
+        self.authorizations.iter().position(|(client, authorized)| {
+            *client == client_id && authorized.transaction_id() == transaction_id
+        })
+    }
+
+    fn held(&self, client_id: ClientId, transaction_id: TransactionId) -> Option<usize> {
+        self.holds
+            .iter()
+            .position(|(client, hold, _)| *client == client_id && *hold == transaction_id)
+    }
+
+    fn is_used(&self, client_id: ClientId, transaction_id: TransactionId) -> bool {
+        self.posted(client_id, transaction_id).is_some()
+            || self.pending(client_id, transaction_id).is_some()
+            || self.held(client_id, transaction_id).is_some()
+    }
+}
+
+impl ModelAccount {
+    fn lock_reason(&self) -> IgnoreReason {
+        match self.status {
+            AccountStatus::Closed => IgnoreReason::AccountClosed,
+            _ => IgnoreReason::AccountLocked,
+        }
+    }
+}
+
+fn check_currency(
+    account: &ModelAccount,
+    transaction: &Transaction,
+) -> Result<(), TransactorError> {
+    match (account.currency, transaction.currency()) {
+        (Some(expected), Some(found)) if expected != found => {
+            Err(CurrencyMismatch { expected, found })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Move funds from available to held, or back for a negative amount, changing neither if either
+/// would overflow.
+fn move_to_held(account: &mut ModelAccount, amount: Decimal) -> Result<(), TransactorError> {
+    let available = account.available.checked_sub(amount).ok_or(Overflow)?;
+    let held = account.held.checked_add(amount).ok_or(Overflow)?;
+    account.available = available;
+    account.held = held;
+    Ok(())
+}
+
+fn describe(result: &Result<Outcome, TransactorError>) -> String {
+    match result {
+        Ok(Outcome::Applied) => "applied".to_string(),
+        Ok(Outcome::Ignored(reason)) => format!("ignored as {}", reason),
+        Err(e) => format!("rejected with {}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn the_bank_agrees_with_the_model() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            withdrawal,1,2,4\n\
+            withdrawal,1,3,40\n\
+            dispute,1,2,\n\
+            resolve,1,2,\n\
+            dispute,1,2,\n\
+            chargeback,1,2,\n\
+            deposit,1,4,1\n\
+            deposit,2,1,5\n\
+            authorize,2,2,3\n\
+            capture,2,2,\n\
+            authorize,2,3,1\n\
+            void,2,9,\n\
+            dispute,2,1,\n\
+            freeze,3,,\n\
+            unfreeze,3,,\n\
+            deposit,2,1,1\n";
+        let (mut bank, mut model) = (Bank::new(), ReferenceModel::new());
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::V1)? {
+            let command = record?.command;
+            model.verify(command, &command.apply(&mut bank))?;
+        }
+        assert_eq!(model.differences(&bank), Vec::<String>::new());
+
+        // A chargeback the model never saw shows up in the state of the accounts
+        bank.chargeback(ClientId(2), TransactionId(1))?;
+        assert_eq!(
+            model.differences(&bank),
+            vec![
+                "client 2 has held 1 in the bank but 6 in the model",
+                "client 2 is frozen in the bank but active in the model",
+                "client 2 transaction 1 is chargedback of 5 in the bank but disputed of 5 in the \
+                 model"
+            ]
+        );
+        let mismatch = model.verify(
+            Command::Unfreeze {
+                client: ClientId(2),
+            },
+            &Ok(Outcome::Applied),
+        );
+        assert!(mismatch.unwrap_err().to_string().ends_with(
+            "the unfreeze was applied by the bank but ignored as not-frozen by the model"
+        ));
+        Ok(())
+    }
+}
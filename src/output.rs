@@ -0,0 +1,89 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::TransactorError;
+
+/// A file that only appears at its path once it has been completely written. Writes go to a
+/// temporary file alongside it which `commit` renames into place, so a reader of the path sees
+/// either the previous file or the whole new one. Dropping without committing removes the
+/// temporary file and leaves the path untouched.
+pub struct AtomicFile {
+    writer: Option<BufWriter<File>>,
+    temporary: PathBuf,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        let path = path.as_ref().to_path_buf();
+        let name = path
+            .file_name()
+            .ok_or_else(|| {
+                TransactorError::InvalidConfig(format!("{} is not a file", path.display()))
+            })?
+            .to_string_lossy();
+        // In the same directory so the rename never crosses filesystems
+        let temporary = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+        Ok(Self {
+            writer: Some(BufWriter::new(File::create(&temporary)?)),
+            temporary,
+            path,
+        })
+    }
+
+    /// Flush everything written to disk and move the file into place.
+    pub fn commit(mut self) -> Result<(), TransactorError> {
+        if let Some(writer) = self.writer.take() {
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        fs::rename(&self.temporary, &self.path)?;
+        Ok(())
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        // Only commit takes the writer and it consumes self
+        self.writer.as_mut().expect("writer taken before commit")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_only_appears_once_committed() -> Result<(), TransactorError> {
+        let path = std::env::temp_dir().join("transactor-atomic-test.csv");
+        fs::write(&path, "previous")?;
+        let mut abandoned = AtomicFile::create(&path)?;
+        abandoned.write_all(b"partial")?;
+        drop(abandoned);
+        assert_eq!(fs::read_to_string(&path)?, "previous");
+
+        let mut file = AtomicFile::create(&path)?;
+        file.write_all(b"complete")?;
+        assert_eq!(fs::read_to_string(&path)?, "previous");
+        file.commit()?;
+        assert_eq!(fs::read_to_string(&path)?, "complete");
+        Ok(())
+    }
+}
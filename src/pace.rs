@@ -0,0 +1,103 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_decimal::prelude::*;
+
+use crate::bank::Timestamp;
+
+/// How fast to replay input relative to the timestamps on its rows, parsed from `realtime` or a
+/// speed up such as `10x`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pace {
+    speed: f64,
+}
+
+impl Pace {
+    pub fn realtime() -> Self {
+        Self { speed: 1.0 }
+    }
+
+    /// Replay `speed` times faster than the original, below 1 is slower.
+    pub fn speed(speed: f64) -> Self {
+        Self { speed }
+    }
+}
+
+impl FromStr for Pace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "realtime" {
+            return Ok(Pace::realtime());
+        }
+        s.strip_suffix('x')
+            .unwrap_or(s)
+            .parse::<f64>()
+            .ok()
+            .filter(|speed| speed.is_finite() && *speed > 0.0)
+            .map(Pace::speed)
+            .ok_or_else(|| {
+                format!(
+                    "Invalid pace {}, expected realtime or a speed such as 10x",
+                    s
+                )
+            })
+    }
+}
+
+/// Holds back rows until their timestamp comes round, as measured from the first timestamped row.
+/// Rows without a timestamp, or already due, are not held back.
+pub struct Pacer {
+    pace: Pace,
+    start: Option<(Instant, Timestamp)>,
+}
+
+impl Pacer {
+    pub fn new(pace: Pace) -> Self {
+        Self { pace, start: None }
+    }
+
+    /// Sleep until the row with this timestamp is due.
+    pub fn wait(&mut self, timestamp: Option<Timestamp>) {
+        if let Some(timestamp) = timestamp {
+            let (started, first) = *self.start.get_or_insert((Instant::now(), timestamp));
+            let delay = self.delay(first, timestamp, started.elapsed());
+            if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+        }
+    }
+
+    /// How much longer to wait for a row, given how long has passed since the first row.
+    fn delay(&self, first: Timestamp, timestamp: Timestamp, elapsed: Duration) -> Duration {
+        let offset = timestamp.0.saturating_sub(first.0) as f64 / self.pace.speed;
+        Duration::from_secs_f64(offset).saturating_sub(elapsed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rows_are_held_back_by_their_scaled_offset() {
+        assert_eq!("realtime".parse(), Ok(Pace::realtime()));
+        assert_eq!("10x".parse(), Ok(Pace::speed(10.0)));
+        assert!("0".parse::<Pace>().is_err());
+
+        let pacer = Pacer::new(Pace::speed(10.0));
+        let first = Timestamp(1000);
+        assert_eq!(
+            pacer.delay(first, Timestamp(1050), Duration::from_secs(2)),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            pacer.delay(first, Timestamp(1010), Duration::from_secs(2)),
+            Duration::from_secs(0)
+        );
+        assert_eq!(
+            pacer.delay(first, Timestamp(900), Duration::from_secs(0)),
+            Duration::from_secs(0)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::io;
+use std::path::Path;
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::bank::{Bank, ClientId, Outcome, Timestamp, Transaction, TransactionId};
+use crate::error::{RecordPosition, TransactorError, TransactorError::*};
+
+/// A standing order: a deposit, or a withdrawal for a negative amount, made every `interval`
+/// seconds from `start`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StandingOrder {
+    pub client: ClientId,
+    pub amount: Decimal,
+    pub interval: u64,
+    pub start: Timestamp,
+}
+
+impl StandingOrder {
+    /// What the bank knows the order by in its ledger of standing orders, made of every field so
+    /// orders differing in any are kept apart.
+    pub fn key(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.client.0,
+            self.amount.normalize(),
+            self.interval,
+            self.start.0
+        )
+    }
+
+    /// The transaction id of an occurrence, the same on every run. It only identifies the
+    /// occurrence to observers, occurrences are not recorded as transactions so it may well be
+    /// the id of a transaction of the input too.
+    pub fn occurrence_id(&self, occurrence: u64) -> TransactionId {
+        // FNV-1a, stable across runs and platforms unlike the std hasher
+        let mut hash: u32 = 0x811c_9dc5;
+        let amount = self.amount.normalize().to_string();
+        let fields = [
+            &self.client.0.to_le_bytes()[..],
+            amount.as_bytes(),
+            &self.interval.to_le_bytes(),
+            &self.start.0.to_le_bytes(),
+            &occurrence.to_le_bytes(),
+        ];
+        for byte in fields.iter().flat_map(|field| field.iter()) {
+            hash ^= u32::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+        TransactionId(hash | 0x8000_0000)
+    }
+}
+
+/// A csv file of standing orders with the columns client, amount, interval and start. The
+/// interval is in seconds, or with a suffix of m, h, d or w for minutes, hours, days or weeks,
+/// and start is in seconds since the unix epoch.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecurringSchedule {
+    orders: Vec<StandingOrder>,
+}
+
+/// An occurrence of a standing order, the `number`th counting from 0 at its start.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Occurrence {
+    pub order: StandingOrder,
+    pub number: u64,
+    pub transaction: Transaction,
+}
+
+/// What applying the standing orders did.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecurringRun {
+    pub applied: u64,
+    /// Occurrences the bank ignored, such as withdrawals exceeding the available funds.
+    pub ignored: u64,
+    /// Occurrences already applied by an earlier run.
+    pub already_applied: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct StandingOrderRecord {
+    client: u16,
+    amount: Decimal,
+    interval: String,
+    start: u64,
+}
+
+impl RecurringSchedule {
+    pub fn new(orders: Vec<StandingOrder>) -> Self {
+        Self { orders }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Read standing orders from csv, failing at the first row that is not one.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self, TransactorError> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut row = StringRecord::new();
+        let mut orders = Vec::new();
+        while reader.read_record(&mut row)? {
+            let mut position = RecordPosition::new(row.position().map_or(0, |p| p.line()));
+            let record: StandingOrderRecord = row
+                .deserialize(Some(&headers))
+                .map_err(|e| TransactorError::from(e).at(position))?;
+            position.client = Some(ClientId(record.client));
+            let interval = parse_interval(&record.interval)
+                .ok_or_else(|| {
+                    InvalidConfig(format!(
+                        "invalid interval {}, expected a number of seconds or of minutes, \
+                         hours, days or weeks such as 30d",
+                        record.interval
+                    ))
+                })
+                .map_err(|e| e.at(position))?;
+            orders.push(StandingOrder {
+                client: ClientId(record.client),
+                amount: record.amount,
+                interval,
+                start: Timestamp(record.start),
+            });
+        }
+        Ok(Self { orders })
+    }
+
+    /// The occurrences falling after `since` and up to and including `as_of`, in time order and
+    /// then in the order of the orders. Every occurrence up to `as_of` without `since`.
+    pub fn due(&self, since: Option<Timestamp>, as_of: Timestamp) -> Vec<Occurrence> {
+        let mut due = Vec::new();
+        for order in &self.orders {
+            if order.start > as_of {
+                continue;
+            }
+            let first = match since {
+                Some(since) if since >= order.start => {
+                    (since.0 - order.start.0) / order.interval + 1
+                }
+                _ => 0,
+            };
+            let last = (as_of.0 - order.start.0) / order.interval;
+            for number in first..=last {
+                let time = Timestamp(order.start.0 + number * order.interval);
+                let transaction = Transaction::new(order.occurrence_id(number), order.amount)
+                    .with_timestamp(time);
+                due.push(Occurrence {
+                    order: *order,
+                    number,
+                    transaction,
+                });
+            }
+        }
+        // A stable sort keeps occurrences at the same time in the order of the orders
+        due.sort_by_key(|occurrence| occurrence.transaction.timestamp());
+        due
+    }
+
+    /// Apply the occurrences due between the time the bank is as of and `as_of`, then move the
+    /// bank on to `as_of`. Occurrences the bank's ledger of standing orders has already were
+    /// applied by an earlier run and are skipped.
+    pub fn apply(
+        &self,
+        bank: &mut Bank,
+        as_of: Timestamp,
+    ) -> Result<RecurringRun, TransactorError> {
+        let mut run = RecurringRun::default();
+        for occurrence in self.due(bank.as_of(), as_of) {
+            match bank.apply_standing_order(
+                &occurrence.order.key(),
+                occurrence.number,
+                occurrence.order.client,
+                occurrence.transaction,
+            )? {
+                Some(Outcome::Applied) => run.applied += 1,
+                Some(Outcome::Ignored(_)) => run.ignored += 1,
+                None => run.already_applied += 1,
+            }
+        }
+        if bank.as_of().is_none_or(|current| current < as_of) {
+            bank.set_as_of(as_of);
+        }
+        Ok(run)
+    }
+}
+
+/// Seconds, or a count of minutes, hours, days or weeks such as `30d`. Never zero.
+fn parse_interval(interval: &str) -> Option<u64> {
+    let (count, unit) = match interval.char_indices().last()? {
+        (index, 'm') => (&interval[..index], 60),
+        (index, 'h') => (&interval[..index], 60 * 60),
+        (index, 'd') => (&interval[..index], 24 * 60 * 60),
+        (index, 'w') => (&interval[..index], 7 * 24 * 60 * 60),
+        (index, 's') => (&interval[..index], 1),
+        _ => (interval, 1),
+    };
+    let count: u64 = count.trim().parse().ok()?;
+    count.checked_mul(unit).filter(|seconds| *seconds > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn standing_orders_are_applied_once_up_to_the_as_of_time() -> Result<(), TransactorError> {
+        let schedule = RecurringSchedule::from_reader(
+            "client,amount,interval,start\n1,10,1d,0\n1,-3,2d,86400\n".as_bytes(),
+        )?;
+        let client = ClientId(1);
+        let day = 86400;
+        let mut bank = Bank::new();
+        let run = schedule.apply(&mut bank, Timestamp(2 * day))?;
+        assert_eq!(run.applied, 4);
+        assert_eq!(bank.as_of(), Some(Timestamp(2 * day)));
+        assert_eq!(
+            bank.get_account(client).unwrap().available(),
+            Decimal::new(27, 0)
+        );
+
+        // The next run carries on from where this one stopped
+        let mut saved = Vec::new();
+        bank.save_state(&mut saved)?;
+        let mut next = Bank::new();
+        next.restore(saved.as_slice())?;
+        let run = schedule.apply(&mut next, Timestamp(3 * day))?;
+        assert_eq!((run.applied, run.already_applied), (2, 0));
+        assert_eq!(
+            next.get_account(client).unwrap().available(),
+            Decimal::new(34, 0)
+        );
+
+        // Running a window again finds its occurrences already applied
+        let repeated = RecurringSchedule::new(vec![StandingOrder {
+            client,
+            amount: Decimal::new(10, 0),
+            interval: day,
+            start: Timestamp(0),
+        }]);
+        let mut again = Bank::new();
+        let first = repeated.due(None, Timestamp(0))[0];
+        again.apply_standing_order(&first.order.key(), 0, client, first.transaction)?;
+        assert_eq!(repeated.apply(&mut again, Timestamp(0))?.already_applied, 1);
+
+        assert!(matches!(
+            RecurringSchedule::from_reader("client,amount,interval,start\n1,1,0d,0\n".as_bytes()),
+            Err(Record { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn occurrences_are_applied_whatever_ids_the_input_uses() -> Result<(), TransactorError> {
+        let order = StandingOrder {
+            client: ClientId(1),
+            amount: Decimal::new(10, 0),
+            interval: 86400,
+            start: Timestamp(0),
+        };
+        let schedule = RecurringSchedule::new(vec![order]);
+        let mut bank = Bank::new();
+        bank.transact(
+            order.client,
+            Transaction::new(order.occurrence_id(0), Decimal::new(1, 0)),
+        )?;
+        let run = schedule.apply(&mut bank, Timestamp(0))?;
+        assert_eq!((run.applied, run.already_applied), (1, 0));
+        assert_eq!(
+            bank.get_account(order.client).unwrap().available(),
+            Decimal::new(11, 0)
+        );
+
+        // The ledger of the order is saved with the state, and ids of the input after it are free
+        let mut saved = Vec::new();
+        bank.save_state(&mut saved)?;
+        let mut next = Bank::new();
+        next.restore(saved.as_slice())?;
+        let first = schedule.due(None, Timestamp(0))[0];
+        assert_eq!(
+            next.apply_standing_order(&order.key(), 0, order.client, first.transaction)?,
+            None
+        );
+        Ok(())
+    }
+}
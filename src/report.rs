@@ -0,0 +1,996 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use csv::Writer;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::bank::{
+    Account, AccountName, Bank, ClientDirectory, ClientField, ClientId, ClientMetadata, ReasonCode,
+    Recovery, Transaction, TransactionMatch, TransactionState,
+};
+use crate::currency::PrecisionTable;
+use crate::error::{TransactorError, TransactorError::*};
+use crate::sla::OpenDispute;
+
+mod aggregate;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod html;
+mod partition;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod writer;
+
+pub use crate::currency::Rounding;
+pub use aggregate::{AggregateReport, TypeTotals};
+#[cfg(feature = "parquet")]
+pub use columnar::ColumnarReportWriter;
+pub use partition::{write_partitioned_accounts, PartitionKey, Partitioning};
+pub use writer::{
+    CsvReportWriter, JsonReportWriter, OutputFormat, ReportValue, ReportWriter, TableReportWriter,
+};
+
+/// A column of the account report.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccountColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Whether the account is active, frozen, closed or under review, see `Account::status`.
+    Status,
+    /// The currency of the account, empty if it has never transacted in one.
+    Currency,
+    /// The number of changes made to the account.
+    Version,
+    /// The batch that last changed the account.
+    LastBatch,
+    /// The fees charged on the account added up.
+    Fees,
+    /// Which of the client's accounts the row is of, added after the client when any client has a
+    /// sub-account.
+    Account,
+    /// The name of the client, from the client metadata.
+    Name,
+    /// The email of the client, from the client metadata.
+    Email,
+    /// The risk tier of the client, from the client metadata.
+    RiskTier,
+}
+
+impl AccountColumn {
+    const ALL: [AccountColumn; 14] = [
+        AccountColumn::Client,
+        AccountColumn::Available,
+        AccountColumn::Held,
+        AccountColumn::Total,
+        AccountColumn::Locked,
+        AccountColumn::Status,
+        AccountColumn::Currency,
+        AccountColumn::Version,
+        AccountColumn::LastBatch,
+        AccountColumn::Fees,
+        AccountColumn::Account,
+        AccountColumn::Name,
+        AccountColumn::Email,
+        AccountColumn::RiskTier,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            AccountColumn::Client => "client",
+            AccountColumn::Available => "available",
+            AccountColumn::Held => "held",
+            AccountColumn::Total => "total",
+            AccountColumn::Locked => "locked",
+            AccountColumn::Status => "status",
+            AccountColumn::Currency => "currency",
+            AccountColumn::Version => "version",
+            AccountColumn::LastBatch => "last_batch",
+            AccountColumn::Fees => "fees",
+            AccountColumn::Account => "account",
+            AccountColumn::Name => "name",
+            AccountColumn::Email => "email",
+            AccountColumn::RiskTier => "risk_tier",
+        }
+    }
+}
+
+impl fmt::Display for AccountColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AccountColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AccountColumn::ALL
+            .iter()
+            .copied()
+            .find(|column| column.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = AccountColumn::ALL.iter().map(|c| c.name()).collect();
+                format!(
+                    "Unknown column {}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The columns of the account report, in the order they are written.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccountColumns(Vec<AccountColumn>);
+
+impl AccountColumns {
+    pub fn new(columns: Vec<AccountColumn>) -> Self {
+        Self(columns)
+    }
+
+    pub fn columns(&self) -> &[AccountColumn] {
+        &self.0
+    }
+}
+
+impl Default for AccountColumns {
+    /// client, available, held, total and locked, as the output specification requires.
+    fn default() -> Self {
+        Self(AccountColumn::ALL[..5].to_vec())
+    }
+}
+
+impl FromStr for AccountColumns {
+    type Err = String;
+
+    /// Parse a comma separated list of column names such as `client,total,locked`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(|name| name.trim().parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        if columns.is_empty() {
+            return Err("No output columns given".to_string());
+        }
+        Ok(Self(columns))
+    }
+}
+
+/// The order accounts are written in.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum AccountOrder {
+    /// By client id.
+    #[default]
+    Client,
+    /// By total balance, smallest first, then by client id.
+    Total,
+}
+
+impl FromStr for AccountOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(AccountOrder::Client),
+            "total" => Ok(AccountOrder::Total),
+            _ => Err(format!(
+                "Unknown sort order {}, expected one of: client, total",
+                s
+            )),
+        }
+    }
+}
+
+/// Write a row for every account with the given columns, in the given order. Amounts are rounded
+/// to the precision of the account's currency, with the rounding of the table, see
+/// `PrecisionTable::with_rounding`.
+pub fn write_accounts(
+    bank: &Bank,
+    precision: &PrecisionTable,
+    columns: &AccountColumns,
+    order: AccountOrder,
+    report: &mut dyn ReportWriter,
+) -> Result<(), TransactorError> {
+    write_selected_accounts(bank, |_| true, precision, columns, order, report)
+}
+
+/// The version of every account of a bank at one time, to tell which accounts change after it.
+#[derive(Clone, Debug, Default)]
+pub struct AccountVersions(HashMap<(ClientId, AccountName), u64>);
+
+impl AccountVersions {
+    pub fn of(bank: &Bank) -> Self {
+        Self(
+            bank.get_accounts()
+                .chain(bank.sub_accounts())
+                .map(|account| ((account.client_id, account.name()), account.version()))
+                .collect(),
+        )
+    }
+
+    /// Whether the account has changed since, or been opened since.
+    pub fn changed(&self, account: &Account) -> bool {
+        self.0.get(&(account.client_id, account.name())) != Some(&account.version())
+    }
+}
+
+/// Write the accounts that changed since `since`, as `write_accounts` does, such as the deltas
+/// of a run on top of a snapshot.
+pub fn write_changed_accounts(
+    bank: &Bank,
+    since: &AccountVersions,
+    precision: &PrecisionTable,
+    columns: &AccountColumns,
+    order: AccountOrder,
+    report: &mut dyn ReportWriter,
+) -> Result<(), TransactorError> {
+    let changed = |account: &Account| since.changed(account);
+    write_selected_accounts(bank, changed, precision, columns, order, report)
+}
+
+fn write_selected_accounts(
+    bank: &Bank,
+    selected: impl Fn(&Account) -> bool,
+    precision: &PrecisionTable,
+    columns: &AccountColumns,
+    order: AccountOrder,
+    report: &mut dyn ReportWriter,
+) -> Result<(), TransactorError> {
+    let mut accounts: Vec<&Account> = bank.get_accounts().collect();
+    let sub_accounts = bank.sub_accounts();
+    let mut with_account;
+    let mut columns = columns;
+    if !sub_accounts.is_empty() && !columns.0.contains(&AccountColumn::Account) {
+        with_account = columns.clone();
+        let client = with_account
+            .0
+            .iter()
+            .position(|c| *c == AccountColumn::Client);
+        with_account.0.insert(
+            client.map_or(0, |client| client + 1),
+            AccountColumn::Account,
+        );
+        columns = &with_account;
+    }
+    accounts.extend(sub_accounts);
+    accounts.retain(|account| selected(account));
+    // The main account of a client comes before its sub-accounts
+    let account_order = |account: &Account| (!account.name().is_main(), account.name());
+    match order {
+        AccountOrder::Client => {
+            accounts.sort_by_key(|account| (account.client_id.0, account_order(account)))
+        }
+        AccountOrder::Total => accounts.sort_by_key(|account| {
+            // An overflowing total sorts last, it fails when it is written
+            let total = account.total().ok();
+            (
+                total.is_none(),
+                total,
+                account.client_id.0,
+                account_order(account),
+            )
+        }),
+    }
+    write_account_rows(
+        accounts.into_iter(),
+        bank.clients(),
+        precision,
+        columns,
+        report,
+    )
+}
+
+fn write_account_rows<'a>(
+    accounts: impl Iterator<Item = &'a Account>,
+    clients: &ClientDirectory,
+    precision: &PrecisionTable,
+    columns: &AccountColumns,
+    report: &mut dyn ReportWriter,
+) -> Result<(), TransactorError> {
+    let names: Vec<_> = columns
+        .columns()
+        .iter()
+        .map(|column| column.name())
+        .collect();
+    report.begin(&names)?;
+    for account in accounts {
+        let precision = precision.precision(account.currency());
+        let mut row = Vec::with_capacity(columns.columns().len());
+        for column in columns.columns() {
+            row.push(match column {
+                AccountColumn::Client => ReportValue::Integer(account.client_id.0.into()),
+                AccountColumn::Available => {
+                    ReportValue::Number(precision.round(account.available()).normalize())
+                }
+                AccountColumn::Held => {
+                    ReportValue::Number(precision.round(account.held()).normalize())
+                }
+                AccountColumn::Total => {
+                    ReportValue::Number(precision.round(account.total()?).normalize())
+                }
+                AccountColumn::Locked => ReportValue::Bool(account.is_locked()),
+                AccountColumn::Status => ReportValue::Text(account.status().to_string()),
+                AccountColumn::Currency => {
+                    account.currency().map_or(ReportValue::Empty, |currency| {
+                        ReportValue::Text(currency.to_string())
+                    })
+                }
+                AccountColumn::Version => ReportValue::Integer(account.version()),
+                AccountColumn::LastBatch => {
+                    account.last_batch().map_or(ReportValue::Empty, |batch| {
+                        ReportValue::Text(batch.as_str().to_string())
+                    })
+                }
+                AccountColumn::Fees => {
+                    ReportValue::Number(precision.round(account.total_fees()?).normalize())
+                }
+                AccountColumn::Account => ReportValue::Text(account.name().to_string()),
+                AccountColumn::Name => client_field(clients, account, ClientField::Name),
+                AccountColumn::Email => client_field(clients, account, ClientField::Email),
+                AccountColumn::RiskTier => client_field(clients, account, ClientField::RiskTier),
+            });
+        }
+        report.row(&row)?;
+    }
+    report.finish()
+}
+
+/// A field of the client's metadata, empty if the client is not listed or it was left out.
+fn client_field(clients: &ClientDirectory, account: &Account, field: ClientField) -> ReportValue {
+    clients
+        .field(account.client_id, field)
+        .map_or(ReportValue::Empty, |value| {
+            ReportValue::Text(value.to_string())
+        })
+}
+
+#[derive(Debug, Serialize)]
+struct StatementRecord {
+    tx: u32,
+    amount: Decimal,
+    state: String,
+    balance: Decimal,
+    reason: Option<String>,
+}
+
+/// One transaction on a statement, with amounts already rounded for display.
+struct StatementLine {
+    transaction: Transaction,
+    amount: Decimal,
+    state: TransactionState,
+    balance: Decimal,
+    /// Why the transaction was disputed, if it was and a reason was given.
+    reason: Option<ReasonCode>,
+}
+
+/// An account's balances and every transaction on it in the order they were applied, with the
+/// running balance. Amounts are rounded for display.
+struct Statement {
+    client: u16,
+    /// What is known of the client, shown as the template's details ask.
+    metadata: ClientMetadata,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    lines: Vec<StatementLine>,
+}
+
+impl Statement {
+    fn new(
+        account: &Account,
+        metadata: Option<&ClientMetadata>,
+        precision: &PrecisionTable,
+    ) -> Result<Self, TransactorError> {
+        let precision = precision.precision(account.currency());
+        let mut balance = Decimal::zero();
+        let mut lines = Vec::new();
+        for (transaction, state) in account.transactions() {
+            balance = balance.checked_add(transaction.amount()).ok_or(Overflow)?;
+            lines.push(StatementLine {
+                transaction,
+                amount: precision.round(transaction.amount()).normalize(),
+                state,
+                balance: precision.round(balance).normalize(),
+                reason: account.dispute_reason(transaction.transaction_id()),
+            });
+        }
+        let total = account.total()?;
+        Ok(Self {
+            client: account.client_id.0,
+            metadata: metadata.cloned().unwrap_or_default(),
+            available: precision.round(account.available()).normalize(),
+            held: precision.round(account.held()).normalize(),
+            total: precision.round(total).normalize(),
+            locked: account.is_locked(),
+            lines,
+        })
+    }
+}
+
+/// Write a statement of every transaction on the account in the order they were applied.
+/// The balance column is the running sum of the transaction amounts, it does not reflect funds
+/// later held or removed by disputes, the state column shows where each transaction ended up.
+/// Amounts are rounded to the precision of the account's currency.
+pub fn write_statement<W: io::Write>(
+    account: &Account,
+    precision: &PrecisionTable,
+    writer: W,
+) -> Result<(), TransactorError> {
+    let mut writer = Writer::from_writer(writer);
+    for line in Statement::new(account, None, precision)?.lines {
+        writer.serialize(StatementRecord {
+            tx: line.transaction.transaction_id().0,
+            amount: line.amount,
+            state: line.state.to_string(),
+            balance: line.balance,
+            reason: line.reason.map(|reason| reason.to_string()),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A field shown on a rendered statement.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StatementField {
+    Tx,
+    Amount,
+    State,
+    Balance,
+    Currency,
+    /// When the transaction happened upstream, in seconds since the unix epoch.
+    Timestamp,
+    /// The reason given for disputing or charging back the transaction.
+    Reason,
+}
+
+impl StatementField {
+    const ALL: [StatementField; 7] = [
+        StatementField::Tx,
+        StatementField::Amount,
+        StatementField::State,
+        StatementField::Balance,
+        StatementField::Currency,
+        StatementField::Timestamp,
+        StatementField::Reason,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            StatementField::Tx => "tx",
+            StatementField::Amount => "amount",
+            StatementField::State => "state",
+            StatementField::Balance => "balance",
+            StatementField::Currency => "currency",
+            StatementField::Timestamp => "timestamp",
+            StatementField::Reason => "reason",
+        }
+    }
+
+    fn value(self, line: &StatementLine) -> String {
+        match self {
+            StatementField::Tx => line.transaction.transaction_id().0.to_string(),
+            StatementField::Amount => line.amount.to_string(),
+            StatementField::State => line.state.to_string(),
+            StatementField::Balance => line.balance.to_string(),
+            StatementField::Currency => line
+                .transaction
+                .currency()
+                .map(|currency| currency.to_string())
+                .unwrap_or_default(),
+            StatementField::Timestamp => line
+                .transaction
+                .timestamp()
+                .map(|timestamp| timestamp.0.to_string())
+                .unwrap_or_default(),
+            StatementField::Reason => line
+                .reason
+                .map(|reason| reason.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for StatementField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StatementField::ALL
+            .iter()
+            .copied()
+            .find(|field| field.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = StatementField::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "Unknown statement field {}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How a rendered statement looks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatementTemplate {
+    pub title: String,
+    /// The location of an image shown above the title of HTML statements.
+    pub logo: Option<String>,
+    /// The columns of the transaction table, in order.
+    pub fields: Vec<StatementField>,
+    /// The client metadata shown with the balances, each only if the client has it.
+    pub details: Vec<ClientField>,
+}
+
+impl Default for StatementTemplate {
+    fn default() -> Self {
+        Self {
+            title: "Account statement".to_string(),
+            logo: None,
+            fields: StatementField::ALL[..4].to_vec(),
+            details: Vec::new(),
+        }
+    }
+}
+
+impl StatementTemplate {
+    /// Parse a comma separated list of field names such as `tx,amount,balance`.
+    pub fn with_fields(mut self, fields: &str) -> Result<Self, String> {
+        self.fields = fields
+            .split(',')
+            .map(|name| name.trim().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// Parse a comma separated list of client fields such as `name,email`.
+    pub fn with_details(mut self, details: &str) -> Result<Self, String> {
+        self.details = details
+            .split(',')
+            .map(|name| name.trim().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+}
+
+/// The rendered form of a statement.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StatementFormat {
+    Csv,
+    Html,
+    /// Only available with the `pdf` feature.
+    Pdf,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(StatementFormat::Csv),
+            "html" => Ok(StatementFormat::Html),
+            "pdf" => Ok(StatementFormat::Pdf),
+            _ => Err(format!(
+                "Unknown statement format {}, expected one of: csv, html, pdf",
+                s
+            )),
+        }
+    }
+}
+
+/// Render a statement of the account as a standalone HTML page, with the details of the client
+/// the template asks for.
+pub fn write_statement_html<W: io::Write>(
+    account: &Account,
+    metadata: Option<&ClientMetadata>,
+    precision: &PrecisionTable,
+    template: &StatementTemplate,
+    writer: W,
+) -> Result<(), TransactorError> {
+    html::write(
+        &Statement::new(account, metadata, precision)?,
+        template,
+        writer,
+    )
+}
+
+/// Render a statement of the account as a PDF document. The logo is not shown.
+#[cfg(feature = "pdf")]
+pub fn write_statement_pdf<W: io::Write>(
+    account: &Account,
+    metadata: Option<&ClientMetadata>,
+    precision: &PrecisionTable,
+    template: &StatementTemplate,
+    writer: W,
+) -> Result<(), TransactorError> {
+    pdf::write(
+        &Statement::new(account, metadata, precision)?,
+        template,
+        writer,
+    )
+}
+
+#[derive(Debug, Serialize)]
+struct TransactionMatchRecord {
+    client: u16,
+    tx: u32,
+    amount: Decimal,
+    state: String,
+}
+
+/// Write transactions found by a search, amounts are rounded to the precision of their currency.
+pub fn write_transaction_matches<W: io::Write>(
+    matches: &[TransactionMatch],
+    precision: &PrecisionTable,
+    writer: W,
+) -> Result<(), TransactorError> {
+    let mut writer = Writer::from_writer(writer);
+    for found in matches {
+        let precision = precision.precision(found.transaction.currency());
+        writer.serialize(TransactionMatchRecord {
+            client: found.client_id.0,
+            tx: found.transaction.transaction_id().0,
+            amount: precision.round(found.transaction.amount()).normalize(),
+            state: found.state.to_string(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct OpenDisputeRecord {
+    client: u16,
+    tx: u32,
+    opened: u64,
+    age: u64,
+    sla_status: &'static str,
+}
+
+/// Write the disputes still open with how long they have been open, in seconds, and whether that
+/// is within the SLA.
+pub fn write_open_disputes<W: io::Write>(
+    disputes: &[OpenDispute],
+    writer: W,
+) -> Result<(), TransactorError> {
+    let mut writer = Writer::from_writer(writer);
+    for dispute in disputes {
+        writer.serialize(OpenDisputeRecord {
+            client: dispute.client.0,
+            tx: dispute.transaction.0,
+            opened: dispute.opened.0,
+            age: dispute.age,
+            sla_status: dispute.status.name(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryRecord {
+    client: u16,
+    outstanding: Decimal,
+    recovered: Decimal,
+}
+
+/// Write the debts chargebacks left and are still owed, rounded to the precision of the currency
+/// of each account.
+pub fn write_recoveries<W: io::Write>(
+    recoveries: &[Recovery],
+    precision: &PrecisionTable,
+    writer: W,
+) -> Result<(), TransactorError> {
+    let mut writer = Writer::from_writer(writer);
+    for recovery in recoveries {
+        let precision = precision.precision(recovery.currency);
+        writer.serialize(RecoveryRecord {
+            client: recovery.client_id.0,
+            outstanding: precision.round(recovery.outstanding).normalize(),
+            recovered: precision.round(recovery.recovered).normalize(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, Transaction, TransactionId};
+
+    #[test]
+    fn statement_lists_transactions_with_running_balance() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(15, 1)),
+        )?;
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(2), Decimal::new(-5, 1)),
+        )?;
+        bank.dispute_with_reason(client, TransactionId(1), Some("fraud".parse()?))?;
+
+        let mut output = Vec::new();
+        write_statement(
+            bank.get_account(client).unwrap(),
+            &PrecisionTable::new(),
+            &mut output,
+        )?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,amount,state,balance,reason\n1,1.5,disputed,1.5,fraud\n2,-0.5,posted,1,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn accounts_are_written_with_selected_columns() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        bank.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(15, 1)),
+        )?;
+
+        let columns: AccountColumns = "client,total,version".parse().map_err(InvalidConfig)?;
+        let mut output = Vec::new();
+        write_accounts(
+            &bank,
+            &PrecisionTable::new(),
+            &columns,
+            AccountOrder::Client,
+            &mut CsvReportWriter::new(&mut output),
+        )?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,total,version\n1,1.5,1\n"
+        );
+        assert!("client,balance".parse::<AccountColumns>().is_err());
+
+        bank.transact(
+            ClientId(2),
+            Transaction::new(TransactionId(2), Decimal::new(5, 1)),
+        )?;
+        let mut output = Vec::new();
+        write_accounts(
+            &bank,
+            &PrecisionTable::new(),
+            &columns,
+            AccountOrder::Total,
+            &mut CsvReportWriter::new(&mut output),
+        )?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,total,version\n2,0.5,1\n1,1.5,1\n"
+        );
+
+        let metadata = ClientMetadata {
+            name: Some("Ada".to_string()),
+            ..ClientMetadata::default()
+        };
+        let bank =
+            bank.with_client_directory(ClientDirectory::new().with_client(ClientId(2), metadata));
+        let columns: AccountColumns = "client,name".parse().map_err(InvalidConfig)?;
+        let mut output = Vec::new();
+        write_accounts(
+            &bank,
+            &PrecisionTable::new(),
+            &columns,
+            AccountOrder::Client,
+            &mut CsvReportWriter::new(&mut output),
+        )?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,name\n1,\n2,Ada\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sub_accounts_add_an_account_column_after_the_client() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(3, 0)),
+        )?;
+        bank.transact(
+            ClientId(2),
+            Transaction::new(TransactionId(2), Decimal::new(1, 0)),
+        )?;
+        let columns: AccountColumns = "total,client".parse().map_err(InvalidConfig)?;
+        let write = |bank: &Bank, columns: &AccountColumns| -> Result<String, TransactorError> {
+            let mut output = Vec::new();
+            write_accounts(
+                bank,
+                &PrecisionTable::new(),
+                columns,
+                AccountOrder::Client,
+                &mut CsvReportWriter::new(&mut output),
+            )?;
+            Ok(String::from_utf8(output).unwrap())
+        };
+        assert_eq!(write(&bank, &columns)?, "total,client\n3,1\n1,2\n");
+
+        bank.in_account(client, "savings".parse()?, |bank| {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(3), Decimal::new(5, 0)),
+            )
+        })?;
+        assert_eq!(
+            write(&bank, &columns)?,
+            "total,client,account\n3,1,main\n5,1,savings\n1,2,main\n"
+        );
+        // An account column asked for stays where it was asked for
+        let columns: AccountColumns = "account,client".parse().map_err(InvalidConfig)?;
+        assert_eq!(
+            write(&bank, &columns)?,
+            "account,client\nmain,1\nsavings,1\nmain,2\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn only_accounts_changed_since_are_written() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        for client in 1..=3 {
+            bank.transact(
+                ClientId(client),
+                Transaction::new(TransactionId(client.into()), Decimal::new(10, 0)),
+            )?;
+        }
+        let loaded = AccountVersions::of(&bank);
+        bank.freeze(ClientId(2), None)?;
+        bank.transact(
+            ClientId(4),
+            Transaction::new(TransactionId(4), Decimal::new(1, 0)),
+        )?;
+        // Ignored operations leave the account as it was
+        bank.dispute_transaction(ClientId(3), TransactionId(9))?;
+
+        let columns: AccountColumns = "client,locked".parse().map_err(InvalidConfig)?;
+        let mut output = Vec::new();
+        write_changed_accounts(
+            &bank,
+            &loaded,
+            &PrecisionTable::new(),
+            &columns,
+            AccountOrder::Client,
+            &mut CsvReportWriter::new(&mut output),
+        )?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,locked\n2,true\n4,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn accounts_are_written_as_json_and_tables() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        bank.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(15, 1)),
+        )?;
+        bank.transact(
+            ClientId(22),
+            Transaction::new(TransactionId(2), Decimal::new(100, 0))
+                .with_currency("USD".parse().unwrap()),
+        )?;
+        let columns: AccountColumns = "client,total,currency".parse().map_err(InvalidConfig)?;
+
+        let mut json = Vec::new();
+        write_accounts(
+            &bank,
+            &PrecisionTable::new(),
+            &columns,
+            AccountOrder::Client,
+            &mut *OutputFormat::Json.writer(&mut json),
+        )?;
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "[\n  {\"client\":1,\"total\":1.5,\"currency\":null},\n  \
+             {\"client\":22,\"total\":100,\"currency\":\"USD\"}\n]\n"
+        );
+
+        let mut table = Vec::new();
+        write_accounts(
+            &bank,
+            &PrecisionTable::new(),
+            &columns,
+            AccountOrder::Client,
+            &mut *OutputFormat::Table.writer(&mut table),
+        )?;
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "client  total  currency\n------  -----  --------\n     1    1.5\n    22    100  USD\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn html_statement_escapes_and_shows_selected_fields() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(7), Decimal::new(15, 1)),
+        )?;
+        let template = StatementTemplate {
+            title: "Tom & Jerry's <Bank>".to_string(),
+            logo: Some("logo.png".to_string()),
+            ..StatementTemplate::default()
+        }
+        .with_fields("tx,balance")
+        .and_then(|template| template.with_details("name,risk_tier"))
+        .map_err(InvalidConfig)?;
+        let metadata = ClientMetadata {
+            name: Some("Ada <A>".to_string()),
+            ..ClientMetadata::default()
+        };
+
+        let mut output = Vec::new();
+        write_statement_html(
+            bank.get_account(client).unwrap(),
+            Some(&metadata),
+            &PrecisionTable::new(),
+            &template,
+            &mut output,
+        )?;
+        let html = String::from_utf8(output).unwrap();
+        assert!(html.contains("<h1>Tom &amp; Jerry&#39;s &lt;Bank&gt;</h1>"));
+        assert!(html.contains("<img src=\"logo.png\""));
+        assert!(html.contains("<tr><td>7</td><td>1.5</td></tr>"));
+        assert!(!html.contains("posted"));
+        assert!(html.contains("<dt>Name</dt><dd>Ada &lt;A&gt;</dd>"));
+        assert!(!html.contains("Risk tier"));
+        Ok(())
+    }
+
+    #[test]
+    fn html_statement_escapes_client_metadata_and_template_fields() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(1, 0)),
+        )?;
+        let template = StatementTemplate {
+            title: "<script>alert(1)</script>".to_string(),
+            logo: Some("logo.png\" onerror=\"alert(1)".to_string()),
+            ..StatementTemplate::default()
+        }
+        .with_details("name,email,risk_tier")
+        .map_err(InvalidConfig)?;
+        let metadata = ClientMetadata {
+            name: Some("<b>Ada</b> & co".to_string()),
+            email: Some("\"ada\"@example.com".to_string()),
+            risk_tier: Some("premium".parse().map_err(InvalidConfig)?),
+        };
+        let mut output = Vec::new();
+        write_statement_html(
+            bank.get_account(client).unwrap(),
+            Some(&metadata),
+            &PrecisionTable::new(),
+            &template,
+            &mut output,
+        )?;
+        let html = String::from_utf8(output).unwrap();
+        assert!(!html.contains("<script>") && !html.contains("<b>"));
+        assert!(html.contains("<title>&lt;script&gt;alert(1)&lt;/script&gt;</title>"));
+        assert!(html.contains("<img src=\"logo.png&quot; onerror=&quot;alert(1)\" alt=\"\">"));
+        assert!(html.contains("<dd>&lt;b&gt;Ada&lt;/b&gt; &amp; co</dd>"));
+        assert!(html.contains("<dd>&quot;ada&quot;@example.com</dd>"));
+        assert!(html.contains("<dt>Risk tier</dt><dd>premium</dd>"));
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::prelude::*;
+
+use super::{ReportValue, ReportWriter};
+use crate::audit::transaction_amount;
+use crate::bank::{AccountStatus, Bank, Outcome};
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::InputRecord;
+
+/// The rows of one type applied in a run and the sum of their amounts.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TypeTotals {
+    pub rows: u64,
+    /// The unsigned amounts of the rows added up, zero for rows without one such as disputes.
+    pub amount: Decimal,
+}
+
+/// A summary of a run on a page: the funds of every account, how many accounts are in each
+/// status and what the rows applied added up to by type. With a journal it also holds the total
+/// debits and credits posted, which must be equal.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AggregateReport {
+    pub available: Decimal,
+    pub held: Decimal,
+    /// The total funds of locked accounts.
+    pub locked: Decimal,
+    /// The accounts in each status, in the order of `AccountStatus::ALL`.
+    pub accounts_by_status: Vec<(AccountStatus, u64)>,
+    pub by_type: BTreeMap<&'static str, TypeTotals>,
+    /// The debits and credits of the journal, if one was kept.
+    pub journal: Option<(Decimal, Decimal)>,
+}
+
+impl AggregateReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the record to the totals of its type if it was applied.
+    pub fn record(
+        &mut self,
+        record: &InputRecord,
+        result: &Result<Outcome, TransactorError>,
+    ) -> Result<(), TransactorError> {
+        if !matches!(result, Ok(Outcome::Applied)) {
+            return Ok(());
+        }
+        let totals = self.by_type.entry(record.command.name()).or_default();
+        totals.rows += 1;
+        if let (Some(amount), _) = transaction_amount(record) {
+            totals.amount = totals.amount.checked_add(amount).ok_or(Overflow)?;
+        }
+        Ok(())
+    }
+
+    /// Take the funds and statuses of every account of the bank, sub-accounts included.
+    pub fn count_accounts(&mut self, bank: &Bank) -> Result<(), TransactorError> {
+        let (mut available, mut held, mut locked) =
+            (Decimal::zero(), Decimal::zero(), Decimal::zero());
+        let mut statuses: Vec<(AccountStatus, u64)> = AccountStatus::ALL
+            .iter()
+            .map(|status| (*status, 0))
+            .collect();
+        for account in bank.get_accounts().chain(bank.sub_accounts()) {
+            available = available.checked_add(account.available()).ok_or(Overflow)?;
+            held = held.checked_add(account.held()).ok_or(Overflow)?;
+            if account.is_locked() {
+                locked = locked.checked_add(account.total()?).ok_or(Overflow)?;
+            }
+            if let Some((_, count)) = statuses
+                .iter_mut()
+                .find(|(status, _)| *status == account.status())
+            {
+                *count += 1;
+            }
+        }
+        self.available = available;
+        self.held = held;
+        self.locked = locked;
+        self.accounts_by_status = statuses;
+        Ok(())
+    }
+
+    /// Check the journal balances, as every entry posted to it does.
+    pub fn check(&self) -> Result<(), TransactorError> {
+        match self.journal {
+            Some((debits, credits)) if debits != credits => {
+                Err(UnbalancedJournal { debits, credits })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write a row for each total, as the group it is in, its name, the rows or accounts it
+    /// counts and the funds it adds up to.
+    pub fn write(&self, report: &mut dyn ReportWriter) -> Result<(), TransactorError> {
+        report.begin(&["group", "name", "count", "amount"])?;
+        let text = |text: &str| ReportValue::Text(text.to_string());
+        let funds = |name: &str, amount: Decimal| {
+            [
+                text("funds"),
+                text(name),
+                ReportValue::Empty,
+                ReportValue::Number(amount.normalize()),
+            ]
+        };
+        report.row(&funds("available", self.available))?;
+        report.row(&funds("held", self.held))?;
+        report.row(&funds("locked", self.locked))?;
+        for (status, accounts) in &self.accounts_by_status {
+            report.row(&[
+                text("accounts"),
+                text(&status.to_string()),
+                ReportValue::Integer(*accounts),
+                ReportValue::Empty,
+            ])?;
+        }
+        for (kind, totals) in &self.by_type {
+            report.row(&[
+                text("type"),
+                text(kind),
+                ReportValue::Integer(totals.rows),
+                ReportValue::Number(totals.amount.normalize()),
+            ])?;
+        }
+        if let Some((debits, credits)) = self.journal {
+            for (name, amount) in [("debits", debits), ("credits", credits)] {
+                report.row(&[
+                    text("journal"),
+                    text(name),
+                    ReportValue::Empty,
+                    ReportValue::Number(amount.normalize()),
+                ])?;
+            }
+        }
+        report.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{InputReader, InputSchema};
+    use crate::journal::{ClientBalances, Journal};
+    use crate::report::CsvReportWriter;
+
+    #[test]
+    fn aggregate_totals_the_run_and_its_balanced_journal() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            deposit,2,2,5.5\n\
+            withdrawal,1,3,4\n\
+            withdrawal,2,4,100\n\
+            dispute,2,2,\n\
+            chargeback,2,2,\n";
+        let mut bank = Bank::new();
+        let mut aggregate = AggregateReport::new();
+        let mut journal = Journal::new(std::io::sink());
+        for record in InputReader::from_reader(input.as_bytes(), InputSchema::Auto)? {
+            let record = record?;
+            let before = ClientBalances::of_record(&bank, &record);
+            let result = record.command.apply(&mut bank);
+            journal.post_record(&record, &result, &before, &bank)?;
+            aggregate.record(&record, &result)?;
+        }
+        aggregate.journal = Some(journal.totals());
+        aggregate.count_accounts(&bank)?;
+        aggregate.check()?;
+
+        let mut output = Vec::new();
+        aggregate.write(&mut CsvReportWriter::new(&mut output))?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "group,name,count,amount\n\
+             funds,available,,6\n\
+             funds,held,,0\n\
+             funds,locked,,0\n\
+             accounts,active,1,\n\
+             accounts,frozen,1,\n\
+             accounts,closed,0,\n\
+             accounts,under-review,0,\n\
+             type,chargeback,1,0\n\
+             type,deposit,2,15.5\n\
+             type,dispute,1,0\n\
+             type,withdrawal,1,4\n\
+             journal,debits,,30.5\n\
+             journal,credits,,30.5\n"
+        );
+
+        aggregate.journal = Some((Decimal::new(31, 0), Decimal::new(30, 0)));
+        assert!(matches!(aggregate.check(), Err(UnbalancedJournal { .. })));
+        Ok(())
+    }
+}
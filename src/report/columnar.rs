@@ -0,0 +1,304 @@
+use std::io;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use rust_decimal::prelude::*;
+
+use super::{ReportValue, ReportWriter};
+use crate::error::{TransactorError, TransactorError::*};
+
+/// The rows written to the file in each record batch.
+const BATCH_ROWS: usize = 65_536;
+
+/// The decimal places of number columns, enough for any amount the bank holds.
+const DECIMAL_SCALE: u32 = 18;
+
+/// Writes rows as an Apache Parquet or Arrow IPC file, a record batch at a time so long reports
+/// are never held whole. Each column takes its type from its values in the first batch: unsigned
+/// integers, decimals, booleans, or text if they are of more than one kind. Empty values are
+/// nulls.
+pub struct ColumnarReportWriter<W: io::Write> {
+    output: Option<W>,
+    parquet: bool,
+    columns: Vec<String>,
+    rows: Vec<Vec<ReportValue>>,
+    /// The schema of the file, taken from the first batch.
+    schema: Option<SchemaRef>,
+    file: Option<BatchWriter<W>>,
+}
+
+enum BatchWriter<W: io::Write> {
+    /// The Parquet writer needs an output it can send between threads, so each batch is encoded
+    /// into a buffer and copied to the output from there.
+    Parquet {
+        writer: ArrowWriter<Vec<u8>>,
+        output: W,
+    },
+    Arrow(FileWriter<W>),
+}
+
+impl<W: io::Write> ColumnarReportWriter<W> {
+    pub fn parquet(writer: W) -> Self {
+        Self::new(writer, true)
+    }
+
+    pub fn arrow(writer: W) -> Self {
+        Self::new(writer, false)
+    }
+
+    fn new(writer: W, parquet: bool) -> Self {
+        Self {
+            output: Some(writer),
+            parquet,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            schema: None,
+            file: None,
+        }
+    }
+
+    /// Write the rows held as a record batch, opening the file with their schema first.
+    fn write_batch(&mut self) -> Result<(), TransactorError> {
+        let schema = match &self.schema {
+            Some(schema) => Arc::clone(schema),
+            None => self.open()?,
+        };
+        let columns = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(index, field)| self.column(index, field))
+            .collect::<Result<Vec<_>, _>>()?;
+        let batch = RecordBatch::try_new(schema, columns).map_err(arrow_error)?;
+        match &mut self.file {
+            Some(BatchWriter::Parquet { writer, output }) => {
+                writer.write(&batch).map_err(parquet_error)?;
+                output.write_all(writer.inner_mut())?;
+                writer.inner_mut().clear();
+            }
+            Some(BatchWriter::Arrow(writer)) => writer.write(&batch).map_err(arrow_error)?,
+            None => {}
+        }
+        self.rows.clear();
+        Ok(())
+    }
+
+    /// Open the file with the schema of the rows held.
+    fn open(&mut self) -> Result<SchemaRef, TransactorError> {
+        let schema = Arc::new(self.infer_schema());
+        let output = self
+            .output
+            .take()
+            .ok_or_else(|| ColumnarError("the report has already been written".to_string()))?;
+        self.file = Some(match self.parquet {
+            true => BatchWriter::Parquet {
+                writer: ArrowWriter::try_new(Vec::new(), Arc::clone(&schema), None)
+                    .map_err(parquet_error)?,
+                output,
+            },
+            false => BatchWriter::Arrow(FileWriter::try_new(output, &schema).map_err(arrow_error)?),
+        });
+        self.schema = Some(Arc::clone(&schema));
+        Ok(schema)
+    }
+
+    /// The schema of the rows held, text columns if there are none.
+    fn infer_schema(&self) -> Schema {
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, name)| Field::new(name, self.column_type(index), true))
+            .collect();
+        Schema::new(fields)
+    }
+
+    fn column_type(&self, index: usize) -> DataType {
+        let mut types = self.rows.iter().filter_map(|row| match &row[index] {
+            ReportValue::Integer(_) => Some(DataType::UInt64),
+            ReportValue::Number(_) => Some(decimal_type()),
+            ReportValue::Bool(_) => Some(DataType::Boolean),
+            ReportValue::Text(_) => Some(DataType::Utf8),
+            ReportValue::Empty => None,
+        });
+        let first = types.next().unwrap_or(DataType::Utf8);
+        types.fold(first, |column, value| match (column, value) {
+            (column, value) if column == value => column,
+            (
+                DataType::UInt64 | DataType::Decimal128(..),
+                DataType::UInt64 | DataType::Decimal128(..),
+            ) => decimal_type(),
+            _ => DataType::Utf8,
+        })
+    }
+
+    /// The values of a column of the rows held, as the type of its field.
+    fn column(&self, index: usize, field: &Field) -> Result<ArrayRef, TransactorError> {
+        let values = self.rows.iter().map(|row| &row[index]);
+        let mismatch = || {
+            InvalidConfig(format!(
+                "the {} column of the report changes type after its first {} rows",
+                field.name(),
+                BATCH_ROWS
+            ))
+        };
+        Ok(match field.data_type() {
+            DataType::UInt64 => Arc::new(
+                values
+                    .map(|value| match value {
+                        ReportValue::Integer(integer) => Ok(Some(*integer)),
+                        ReportValue::Empty => Ok(None),
+                        _ => Err(mismatch()),
+                    })
+                    .collect::<Result<UInt64Array, _>>()?,
+            ),
+            DataType::Decimal128(..) => Arc::new(
+                values
+                    .map(|value| match value {
+                        ReportValue::Number(number) => scaled(*number).map(Some),
+                        ReportValue::Integer(integer) => scaled(Decimal::from(*integer)).map(Some),
+                        ReportValue::Empty => Ok(None),
+                        _ => Err(mismatch()),
+                    })
+                    .collect::<Result<Decimal128Array, _>>()?
+                    .with_data_type(decimal_type()),
+            ),
+            DataType::Boolean => Arc::new(
+                values
+                    .map(|value| match value {
+                        ReportValue::Bool(value) => Ok(Some(*value)),
+                        ReportValue::Empty => Ok(None),
+                        _ => Err(mismatch()),
+                    })
+                    .collect::<Result<BooleanArray, _>>()?,
+            ),
+            _ => Arc::new(
+                values
+                    .map(|value| match value {
+                        ReportValue::Empty => None,
+                        value => Some(value.to_string()),
+                    })
+                    .collect::<StringArray>(),
+            ),
+        })
+    }
+}
+
+impl<W: io::Write> ReportWriter for ColumnarReportWriter<W> {
+    fn begin(&mut self, columns: &[&str]) -> Result<(), TransactorError> {
+        self.columns = columns.iter().map(|column| column.to_string()).collect();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ReportValue]) -> Result<(), TransactorError> {
+        self.rows.push(values.to_vec());
+        if self.rows.len() == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactorError> {
+        if !self.rows.is_empty() || self.file.is_none() {
+            self.write_batch()?;
+        }
+        match &mut self.file {
+            Some(BatchWriter::Parquet { writer, output }) => {
+                writer.finish().map_err(parquet_error)?;
+                output.write_all(writer.inner_mut())?;
+                writer.inner_mut().clear();
+                output.flush()?;
+            }
+            Some(BatchWriter::Arrow(writer)) => {
+                writer.finish().map_err(arrow_error)?;
+                writer.get_mut().flush()?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn decimal_type() -> DataType {
+    DataType::Decimal128(38, DECIMAL_SCALE as i8)
+}
+
+/// The number as a count of the smallest unit of the decimal columns.
+fn scaled(number: Decimal) -> Result<i128, TransactorError> {
+    let parts = number.round_dp(DECIMAL_SCALE).unpack();
+    let mantissa = i128::from(parts.hi) << 64 | i128::from(parts.mid) << 32 | i128::from(parts.lo);
+    let scaled = 10_i128
+        .checked_pow(DECIMAL_SCALE - parts.scale)
+        .and_then(|unit| mantissa.checked_mul(unit))
+        .ok_or(Overflow)?;
+    Ok(if parts.is_negative { -scaled } else { scaled })
+}
+
+fn arrow_error(e: ArrowError) -> TransactorError {
+    ColumnarError(e.to_string())
+}
+
+fn parquet_error(e: ParquetError) -> TransactorError {
+    ColumnarError(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Decimal128Type, UInt64Type};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::{self, File};
+
+    #[test]
+    fn reports_are_written_as_parquet_and_arrow() -> Result<(), TransactorError> {
+        let write = |report: &mut dyn ReportWriter| -> Result<(), TransactorError> {
+            report.begin(&["client", "available", "locked", "currency"])?;
+            report.row(&[
+                ReportValue::Integer(1),
+                ReportValue::Number(Decimal::new(15, 1)),
+                ReportValue::Bool(false),
+                ReportValue::Text("EUR".to_string()),
+            ])?;
+            report.row(&[
+                ReportValue::Integer(2),
+                ReportValue::Integer(3),
+                ReportValue::Bool(true),
+                ReportValue::Empty,
+            ])?;
+            report.finish()
+        };
+        let path = std::env::temp_dir().join("transactor-columnar-report-test.parquet");
+        write(&mut ColumnarReportWriter::parquet(File::create(&path)?))?;
+        let mut arrow = Vec::new();
+        write(&mut ColumnarReportWriter::arrow(&mut arrow))?;
+
+        let from_parquet = ParquetRecordBatchReaderBuilder::try_new(File::open(&path)?)
+            .and_then(|builder| builder.build())
+            .map_err(parquet_error)?;
+        fs::remove_file(&path)?;
+        let from_arrow = arrow_ipc::reader::FileReader::try_new(io::Cursor::new(arrow), None)
+            .map_err(arrow_error)?;
+        for batches in [
+            from_parquet.collect::<Result<Vec<_>, _>>(),
+            from_arrow.collect::<Result<Vec<_>, _>>(),
+        ] {
+            let batch = &batches.map_err(arrow_error)?[0];
+            assert_eq!(batch.num_rows(), 2);
+            assert_eq!(batch.schema().field(0).data_type(), &DataType::UInt64);
+            assert_eq!(batch.column(0).as_primitive::<UInt64Type>().value(1), 2);
+            let available = batch.column(1).as_primitive::<Decimal128Type>();
+            assert_eq!(available.value(0), 15 * 10_i128.pow(DECIMAL_SCALE - 1));
+            assert_eq!(batch.schema().field(2).data_type(), &DataType::Boolean);
+            let currency = batch.column(3).as_string::<i32>();
+            assert_eq!(currency.value(0), "EUR");
+            assert!(currency.is_null(1));
+        }
+        Ok(())
+    }
+}
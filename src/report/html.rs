@@ -0,0 +1,94 @@
+use std::io;
+
+use super::{Statement, StatementTemplate};
+use crate::error::TransactorError;
+
+pub(super) fn write<W: io::Write>(
+    statement: &Statement,
+    template: &StatementTemplate,
+    mut writer: W,
+) -> Result<(), TransactorError> {
+    let title = escape(&template.title);
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", title)?;
+    writeln!(
+        writer,
+        "<style>body{{font-family:sans-serif}}table{{border-collapse:collapse}}\
+         th,td{{border:1px solid #ccc;padding:4px 8px;text-align:right}}</style>"
+    )?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    if let Some(logo) = &template.logo {
+        writeln!(writer, "<img src=\"{}\" alt=\"\">", escape(logo))?;
+    }
+    writeln!(writer, "<h1>{}</h1>", title)?;
+    writeln!(writer, "<dl>")?;
+    writeln!(writer, "<dt>Client</dt><dd>{}</dd>", statement.client)?;
+    for field in &template.details {
+        if let Some(value) = statement.metadata.field(*field) {
+            writeln!(
+                writer,
+                "<dt>{}</dt><dd>{}</dd>",
+                field.label(),
+                escape(value)
+            )?;
+        }
+    }
+    writeln!(writer, "<dt>Available</dt><dd>{}</dd>", statement.available)?;
+    writeln!(writer, "<dt>Held</dt><dd>{}</dd>", statement.held)?;
+    writeln!(writer, "<dt>Total</dt><dd>{}</dd>", statement.total)?;
+    if statement.locked {
+        writeln!(writer, "<dt>Status</dt><dd>Locked</dd>")?;
+    }
+    writeln!(writer, "</dl>")?;
+    writeln!(writer, "<table>")?;
+    write!(writer, "<tr>")?;
+    for field in &template.fields {
+        write!(writer, "<th>{}</th>", field.name())?;
+    }
+    writeln!(writer, "</tr>")?;
+    for line in &statement.lines {
+        write!(writer, "<tr>")?;
+        for field in &template.fields {
+            write!(writer, "<td>{}</td>", escape(&field.value(line)))?;
+        }
+        writeln!(writer, "</tr>")?;
+    }
+    writeln!(writer, "</table>")?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn markup_and_quotes_are_escaped() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("Zoë €5"), "Zoë €5");
+    }
+}
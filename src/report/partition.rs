@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use csv::Writer;
+use rust_decimal::prelude::*;
+
+use super::{write_account_rows, AccountColumns, OutputFormat};
+use crate::bank::{Account, Bank};
+use crate::currency::PrecisionTable;
+use crate::error::TransactorError;
+
+/// The file listing every partition written and its row count.
+const MANIFEST: &str = "manifest.csv";
+
+/// What the account report can be split by.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PartitionKey {
+    /// The currency of the account, `none` if it has never transacted in one.
+    Currency,
+    /// The client id modulo the number of shards.
+    ClientShard(u16),
+}
+
+impl PartitionKey {
+    /// The directory name of the partition holding an account, such as `client_shard=03`.
+    fn directory(self, account: &Account) -> String {
+        match self {
+            PartitionKey::Currency => format!(
+                "currency={}",
+                account
+                    .currency()
+                    .map_or_else(|| "none".to_string(), |currency| currency.to_string())
+            ),
+            PartitionKey::ClientShard(shards) => format!(
+                "client_shard={:0width$}",
+                account.client_id.0 % shards,
+                width = (shards - 1).to_string().len()
+            ),
+        }
+    }
+}
+
+impl FromStr for PartitionKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "currency" => Ok(PartitionKey::Currency),
+            Some(("client_shard", shards)) => shards
+                .parse()
+                .ok()
+                .filter(|shards| *shards > 0)
+                .map(PartitionKey::ClientShard)
+                .ok_or_else(|| format!("Invalid number of client shards {}", shards)),
+            _ => Err(format!(
+                "Unknown partition {}, expected one of: currency, client_shard:N",
+                s
+            )),
+        }
+    }
+}
+
+/// The keys the account report is partitioned by, outermost directory first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Partitioning(Vec<PartitionKey>);
+
+impl Partitioning {
+    pub fn new(keys: Vec<PartitionKey>) -> Self {
+        Self(keys)
+    }
+
+    pub fn keys(&self) -> &[PartitionKey] {
+        &self.0
+    }
+
+    /// The path of the partition holding an account, relative to the output directory.
+    fn path(&self, account: &Account, format: OutputFormat) -> PathBuf {
+        let mut path: PathBuf = self.0.iter().map(|key| key.directory(account)).collect();
+        path.push(format!("accounts.{}", format.extension()));
+        path
+    }
+}
+
+impl FromStr for Partitioning {
+    type Err = String;
+
+    /// Parse a comma separated list of keys such as `currency,client_shard:16`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split(',')
+            .map(|key| key.trim().parse())
+            .collect::<Result<Vec<PartitionKey>, _>>()?;
+        let repeated = keys.iter().enumerate().any(|(index, key)| {
+            keys[..index]
+                .iter()
+                .any(|other| std::mem::discriminant(other) == std::mem::discriminant(key))
+        });
+        if repeated {
+            return Err(format!("Partition keys repeated in {}", s));
+        }
+        Ok(Self(keys))
+    }
+}
+
+/// Write the account report split into a file per partition under `directory`, laid out as
+/// `currency=USD/client_shard=03/accounts.csv`, along with a manifest of the partitions and their
+/// row counts. Only partitions with accounts are written and rows are ordered by client so the
+/// same bank always gives the same files. The manifest is always csv.
+pub fn write_partitioned_accounts(
+    bank: &Bank,
+    precision: &PrecisionTable,
+    columns: &AccountColumns,
+    format: OutputFormat,
+    partitioning: &Partitioning,
+    directory: &Path,
+) -> Result<(), TransactorError> {
+    let mut partitions: BTreeMap<PathBuf, Vec<&Account>> = BTreeMap::new();
+    for account in bank.get_accounts() {
+        partitions
+            .entry(partitioning.path(account, format))
+            .or_default()
+            .push(account);
+    }
+    fs::create_dir_all(directory)?;
+    let mut manifest = Writer::from_path(directory.join(MANIFEST))?;
+    manifest.write_record(["path", "rows"])?;
+    for (path, mut accounts) in partitions {
+        accounts.sort_by_key(|account| account.client_id.0);
+        let file = directory.join(&path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_account_rows(
+            accounts.iter().copied(),
+            bank.clients(),
+            precision,
+            columns,
+            &mut *format.writer(BufWriter::new(File::create(file)?)),
+        )?;
+        // Forward slashes whatever the platform so the manifest reads the same everywhere
+        let components: Vec<_> = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        manifest.write_record([components.join("/"), accounts.len().to_string()])?;
+    }
+    manifest.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{ClientId, Transaction, TransactionId};
+
+    #[test]
+    fn accounts_are_split_by_currency_and_client_shard() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        for client in 1..=3 {
+            bank.transact(
+                ClientId(client),
+                Transaction::new(TransactionId(client as u32), Decimal::new(1, 0))
+                    .with_currency("USD".parse().unwrap()),
+            )?;
+        }
+        bank.transact(
+            ClientId(12),
+            Transaction::new(TransactionId(12), Decimal::new(1, 0)),
+        )?;
+        let directory = std::env::temp_dir().join("transactor-partition-test");
+        let _ = fs::remove_dir_all(&directory);
+        write_partitioned_accounts(
+            &bank,
+            &PrecisionTable::default(),
+            &AccountColumns::default(),
+            OutputFormat::Csv,
+            &"currency,client_shard:10"
+                .parse()
+                .map_err(TransactorError::InvalidConfig)?,
+            &directory,
+        )?;
+        assert_eq!(
+            fs::read_to_string(directory.join(MANIFEST))?,
+            "path,rows\n\
+             currency=USD/client_shard=1/accounts.csv,1\n\
+             currency=USD/client_shard=2/accounts.csv,1\n\
+             currency=USD/client_shard=3/accounts.csv,1\n\
+             currency=none/client_shard=2/accounts.csv,1\n"
+        );
+        assert!(
+            fs::read_to_string(directory.join("currency=none/client_shard=2/accounts.csv"))?
+                .contains("\n12,")
+        );
+        assert!("client_shard:0".parse::<Partitioning>().is_err());
+        assert!("currency,currency".parse::<Partitioning>().is_err());
+        Ok(())
+    }
+}
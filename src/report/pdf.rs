@@ -0,0 +1,197 @@
+use std::io;
+
+use super::{Statement, StatementTemplate};
+use crate::error::TransactorError;
+
+const PAGE_WIDTH: u32 = 595;
+const PAGE_HEIGHT: u32 = 842;
+const MARGIN: u32 = 50;
+const LINE_HEIGHT: u32 = 14;
+const COLUMN_WIDTH: usize = 14;
+
+/// Write the statement as a minimal PDF: A4 pages of Courier text with the title, the balances
+/// and then the transaction table, split across as many pages as needed.
+pub(super) fn write<W: io::Write>(
+    statement: &Statement,
+    template: &StatementTemplate,
+    mut writer: W,
+) -> Result<(), TransactorError> {
+    let mut text = vec![
+        template.title.clone(),
+        String::new(),
+        format!("Client     {}", statement.client),
+    ];
+    for field in &template.details {
+        if let Some(value) = statement.metadata.field(*field) {
+            text.push(format!("{:<10} {}", field.label(), value));
+        }
+    }
+    text.extend([
+        format!("Available  {}", statement.available),
+        format!("Held       {}", statement.held),
+        format!("Total      {}", statement.total),
+    ]);
+    if statement.locked {
+        text.push("Status     Locked".to_string());
+    }
+    text.push(String::new());
+    text.push(row(template
+        .fields
+        .iter()
+        .map(|field| field.name().to_string())));
+    for line in &statement.lines {
+        text.push(row(template.fields.iter().map(|field| field.value(line))));
+    }
+
+    let lines_per_page = ((PAGE_HEIGHT - 2 * MARGIN) / LINE_HEIGHT) as usize;
+    let pages: Vec<&[String]> = text.chunks(lines_per_page).collect();
+    // Objects are the catalog, the page tree, the font and then a page and its content per page
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|page| format!("{} 0 R", 4 + 2 * page))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (page, lines) in pages.iter().enumerate() {
+        let mut content = format!(
+            "BT /F1 10 Tf {} TL {} {} Td",
+            LINE_HEIGHT,
+            MARGIN,
+            PAGE_HEIGHT - MARGIN
+        );
+        for line in lines.iter() {
+            content.push_str(&format!(" ({}) '", escape(line)));
+        }
+        content.push_str(" ET");
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            5 + 2 * page
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            content.len(),
+            content
+        ));
+    }
+
+    let mut output = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(output.len());
+        output.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+    }
+    let xref = output.len();
+    output.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        output.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    output.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    writer.write_all(&output)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn row(values: impl Iterator<Item = String>) -> String {
+    values
+        .map(|value| format!("{:<width$}", value, width = COLUMN_WIDTH))
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Escape text for a PDF string literal, characters outside ascii cannot be shown by the standard
+/// fonts so are replaced.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{Bank, ClientId, ClientMetadata, Transaction, TransactionId};
+    use crate::currency::PrecisionTable;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn statements_are_split_across_pages_of_a_well_formed_document() -> Result<(), TransactorError>
+    {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        for tx in 1..=60 {
+            bank.transact(
+                client,
+                Transaction::new(TransactionId(tx), Decimal::new(1, 0)),
+            )?;
+        }
+        let metadata = ClientMetadata {
+            name: Some("Zoë (née Smith) \\ Co".to_string()),
+            ..ClientMetadata::default()
+        };
+        let template = StatementTemplate {
+            title: "Tom & Jerry's (Bank)".to_string(),
+            ..StatementTemplate::default()
+        }
+        .with_details("name")
+        .map_err(TransactorError::InvalidConfig)?;
+        let statement = Statement::new(
+            bank.get_account(client).unwrap(),
+            Some(&metadata),
+            &PrecisionTable::new(),
+        )?;
+        let mut output = Vec::new();
+        write(&statement, &template, &mut output)?;
+        let pdf = String::from_utf8(output).unwrap();
+
+        assert!(pdf.starts_with("%PDF-1.4\n") && pdf.ends_with("%%EOF\n"));
+        // The 69 lines of text take two pages of 53
+        assert!(pdf.contains("/Kids [4 0 R 6 0 R] /Count 2"));
+        assert!(pdf.contains("(Tom & Jerry's \\(Bank\\)) '"));
+        assert!(pdf.contains("(Name       Zo? \\(n?e Smith\\) \\\\ Co) '"));
+        assert!(pdf.contains("(60            1             posted        60) '"));
+
+        // Every object is where the cross-reference table says it is
+        let xref = pdf.rfind("startxref\n").unwrap();
+        let xref: usize = pdf[xref + 10..].lines().next().unwrap().parse().unwrap();
+        assert!(pdf[xref..].starts_with("xref\n0 8\n"));
+        let offsets = pdf[xref..].lines().skip(3).take(7);
+        for (index, offset) in offsets.enumerate() {
+            let offset: usize = offset[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", index + 1)));
+        }
+        for stream in pdf.split("/Length ").skip(1) {
+            let (length, content) = stream.split_once(" >>\nstream\n").unwrap();
+            let length: usize = length.parse().unwrap();
+            assert!(content[length..].starts_with("\nendstream"));
+        }
+        Ok(())
+    }
+}
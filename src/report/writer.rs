@@ -0,0 +1,288 @@
+use std::fmt;
+use std::io;
+
+use csv::Writer;
+use rust_decimal::prelude::*;
+
+use crate::error::{TransactorError, TransactorError::*};
+
+/// A value in a row of a report, typed so each format can present it properly.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReportValue {
+    Text(String),
+    Number(Decimal),
+    Integer(u64),
+    Bool(bool),
+    Empty,
+}
+
+impl ReportValue {
+    fn is_numeric(&self) -> bool {
+        matches!(self, ReportValue::Number(_) | ReportValue::Integer(_))
+    }
+}
+
+impl fmt::Display for ReportValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportValue::Text(text) => f.write_str(text),
+            ReportValue::Number(number) => write!(f, "{}", number),
+            ReportValue::Integer(integer) => write!(f, "{}", integer),
+            ReportValue::Bool(value) => write!(f, "{}", value),
+            ReportValue::Empty => Ok(()),
+        }
+    }
+}
+
+/// Writes a table of rows in one output format. `begin` is called once with the column names,
+/// then `row` for each row and finally `finish`.
+pub trait ReportWriter {
+    fn begin(&mut self, columns: &[&str]) -> Result<(), TransactorError>;
+
+    fn row(&mut self, values: &[ReportValue]) -> Result<(), TransactorError>;
+
+    fn finish(&mut self) -> Result<(), TransactorError>;
+}
+
+/// The formats reports can be written in.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// An array of objects keyed by column name.
+    Json,
+    /// Columns aligned with spaces, for reading in a terminal.
+    Table,
+    /// An Apache Parquet file, only written with the parquet feature.
+    Parquet,
+    /// An Arrow IPC file, only written with the parquet feature.
+    Arrow,
+}
+
+impl OutputFormat {
+    /// The file extension for output in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Table => "txt",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
+        }
+    }
+
+    /// Fail if writing this format needs a feature the build does not have.
+    pub fn check_available(self) -> Result<(), TransactorError> {
+        match self {
+            OutputFormat::Parquet | OutputFormat::Arrow if cfg!(not(feature = "parquet")) => {
+                Err(InvalidConfig(format!(
+                    "{} output needs the parquet feature",
+                    self.extension()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn writer<'a, W: io::Write + 'a>(self, writer: W) -> Box<dyn ReportWriter + 'a> {
+        match self {
+            OutputFormat::Csv => Box::new(CsvReportWriter::new(writer)),
+            OutputFormat::Json => Box::new(JsonReportWriter::new(writer)),
+            OutputFormat::Table => Box::new(TableReportWriter::new(writer)),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => Box::new(super::ColumnarReportWriter::parquet(writer)),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Arrow => Box::new(super::ColumnarReportWriter::arrow(writer)),
+            #[cfg(not(feature = "parquet"))]
+            OutputFormat::Parquet | OutputFormat::Arrow => Box::new(Unavailable(self)),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" => Ok(OutputFormat::Arrow),
+            _ => Err(format!(
+                "Unknown output format {}, expected one of: csv, json, table, parquet, arrow",
+                s
+            )),
+        }
+    }
+}
+
+/// Stands in for the writer of a format the build does not have, failing as soon as it is used.
+#[cfg(not(feature = "parquet"))]
+struct Unavailable(OutputFormat);
+
+#[cfg(not(feature = "parquet"))]
+impl ReportWriter for Unavailable {
+    fn begin(&mut self, _: &[&str]) -> Result<(), TransactorError> {
+        self.0.check_available()
+    }
+
+    fn row(&mut self, _: &[ReportValue]) -> Result<(), TransactorError> {
+        self.0.check_available()
+    }
+
+    fn finish(&mut self) -> Result<(), TransactorError> {
+        self.0.check_available()
+    }
+}
+
+pub struct CsvReportWriter<W: io::Write> {
+    writer: Writer<W>,
+}
+
+impl<W: io::Write> CsvReportWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Writer::from_writer(writer),
+        }
+    }
+}
+
+impl<W: io::Write> ReportWriter for CsvReportWriter<W> {
+    fn begin(&mut self, columns: &[&str]) -> Result<(), TransactorError> {
+        Ok(self.writer.write_record(columns)?)
+    }
+
+    fn row(&mut self, values: &[ReportValue]) -> Result<(), TransactorError> {
+        Ok(self
+            .writer
+            .write_record(values.iter().map(|value| value.to_string()))?)
+    }
+
+    fn finish(&mut self) -> Result<(), TransactorError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes rows as a json array of objects. Numbers are written exactly as json numbers so no
+/// precision is lost, consumers parsing them as floats may still lose it.
+pub struct JsonReportWriter<W: io::Write> {
+    writer: W,
+    columns: Vec<String>,
+    rows: usize,
+}
+
+impl<W: io::Write> JsonReportWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            columns: Vec::new(),
+            rows: 0,
+        }
+    }
+}
+
+impl<W: io::Write> ReportWriter for JsonReportWriter<W> {
+    fn begin(&mut self, columns: &[&str]) -> Result<(), TransactorError> {
+        self.columns = columns.iter().map(|column| json_string(column)).collect();
+        self.writer.write_all(b"[")?;
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ReportValue]) -> Result<(), TransactorError> {
+        let separator = if self.rows == 0 { "\n" } else { ",\n" };
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| {
+                let value = match value {
+                    ReportValue::Text(text) => json_string(text),
+                    ReportValue::Empty => "null".to_string(),
+                    value => value.to_string(),
+                };
+                format!("{}:{}", column, value)
+            })
+            .collect();
+        write!(self.writer, "{}  {{{}}}", separator, fields.join(","))?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactorError> {
+        self.writer.write_all(b"\n]\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn json_string(text: &str) -> String {
+    // Serializing a str cannot fail
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// Writes rows as a table with aligned columns, numbers to the right. Every row is held until
+/// `finish` so the column widths are known.
+pub struct TableReportWriter<W: io::Write> {
+    writer: W,
+    columns: Vec<String>,
+    rows: Vec<Vec<(String, bool)>>,
+}
+
+impl<W: io::Write> TableReportWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            columns: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl<W: io::Write> ReportWriter for TableReportWriter<W> {
+    fn begin(&mut self, columns: &[&str]) -> Result<(), TransactorError> {
+        self.columns = columns.iter().map(|column| column.to_string()).collect();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[ReportValue]) -> Result<(), TransactorError> {
+        self.rows.push(
+            values
+                .iter()
+                .map(|value| (value.to_string(), value.is_numeric()))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactorError> {
+        let mut widths: Vec<usize> = self.columns.iter().map(|column| column.len()).collect();
+        for row in &self.rows {
+            for (width, (value, _)) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        let header: Vec<String> = self
+            .columns
+            .iter()
+            .zip(&widths)
+            .map(|(column, width)| format!("{:<width$}", column, width = width))
+            .collect();
+        writeln!(self.writer, "{}", header.join("  ").trim_end())?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        writeln!(self.writer, "{}", rule.join("  "))?;
+        for row in &self.rows {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|((value, numeric), width)| match numeric {
+                    true => format!("{:>width$}", value, width = width),
+                    false => format!("{:<width$}", value, width = width),
+                })
+                .collect();
+            writeln!(self.writer, "{}", cells.join("  ").trim_end())?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
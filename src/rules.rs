@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::bank::{AccountName, Bank, ClientId, RiskTier, Timestamp, TransactionKind};
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::Command;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What happens to a row that breaks a rule.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleAction {
+    /// The row is not applied.
+    #[default]
+    Reject,
+    /// The row is applied and the breach recorded.
+    Flag,
+}
+
+/// What a rule limits, for each client.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+enum Check {
+    /// The amount of a single transaction.
+    #[serde(rename = "max-amount")]
+    Amount,
+    /// The sum of the amounts of the transactions in the window.
+    #[serde(rename = "max-total")]
+    Total,
+    /// The number of transactions in the window.
+    #[serde(rename = "max-count")]
+    Count,
+}
+
+/// The period totals and counts are kept over.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Window {
+    /// Everything processed in the run.
+    #[default]
+    File,
+    /// Each utc day of the row timestamps, rows without a timestamp share a day of their own.
+    Day,
+}
+
+/// A limit as written in the rules file, a string or an integer so amounts stay exact.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Limit {
+    Integer(i64),
+    Text(String),
+}
+
+/// A rule as written in the rules file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    name: String,
+    check: Check,
+    /// Only rows of this type, deposit or withdrawal, both if not given.
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    limit: Limit,
+    #[serde(default)]
+    window: Window,
+    #[serde(default)]
+    action: RuleAction,
+}
+
+/// The limits of a risk tier as written in the rules file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TierConfig {
+    max_balance: Option<Limit>,
+    max_withdrawal: Option<Limit>,
+    max_disputes: Option<u64>,
+    #[serde(default)]
+    action: RuleAction,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleConfig>,
+    #[serde(default)]
+    tier: HashMap<String, TierConfig>,
+}
+
+impl Limit {
+    fn parse(
+        &self,
+        invalid: impl Fn(String) -> TransactorError,
+    ) -> Result<Decimal, TransactorError> {
+        let limit = match self {
+            Limit::Integer(limit) => Decimal::from(*limit),
+            Limit::Text(limit) => limit
+                .parse()
+                .map_err(|_| invalid(format!("invalid limit {}", limit)))?,
+        };
+        if limit < Decimal::zero() {
+            return Err(invalid(format!("limit {} is negative", limit)));
+        }
+        Ok(limit)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    name: String,
+    check: Check,
+    kind: Option<TransactionKind>,
+    limit: Decimal,
+    window: Window,
+    action: RuleAction,
+}
+
+impl Rule {
+    fn from_config(config: RuleConfig) -> Result<Self, TransactorError> {
+        let invalid = |problem: String| InvalidConfig(format!("rule {}: {}", config.name, problem));
+        let limit = config.limit.parse(invalid)?;
+        let kind = config
+            .kind
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(invalid)?;
+        Ok(Self {
+            name: config.name,
+            check: config.check,
+            kind,
+            limit,
+            window: config.window,
+            action: config.action,
+        })
+    }
+}
+
+/// The limits on clients of a risk tier, none if not given.
+#[derive(Copy, Clone, Debug, Default)]
+struct TierLimits {
+    /// The largest total an account may reach through deposits.
+    max_balance: Option<Decimal>,
+    /// The largest amount of a single withdrawal.
+    max_withdrawal: Option<Decimal>,
+    /// How many disputes a client may open over the run.
+    max_disputes: Option<u64>,
+    action: RuleAction,
+}
+
+impl TierLimits {
+    fn from_config(tier: RiskTier, config: TierConfig) -> Result<Self, TransactorError> {
+        let invalid = |problem: String| InvalidConfig(format!("tier {}: {}", tier, problem));
+        let parse = |limit: Option<Limit>| limit.map(|limit| limit.parse(invalid)).transpose();
+        Ok(Self {
+            max_balance: parse(config.max_balance)?,
+            max_withdrawal: parse(config.max_withdrawal)?,
+            max_disputes: config.max_disputes,
+            action: config.action,
+        })
+    }
+}
+
+/// A rule broken by a row.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    pub rule: String,
+    pub action: RuleAction,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Usage {
+    total: Decimal,
+    count: u64,
+}
+
+/// Checks deposits and withdrawals against velocity and limit rules read from a TOML file of
+/// `[[rule]]` tables, for example
+///
+/// ```toml
+/// [[rule]]
+/// name = "daily-withdrawals"
+/// check = "max-total"     # or max-amount, max-count
+/// type = "withdrawal"     # or deposit, both if left out
+/// limit = "5000.00"
+/// window = "day"          # or file, the default
+/// action = "flag"         # or reject, the default
+///
+/// [tier.basic]            # or verified, premium
+/// max-balance = "1000"
+/// max-withdrawal = "250"
+/// max-disputes = 1        # disputes a client may open over the run
+/// action = "reject"       # or flag
+/// ```
+///
+/// Tier limits apply to the clients the bank's client metadata puts in the tier, see
+/// `ClientDirectory`, and are broken as a rule named after the limit and the tier.
+///
+/// Only rows that are applied count towards the totals and counts, so rows are checked with
+/// `check` and then `record`ed once the bank has applied them.
+#[derive(Clone, Debug, Default)]
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+    /// Usage of each windowed rule, by rule, client and day.
+    usage: HashMap<(usize, ClientId, Option<u64>), Usage>,
+    tiers: HashMap<RiskTier, TierLimits>,
+    /// The disputes each client has opened, for the dispute allowance of tiers.
+    disputes: HashMap<ClientId, u64>,
+}
+
+impl RuleEngine {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TransactorError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(rules: &str) -> Result<Self, TransactorError> {
+        let file: RulesFile = toml::from_str(rules)
+            .map_err(|e| InvalidConfig(format!("invalid rules file: {}", e)))?;
+        Ok(Self {
+            rules: file
+                .rules
+                .into_iter()
+                .map(Rule::from_config)
+                .collect::<Result<_, _>>()?,
+            usage: HashMap::new(),
+            tiers: file
+                .tier
+                .into_iter()
+                .map(|(tier, config)| {
+                    let tier: RiskTier = tier
+                        .parse()
+                        .map_err(|e| InvalidConfig(format!("invalid rules file: {}", e)))?;
+                    Ok((tier, TierLimits::from_config(tier, config)?))
+                })
+                .collect::<Result<_, TransactorError>>()?,
+            disputes: HashMap::new(),
+        })
+    }
+
+    /// Every rule the row would break if it were applied to the account of the bank, including the
+    /// limits of the tier the bank's client metadata puts the client in.
+    pub fn check(
+        &self,
+        command: &Command,
+        timestamp: Option<Timestamp>,
+        bank: &Bank,
+        account: AccountName,
+    ) -> Vec<Violation> {
+        let mut violations: Vec<Violation> = self
+            .check_tier(command, bank, account)
+            .into_iter()
+            .collect();
+        let (client, kind, amount) = match movement(command) {
+            Some(movement) => movement,
+            None => return violations,
+        };
+        let broken = self
+            .matching(kind)
+            .filter(|(index, rule)| {
+                let usage = self
+                    .usage
+                    .get(&(*index, client, day(rule.window, timestamp)))
+                    .copied()
+                    .unwrap_or_default();
+                match rule.check {
+                    Check::Amount => amount > rule.limit,
+                    Check::Total => usage
+                        .total
+                        .checked_add(amount)
+                        .is_none_or(|total| total > rule.limit),
+                    Check::Count => Decimal::from(usage.count + 1) > rule.limit,
+                }
+            })
+            .map(|(_, rule)| Violation {
+                rule: rule.name.clone(),
+                action: rule.action,
+            });
+        violations.extend(broken);
+        violations
+    }
+
+    /// The limit of the client's tier the row would break, named after the limit and the tier.
+    fn check_tier(
+        &self,
+        command: &Command,
+        bank: &Bank,
+        account: AccountName,
+    ) -> Option<Violation> {
+        let client = command.client();
+        let tier = bank.clients().risk_tier(client)?;
+        let limits = self.tiers.get(&tier)?;
+        let (check, limit) = match command {
+            Command::Deposit { transaction, .. } => {
+                let limit = limits.max_balance?;
+                let total = match bank.get_sub_account(client, account) {
+                    Some(account) => account.total().ok(),
+                    None => Some(Decimal::zero()),
+                };
+                let within = total
+                    .and_then(|total| total.checked_add(transaction.amount()))
+                    .is_some_and(|total| total <= limit);
+                if within {
+                    return None;
+                }
+                ("max-balance", limit.to_string())
+            }
+            Command::Withdrawal { transaction, .. } => {
+                let limit = limits.max_withdrawal?;
+                if transaction.amount().abs() <= limit {
+                    return None;
+                }
+                ("max-withdrawal", limit.to_string())
+            }
+            Command::Dispute { .. } => {
+                let limit = limits.max_disputes?;
+                if self.disputes.get(&client).copied().unwrap_or_default() < limit {
+                    return None;
+                }
+                ("max-disputes", limit.to_string())
+            }
+            _ => return None,
+        };
+        Some(Violation {
+            rule: format!("{} {} of the {} tier", check, limit, tier),
+            action: limits.action,
+        })
+    }
+
+    /// Count an applied row towards the totals and counts of the rules.
+    pub fn record(&mut self, command: &Command, timestamp: Option<Timestamp>) {
+        if let Command::Dispute { client, .. } = command {
+            if !self.tiers.is_empty() {
+                *self.disputes.entry(*client).or_default() += 1;
+            }
+        }
+        let (client, kind, amount) = match movement(command) {
+            Some(movement) => movement,
+            None => return,
+        };
+        let keys: Vec<_> = self
+            .matching(kind)
+            .filter(|(_, rule)| rule.check != Check::Amount)
+            .map(|(index, rule)| (index, client, day(rule.window, timestamp)))
+            .collect();
+        for key in keys {
+            let usage = self.usage.entry(key).or_default();
+            // Past the largest decimal every later row breaks the rule anyway
+            usage.total = usage.total.checked_add(amount).unwrap_or(usage.total);
+            usage.count += 1;
+        }
+    }
+
+    fn matching(&self, kind: TransactionKind) -> impl Iterator<Item = (usize, &Rule)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(move |(_, rule)| rule.kind.is_none_or(|only| only == kind))
+    }
+}
+
+/// The client, direction and unsigned amount of a deposit or withdrawal.
+fn movement(command: &Command) -> Option<(ClientId, TransactionKind, Decimal)> {
+    match command {
+        Command::Deposit {
+            client,
+            transaction,
+        } => Some((*client, TransactionKind::Deposit, transaction.amount())),
+        Command::Withdrawal {
+            client,
+            transaction,
+        } => Some((
+            *client,
+            TransactionKind::Withdrawal,
+            transaction.amount().abs(),
+        )),
+        _ => None,
+    }
+}
+
+fn day(window: Window, timestamp: Option<Timestamp>) -> Option<u64> {
+    match window {
+        Window::File => None,
+        Window::Day => timestamp.map(|timestamp| timestamp.0 / SECONDS_PER_DAY),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{ClientDirectory, ClientMetadata, Transaction, TransactionId};
+
+    fn withdrawal(amount: i64) -> Command {
+        Command::Withdrawal {
+            client: ClientId(1),
+            transaction: Transaction::new(TransactionId(1), Decimal::new(-amount, 0)),
+        }
+    }
+
+    #[test]
+    fn rules_limit_amounts_totals_and_counts() -> Result<(), TransactorError> {
+        let bank = Bank::new();
+        let mut rules = RuleEngine::from_toml(
+            r#"
+            [[rule]]
+            name = "large"
+            check = "max-amount"
+            limit = 100
+            action = "flag"
+
+            [[rule]]
+            name = "daily"
+            check = "max-total"
+            type = "withdrawal"
+            limit = "150.5"
+            window = "day"
+            "#,
+        )?;
+        let first_day = Some(Timestamp(0));
+        let names = |violations: Vec<Violation>| -> Vec<String> {
+            violations
+                .into_iter()
+                .map(|violation| violation.rule)
+                .collect()
+        };
+        assert!(rules
+            .check(&withdrawal(100), first_day, &bank, AccountName::MAIN)
+            .is_empty());
+        rules.record(&withdrawal(100), first_day);
+        assert_eq!(
+            names(rules.check(&withdrawal(60), first_day, &bank, AccountName::MAIN)),
+            vec!["daily"]
+        );
+        assert_eq!(
+            rules.check(
+                &withdrawal(101),
+                Some(Timestamp(SECONDS_PER_DAY)),
+                &bank,
+                AccountName::MAIN
+            ),
+            vec![Violation {
+                rule: "large".to_string(),
+                action: RuleAction::Flag
+            }]
+        );
+        assert!(RuleEngine::from_toml(
+            "[[rule]]\nname = \"x\"\ncheck = \"max-count\"\nlimit = -1\n"
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tiers_limit_balances_withdrawals_and_disputes() -> Result<(), TransactorError> {
+        let basic = ClientMetadata {
+            risk_tier: Some(RiskTier::Basic),
+            ..ClientMetadata::default()
+        };
+        let mut bank = Bank::new()
+            .with_client_directory(ClientDirectory::new().with_client(ClientId(1), basic));
+        bank.transact(
+            ClientId(1),
+            Transaction::new(TransactionId(1), Decimal::new(80, 0)),
+        )?;
+        let mut rules = RuleEngine::from_toml(
+            r#"
+            [tier.basic]
+            max-balance = "100"
+            max-withdrawal = 50
+            max-disputes = 1
+            "#,
+        )?;
+        let check = |rules: &RuleEngine, command: &Command| -> Vec<String> {
+            rules
+                .check(command, None, &bank, AccountName::MAIN)
+                .into_iter()
+                .map(|violation| violation.rule)
+                .collect()
+        };
+        let deposit = |client, amount| Command::Deposit {
+            client: ClientId(client),
+            transaction: Transaction::new(TransactionId(2), Decimal::new(amount, 0)),
+        };
+        assert!(check(&rules, &deposit(1, 20)).is_empty());
+        assert_eq!(
+            check(&rules, &deposit(1, 21)),
+            vec!["max-balance 100 of the basic tier"]
+        );
+        // Clients without a tier only have the rules
+        assert!(check(&rules, &deposit(2, 500)).is_empty());
+        assert_eq!(
+            check(&rules, &withdrawal(51)),
+            vec!["max-withdrawal 50 of the basic tier"]
+        );
+
+        let dispute = Command::Dispute {
+            client: ClientId(1),
+            transaction: TransactionId(1),
+            reason: None,
+        };
+        assert!(check(&rules, &dispute).is_empty());
+        rules.record(&dispute, None);
+        assert_eq!(
+            check(&rules, &dispute),
+            vec!["max-disputes 1 of the basic tier"]
+        );
+        assert!(RuleEngine::from_toml("[tier.gold]\nmax-disputes = 1\n").is_err());
+        Ok(())
+    }
+}
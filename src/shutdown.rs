@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{TransactorError, TransactorError::*};
+
+/// A request to stop processing early, such as on SIGINT or SIGTERM. Processing loops check it
+/// between records so a run stops on a record boundary with the state it has reached.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A shutdown requested by SIGINT or SIGTERM. A second signal still ends the process at once,
+    /// for when stopping on a record boundary takes too long. Only one handler can be installed
+    /// per process.
+    pub fn on_signals() -> Result<Self, TransactorError> {
+        let shutdown = Self::new();
+        let requested = shutdown.requested.clone();
+        ctrlc::set_handler(move || {
+            if requested.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+        })
+        .map_err(|e| InvalidConfig(format!("cannot handle signals: {}", e)))?;
+        Ok(shutdown)
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_are_seen_by_every_clone() {
+        let shutdown = Shutdown::new();
+        let loop_side = shutdown.clone();
+        assert!(!loop_side.is_requested());
+        shutdown.request();
+        assert!(loop_side.is_requested());
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::bank::{ClientId, Timestamp, TransactionId};
+use crate::error::{TransactorError, TransactorError::*};
+
+/// Whether a dispute has been open longer than the SLA allows.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SlaStatus {
+    Within,
+    Breached,
+}
+
+impl SlaStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            SlaStatus::Within => "within",
+            SlaStatus::Breached => "breached",
+        }
+    }
+}
+
+/// A dispute still open, as reported by `DisputeTimers::open_disputes`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OpenDispute {
+    pub client: ClientId,
+    pub transaction: TransactionId,
+    pub opened: Timestamp,
+    /// Seconds the dispute has been open for.
+    pub age: u64,
+    pub status: SlaStatus,
+}
+
+/// A dispute found to have been open longer than the SLA, escalated once per dispute.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SlaBreach {
+    pub client: ClientId,
+    pub transaction: TransactionId,
+    pub opened: Timestamp,
+    /// When the dispute went over the SLA.
+    pub breached_at: Timestamp,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Timer {
+    opened: Timestamp,
+    escalated: bool,
+}
+
+/// Times how long disputes stay open against an SLA, measured by the clock of the input: the
+/// latest timestamp seen. There is no clock until a timestamped row has been seen, disputes opened
+/// before then are timed from the first timestamp.
+#[derive(Clone, Debug)]
+pub struct DisputeTimers {
+    /// The longest a dispute may stay open, in seconds.
+    threshold: u64,
+    now: Option<Timestamp>,
+    open: HashMap<(ClientId, TransactionId), Timer>,
+    /// Disputes opened before there was a clock.
+    untimed: Vec<(ClientId, TransactionId)>,
+}
+
+impl DisputeTimers {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            now: None,
+            open: HashMap::new(),
+            untimed: Vec::new(),
+        }
+    }
+
+    /// The latest time seen in the input.
+    pub fn now(&self) -> Option<Timestamp> {
+        self.now
+    }
+
+    /// Start timing a dispute, from when it was opened if known and otherwise from now.
+    pub fn opened(&mut self, client: ClientId, transaction: TransactionId, at: Option<Timestamp>) {
+        match at.or(self.now) {
+            Some(opened) => {
+                self.open.insert(
+                    (client, transaction),
+                    Timer {
+                        opened,
+                        escalated: false,
+                    },
+                );
+            }
+            None => self.untimed.push((client, transaction)),
+        }
+    }
+
+    /// Stop timing a dispute once it is resolved or charged back.
+    pub fn closed(&mut self, client: ClientId, transaction: TransactionId) {
+        self.open.remove(&(client, transaction));
+        self.untimed
+            .retain(|dispute| *dispute != (client, transaction));
+    }
+
+    /// Move the clock on to a row's timestamp, returning the disputes that have newly gone over
+    /// the SLA ordered by when they went over. The clock never goes backwards.
+    pub fn advance(&mut self, now: Timestamp) -> Vec<SlaBreach> {
+        if self.now.is_some_and(|current| current >= now) {
+            return Vec::new();
+        }
+        self.now = Some(now);
+        for (client, transaction) in self.untimed.drain(..) {
+            self.open.insert(
+                (client, transaction),
+                Timer {
+                    opened: now,
+                    escalated: false,
+                },
+            );
+        }
+        let threshold = self.threshold;
+        let mut breaches: Vec<SlaBreach> = self
+            .open
+            .iter_mut()
+            .filter(|(_, timer)| {
+                !timer.escalated && now.0.saturating_sub(timer.opened.0) > threshold
+            })
+            .map(|(&(client, transaction), timer)| {
+                timer.escalated = true;
+                SlaBreach {
+                    client,
+                    transaction,
+                    opened: timer.opened,
+                    breached_at: Timestamp(timer.opened.0.saturating_add(threshold)),
+                }
+            })
+            .collect();
+        breaches.sort_by_key(|breach| (breach.breached_at, breach.client.0, breach.transaction.0));
+        breaches
+    }
+
+    /// Every dispute still open with its SLA status as of now, ordered by client and transaction.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let now = self.now.unwrap_or(Timestamp(0));
+        let mut disputes: Vec<OpenDispute> = self
+            .open
+            .iter()
+            .map(|(&(client, transaction), timer)| {
+                let age = now.0.saturating_sub(timer.opened.0);
+                OpenDispute {
+                    client,
+                    transaction,
+                    opened: timer.opened,
+                    age,
+                    status: if age > self.threshold {
+                        SlaStatus::Breached
+                    } else {
+                        SlaStatus::Within
+                    },
+                }
+            })
+            .collect();
+        disputes.sort_by_key(|dispute| (dispute.client.0, dispute.transaction.0));
+        disputes
+    }
+}
+
+impl SlaBreach {
+    /// The breach as json, as posted by `SlaWebhook`.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "event": "dispute_sla_breached",
+            "client": self.client.0,
+            "tx": self.transaction.0,
+            "opened": self.opened.0,
+            "breached_at": self.breached_at.0,
+        })
+    }
+}
+
+/// Posts each SLA escalation as json to a url, such as the webhook of an alerting or ticketing
+/// system. Posting needs the remote feature.
+pub struct SlaWebhook {
+    url: String,
+}
+
+impl SlaWebhook {
+    pub fn new(url: &str) -> Result<Self, TransactorError> {
+        if cfg!(not(feature = "remote")) {
+            return Err(InvalidConfig(
+                "posting SLA escalations to a webhook needs the remote feature".to_string(),
+            ));
+        }
+        Ok(Self {
+            url: url.to_string(),
+        })
+    }
+
+    /// Post a breach, failing if the webhook cannot be reached or does not accept it.
+    #[cfg(feature = "remote")]
+    pub fn send(&self, breach: &SlaBreach) -> Result<(), TransactorError> {
+        ureq::post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&breach.to_json().to_string())
+            .map(|_| ())
+            .map_err(|e| RemoteError(format!("cannot post to {}: {}", self.url, e)))
+    }
+
+    #[cfg(not(feature = "remote"))]
+    pub fn send(&self, _breach: &SlaBreach) -> Result<(), TransactorError> {
+        Err(InvalidConfig(format!(
+            "cannot post to {}, posting SLA escalations to a webhook needs the remote feature",
+            self.url
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disputes_are_escalated_once_when_open_past_the_sla() {
+        let mut timers = DisputeTimers::new(100);
+        timers.advance(Timestamp(1000));
+        timers.opened(ClientId(1), TransactionId(1), Some(Timestamp(1000)));
+        timers.opened(ClientId(1), TransactionId(2), None);
+        timers.opened(ClientId(2), TransactionId(3), Some(Timestamp(1050)));
+        assert!(timers.advance(Timestamp(1100)).is_empty());
+        timers.closed(ClientId(1), TransactionId(2));
+
+        let breaches = timers.advance(Timestamp(1101));
+        assert_eq!(
+            breaches,
+            vec![SlaBreach {
+                client: ClientId(1),
+                transaction: TransactionId(1),
+                opened: Timestamp(1000),
+                breached_at: Timestamp(1100),
+            }]
+        );
+        assert!(timers.advance(Timestamp(1120)).is_empty());
+
+        let statuses: Vec<_> = timers
+            .open_disputes()
+            .iter()
+            .map(|dispute| (dispute.transaction, dispute.age, dispute.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (TransactionId(1), 120, SlaStatus::Breached),
+                (TransactionId(3), 70, SlaStatus::Within)
+            ]
+        );
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn escalations_are_posted_to_the_webhook() -> Result<(), TransactorError> {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let webhook = SlaWebhook::new(&format!("http://{}/escalations", listener.local_addr()?))?;
+        let server = std::thread::spawn(move || -> std::io::Result<(String, String)> {
+            let (stream, _) = listener.accept()?;
+            let mut request = BufReader::new(&stream);
+            let mut request_line = String::new();
+            request.read_line(&mut request_line)?;
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                request.read_line(&mut header)?;
+                if header.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            request.read_exact(&mut body)?;
+            (&stream).write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")?;
+            Ok((request_line, String::from_utf8_lossy(&body).into_owned()))
+        });
+        let breach = SlaBreach {
+            client: ClientId(1),
+            transaction: TransactionId(2),
+            opened: Timestamp(1000),
+            breached_at: Timestamp(1100),
+        };
+        webhook.send(&breach)?;
+        let (request_line, body) = server.join().expect("server panicked")?;
+        assert!(request_line.starts_with("POST /escalations "));
+        let posted: Value = serde_json::from_str(&body).map_err(std::io::Error::from)?;
+        assert_eq!(posted, breach.to_json());
+        assert_eq!(posted["tx"], 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::bank::{Bank, Outcome};
+use crate::error::TransactorError;
+
+/// Totals of a run, for monitoring the health of batch runs. Unlike `Metrics` these count input
+/// rows rather than bank operations, and include the state the accounts were left in.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RunStats {
+    pub rows: u64,
+    /// Rows by their type, such as deposit.
+    pub rows_by_type: BTreeMap<&'static str, u64>,
+    pub applied: u64,
+    pub ignored: u64,
+    pub rejected: u64,
+    pub accounts_created: u64,
+    pub locked_accounts: u64,
+    pub total_held: Decimal,
+    pub elapsed_seconds: f64,
+    pub rows_per_second: f64,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a row of the given type and the result of applying it.
+    pub fn record(&mut self, kind: &'static str, result: &Result<Outcome, TransactorError>) {
+        self.rows += 1;
+        *self.rows_by_type.entry(kind).or_default() += 1;
+        match result {
+            Ok(Outcome::Applied) => self.applied += 1,
+            Ok(Outcome::Ignored(_)) => self.ignored += 1,
+            Err(_) => self.rejected += 1,
+        }
+    }
+
+    /// Take the state of the accounts at the end of a run, which started with `accounts_before`
+    /// accounts and took `elapsed`.
+    pub fn finish(&mut self, bank: &Bank, accounts_before: usize, elapsed: Duration) {
+        let mut accounts: usize = 0;
+        self.locked_accounts = 0;
+        self.total_held = Decimal::zero();
+        for account in bank.get_accounts() {
+            accounts += 1;
+            self.locked_accounts += u64::from(account.is_locked());
+            // Past the largest decimal the total is as good as it gets
+            self.total_held = self
+                .total_held
+                .checked_add(account.held())
+                .unwrap_or(self.total_held);
+        }
+        self.accounts_created = accounts.saturating_sub(accounts_before) as u64;
+        self.elapsed_seconds = elapsed.as_secs_f64();
+        self.rows_per_second = match self.elapsed_seconds {
+            seconds if seconds > 0.0 => self.rows as f64 / seconds,
+            _ => 0.0,
+        };
+    }
+}
+
+impl fmt::Display for RunStats {
+    /// A human readable summary of the run.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "rows: {} ({} applied, {} ignored, {} rejected)",
+            self.rows, self.applied, self.ignored, self.rejected
+        )?;
+        for (kind, rows) in &self.rows_by_type {
+            writeln!(f, "  {:<12}{:>10}", kind, rows)?;
+        }
+        writeln!(f, "accounts created: {}", self.accounts_created)?;
+        writeln!(f, "locked accounts: {}", self.locked_accounts)?;
+        writeln!(f, "total held: {}", self.total_held)?;
+        write!(
+            f,
+            "elapsed: {:.3}s, {:.0} rows/s",
+            self.elapsed_seconds, self.rows_per_second
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bank::{ClientId, IgnoreReason, Transaction, TransactionId};
+
+    #[test]
+    fn rows_and_final_accounts_are_totalled() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        bank.open_account(ClientId(1), Decimal::zero(), Decimal::zero(), false, None)?;
+        let mut stats = RunStats::new();
+        let deposit = Transaction::new(TransactionId(1), Decimal::new(25, 1));
+        let result = bank.transact(ClientId(2), deposit);
+        stats.record("deposit", &result);
+        let result = bank.dispute_transaction(ClientId(2), TransactionId(1));
+        stats.record("dispute", &result);
+        stats.record(
+            "withdrawal",
+            &Ok(Outcome::Ignored(IgnoreReason::AccountLocked)),
+        );
+        stats.record("deposit", &Err(TransactorError::TransactionIdReuse));
+        stats.finish(&bank, 1, Duration::from_secs(2));
+
+        assert_eq!(stats.rows, 4);
+        assert_eq!(stats.rows_by_type["deposit"], 2);
+        assert_eq!((stats.applied, stats.ignored, stats.rejected), (2, 1, 1));
+        assert_eq!(stats.accounts_created, 1);
+        assert_eq!(stats.total_held, Decimal::new(25, 1));
+        assert_eq!(stats.rows_per_second, 2.0);
+        let json = serde_json::to_string(&stats).map_err(std::io::Error::from)?;
+        assert!(json.contains("\"total_held\":\"2.5\""));
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use proptest::prelude::*;
+use proptest::sample::Index;
+use rust_decimal::prelude::*;
+
+use crate::bank::{Bank, ClientId, Transaction, TransactionId};
+use crate::error::TransactorError;
+use crate::input::Command;
+use crate::model::ReferenceModel;
+
+/// Amounts of up to a million with up to four decimal places, including zero.
+pub fn amounts() -> impl Strategy<Value = Decimal> {
+    (0i64..10_000_000_000, 0u32..=4).prop_map(|(units, scale)| Decimal::new(units, scale))
+}
+
+/// A step of a generated sequence, before transaction ids are given out.
+#[derive(Clone, Debug)]
+enum Step {
+    Deposit(Decimal),
+    Withdrawal(Decimal),
+    Authorize(Decimal),
+    /// A dispute, resolve, chargeback, representment, capture or void of the transaction made at some step of
+    /// the sequence, by the client of that step if `owner`.
+    Refer {
+        name: &'static str,
+        target: Index,
+        owner: bool,
+    },
+    Freeze,
+    Unfreeze,
+    Close,
+}
+
+fn steps() -> impl Strategy<Value = Step> {
+    let refer = |name| {
+        (any::<Index>(), prop::bool::weighted(0.8)).prop_map(move |(target, owner)| Step::Refer {
+            name,
+            target,
+            owner,
+        })
+    };
+    prop_oneof![
+        6 => amounts().prop_map(Step::Deposit),
+        4 => amounts().prop_map(Step::Withdrawal),
+        2 => amounts().prop_map(Step::Authorize),
+        4 => refer("dispute"),
+        2 => refer("resolve"),
+        2 => refer("chargeback"),
+        1 => refer("represent"),
+        1 => refer("capture"),
+        1 => refer("void"),
+        1 => Just(Step::Freeze),
+        1 => Just(Step::Unfreeze),
+        1 => Just(Step::Close),
+    ]
+}
+
+/// Sequences of up to `max_len` commands for `clients` clients, as read from input rows.
+///
+/// Every deposit, withdrawal and authorization has its own transaction id, so no sequence fails
+/// on a reused id. Disputes, resolves, chargebacks, representments, captures and voids refer to any step of the
+/// sequence, so they come before the transaction they refer to, refer to it more than once, to
+/// the wrong kind of transaction, to another clients transaction or to one that is never made.
+pub fn commands(clients: u16, max_len: usize) -> impl Strategy<Value = Vec<Command>> {
+    let clients = clients.max(1);
+    prop::collection::vec((0..clients, steps()), 0..=max_len).prop_map(|steps| {
+        // One past the end refers to a transaction that is never made
+        let len = steps.len() + 1;
+        steps
+            .iter()
+            .enumerate()
+            .map(|(i, (client, step))| {
+                let client = ClientId(*client);
+                let transaction_id = TransactionId(i as u32 + 1);
+                match step {
+                    Step::Deposit(amount) => Command::Deposit {
+                        client,
+                        transaction: Transaction::new(transaction_id, *amount),
+                    },
+                    Step::Withdrawal(amount) => Command::Withdrawal {
+                        client,
+                        transaction: Transaction::new(transaction_id, -*amount),
+                    },
+                    Step::Authorize(amount) => Command::Authorize {
+                        client,
+                        transaction: Transaction::new(transaction_id, *amount),
+                    },
+                    Step::Refer {
+                        name,
+                        target,
+                        owner,
+                    } => {
+                        let target = target.index(len);
+                        let client = match steps.get(target) {
+                            Some((owner_client, _)) if *owner => ClientId(*owner_client),
+                            _ => client,
+                        };
+                        refer(name, client, TransactionId(target as u32 + 1))
+                    }
+                    Step::Freeze => Command::Freeze {
+                        client,
+                        reason: None,
+                    },
+                    Step::Unfreeze => Command::Unfreeze { client },
+                    Step::Close => Command::Close { client },
+                }
+            })
+            .collect()
+    })
+}
+
+fn refer(name: &str, client: ClientId, transaction: TransactionId) -> Command {
+    match name {
+        "dispute" => Command::Dispute {
+            client,
+            transaction,
+            reason: None,
+        },
+        "resolve" => Command::Resolve {
+            client,
+            transaction,
+        },
+        "chargeback" => Command::Chargeback {
+            client,
+            transaction,
+            reason: None,
+        },
+        "represent" => Command::Represent {
+            client,
+            transaction,
+        },
+        "capture" => Command::Capture {
+            client,
+            transaction,
+        },
+        _ => Command::Void {
+            client,
+            transaction,
+        },
+    }
+}
+
+impl Arbitrary for Command {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// A single command for one of a few clients and transactions, see `commands` for sequences.
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        commands(4, 1)
+            .prop_filter_map("no command", |commands| commands.into_iter().next())
+            .boxed()
+    }
+}
+
+/// Check every invariant of the bank: those of `Bank::check_invariants` and that funds are
+/// conserved. The bank must have started empty and keep all history in memory.
+pub fn check_bank(bank: &Bank) -> Result<(), TransactorError> {
+    bank.check_invariants()?;
+    bank.check_funds_conserved()
+}
+
+/// Apply the commands to the bank, checking the accounts of each client after every command
+/// touching it, that the bank gets the same result as `ReferenceModel` for every command and
+/// that they agree on the accounts at the end. The bank must have started empty, keep all
+/// history in memory and follow the default rules, errors from the commands are not failures
+/// as long as the model has them too.
+pub fn check_commands(bank: &mut Bank, commands: &[Command]) -> Result<(), TransactorError> {
+    let mut model = ReferenceModel::new();
+    for command in commands {
+        let result = command.apply(bank);
+        model.verify(*command, &result)?;
+        bank.check_account_invariants(command.client())?;
+    }
+    check_bank(bank)?;
+    match model.differences(bank).first() {
+        Some(difference) => Err(TransactorError::VerificationFailed(difference.clone())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::TransactorError::*;
+
+    proptest! {
+        #[test]
+        fn generated_commands_keep_every_invariant(commands in commands(4, 200)) {
+            check_commands(&mut Bank::new(), &commands)?;
+        }
+    }
+
+    #[test]
+    fn money_from_nowhere_is_caught() -> Result<(), TransactorError> {
+        let mut bank = Bank::new();
+        let client = ClientId(1);
+        bank.transact(
+            client,
+            Transaction::new(TransactionId(1), Decimal::new(5, 0)),
+        )?;
+        check_bank(&bank)?;
+        bank.open_account(
+            ClientId(2),
+            Decimal::new(1, 0),
+            Decimal::zero(),
+            false,
+            None,
+        )?;
+        assert!(matches!(
+            check_bank(&bank),
+            Err(InvariantViolation { client, invariant, .. })
+                if client == ClientId(2) && invariant == "total 1 is not the 0 the history adds up to"
+        ));
+        Ok(())
+    }
+}
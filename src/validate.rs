@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+
+use crate::bank::{ClientId, TransactionId};
+use crate::currency::PrecisionTable;
+use crate::error::{TransactorError, TransactorError::*};
+use crate::input::{Command, InputRecord};
+
+/// Checks input rows without applying them, for pre-flighting a file before it is processed.
+///
+/// Rows that cannot be read, transaction ids used twice, references to transactions never made
+/// and amounts with too many decimal places are all found. Amounts are checked against the
+/// precision of their currency even when it is only the default, where a run would accept them
+/// and round them on output.
+#[derive(Clone, Debug, Default)]
+pub struct Validator {
+    precision: PrecisionTable,
+    global_transaction_ids: bool,
+    /// Every transaction made so far, by deposit, withdrawal or authorization.
+    made: HashSet<(ClientId, TransactionId)>,
+    /// Every transaction id used so far, only kept when ids must be unique across clients.
+    used: HashSet<TransactionId>,
+}
+
+impl Validator {
+    pub fn new(precision: PrecisionTable) -> Self {
+        Self {
+            precision,
+            ..Self::default()
+        }
+    }
+
+    /// Treat transaction ids used by two different clients as duplicates too.
+    pub fn with_global_transaction_ids(mut self) -> Self {
+        self.global_transaction_ids = true;
+        self
+    }
+
+    /// The problems with the next row of the input, each with the position of the row.
+    pub fn check(&mut self, result: Result<InputRecord, TransactorError>) -> Vec<TransactorError> {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => return vec![e],
+        };
+        let mut issues = Vec::new();
+        match &record.command {
+            Command::Deposit {
+                client,
+                transaction,
+            }
+            | Command::Withdrawal {
+                client,
+                transaction,
+            }
+            | Command::Authorize {
+                client,
+                transaction,
+            }
+            | Command::Hold {
+                client,
+                transaction,
+            }
+            | Command::Move {
+                client,
+                transaction,
+                ..
+            } => {
+                let precision = self.precision.precision(transaction.currency());
+                if let Err(e) = precision.validate(transaction.amount()) {
+                    issues.push(e);
+                }
+                let tx = transaction.transaction_id();
+                let reused = !self.made.insert((*client, tx))
+                    || (self.global_transaction_ids && !self.used.insert(tx));
+                if reused {
+                    issues.push(DuplicateTransaction {
+                        client: *client,
+                        tx,
+                    });
+                }
+            }
+            Command::Dispute {
+                client,
+                transaction,
+                ..
+            }
+            | Command::Resolve {
+                client,
+                transaction,
+            }
+            | Command::Chargeback {
+                client,
+                transaction,
+                ..
+            }
+            | Command::Represent {
+                client,
+                transaction,
+            }
+            | Command::Capture {
+                client,
+                transaction,
+            }
+            | Command::Void {
+                client,
+                transaction,
+            }
+            | Command::Release {
+                client,
+                transaction,
+            } => {
+                if !self.made.contains(&(*client, *transaction)) {
+                    issues.push(DanglingReference {
+                        client: *client,
+                        tx: *transaction,
+                    });
+                }
+            }
+            Command::Freeze { .. } | Command::Unfreeze { .. } | Command::Close { .. } => {}
+        }
+        issues
+            .into_iter()
+            .map(|issue| issue.at(record.position))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{InputReader, InputSchema};
+
+    #[test]
+    fn every_problem_is_found_with_its_line() -> Result<(), TransactorError> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1.00001\n\
+            deposit,1,1,2\n\
+            deposit,2,1,2\n\
+            withdrawal,x,2,1\n\
+            dispute,2,7,\n\
+            dispute,1,1,\n";
+        let mut validator = Validator::new(PrecisionTable::new());
+        let issues: Vec<String> = InputReader::from_reader(input.as_bytes(), InputSchema::V1)?
+            .flat_map(|result| validator.check(result))
+            .map(|issue| issue.to_string())
+            .collect();
+        assert_eq!(issues.len(), 4);
+        assert!(issues[0].starts_with("Line 2, client 1, transaction 1: Amount 1.00001"));
+        assert!(issues[1].starts_with("Line 3, client 1, transaction 1: Client 1 has"));
+        assert!(issues[2].starts_with("Line 5: "));
+        assert!(issues[3].starts_with("Line 6, client 2, transaction 7: Client 2 referred"));
+
+        let mut global = Validator::new(PrecisionTable::new()).with_global_transaction_ids();
+        let issues = InputReader::from_reader(input.as_bytes(), InputSchema::V1)?
+            .flat_map(|result| global.check(result))
+            .count();
+        assert_eq!(issues, 5);
+        Ok(())
+    }
+}